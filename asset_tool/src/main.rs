@@ -0,0 +1,254 @@
+//! Host tool for card assets
+//!
+//! Finds seamless loop points in each recording, checks the joins and writes
+//! them into a [`LoopManifest`] for the card to embed, in the order the
+//! files are given:
+//!
+//! ```text
+//! cargo run --release -- --out ../backyard_rain/data/backyard_rain_loops.bin \
+//!     light.wav medium.wav heavy.wav
+//! ```
+//!
+//! Recordings can be mono 16 bit PCM or IMA ADPCM WAVs. A join that isn't
+//! seamless is reported, and with `--bake` a PCM recording gets a crossfade
+//! baked into the end of its loop, written next to it as `<name>_baked.wav`
+//! for exporting to ADPCM.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use wscomp::adpcm::AdpcmStream;
+use wscomp::assets::{LoopManifest, LoopPoints};
+use wscomp::loops::{self, LoopError, LoopJoin};
+use wscomp::wav::{Wav, WavCodec};
+
+const USAGE: &str = "\
+usage: asset_tool --out LOOPS.bin [--search SECONDS] [--bake MILLISECONDS] WAV...
+
+  --out       loop manifest to write, one entry per WAV in order
+  --search    how far from the end to look for the loop end [default: 2]
+  --bake      crossfade length to bake into PCM recordings whose loop
+              doesn't join seamlessly, written as <name>_baked.wav";
+
+/// Most recordings in one manifest
+const MAX_ASSETS: usize = 8;
+
+struct Options {
+    out: PathBuf,
+    search_seconds: f32,
+    bake_millis: Option<u32>,
+    wavs: Vec<PathBuf>,
+}
+
+/// A recording decoded to 16 bit samples
+struct Recording {
+    codec: WavCodec,
+    sample_rate: u32,
+    samples: Vec<i16>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut out = None;
+    let mut search_seconds = 2.0;
+    let mut bake_millis = None;
+    let mut wavs = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "--out" => out = Some(PathBuf::from(value("--out")?)),
+            "--search" => {
+                search_seconds = value("--search")?
+                    .parse()
+                    .map_err(|_| "--search takes a number of seconds")?;
+            }
+            "--bake" => {
+                bake_millis = Some(
+                    value("--bake")?
+                        .parse()
+                        .map_err(|_| "--bake takes a whole number of milliseconds")?,
+                );
+            }
+            "-h" | "--help" => return Err("Finds seamless loop points for card assets".into()),
+            flag if flag.starts_with('-') => return Err(format!("unknown option {flag}")),
+            _ => wavs.push(PathBuf::from(arg)),
+        }
+    }
+    let out = out.ok_or("--out is required")?;
+    if wavs.is_empty() || wavs.len() > MAX_ASSETS {
+        return Err(format!("give 1 to {MAX_ASSETS} WAV files"));
+    }
+    Ok(Options {
+        out,
+        search_seconds,
+        bake_millis,
+        wavs,
+    })
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut loops = Vec::new();
+    for path in &options.wavs {
+        let mut recording = read_recording(path)?;
+        let rate = recording.sample_rate as f32;
+        let search = (options.search_seconds * rate) as usize;
+        let Some(points) = loops::find_loop(&recording.samples, search) else {
+            println!(
+                "{}: no zero crossings to loop on, loops whole",
+                path.display()
+            );
+            loops.push(None);
+            continue;
+        };
+        let failed = |error: LoopError| format!("{}: {error:?}", path.display());
+        let join = loops::check_loop(&recording.samples, points).map_err(failed)?;
+        report(path, points, join, rate);
+
+        if let (false, Some(millis)) = (join.is_seamless(), options.bake_millis) {
+            if recording.codec != WavCodec::Pcm {
+                return Err(format!(
+                    "{}: can only bake a crossfade into a PCM recording, bake the master \
+                     and export that to ADPCM",
+                    path.display()
+                )
+                .into());
+            }
+            let len = (millis * recording.sample_rate / 1000) as usize;
+            loops::bake_crossfade(&mut recording.samples, points, len).map_err(failed)?;
+            let baked = baked_path(path);
+            write_pcm_wav(&baked, recording.sample_rate, &recording.samples)?;
+            let join = loops::check_loop(&recording.samples, points).map_err(failed)?;
+            println!("  baked a {millis}ms crossfade into {}", baked.display());
+            report(path, points, join, rate);
+        }
+        loops.push(Some(points));
+    }
+
+    let bytes = manifest_bytes(&loops)?;
+    std::fs::write(&options.out, bytes)?;
+    println!("wrote {}", options.out.display());
+    Ok(())
+}
+
+fn report(path: &Path, points: LoopPoints, join: LoopJoin, rate: f32) {
+    println!(
+        "{}: loop {}..{} ({:.2}s to {:.2}s), jump {} against steps up to {}, {}",
+        path.display(),
+        points.start,
+        points.end,
+        points.start as f32 / rate,
+        points.end as f32 / rate,
+        join.jump,
+        join.local_step,
+        if join.is_seamless() {
+            "seamless"
+        } else {
+            "NOT seamless"
+        }
+    );
+}
+
+fn read_recording(path: &Path) -> Result<Recording, Box<dyn Error>> {
+    let bytes = std::fs::read(path).map_err(|error| format!("{}: {error}", path.display()))?;
+    let wav = Wav::parse(&bytes).map_err(|error| format!("{}: {error:?}", path.display()))?;
+    let format = wav.format;
+    if format.channels != 1 {
+        return Err(format!("{}: only mono recordings are supported", path.display()).into());
+    }
+    let samples = match (format.codec, format.bits_per_sample) {
+        (WavCodec::Pcm, 16) => wav
+            .data
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect(),
+        (WavCodec::ImaAdpcm, _) => {
+            let mut stream =
+                AdpcmStream::new(&wav).map_err(|error| format!("{}: {error:?}", path.display()))?;
+            std::iter::from_fn(|| stream.next_sample()).collect()
+        }
+        (codec, bits) => {
+            return Err(format!(
+                "{}: {codec:?} at {bits} bits, only 16 bit PCM and IMA ADPCM are supported",
+                path.display()
+            )
+            .into())
+        }
+    };
+    Ok(Recording {
+        codec: format.codec,
+        sample_rate: format.sample_rate,
+        samples,
+    })
+}
+
+fn baked_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}_baked.wav"))
+}
+
+fn write_pcm_wav(path: &Path, sample_rate: u32, samples: &[i16]) -> std::io::Result<()> {
+    let data_len = 2 * samples.len() as u32;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16_u32.to_le_bytes());
+    // PCM, mono, rate, bytes per second, block align, bits
+    bytes.extend_from_slice(&1_u16.to_le_bytes());
+    bytes.extend_from_slice(&1_u16.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(2 * sample_rate).to_le_bytes());
+    bytes.extend_from_slice(&2_u16.to_le_bytes());
+    bytes.extend_from_slice(&16_u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    std::fs::write(path, bytes)
+}
+
+/// Serialized [`LoopManifest`], sized for the number of recordings
+fn manifest_bytes(loops: &[Option<LoopPoints>]) -> Result<Vec<u8>, Box<dyn Error>> {
+    fn serialize<const N: usize>(loops: &[Option<LoopPoints>]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let mut manifest = LoopManifest::<N>::new();
+        for (asset, points) in loops.iter().enumerate() {
+            manifest
+                .set(asset, *points)
+                .map_err(|error| format!("{error:?}"))?;
+        }
+        let mut bytes = vec![0; LoopManifest::<N>::SERIALIZED_SIZE];
+        manifest
+            .to_bytes(&mut bytes)
+            .map_err(|error| format!("{error:?}"))?;
+        Ok(bytes)
+    }
+    match loops.len() {
+        1 => serialize::<1>(loops),
+        2 => serialize::<2>(loops),
+        3 => serialize::<3>(loops),
+        4 => serialize::<4>(loops),
+        5 => serialize::<5>(loops),
+        6 => serialize::<6>(loops),
+        7 => serialize::<7>(loops),
+        8 => serialize::<8>(loops),
+        count => Err(format!("{count} recordings, at most {MAX_ASSETS} fit a manifest").into()),
+    }
+}
@@ -0,0 +1,139 @@
+//! Cycle counts for the wavetable and crossfade paths, on the RP2040's SIO
+//! interpolators against the portable code
+//!
+//! The Cortex-M0+ has no DWT cycle counter, so this times with SysTick on
+//! the core clock. Run it with probe-rs and read the counts over defmt:
+//!
+//! ```text
+//! cargo run --release --example interp_bench --features sio-interp
+//! ```
+//!
+//! Each result is checked against the portable code before it's timed.
+
+#![no_std]
+#![no_main]
+
+use core::hint::black_box;
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+use cortex_m_rt::entry;
+use defmt::*;
+#[cfg(not(feature = "console"))]
+use panic_halt as _;
+#[cfg(feature = "console")]
+use {defmt_rtt as _, panic_probe as _};
+
+use wscomp::interp::{SioInterp, SoftInterp};
+use wscomp::tables;
+use wscomp::units::Hertz;
+use wscomp::wavetable::{Wavetable, WavetableOsc};
+use wscomp::Sample;
+
+/// Calls timed in each run, few enough that SysTick's 24 bits don't wrap
+const RUNS: u32 = 4096;
+
+/// Two tables, a saw and a falling saw, so morphs blend between them
+const TABLE_BYTES: [u8; 2 * 2 * Wavetable::LEN] = saws();
+const TABLES: Wavetable<'static> = Wavetable::from_bytes(&TABLE_BYTES).unwrap();
+
+const fn saws() -> [u8; 2 * 2 * Wavetable::LEN] {
+    let mut bytes = [0; 2 * 2 * Wavetable::LEN];
+    let mut index = 0;
+    while index < Wavetable::LEN {
+        let rising = (index as i32 * 16 - 2048) as i16;
+        let [low, high] = rising.to_le_bytes();
+        bytes[2 * index] = low;
+        bytes[2 * index + 1] = high;
+        let [low, high] = (-rising - 1).to_le_bytes();
+        bytes[2 * (Wavetable::LEN + index)] = low;
+        bytes[2 * (Wavetable::LEN + index) + 1] = high;
+        index += 1;
+    }
+    bytes
+}
+
+/// Core clock cycles per call of `run`, averaged over [`RUNS`] calls
+fn cycles_per_call(mut run: impl FnMut(u32)) -> u32 {
+    // SysTick counts down
+    let start = SYST::get_current();
+    for index in 0..RUNS {
+        run(black_box(index));
+    }
+    let end = SYST::get_current();
+    (start.wrapping_sub(end) & 0x00ff_ffff) / RUNS
+}
+
+fn oscillator() -> WavetableOsc<'static> {
+    let mut osc = WavetableOsc::new(TABLES, Hertz::new(110), Hertz::new(48_000));
+    // between the tables, so every tick blends both
+    osc.set_morph(Sample::from(0_i32));
+    osc
+}
+
+/// Crossfade positions sweeping the whole range
+fn position(index: u32) -> Sample {
+    Sample::from((index % 4096) as i32 + Sample::MIN)
+}
+
+#[entry]
+fn main() -> ! {
+    // clocks at 125MHz, as in the cards
+    let _p = embassy_rp::init(Default::default());
+    let Some(mut core) = cortex_m::Peripherals::take() else {
+        defmt::panic!("core peripherals already taken");
+    };
+    core.SYST.set_clock_source(SystClkSource::Core);
+    core.SYST.set_reload(0x00ff_ffff);
+    core.SYST.clear_current();
+    core.SYST.enable_counter();
+
+    // SAFETY: nothing else runs on this core
+    let mut sio = unsafe { SioInterp::new() };
+
+    let (mut reference, mut soft_osc, mut sio_osc) = (oscillator(), oscillator(), oscillator());
+    for index in 0..RUNS {
+        let expected = reference.tick();
+        assert_eq!(soft_osc.tick_with(&mut SoftInterp), expected);
+        assert_eq!(sio_osc.tick_with(&mut sio), expected);
+        let fade = position(index);
+        assert_eq!(
+            tables::equal_power_with(&mut sio, fade),
+            tables::equal_power(fade)
+        );
+    }
+    info!("SioInterp matches the portable code");
+
+    let mut osc = oscillator();
+    let portable = cycles_per_call(|_| {
+        black_box(osc.tick());
+    });
+    let soft = cycles_per_call(|_| {
+        black_box(osc.tick_with(&mut SoftInterp));
+    });
+    let hardware = cycles_per_call(|_| {
+        black_box(osc.tick_with(&mut sio));
+    });
+    info!(
+        "WavetableOsc tick, cycles: portable {}, SoftInterp {}, SioInterp {}",
+        portable, soft, hardware
+    );
+
+    let portable = cycles_per_call(|index| {
+        black_box(tables::equal_power(position(index)));
+    });
+    let soft = cycles_per_call(|index| {
+        black_box(tables::equal_power_with(&mut SoftInterp, position(index)));
+    });
+    let hardware = cycles_per_call(|index| {
+        black_box(tables::equal_power_with(&mut sio, position(index)));
+    });
+    info!(
+        "equal_power, cycles: portable {}, SoftInterp {}, SioInterp {}",
+        portable, soft, hardware
+    );
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
@@ -1,6 +1,7 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 16M - 0x100
+    /* the last 8K (two sectors) hold settings, see SETTINGS_SECTORS */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 16M - 0x100 - 8K
 
     /* Pick one of the two options for RAM layout     */
 
@@ -1,59 +1,160 @@
 #![no_std]
 #![no_main]
 
+use core::mem::{size_of, MaybeUninit};
+
 use cortex_m_rt::entry;
 use defmt::*;
 
 use embassy_executor::Executor;
+use embassy_futures::join::join;
+use embassy_futures::yield_now;
 use embassy_rp::bind_interrupts;
 use embassy_rp::clocks;
 use embassy_rp::gpio::{self};
 // use embassy_rp::interrupt;
 use embassy_rp::multicore::{spawn_core1, Stack};
+use embassy_rp::pac;
 use embassy_rp::peripherals;
+use embassy_rp::pio;
 use embassy_rp::pwm;
-use embassy_rp::pwm::SetDutyCycle;
-use embassy_rp::spi;
 use embassy_rp::{adc, Peripheral};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::watch::Watch;
 use embassy_time::{Duration, Instant, Ticker, Timer};
 
-use audio_codec_algorithms::decode_adpcm_ima_ms;
+use fixed::types::U24F8;
 use gpio::{Level, Output};
-use portable_atomic::{AtomicU32, Ordering};
+#[cfg(not(feature = "console"))]
+use panic_halt as _;
+#[cfg(feature = "loopback")]
+use portable_atomic::AtomicU16;
+#[cfg(feature = "stats")]
+use portable_atomic::AtomicU32;
+use portable_atomic::Ordering;
 use static_cell::StaticCell;
+#[cfg(feature = "console")]
 use {defmt_rtt as _, panic_probe as _};
 
-use wscomp::{JackSample, Sample, SampleUpdate, U12_MAX};
+use wscomp::accessibility::Accessibility;
+use wscomp::adpcm::AdpcmStream;
+use wscomp::arena::ArenaStorage;
+use wscomp::assets::{LoopManifest, LoopPoints};
+use wscomp::batch::AdaptiveBatch;
+use wscomp::cv::{self, CvOut};
+use wscomp::dac::DacSamplePair;
+#[cfg(feature = "loopback")]
+use wscomp::diagnostics::LatencyMeter;
+use wscomp::diagnostics::{CrashLog, CrashReport, ResetReason};
+use wscomp::inputs::{InputState, INPUTS};
+#[cfg(feature = "sio-interp")]
+use wscomp::interp::SioInterp;
+#[cfg(not(feature = "sio-interp"))]
+use wscomp::interp::SoftInterp;
+use wscomp::leds::{self, Leds, PlugFlash};
+use wscomp::lfo::{Lfo, LfoShape};
+use wscomp::mixer::Mixer;
+use wscomp::power;
+use wscomp::resample::Resampler;
+use wscomp::ring::{RingConsumer, RingProducer, SampleRing};
+use wscomp::settings::SettingsStore;
+use wscomp::switch::SwitchEvent;
+use wscomp::tables;
+use wscomp::units::{Hertz, Millis};
+use wscomp::wav::Wav;
+use wscomp::{Sample, SampleUpdate, U12_MAX};
 
 use mutually_exclusive_features::none_or_one_of;
 none_or_one_of!("audio_sine", "audio_micro", "audio_2mb", "audio_16mb");
 
 // This is a port of the Backyard Rain Soundscape app from Playdate to the
 // Music Thing Modular Workshop System Computer via Rust & Embassy.
+//
+// The main knob sets the rain intensity, modulated by audio in 1 if plugged,
+// else by a slow internal LFO. Pressing the Z switch down turns the LFO off
+// and on, holding it down restarts the LFO from the start of its cycle.
 
 // inputs seem to be numbers from 0..4095 (12 bit), sometimes inverted from the thing they represent.
 // outputs seem to be numbers from 0..4095 (12 bit), inverted from the thing they represent.
 
+/// Rate samples are written to the DAC, WAV files at other rates are resampled
+#[cfg(not(feature = "output-96khz"))]
+const OUTPUT_SAMPLE_RATE: Hertz = Hertz::new(48_000);
+#[cfg(feature = "output-96khz")]
+const OUTPUT_SAMPLE_RATE: Hertz = Hertz::new(96_000);
+/// Step of the audio out 2 saw per sample, so it's the same pitch at either
+/// output rate
+const SAW_STEP: u16 = (16 * 48_000 / OUTPUT_SAMPLE_RATE.hz()) as u16;
+/// Rate of logic_loop() and update_pwm_loop()
+const CONTROL_RATE: Hertz = Hertz::new(480);
+/// Rate inputs are read by input_loop()
+const INPUT_RATE: Hertz = Hertz::new(60);
+/// Intensity LFO frequency, a cycle every 8 minutes or so
+const LFO_FREQUENCY: Hertz = Hertz::from_millihertz(2);
+/// Intensity LFO depth, as a divisor of full scale
+const LFO_DEPTH_DIVISOR: i32 = 4;
+/// How often periodic_stats() reports
+#[cfg(feature = "stats")]
+const STATS_PERIOD: Millis = Millis::new(1000);
+/// Fewest samples mixer_loop() renders before yielding, when AUDIO_OUT_SAMPLES is full
+const MIXER_MIN_BATCH: usize = 16;
+/// Most samples mixer_loop() renders before yielding, when AUDIO_OUT_SAMPLES is empty
+const MIXER_MAX_BATCH: usize = 256;
+/// Sample pairs per DMA transfer to the DAC, sample_write_loop() fills one
+/// block while the other is sent
+const DAC_BLOCK: usize = 32;
+/// PIO cycles the DAC program takes per sample pair, with the delays that pad
+/// it out, so the PIO clock sets the output sample rate
+const DAC_PIO_CYCLES: u32 = 80;
+/// Decoded samples buffered per stream, enough to cover a full ADPCM block
+const DECODE_RING_SIZE: usize = 4096;
+/// Most samples decoded per stream before letting other tasks run
+const DECODE_BATCH: usize = 512;
+/// Time between loopback test edges
+#[cfg(feature = "loopback")]
+const LOOPBACK_INTERVAL: Millis = Millis::new(500);
+/// Loopback test edges that take longer count as lost
+#[cfg(feature = "loopback")]
+const LOOPBACK_TIMEOUT: Millis = Millis::new(250);
+
+#[cfg(feature = "stats")]
 static AUDIO_FREQ_COUNTER: AtomicU32 = AtomicU32::new(0);
+#[cfg(feature = "stats")]
 static AUDIO_MAX_TICKS: AtomicU32 = AtomicU32::new(0);
+/// Audio out 2 DAC value for the loopback test, set by input_loop
+#[cfg(feature = "loopback")]
+static LOOPBACK_OUT: AtomicU16 = AtomicU16::new(U12_MAX / 2);
+
+/// With the console off, defmt output has nowhere to go
+#[cfg(not(feature = "console"))]
+#[defmt::global_logger]
+struct NoConsole;
+
+#[cfg(not(feature = "console"))]
+unsafe impl defmt::Logger for NoConsole {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
 
 bind_interrupts!(struct Irqs {
     ADC_IRQ_FIFO => adc::InterruptHandler;
+    PIO0_IRQ_0 => pio::InterruptHandler<peripherals::PIO0>;
 });
 
+/// Size of the Computer's flash chip, as in memory.x
+const FLASH_SIZE: usize = 16 * 1024 * 1024;
+/// Flash sectors for settings at the end of flash, left out of memory.x
+const SETTINGS_SECTORS: usize = 2;
+
+wscomp::rp_settings_flash!(struct SettingsSectors, FLASH_SIZE);
+
 // TODO: troubleshoot AUDIO_MAX_TICKS, seems to be intermittently lagging.
 // TODO: review mutexes... maybe only need CriticalSection for cross-CPU data?
 // single writer, multple reader
 
-/// [`MuxState`] with most recent values of inputs behind the mux switcher, wrapped in [`Watch`].
-///
-/// Updated by input_loop(). All inputs except audio and pulse are behind the
-/// mux switcher.
-static MUX_INPUT: Watch<CriticalSectionRawMutex, MuxState, 2> = Watch::new();
-
 /// Logical rain intensity stored as a [`Sample`], wrapped in [`Watch`].
 ///
 /// Updated by logic_loop().
@@ -67,84 +168,36 @@ static INTENSITY: Watch<CriticalSectionRawMutex, Sample, 2> = Watch::new();
 
 /// Slow LFO for modulating intensity
 static LFO: Watch<CriticalSectionRawMutex, Sample, 2> = Watch::new();
-static AUDIO_INPUT: Watch<CriticalSectionRawMutex, AudioState, 2> = Watch::new();
-static AUDIO_OUT_SAMPLES: Channel<CriticalSectionRawMutex, DACSamplePair, 1024> = Channel::new();
-
-/// The state of the three position Z switch
-#[derive(Clone, Format)]
-enum ZSwitch {
-    On,
-    Off,
-    Momentary,
-}
-
-impl ZSwitch {
-    fn default() -> ZSwitch {
-        ZSwitch::Off
-    }
-}
+static AUDIO_OUT_SAMPLES: Channel<CriticalSectionRawMutex, DacSamplePair, 1024> = Channel::new();
 
-/// State of inputs collected via the ADC mux device.
-#[derive(Clone, Format)]
-struct MuxState {
-    main_knob: Sample,
-    x_knob: Sample,
-    y_knob: Sample,
-    zswitch: ZSwitch,
-    cv1: JackSample,
-    cv2: JackSample,
-    sequence_counter: usize,
-}
-
-impl MuxState {
-    fn default() -> Self {
-        MuxState {
-            main_knob: Sample::new(Sample::CENTER, false),
-            x_knob: Sample::new(Sample::CENTER, false),
-            y_knob: Sample::new(Sample::CENTER, false),
-            zswitch: ZSwitch::default(),
-            // CV inputs are not inverted according to docs.  0V reads ~ 2030
-            // NOTE: I get inverted data, and ~2060 as 0v
-            cv1: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-            cv2: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-            sequence_counter: 0,
-        }
-    }
-}
-
-/// State of audio inputs collected via direct ADC read.
-#[derive(Clone, Format)]
-struct AudioState {
-    audio1: JackSample,
-    audio2: JackSample,
-}
-
-impl AudioState {
-    fn default() -> Self {
-        AudioState {
-            audio1: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-            audio2: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-        }
-    }
-}
+/// RAM for the decode rings, one per rain stream, plus alignment padding
+static ARENA: ArenaStorage<{ 3 * size_of::<SampleRing<DECODE_RING_SIZE>>() + 8 }> =
+    ArenaStorage::new();
 
 static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
 static mut CORE1_STACK: Stack<{ 1024 * 16 }> = Stack::new();
 // static EXECUTOR_HIGH: InterruptExecutor = InterruptExecutor::new();
 static EXECUTOR_DEFAULT: StaticCell<Executor> = StaticCell::new();
 
+/// Not initialized at boot, so it survives resets where RAM keeps power
+#[link_section = ".uninit.CRASH_LOG"]
+static CRASH_LOG: MaybeUninit<CrashLog> = MaybeUninit::uninit();
+
+// CrashLog task check in bits, tasks check in at least once per STATS_PERIOD
+const TASK_INPUT: u32 = 1 << 0;
+const TASK_LOGIC: u32 = 1 << 1;
+const TASK_PWM: u32 = 1 << 2;
+const TASK_MIXER: u32 = 1 << 3;
+const TASK_SAMPLE_WRITE: u32 = 1 << 4;
+const TASK_DECODE: u32 = 1 << 5;
+const ALL_TASKS: u32 =
+    TASK_INPUT | TASK_LOGIC | TASK_PWM | TASK_MIXER | TASK_SAMPLE_WRITE | TASK_DECODE;
+
+// CrashLog breadcrumbs, startup stages
+const BREADCRUMB_MAIN: u32 = 1;
+const BREADCRUMB_CORE1_STARTED: u32 = 2;
+const BREADCRUMB_RUNNING: u32 = 3;
+
 // #[interrupt]
 // unsafe fn SWI_IRQ_1() {
 //     EXECUTOR_HIGH.on_interrupt()
@@ -155,6 +208,14 @@ fn main() -> ! {
     info!("Starting main()");
 
     let p = embassy_rp::init(Default::default());
+    // stop driving CV and pulse outputs when built for USB power only
+    power::set_usb_only(cfg!(feature = "usb-power"));
+    let (reset_reason, crash_report) = read_reset_reason();
+    crash_log().breadcrumb(BREADCRUMB_MAIN);
+    log_reset_reason(reset_reason, crash_report);
+
+    // before core1 starts, the flash driver only blocks, so this doesn't wait
+    let accessibility = embassy_futures::block_on(load_accessibility(p.FLASH));
 
     // // High-priority executor: SWI_IRQ_1, priority level 2
     // interrupt::SWI_IRQ_1.set_priority(Priority::P2);
@@ -171,20 +232,38 @@ fn main() -> ! {
             let executor1 = EXECUTOR1.init(Executor::new());
             executor1.run(|spawner| {
                 unwrap!(spawner.spawn(sample_write_loop(
-                    p.SPI0, p.PIN_18, p.PIN_19, p.DMA_CH0, p.PIN_21, p.PIN_8, p.PIN_9,
+                    p.PIO0, p.PIN_18, p.PIN_19, p.DMA_CH0, p.PIN_21, p.PIN_8, p.PIN_9,
                 )))
             })
         },
     );
+    crash_log().breadcrumb(BREADCRUMB_CORE1_STARTED);
 
     // Low priority executor: runs in thread mode, using WFE/SEV
     let executor = EXECUTOR_DEFAULT.init(Executor::new());
     executor.run(|spawner| {
         unwrap!(spawner.spawn(input_loop(
-            p.PIN_4, p.PIN_24, p.PIN_25, p.ADC, p.PIN_28, p.PIN_29, p.PIN_27, p.PIN_26,
+            p.PIN_4,
+            p.PIN_24,
+            p.PIN_25,
+            p.ADC,
+            p.PIN_28,
+            p.PIN_29,
+            p.PIN_27,
+            p.PIN_26,
+            accessibility,
         )));
+        #[cfg(feature = "stats")]
         unwrap!(spawner.spawn(periodic_stats()));
-        unwrap!(spawner.spawn(mixer_loop()));
+        // decoded PCM for each rain stream, filled by decode_loop, drained by
+        // mixer_loop
+        let mut arena = unwrap!(ARENA.take());
+        let mut ring = || unwrap!(arena.alloc_value(SampleRing::<DECODE_RING_SIZE>::new()));
+        let (light_producer, light_consumer) = ring().split();
+        let (medium_producer, medium_consumer) = ring().split();
+        let (heavy_producer, heavy_consumer) = ring().split();
+        unwrap!(spawner.spawn(decode_loop(light_producer, medium_producer, heavy_producer)));
+        unwrap!(spawner.spawn(mixer_loop(light_consumer, medium_consumer, heavy_consumer)));
         unwrap!(spawner.spawn(logic_loop()));
         unwrap!(spawner.spawn(update_pwm_loop(
             p.PWM_SLICE5,
@@ -199,35 +278,12 @@ fn main() -> ! {
             p.PWM_SLICE3,
             p.PIN_23,
             p.PIN_22,
+            accessibility,
         )));
+        crash_log().breadcrumb(BREADCRUMB_RUNNING);
     })
 }
 
-/// Triangle wave - hardcoded for default intensity modulation
-struct TriangleWave11 {
-    value: i16,
-}
-
-impl TriangleWave11 {
-    pub fn new() -> Self {
-        TriangleWave11 { value: 0 }
-    }
-
-    pub fn tick(&mut self) {
-        // wrap at 11bit MIN so abs() is never more than positive 11bit value
-        if self.value <= -2_i16.pow(11) {
-            self.value = 2_i16.pow(11) - 1
-        }
-        self.value -= 1;
-    }
-
-    pub fn current(&self) -> Sample {
-        // TODO: troubleshoot, doesn't seem to be as smooth as it should be when
-        // mapped to pitch. Also "flickers" at transition occasionally.
-        Sample::from((self.value.abs() - 2_i16.pow(10)) / 2)
-    }
-}
-
 #[embassy_executor::task]
 async fn logic_loop() {
     info!("Starting logic_loop()");
@@ -238,38 +294,50 @@ async fn logic_loop() {
     let intensity_snd = INTENSITY.sender();
     intensity_snd.send(Sample::new(0, false));
 
-    let mut lfo = TriangleWave11::new();
+    let mut lfo = Lfo::new(LfoShape::Triangle, LFO_FREQUENCY, CONTROL_RATE);
+    let mut lfo_on = true;
     let lfo_snd = LFO.sender();
-    lfo_snd.send(lfo.current());
+    lfo_snd.send(lfo.current() / LFO_DEPTH_DIVISOR);
 
-    let mut mux_rcv = MUX_INPUT.anon_receiver();
-    let mut audio_rcv = AUDIO_INPUT.anon_receiver();
+    let mut input_rcv = INPUTS.anon_receiver();
+    let mut switch_events = 0;
 
-    let mut counter = 0_usize;
-    let mut ticker = Ticker::every(Duration::from_hz(480));
+    let mut ticker = Ticker::every(Duration::from_hz(CONTROL_RATE.hz().into()));
     loop {
-        counter = counter.wrapping_add(1);
-
-        // update LFO slowly
-        if counter % 2_usize.pow(6) == 0 {
-            lfo.tick();
-            lfo_snd.send(lfo.current());
+        crash_log().check_in(TASK_LOGIC);
+
+        let inputs = input_rcv.try_get();
+        match inputs
+            .as_ref()
+            .and_then(|inputs| inputs.mux.switch_event_since(&mut switch_events))
+        {
+            Some(SwitchEvent::Press) => {
+                lfo_on = !lfo_on;
+                info!("LFO on: {}", lfo_on);
+            }
+            Some(SwitchEvent::LongHold) => {
+                lfo.reset();
+                info!("LFO restarted");
+            }
+            _ => {}
         }
 
+        // update LFO, while it's off CV2 and its LED rest at 0v
+        let lfo_value = match lfo_on {
+            true => lfo.tick() / LFO_DEPTH_DIVISOR,
+            false => Sample::from(0_i32),
+        };
+        lfo_snd.send(lfo_value);
+
         // update intensity
-        if let Some(mux_state) = mux_rcv.try_get() {
-            // map intensity directly to main knob to start
-            let mut intensity = mux_state.main_knob;
-
-            if let Some(audio_state) = audio_rcv.try_get() {
-                // If cable plugged into audio1 input, then offset that signal
-                if let Some(input) = audio_state.audio1.plugged_value() {
-                    intensity = *input + intensity;
-                } else {
-                    // offset by the internal LFO
-                    intensity = lfo.current() + intensity;
-                }
-            }
+        if let Some(InputState {
+            mux: mux_state,
+            audio: audio_state,
+        }) = inputs
+        {
+            // map intensity directly to main knob to start, offset by audio1
+            // input if plugged, else by the internal LFO
+            let intensity = audio_state.audio1.value_or(lfo_value) + mux_state.main_knob;
 
             smooth_intensity.update(intensity);
             intensity_snd.send(smooth_intensity);
@@ -278,19 +346,6 @@ async fn logic_loop() {
     }
 }
 
-/// Rough LED brightness correction
-fn led_gamma(value: u16) -> u16 {
-    // based on: https://github.com/TomWhitwell/Workshop_Computer/blob/main/Demonstrations%2BHelloWorlds/CircuitPython/mtm_computer.py
-    let temp: u32 = value.into();
-    ((temp * temp) / U12_MAX as u32).clamp(0, u16::MAX.into()) as u16
-}
-
-fn set_led(led: &mut pwm::PwmOutput, value: u16) {
-    // TODO: fix error messge (use actual LED #)
-    led.set_duty_cycle_fraction(led_gamma(value), wscomp::U12_MAX)
-        .unwrap_or_else(|_| error!("error setting LED 3 PWM to : {}", led_gamma(value)));
-}
-
 #[allow(clippy::too_many_arguments)]
 #[embassy_executor::task]
 async fn update_pwm_loop(
@@ -306,114 +361,111 @@ async fn update_pwm_loop(
     cv_pwm_slice: peripherals::PWM_SLICE3,
     cv1_pin: peripherals::PIN_23,
     cv2_pin: peripherals::PIN_22,
+    accessibility: Accessibility,
 ) {
     info!("Starting update_leds_loop()");
 
     // LED PWM setup
     let mut led_pwm_config = pwm::Config::default();
-    // 12 bit PWM * 10. 10x is to increase PWM rate, reducing visible flicker.
-    led_pwm_config.top = 40950;
+    led_pwm_config.top = leds::PWM_TOP;
 
     let pwm5 = pwm::Pwm::new_output_ab(led12_pwm_slice, led1_pin, led2_pin, led_pwm_config.clone());
-    let (Some(mut led1), Some(_led2)) = pwm5.split() else {
+    let (Some(led1), Some(led2)) = pwm5.split() else {
         error!("Error setting up LED PWM channels for 1 & 2");
         return;
     };
 
     let pwm6 = pwm::Pwm::new_output_ab(led34_pwm_slice, led3_pin, led4_pin, led_pwm_config.clone());
-    let (Some(mut led3), Some(mut led4)) = pwm6.split() else {
+    let (Some(led3), Some(led4)) = pwm6.split() else {
         error!("Error setting up LED PWM channels for 3 & 4");
         return;
     };
 
     let pwm7 = pwm::Pwm::new_output_ab(led56_pwm_slice, led5_pin, led6_pin, led_pwm_config.clone());
-    let (Some(mut led5), Some(_led6)) = pwm7.split() else {
+    let (Some(led5), Some(led6)) = pwm7.split() else {
         error!("Error setting up LED PWM channels for 5 & 6");
         return;
     };
+    let mut leds = Leds::new([led1, led2, led3, led4, led5, led6], CONTROL_RATE);
+    leds.set_accessibility(accessibility);
 
-    // CV setup
-    // If we aim for a specific frequency, here is how we can calculate the top value.
-    // The top value sets the period of the PWM cycle, so a counter goes from 0 to top and then wraps around to 0.
-    // Every such wraparound is one PWM cycle. So here is how we get 60KHz:
-    // 60khz target from Computer docs
-    let desired_freq_hz = 60_000;
-    let clock_freq_hz = embassy_rp::clocks::clk_sys_freq();
-    let divider = 16u8;
-    let period = (clock_freq_hz / (desired_freq_hz * divider as u32)) as u16 - 1;
-
-    // CV PWM setup
-    // Inverted PWM output. Two pole active filtered. Use 12 bit PWM at 60khz.
-    // 4095 = -6v
-    // 2048 =  0v
-    // 0    = +6v
+    // CV PWM setup, 60kHz inverted PWM, see wscomp::cv
     let mut cv_pwm_config = pwm::Config::default();
-    cv_pwm_config.top = period;
-    cv_pwm_config.divider = divider.into();
+    cv_pwm_config.top = cv::pwm_top(clocks::clk_sys_freq());
+    cv_pwm_config.divider = cv::PWM_DIVIDER.into();
 
     let pwm3 = pwm::Pwm::new_output_ab(cv_pwm_slice, cv2_pin, cv1_pin, cv_pwm_config.clone());
     // Yes, cv_2_pwm has the lower GPIO pin.
-    let (Some(mut cv2_pwm), Some(mut cv1_pwm)) = pwm3.split() else {
+    let (Some(cv2_pwm), Some(cv1_pwm)) = pwm3.split() else {
         error!("Error setting up CV PWM channels");
         return;
     };
+    let mut cv1_out = CvOut::new(cv1_pwm);
+    let mut cv2_out = CvOut::new(cv2_pwm);
 
     let mut intensity_rcv = INTENSITY.anon_receiver();
     let mut lfo_rcv = LFO.anon_receiver();
+    let mut input_rcv = INPUTS.anon_receiver();
+
+    // flash the LFO LED when audio1 is plugged or unplugged, as that switches
+    // intensity modulation between the LFO and the input
+    let mut audio1_flash = PlugFlash::with_accessibility(CONTROL_RATE, accessibility);
 
-    let mut ticker = Ticker::every(Duration::from_hz(480));
+    let mut ticker = Ticker::every(Duration::from_hz(CONTROL_RATE.hz().into()));
     loop {
+        crash_log().check_in(TASK_PWM);
         // LEDs
-        // set_led(&mut led1, Sample::from(0_i32).to_output_abs());
-        // set_led(&mut led3, Sample::from(0_i32).to_output_abs());
-        // set_led(&mut led5, Sample::from(0_i32).to_output_abs());
+        // leds.set(0, Sample::from(0_i32).to_output_abs());
+        // leds.set(2, Sample::from(0_i32).to_output_abs());
+        // leds.set(4, Sample::from(0_i32).to_output_abs());
 
         // left three leds visualize rain intensity
 
         if let Some(intensity) = intensity_rcv.try_get() {
             // led2 represents heavy rain
             if intensity > Sample::from(0_i32) {
-                set_led(&mut led1, intensity.to_output_abs());
+                leds.set(0, intensity.to_output_abs());
             } else {
-                set_led(&mut led1, Sample::from(0_i32).to_output_abs());
+                leds.set(0, Sample::from(0_i32).to_output_abs());
             }
 
             // led4 represents medium rain
-            set_led(&mut led3, intensity.to_output_abs_inverted());
+            leds.set(2, intensity.to_output_abs_inverted());
 
             // led 6 represents light rain
             if intensity < Sample::from(0_i32) {
-                set_led(&mut led5, intensity.to_output_abs());
+                leds.set(4, intensity.to_output_abs());
             } else {
-                set_led(&mut led5, Sample::from(0_i32).to_output_abs());
+                leds.set(4, Sample::from(0_i32).to_output_abs());
             }
 
             // set CV1 to intensity
-            cv1_pwm
-                .set_duty_cycle_fraction(intensity.to_output_inverted(), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting CV1 PWM to : {}",
-                        intensity.to_output_inverted()
-                    )
-                });
+            let cv1_value = power::safe_cv(intensity);
+            cv1_out
+                .set(cv1_value)
+                .unwrap_or_else(|_| error!("error setting CV1 to : {}", cv1_value));
 
             // set CV2 and LED4 to LFO value
+            if let Some(inputs) = input_rcv.try_get() {
+                audio1_flash.update(&inputs.audio.audio1);
+            }
             if let Some(lfo) = lfo_rcv.try_get() {
-                set_led(&mut led4, lfo.to_output());
-                cv2_pwm
-                    .set_duty_cycle_fraction(lfo.to_output_inverted(), U12_MAX)
-                    .unwrap_or_else(|_| {
-                        error!("error setting CV2 PWM to : {}", lfo.to_output_inverted())
-                    });
+                leds.set(3, audio1_flash.apply(lfo.to_output()));
+                let cv2_value = power::safe_cv(lfo);
+                cv2_out
+                    .set(cv2_value)
+                    .unwrap_or_else(|_| error!("error setting CV2 to : {}", cv2_value));
             };
         }
+        leds.update()
+            .unwrap_or_else(|e| error!("error setting LED {} PWM", e.led + 1));
 
         ticker.next().await
     }
 }
 
-// this loop should probably be moved into a shared library
+/// Reads inputs with wscomp's [`InputReader`](wscomp::inputs::InputReader)
+/// and publishes them to [`INPUTS`]
 #[allow(clippy::too_many_arguments)]
 #[embassy_executor::task]
 async fn input_loop(
@@ -425,195 +477,118 @@ async fn input_loop(
     mux_io_2_pin: peripherals::PIN_29,
     audio1_pin: peripherals::PIN_27,
     audio2_pin: peripherals::PIN_26,
+    accessibility: Accessibility,
 ) {
     info!("Starting input_loop()");
 
-    // Normalization probe
-    let mut probe = Output::new(probe_pin, Level::Low);
-
-    // audio input setup (used for CV in this card)
-    let mut audio1 = adc::Channel::new_pin(audio1_pin, gpio::Pull::None);
-    let mut audio2 = adc::Channel::new_pin(audio2_pin, gpio::Pull::None);
-    let mut audio_state = AudioState::default();
-    let audio_snd = AUDIO_INPUT.sender();
-
-    // Set mux to read switch Z
-    let mut muxlogic_a = Output::new(muxlogic_a_pin, Level::Low);
-    let mut muxlogic_b = Output::new(muxlogic_b_pin, Level::Low);
-
-    let mut adc_device = adc::Adc::new(p_adc, Irqs, adc::Config::default());
-    let mut mux_io_1 = adc::Channel::new_pin(mux_io_1_pin, gpio::Pull::None);
-    let mut mux_io_2 = adc::Channel::new_pin(mux_io_2_pin, gpio::Pull::None);
+    let mut reader = wscomp::rp_input_reader!(p_adc, Irqs,
+        probe: probe_pin,
+        mux_logic: (muxlogic_a_pin, muxlogic_b_pin),
+        mux_io: (mux_io_1_pin, mux_io_2_pin),
+        audio: (audio1_pin, audio2_pin),
+    );
+    reader.set_accessibility(accessibility);
 
-    let mut mux_state = MuxState::default();
-    let mux_snd = MUX_INPUT.sender();
-    let mux_settle_micros = 20;
-    let probe_settle_micros = 200;
+    // the loopback edge is only timed at INPUT_RATE, so results are in
+    // steps of one input period
+    #[cfg(feature = "loopback")]
+    let mut loopback = LatencyMeter::new(LOOPBACK_INTERVAL, LOOPBACK_TIMEOUT);
 
-    let mut ticker = Ticker::every(Duration::from_hz(60));
-    // read from physical knobs, inputs and switch, write to `mux_state`
+    let mut ticker = Ticker::every(Duration::from_hz(INPUT_RATE.hz().into()));
+    // read from physical knobs, inputs and switch
     loop {
-        mux_state.sequence_counter = mux_state.sequence_counter.wrapping_add(1);
-
-        // read audio inputs and normalization probe input
-        match adc_device.read(&mut audio1).await {
-            Ok(level) => {
-                audio_state.audio1.raw.update(level);
-                // info!("audio1: {}, {}", level, mux_state.audio1.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio1: {}", e),
-        };
-        match adc_device.read(&mut audio2).await {
-            Ok(level) => {
-                audio_state.audio2.raw.update(level);
-                // info!("audio2: {}, {}", level, mux_state.audio2.to_output());
+        crash_log().check_in(TASK_INPUT);
+        let now = Instant::now().as_micros();
+        reader.read_and_publish(now).await;
+
+        #[cfg(feature = "loopback")]
+        {
+            if let Some(latency) = loopback.update(reader.state().audio.audio2.value(), now) {
+                info!("loopback: {}us, {}", latency, loopback.stats());
             }
-            Err(e) => error!("ADC read failed, while reading audio2: {}", e),
-        };
-
-        probe.set_high();
-        Timer::after_micros(mux_settle_micros).await;
-        match adc_device.read(&mut audio1).await {
-            Ok(level) => {
-                audio_state.audio1.probe.update(level);
-                // info!("audio1: {}, {}", level, mux_state.audio1.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio1: {}", e),
-        };
-        match adc_device.read(&mut audio2).await {
-            Ok(level) => {
-                audio_state.audio2.probe.update(level);
-                // info!("audio2: {}, {}", level, mux_state.audio2.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio2: {}", e),
-        };
-        probe.set_low();
-
-        // read Main knob & cv1
-        muxlogic_a.set_low();
-        muxlogic_b.set_low();
-        // this seems to need a delay for pins to settle before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                mux_state.main_knob.update(level);
-                // info!("M knob: {}, {}", level, mux_state.main_knob.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading Main: {}", e),
-        };
+            // audio outputs are inverted
+            LOOPBACK_OUT.store(loopback.output().to_output_inverted(), Ordering::Relaxed);
+        }
 
-        // read cv1 (inverted data)
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv1.raw.update(level);
-                // info!("cv1: {}, {}", level, mux_state.cv1.raw.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV1: {}", e),
-        };
-        probe.set_high();
-        Timer::after_micros(probe_settle_micros).await;
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv1.probe.update(level);
-                // info!("cv1: {}, {}", level, mux_state.cv1.probe.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV1: {}", e),
-        };
-        probe.set_low();
-        Timer::after_micros(probe_settle_micros).await;
-
-        // read X knob & cv2
-        // NOTE: X and Y appear to be swapped compared to how I read the logic table
-        // not sure why.... :/
-        muxlogic_a.set_high();
-        muxlogic_b.set_low();
-        // this seems to need a delay for pins to settle before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                mux_state.x_knob.update(level);
-                // info!("x knob: {}, {}", level, mux_state.x_knob.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading X: {}", e),
-        };
+        ticker.next().await;
+        // yield_now().await;
+    }
+}
 
-        // read cv2 (inverted data)
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv2.raw.update(level);
-                // info!("cv2: {}, {}", level, mux_state.cv2.raw.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV2: {}", e),
-        };
-        probe.set_high();
-        Timer::after_micros(probe_settle_micros).await;
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv2.probe.update(level);
-                // info!("cv2: {}, {}", level, mux_state.cv2.probe.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV2: {}", e),
-        };
-        probe.set_low();
-        Timer::after_micros(probe_settle_micros).await;
-
-        // read Y knob
-        muxlogic_a.set_low();
-        muxlogic_b.set_high();
-        // this seems to need 1us delay for pins to 'settle' before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                mux_state.y_knob.update(level);
-                // info!("y knob: {}, {}", level, mux_state.y_knob.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading Y: {}", e),
-        };
+/// Accessibility profile from settings, the standard one if flash can't be read
+async fn load_accessibility(flash: peripherals::FLASH) -> Accessibility {
+    let flash = SettingsSectors::new(flash, SETTINGS_SECTORS);
+    match SettingsStore::<_, SETTINGS_SECTORS>::mount(flash).await {
+        Ok(mut settings) => Accessibility::load(&mut settings).await,
+        Err(e) => Err(e),
+    }
+    .unwrap_or_else(|e| {
+        error!("error loading accessibility profile: {}", e);
+        Accessibility::STANDARD
+    })
+}
 
-        // read Z switch
-        muxlogic_a.set_high();
-        muxlogic_b.set_high();
-        // this seems to need 1us delay for pins to 'settle' before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                // info!("MUX_IO_1 ADC: {}", level);
-                mux_state.zswitch = match level {
-                    level if level < 1000 => ZSwitch::Momentary,
-                    level if level > 3000 => ZSwitch::On,
-                    _ => ZSwitch::Off,
-                };
-            }
-            Err(e) => error!("ADC read failed, while reading Z: {}", e),
-        };
+/// Why we last reset, and the previous run's crash log if RAM kept it
+///
+/// Also makes sure the brown-out detector is enabled. Must be called once,
+/// before anything uses `crash_log()`.
+fn read_reset_reason() -> (ResetReason, Option<CrashReport>) {
+    // the brown-out detector resets the chip when the core supply dips, it's
+    // on by default but make sure, since power dips are what we're looking for
+    pac::VREG_AND_CHIP_RESET.bod().modify(|w| w.set_en(true));
+    let chip_reset = pac::VREG_AND_CHIP_RESET.chip_reset().read().0;
+    let watchdog_reason = pac::WATCHDOG.reason().read().0;
+    let (_, crash_report) = CrashLog::start(&CRASH_LOG);
+    let reset_reason =
+        ResetReason::from_registers(chip_reset, watchdog_reason, crash_report.is_some());
+    (reset_reason, crash_report)
+}
 
-        audio_snd.send(audio_state.clone());
-        mux_snd.send(mux_state.clone());
+fn crash_log() -> &'static CrashLog {
+    // SAFETY: initialized by CrashLog::start() in read_reset_reason(), at the
+    // start of main()
+    unsafe { CRASH_LOG.assume_init_ref() }
+}
 
-        ticker.next().await;
-        // yield_now().await;
+/// Log why we last reset, and what the previous run was doing if it crashed
+fn log_reset_reason(reset_reason: ResetReason, crash_report: Option<CrashReport>) {
+    match reset_reason {
+        ResetReason::Brownout => warn!(
+            "last reset: {}, power dipped, check the power supply if this repeats",
+            reset_reason
+        ),
+        reason if reason.is_unexpected() => warn!("last reset: {}", reason),
+        reason => info!("last reset: {}", reason),
+    }
+    if let Some(report) = crash_report {
+        if reset_reason.is_unexpected() {
+            warn!(
+                "previous run: {}, tasks not checked in: {:06b}",
+                report,
+                ALL_TASKS & !report.partial_check_ins
+            );
+        } else {
+            debug!("previous run: {}", report);
+        }
     }
 }
 
+#[cfg(feature = "stats")]
 #[embassy_executor::task]
 async fn periodic_stats() {
     info!("Starting periodic_stats()");
     debug!("sys clock: {}", clocks::clk_sys_freq());
 
-    let mut mux_rcv = MUX_INPUT.anon_receiver();
+    let mut input_rcv = INPUTS.anon_receiver();
     let mut last_sequence: usize = 0;
     let mut last_audio_counter: u32 = 0;
     let mut current_audio_counter: u32;
 
-    let mut ticker = Ticker::every(Duration::from_millis(1000));
+    let mut ticker = Ticker::every(Duration::from_millis(STATS_PERIOD.millis().into()));
     loop {
         current_audio_counter = AUDIO_FREQ_COUNTER.load(Ordering::Relaxed);
         debug!("current_audio_counter: {}", current_audio_counter);
-        if let Some(mux_state) = mux_rcv.try_get() {
+        if let Some(InputState { mux: mux_state, .. }) = input_rcv.try_get() {
             info!(
                 "rates: input: {}, audio: {} per sec, max: {}",
                 mux_state.sequence_counter - last_sequence,
@@ -630,30 +605,12 @@ async fn periodic_stats() {
         }
         last_audio_counter = current_audio_counter;
 
-        ticker.next().await
-    }
-}
-
-/// Raw data ready to send to the DAC
-struct DACSamplePair {
-    pub audio1: u16,
-    pub audio2: u16,
-}
-
-impl DACSamplePair {
-    // DAC config bits
-    // 0: channel select 0 = A, 1 = B
-    // 1: unused
-    // 2: 0 = 2x gain, 1 = 1x
-    // 3: 0 = shutdown channel
-    const CONFIG1: u16 = 0b0011000000000000u16;
-    const CONFIG2: u16 = 0b1011000000000000u16;
-
-    fn new(sample1: u16, sample2: u16) -> Self {
-        Self {
-            audio1: sample1 << 4 >> 4 | DACSamplePair::CONFIG1,
-            audio2: sample2 << 4 >> 4 | DACSamplePair::CONFIG2,
+        let check_ins = crash_log().end_period();
+        if check_ins != ALL_TASKS {
+            warn!("tasks not checked in: {:06b}", ALL_TASKS & !check_ins);
         }
+
+        ticker.next().await
     }
 }
 
@@ -662,6 +619,7 @@ mod audio {
     pub const AUDIO_LIGHT: &[u8; 12432] = include_bytes!("../data/sine_light.wav");
     pub const AUDIO_MEDIUM: &[u8; 12432] = include_bytes!("../data/sine_medium.wav");
     pub const AUDIO_HEAVY: &[u8; 12432] = include_bytes!("../data/sine_heavy.wav");
+    pub const LOOPS: Option<&[u8]> = None;
 }
 
 #[cfg(feature = "audio_micro")]
@@ -672,6 +630,7 @@ mod audio {
         include_bytes!("../data/backyard_rain_medium_loop_micro.wav");
     pub const AUDIO_HEAVY: &[u8; 50320] =
         include_bytes!("../data/backyard_rain_heavy_loop_micro.wav");
+    pub const LOOPS: Option<&[u8]> = None;
 }
 
 // default to "audio_2mb" if no other audio_* feature is set
@@ -687,6 +646,7 @@ mod audio {
         include_bytes!("../data/backyard_rain_medium_loop_short.wav");
     pub const AUDIO_HEAVY: &[u8; 482464] =
         include_bytes!("../data/backyard_rain_heavy_loop_short.wav");
+    pub const LOOPS: Option<&[u8]> = Some(include_bytes!("../data/backyard_rain_loops_short.bin"));
 }
 
 #[cfg(feature = "audio_16mb")]
@@ -695,118 +655,158 @@ mod audio {
     pub const AUDIO_MEDIUM: &[u8; 7428102] =
         include_bytes!("../data/backyard_rain_medium_loop.wav");
     pub const AUDIO_HEAVY: &[u8; 4053120] = include_bytes!("../data/backyard_rain_heavy_loop.wav");
+    pub const LOOPS: Option<&[u8]> = Some(include_bytes!("../data/backyard_rain_loops.bin"));
 }
 
 // alternates for testing
 // const AUDIO_MEDIUM: &[u8; 123024] = include_bytes!("../data/sine_long.wav");
 
-/// A very simplistic WAVE parser, returns slice of samples in DATA chunk
+fn adpcm_to_stream(
+    data: &[u8],
+    loop_points: Option<LoopPoints>,
+    sample_offset: usize,
+) -> impl Iterator<Item = i16> + use<'_> {
+    // the files are embedded at build time, so a bad one is a build mistake
+    let wav = unwrap!(Wav::parse(data));
+    info!("WAV format: {}, {} data bytes", wav.format, wav.data.len());
+    let mut samples = unwrap!(AdpcmStream::new(&wav));
+    samples.set_looping(true);
+    if let Some(points) = loop_points {
+        unwrap!(samples.set_loop_points(points));
+    }
+    unwrap!(samples.seek_to_sample(sample_offset));
+    // passes samples straight through when the file is already at the output rate
+    unwrap!(Resampler::new(
+        samples,
+        wav.format.sample_rate,
+        OUTPUT_SAMPLE_RATE.hz()
+    ))
+}
+
+/// ADPCM decoding loop
 ///
-/// Assumes DATA chunk starts at offset 136, which is true for these specific files.
-/// Will panic if DATA not found.
-fn data_chunk(wav: &[u8]) -> &[u8] {
-    let mut offset = 12;
+/// Keeps the per-stream rings topped up, so the block decodes (2041 samples
+/// each) happen here instead of in the middle of mixer_loop. Runs
+/// interleaved with the mixer on CORE0, rather than on CORE1, because a
+/// block decode would hold up sample_write_loop long enough to starve the
+/// DAC.
+#[embassy_executor::task]
+async fn decode_loop(
+    mut light: RingProducer<'static, DECODE_RING_SIZE>,
+    mut medium: RingProducer<'static, DECODE_RING_SIZE>,
+    mut heavy: RingProducer<'static, DECODE_RING_SIZE>,
+) {
+    info!("Starting decode_loop()");
+
+    // Create three iterators which produce full range i16 samples by decoding
+    // the ADPCM blocks and repeatedly cylcing through the data. Offset the
+    // starting samples with prime numbers, so the three streams don't need
+    // to decode a full block at the same time.
+    // loop points from asset_tool, in light, medium, heavy order; without
+    // them each file loops whole
+    let loops = audio::LOOPS
+        .map(|bytes| unwrap!(LoopManifest::<3>::from_bytes(bytes)))
+        .unwrap_or_default();
+    let mut light_samples = adpcm_to_stream(audio::AUDIO_LIGHT, loops.get(0), 0);
+    let mut medium_samples = adpcm_to_stream(audio::AUDIO_MEDIUM, loops.get(1), 277);
+    let mut heavy_samples = adpcm_to_stream(audio::AUDIO_HEAVY, loops.get(2), 691);
+
     loop {
-        let chunk = &wav[offset..offset + 4];
-        let mut length_bytes = [0_u8; 4];
-        length_bytes.clone_from_slice(&wav[offset + 4..offset + 8]);
-        let length = u32::from_le_bytes(length_bytes) as usize;
-        if b"data" != chunk {
-            offset += length + 8;
-            continue;
+        crash_log().check_in(TASK_DECODE);
+        let decoded = light.fill_from(&mut light_samples, DECODE_BATCH)
+            + medium.fill_from(&mut medium_samples, DECODE_BATCH)
+            + heavy.fill_from(&mut heavy_samples, DECODE_BATCH);
+
+        if decoded == 0 {
+            // all full, the mixer needs a while to drain a batch
+            Timer::after_millis(1).await;
+        } else {
+            yield_now().await;
         }
-        info!("WAV DATA offset, size: {}, {}", offset, length);
-        return &wav[offset + 8..length];
     }
 }
 
-fn adpcm_to_stream(data: &[u8], sample_offset: usize) -> impl Iterator<Item = i16> + use<'_> {
-    const BLOCK_SIZE: usize = 1024;
-
-    // IMA ADPCM files are 4 bits per sample, these files have a consistent
-    // 1024 byte block size and the WAV DATA chunk starts at byte 136.
-    // It would probably be better to actually parse the WAV files if they
-    // were updatable... but... they aren't and this works for now.
-    // This is ignoring any data after the end of the last full BLOCK_SIZE..
-    // but in theory, IMA ADPCM DATA chunks should be a multiple of BLOCK_SIZE.
-    data_chunk(data)
-        .chunks_exact(BLOCK_SIZE)
-        .cycle()
-        .flat_map(|data| {
-            let mut adpcm_output_buffer = [0_i16; 2 * BLOCK_SIZE - 7];
-            decode_adpcm_ima_ms(data, false, &mut adpcm_output_buffer).unwrap();
-            adpcm_output_buffer
-        })
-        .skip(sample_offset)
-}
-
 #[embassy_executor::task]
-async fn mixer_loop() {
+async fn mixer_loop(
+    mut light_samples: RingConsumer<'static, DECODE_RING_SIZE>,
+    mut medium_samples: RingConsumer<'static, DECODE_RING_SIZE>,
+    mut heavy_samples: RingConsumer<'static, DECODE_RING_SIZE>,
+) {
     info!("Starting mixer_loop()");
 
-    // Create three iterators which produce full range i16 samples by decoding
-    // the ADPCM blocks and repeatedly cylcing through the data. Offset the
-    // starting samples with prime numbers, so the three buffers don't run out
-    // and process a full block at the same time.
-    let mut light_samples = adpcm_to_stream(audio::AUDIO_LIGHT, 0);
-    let mut medium_samples = adpcm_to_stream(audio::AUDIO_MEDIUM, 277);
-    let mut heavy_samples = adpcm_to_stream(audio::AUDIO_HEAVY, 691);
-
     let mut intensity_rcv = INTENSITY.anon_receiver();
     let mut saw_value = 0u16;
 
     // TODO: need to smooth intensity changes over time
     // let mut counter = 0_isize;
 
+    // render in batches sized by how full the output channel is, then let
+    // other tasks run, so decode_loop gets a chance to refill the rings
+    let batching = AdaptiveBatch::new(MIXER_MIN_BATCH, MIXER_MAX_BATCH);
+
+    // light, medium and heavy, only medium until the first intensity
+    let mut mixer = Mixer::<3>::new();
+    mixer.set_levels([0_i32, Sample::MAX, 0].map(Sample::from));
+    // bend peaks over rather than clamping them
+    mixer.set_limiter(true);
+
+    // crossfade gains come from a table, blended in hardware with sio-interp
+    #[cfg(feature = "sio-interp")]
+    // SAFETY: nothing else on core0 uses its interpolators
+    let mut interp = unsafe { SioInterp::new() };
+    #[cfg(not(feature = "sio-interp"))]
+    let mut interp = SoftInterp;
+
     loop {
-        let mut light = light_samples
-            .next()
-            .expect("iterator over cycle() returned None somehow?!?!");
-        // down sample from 16 to 12 bit
-        light >>= 4;
-        let light = Sample::from(light);
-
-        let mut medium = medium_samples
-            .next()
-            .expect("iterator over cycle() returned None somehow?!?!");
-        // down sample from 16 to 12 bit
-        medium >>= 4;
-        let medium = Sample::from(medium);
-
-        let mut heavy = heavy_samples
-            .next()
-            .expect("iterator over cycle() returned None somehow?!?!");
-        // down sample from 16 to 12 bit
-        heavy >>= 4;
-        let heavy = Sample::from(heavy);
-
-        let mut mixed = medium;
-        if let Some(intensity) = intensity_rcv.try_get() {
-            match intensity {
-                intensity if intensity >= Sample::from(0_i32) => {
-                    mixed = medium.scale_inverted(intensity) + heavy.scale(intensity)
-                }
-                _ => mixed = medium.scale_inverted(intensity.abs()) + light.scale(intensity.abs()),
+        // never more than decode_loop has ready
+        let batch = batching
+            .size(AUDIO_OUT_SAMPLES.len(), AUDIO_OUT_SAMPLES.capacity())
+            .min(light_samples.len())
+            .min(medium_samples.len())
+            .min(heavy_samples.len());
+        for _ in 0..batch {
+            // the batch size guarantees each ring has a sample ready
+            let light = Sample::from_pcm16(light_samples.pop().unwrap_or_default());
+            let medium = Sample::from_pcm16(medium_samples.pop().unwrap_or_default());
+            let heavy = Sample::from_pcm16(heavy_samples.pop().unwrap_or_default());
+
+            if let Some(intensity) = intensity_rcv.try_get() {
+                // equal power crossfade from medium towards light or heavy
+                let zero = Sample::from(0_i32);
+                let fade = Sample::from(2 * intensity.abs().to_clamped() + Sample::MIN);
+                let (medium, edge) = tables::equal_power_with(&mut interp, fade);
+                mixer.set_levels([
+                    if intensity < zero { edge } else { zero },
+                    medium,
+                    if intensity > zero { edge } else { zero },
+                ]);
             }
-        }
-
-        // saw from audio output 2, just because
-        saw_value += 16;
-        if saw_value > U12_MAX {
-            saw_value = 0
-        };
+            let mixed = mixer.mix([light, medium, heavy]);
+
+            // saw from audio output 2, just because
+            saw_value += SAW_STEP;
+            if saw_value > U12_MAX {
+                saw_value = 0;
+                // once per saw cycle is plenty
+                crash_log().check_in(TASK_MIXER);
+            };
 
-        let dac_sample = DACSamplePair::new(mixed.to_output(), saw_value);
+            #[cfg(feature = "loopback")]
+            let out2 = LOOPBACK_OUT.load(Ordering::Relaxed);
+            #[cfg(not(feature = "loopback"))]
+            let out2 = saw_value;
+            let dac_sample = DacSamplePair::new(mixed.to_output(), out2);
 
-        // counter += 1;
-        // if counter % 2_isize.pow(15) == 0 {
-        //     info!("free_capacity(): {}", AUDIO_OUT_SAMPLES.free_capacity());
-        // }
+            // counter += 1;
+            // if counter % 2_isize.pow(15) == 0 {
+            //     info!("free_capacity(): {}", AUDIO_OUT_SAMPLES.free_capacity());
+            // }
 
-        // push samples until channel full then block the loop
-        AUDIO_OUT_SAMPLES.send(dac_sample).await;
+            // only blocks if the channel filled up mid batch
+            AUDIO_OUT_SAMPLES.send(dac_sample).await;
+        }
 
-        // ticker.next().await
+        yield_now().await;
     }
 }
 
@@ -815,9 +815,14 @@ async fn mixer_loop() {
 /// Audio sample writing loop
 ///
 /// Runs on the second core (CORE1), all shared data must be safe for concurrency.
+///
+/// DAC words are clocked out by a PIO state machine which handles the CS
+/// framing. The program takes [`DAC_PIO_CYCLES`] per sample pair, so its
+/// clock sets the sample rate and its FIFO's DREQ paces the DMA. This loop
+/// only fills blocks of [`DAC_BLOCK`] words, one while DMA sends the other.
 #[embassy_executor::task]
 async fn sample_write_loop(
-    spi0: peripherals::SPI0,
+    pio0: peripherals::PIO0,
     clk: peripherals::PIN_18,
     mosi: peripherals::PIN_19,
     dma0: peripherals::DMA_CH0,
@@ -827,67 +832,135 @@ async fn sample_write_loop(
 ) {
     info!("Starting sample_write_loop()");
     let mut local_counter = 0u32;
+    #[cfg(feature = "stats")]
     let mut local_max_ticks = 0u32;
+    #[cfg(feature = "stats")]
     let mut previous_loop_end = Instant::now();
 
     // pulse setup
     let mut pulse1 = Output::new(pulse1_pin, Level::High);
     let mut pulse2 = Output::new(pulse2_pin, Level::High);
+    // power mode is set before tasks are spawned
+    let pulses_enabled = power::outputs_enabled();
 
     // DAC setup
-    let mut config = spi::Config::default();
-    config.frequency = 8_000_000;
-
-    let mut spi = spi::Spi::new_txonly(spi0, clk, mosi, dma0, config);
-    let mut cs = Output::new(cs_pin, Level::High);
-
-    // Since embassy_rp only supports a fixed 1_000_000 hz tick rate, we can
-    // only approximate 48_000 hz. Measured at ~ 47_630, with significant jitter.
-    // TODO: look into configuring a custom interrupt and running this task
-    // from it. (Or maybe even just outside of embassy?)
-    let mut ticker = Ticker::every(Duration::from_hz(48_000));
+    let pio::Pio {
+        mut common,
+        mut sm0,
+        ..
+    } = pio::Pio::new(pio0, Irqs);
+    // PIO program writing a pair of 16 bit words to the MCP4822 DAC.
+    // Each 32 bit word pulled from the FIFO holds channel A in the high half
+    // and channel B in the low half. CS is raised after each 16 bits so the
+    // DAC latches each channel. Side-set drives SCK, data changes while SCK
+    // is low and the DAC samples it on the rising edge. 71 cycles of work,
+    // plus delays on the CS edges, make DAC_PIO_CYCLES per pair.
+    let program = ::pio::pio_asm!(
+        ".side_set 1 opt",
+        ".wrap_target",
+        "    pull block       side 0",
+        "    set pins, 0",
+        "    set x, 15",
+        "word_a:",
+        "    out pins, 1      side 0",
+        "    jmp x-- word_a   side 1",
+        "    set pins, 1      side 0 [2]",
+        "    set x, 15",
+        "    set pins, 0",
+        "word_b:",
+        "    out pins, 1      side 0",
+        "    jmp x-- word_b   side 1",
+        "    set pins, 1      side 0 [7]",
+        ".wrap",
+    );
+    let loaded_program = common.load_program(&program.program);
+    let clk = common.make_pio_pin(clk);
+    let mosi = common.make_pio_pin(mosi);
+    let cs = common.make_pio_pin(cs_pin);
+    sm0.set_pins(Level::High, &[&cs]);
+    sm0.set_pin_dirs(pio::Direction::Out, &[&clk, &mosi, &cs]);
+
+    let mut config = pio::Config::default();
+    config.use_program(&loaded_program, &[&clk]);
+    config.set_out_pins(&[&mosi]);
+    config.set_set_pins(&[&cs]);
+    // DAC expects MSB first, words are pulled manually by the program
+    config.shift_out = pio::ShiftConfig {
+        auto_fill: false,
+        threshold: 32,
+        direction: pio::ShiftDirection::Left,
+    };
+    config.fifo_join = pio::FifoJoin::TxOnly;
+    // one sample pair per DAC_PIO_CYCLES, a 1.92MHz SPI clock at 48kHz. In
+    // kHz, as U24F8 can't hold the system clock in Hz. The fractional divider
+    // is within 0.01% of the rate, and jitters by one system clock cycle.
+    let pio_khz = DAC_PIO_CYCLES * OUTPUT_SAMPLE_RATE.hz() / 1000;
+    config.clock_divider =
+        U24F8::from_num(clocks::clk_sys_freq() / 1000) / U24F8::from_num(pio_khz);
+    sm0.set_config(&config);
+    sm0.set_enable(true);
+
+    let mut dma = dma0.into_ref();
+
+    // a two block ring, DMA sends one block while the other fills. Unlike a
+    // timer, the PIO clock doesn't drift or jitter with other tasks, and DMA
+    // keeps sending through flash stalls.
+    let mut blocks = [[0u32; DAC_BLOCK]; 2];
+    let [mut sending, mut filling] = blocks.each_mut();
+    fill_block(sending).await;
     loop {
-        pulse1.toggle();
-        pulse2.set_high();
-        local_counter += 1;
-
-        if local_counter % 16 == 0 {
-            AUDIO_FREQ_COUNTER.store(local_counter, Ordering::Relaxed);
+        if pulses_enabled {
+            pulse1.toggle();
+            pulse2.set_high();
         }
+        local_counter += DAC_BLOCK as u32;
 
-        let dac_sample_pair = AUDIO_OUT_SAMPLES.receive().await;
+        #[cfg(feature = "stats")]
+        AUDIO_FREQ_COUNTER.store(local_counter, Ordering::Relaxed);
+        if local_counter % 1024 == 0 {
+            crash_log().check_in(TASK_SAMPLE_WRITE);
+        }
 
-        cs.set_low();
-        spi.blocking_write(&dac_sample_pair.audio1.to_be_bytes())
-            .unwrap_or_else(|e| error!("error writing buff a to DAC: {}", e));
-        cs.set_high();
-        cs.set_low();
-        spi.blocking_write(&dac_sample_pair.audio2.to_be_bytes())
-            .unwrap_or_else(|e| error!("error writing buff b to DAC: {}", e));
-        cs.set_high();
+        // if the mixer falls behind, the PIO stalls on an empty FIFO
+        let fill = async {
+            fill_block(filling).await;
+            if pulses_enabled {
+                pulse2.set_low();
+            }
+        };
+        join(sm0.tx().dma_push(dma.reborrow(), &sending[..]), fill).await;
+        core::mem::swap(&mut sending, &mut filling);
 
         // update max ticks this loop has ever taken
-        let end = Instant::now();
-        let diff = end.saturating_duration_since(previous_loop_end);
-        // we're just going to hope a tick never takes more than 71.5 hours,
-        // and deal with a rollover if it does
-        let diff = diff.as_ticks() as u32;
-        previous_loop_end = end;
-        // Using this local variable to only mess with locks when the values
-        // are actually different. Seems to make a small difference... ~15 ticks
-        // added to max if updating atomic each loop
-        if diff > local_max_ticks {
-            // fetch_max() also updates the atomic value to the max
-            AUDIO_MAX_TICKS.fetch_max(diff, Ordering::Relaxed);
-            local_max_ticks = diff;
-        }
-        // reset max every second, for better reporting
-        if local_counter % 48000 == 0 {
-            local_max_ticks = 0;
-            AUDIO_MAX_TICKS.store(0, Ordering::Relaxed);
+        #[cfg(feature = "stats")]
+        {
+            let end = Instant::now();
+            let diff = end.saturating_duration_since(previous_loop_end);
+            // we're just going to hope a tick never takes more than 71.5 hours,
+            // and deal with a rollover if it does
+            let diff = diff.as_ticks() as u32;
+            previous_loop_end = end;
+            // Using this local variable to only mess with locks when the values
+            // are actually different. Seems to make a small difference... ~15 ticks
+            // added to max if updating atomic each loop
+            if diff > local_max_ticks {
+                // fetch_max() also updates the atomic value to the max
+                AUDIO_MAX_TICKS.fetch_max(diff, Ordering::Relaxed);
+                local_max_ticks = diff;
+            }
+            // reset max every second, for better reporting
+            if local_counter % OUTPUT_SAMPLE_RATE.hz() == 0 {
+                local_max_ticks = 0;
+                AUDIO_MAX_TICKS.store(0, Ordering::Relaxed);
+            }
         }
+    }
+}
 
-        pulse2.set_low();
-        ticker.next().await
+/// Fill a block of DAC words from [`AUDIO_OUT_SAMPLES`], waiting for the
+/// mixer if it's behind
+async fn fill_block(block: &mut [u32; DAC_BLOCK]) {
+    for word in block.iter_mut() {
+        *word = AUDIO_OUT_SAMPLES.receive().await.to_pio_word();
     }
 }
@@ -4,24 +4,33 @@
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_futures::yield_now;
-use embassy_rp::adc;
-use embassy_rp::bind_interrupts;
-use embassy_rp::gpio::{self};
+use embassy_rp::gpio::{Input, Output};
 use embassy_rp::peripherals;
-use embassy_rp::pwm;
-use embassy_rp::pwm::SetDutyCycle;
-use embassy_rp::spi;
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
-use embassy_sync::watch::Watch;
-use embassy_time::Timer;
-
-use gpio::{Level, Output};
+use embassy_rp::pwm::PwmOutput;
+use embassy_time::{Instant, Timer};
+
+#[cfg(not(feature = "console"))]
+use panic_halt as _;
+#[cfg(feature = "console")]
 use {defmt_rtt as _, panic_probe as _};
 
-use wscomp::{JackSample, Sample, SampleUpdate, U12_MAX};
+use wscomp::accessibility::Accessibility;
+use wscomp::cv::CvOut;
+use wscomp::dac::DacChannel;
+use wscomp::inputs::{InputState, INPUTS};
+use wscomp::leds::{Leds, PlugFlash};
+use wscomp::power;
+use wscomp::pulse::{PulseInput, PulseOut};
+use wscomp::settings::SettingsStore;
+use wscomp::switch::{SwitchEvent, ZSwitch};
+use wscomp::units::{Hertz, Millis};
+use wscomp::U12_MAX;
+use wscomp_rp::{AudioDac, Computer};
 
 // This is an attempt to learn how use all inputs & outputs of the Music Thing Modular Workshop System Computer via Rust & Embassy.
-// The card maps knobs and the switch to manually set voltages.
+// The card maps knobs and the switch to manually set voltages. Pulse input 1
+// acts like holding the switch up. Double tapping the switch down latches the
+// pulses instead, each press then flips them, until another double tap.
 
 // inputs seem to be numbers from 0..4095 (12 bit), sometimes inverted from the thing they represent.
 // outputs seem to be numbers from 0..4095 (12 bit), inverted from the thing they represent.
@@ -31,286 +40,68 @@ use wscomp::{JackSample, Sample, SampleUpdate, U12_MAX};
 // TODO: move more data strctures and logic into shared wscomp library
 // TODO: experiment with task communication to eliminate clone of MuxState
 // TODO: consider event based pulse updates: only change pulse outputs on switch change or pulse input edge detection (rather than on a loop)
-// TODO: read and use calibration data from EEPROM
 // TODO: read about defmt levels and overhead (can we leave logging statements in a release build? What are the effects?)
 // TODO: read about embassy tasks and peripheral ownership...
 // do I need to pass them this way?
 
-bind_interrupts!(struct Irqs {
-    ADC_IRQ_FIFO => adc::InterruptHandler;
-});
-
-// single writer, multple reader
-static MUX_INPUT: Watch<CriticalSectionRawMutex, MuxState, 2> = Watch::new();
-static AUDIO_INPUT: Watch<CriticalSectionRawMutex, AudioState, 2> = Watch::new();
-
-/// The state of the three position Z switch
-#[derive(Clone, Format)]
-enum ZSwitch {
-    On,
-    Off,
-    Momentary,
+/// With the console off, defmt output has nowhere to go
+#[cfg(not(feature = "console"))]
+#[defmt::global_logger]
+struct NoConsole;
+
+#[cfg(not(feature = "console"))]
+unsafe impl defmt::Logger for NoConsole {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
 }
 
-impl ZSwitch {
-    fn default() -> ZSwitch {
-        ZSwitch::Off
-    }
-}
+/// Size of the Computer's flash chip, as in memory.x
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Flash sectors for settings at the end of flash, left out of memory.x
+const SETTINGS_SECTORS: usize = 2;
 
-/// State of inputs collected via the ADC mux device.
-#[derive(Clone, Format)]
-struct MuxState {
-    main_knob: Sample,
-    x_knob: Sample,
-    y_knob: Sample,
-    zswitch: ZSwitch,
-    cv1: JackSample,
-    cv2: JackSample,
-    sequence_counter: usize,
-}
-
-impl MuxState {
-    fn default() -> Self {
-        MuxState {
-            main_knob: Sample::new(Sample::CENTER, false),
-            x_knob: Sample::new(Sample::CENTER, false),
-            y_knob: Sample::new(Sample::CENTER, false),
-            zswitch: ZSwitch::default(),
-            // CV inputs are not inverted according to docs.  0V reads ~ 2030
-            // NOTE: I get inverted data, and ~2060 as 0v
-            cv1: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-            cv2: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-            sequence_counter: 0,
-        }
-    }
-}
-
-/// State of audio inputs collected via direct ADC.
-#[derive(Clone, Format)]
-struct AudioState {
-    audio1: JackSample,
-    audio2: JackSample,
-}
-
-impl AudioState {
-    fn default() -> Self {
-        AudioState {
-            audio1: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-            audio2: JackSample::new(
-                Sample::new(Sample::CENTER, true),
-                Sample::new(Sample::CENTER, true),
-            ),
-        }
-    }
-}
+wscomp::rp_settings_flash!(struct SettingsSectors, FLASH_SIZE);
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     info!("Starting main()");
     let p = embassy_rp::init(Default::default());
-
-    // Normalization probe
-    let mut probe = Output::new(p.PIN_4, Level::Low);
-
-    // Set mux to read switch Z
-    let mut muxlogic_a = Output::new(p.PIN_24, Level::Low);
-    let mut muxlogic_b = Output::new(p.PIN_25, Level::Low);
-
-    let mut adc_device = adc::Adc::new(p.ADC, Irqs, adc::Config::default());
-    let mut mux_io_1 = adc::Channel::new_pin(p.PIN_28, gpio::Pull::None);
-    let mut mux_io_2 = adc::Channel::new_pin(p.PIN_29, gpio::Pull::None);
-
-    // audio input setup (used for CV in this card)
-    let mut audio1 = adc::Channel::new_pin(p.PIN_27, gpio::Pull::None);
-    let mut audio2 = adc::Channel::new_pin(p.PIN_26, gpio::Pull::None);
+    // stop driving CV and pulse outputs when built for USB power only
+    power::set_usb_only(cfg!(feature = "usb-power"));
+
+    // audio inputs are used for CV in this card
+    let Computer {
+        inputs: mut reader,
+        pulse_in: [pulse_in1, _],
+        pulse_out,
+        leds: [led1, led2, led3, led4, led5, led6],
+        cv_out,
+        dac,
+        spare,
+        ..
+    } = Computer::init(p);
+
+    let accessibility = load_accessibility(spare.flash).await;
+    reader.set_accessibility(accessibility);
 
     // if we can't spawn tasks, panic is the only option? Thus unwrap() OK here.
     spawner
-        .spawn(audio_loop(
-            p.PWM_SLICE5,
-            p.PIN_10,
-            p.PIN_11,
-            p.SPI0,
-            p.PIN_18,
-            p.PIN_19,
-            p.DMA_CH0,
-            p.PIN_21,
-        ))
+        .spawn(audio_loop([led1, led2], dac, accessibility))
         .unwrap();
     spawner
-        .spawn(cv_loop(
-            p.PWM_SLICE6,
-            p.PIN_12,
-            p.PIN_13,
-            p.PWM_SLICE3,
-            p.PIN_23,
-            p.PIN_22,
-        ))
+        .spawn(cv_loop([led3, led4], cv_out, accessibility))
         .unwrap();
     spawner
-        .spawn(pulse_loop(p.PIN_14, p.PIN_15, p.PIN_8, p.PIN_9))
+        .spawn(pulse_loop([led5, led6], pulse_out, pulse_in1))
         .unwrap();
+    #[cfg(feature = "stats")]
     spawner.spawn(periodic_stats()).unwrap();
 
-    let mut mux_state = MuxState::default();
-    let mux_snd = MUX_INPUT.sender();
-    let mut audio_state = AudioState::default();
-    let audio_snd = AUDIO_INPUT.sender();
-    let mux_settle_micros = 20;
-    let probe_settle_micros = 200;
-
-    // read from physical knobs, inputs and switch, write to `mux_state`
+    // read from physical knobs, inputs and switch
     loop {
-        mux_state.sequence_counter = mux_state.sequence_counter.wrapping_add(1);
-
-        // read audio inputs and their normalization probe inputs
-        match adc_device.read(&mut audio1).await {
-            Ok(level) => {
-                audio_state.audio1.raw.update(level);
-                // info!("audio1: {}, {}", level, mux_state.audio1.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio1: {}", e),
-        };
-        match adc_device.read(&mut audio2).await {
-            Ok(level) => {
-                audio_state.audio2.raw.update(level);
-                // info!("audio2: {}, {}", level, mux_state.audio2.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio2: {}", e),
-        };
-
-        probe.set_high();
-        Timer::after_micros(mux_settle_micros).await;
-        match adc_device.read(&mut audio1).await {
-            Ok(level) => {
-                audio_state.audio1.probe.update(level);
-                // info!("audio1: {}, {}", level, mux_state.audio1.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio1: {}", e),
-        };
-        match adc_device.read(&mut audio2).await {
-            Ok(level) => {
-                audio_state.audio2.probe.update(level);
-                // info!("audio2: {}, {}", level, mux_state.audio2.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading audio2: {}", e),
-        };
-        probe.set_low();
-
-        // read Main knob & cv1
-        muxlogic_a.set_low();
-        muxlogic_b.set_low();
-        // this seems to need a delay for pins to settle before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                mux_state.main_knob.update(level);
-                // info!("M knob: {}, {}", level, mux_state.main_knob.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading Main: {}", e),
-        };
-
-        // read cv1 (inverted data)
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv1.raw.update(level);
-                // info!("cv1: {}, {}", level, mux_state.cv1.raw.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV1: {}", e),
-        };
-        probe.set_high();
-        Timer::after_micros(probe_settle_micros).await;
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv1.probe.update(level);
-                // info!("cv1: {}, {}", level, mux_state.cv1.probe.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV1: {}", e),
-        };
-        probe.set_low();
-        Timer::after_micros(probe_settle_micros).await;
-
-        // read X knob & cv2
-        // NOTE: X and Y appear to be swapped compared to how I read the logic table
-        // not sure why.... :/
-        muxlogic_a.set_high();
-        muxlogic_b.set_low();
-        // this seems to need a delay for pins to settle before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                mux_state.x_knob.update(level);
-                // info!("x knob: {}, {}", level, mux_state.x_knob.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading X: {}", e),
-        };
-
-        // read cv2 (inverted data)
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv2.raw.update(level);
-                // info!("cv2: {}, {}", level, mux_state.cv2.raw.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV2: {}", e),
-        };
-        probe.set_high();
-        Timer::after_micros(probe_settle_micros).await;
-        match adc_device.read(&mut mux_io_2).await {
-            Ok(level) => {
-                mux_state.cv2.probe.update(level);
-                // info!("cv2: {}, {}", level, mux_state.cv2.probe.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading CV2: {}", e),
-        };
-        probe.set_low();
-        Timer::after_micros(probe_settle_micros).await;
-
-        // read Y knob
-        muxlogic_a.set_low();
-        muxlogic_b.set_high();
-        // this seems to need 1us delay for pins to 'settle' before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                mux_state.y_knob.update(level);
-                // info!("y knob: {}, {}", level, mux_state.y_knob.to_output());
-            }
-            Err(e) => error!("ADC read failed, while reading Y: {}", e),
-        };
-
-        // read Z switch
-        muxlogic_a.set_high();
-        muxlogic_b.set_high();
-        // this seems to need 1us delay for pins to 'settle' before reading.
-        Timer::after_micros(mux_settle_micros).await;
-
-        match adc_device.read(&mut mux_io_1).await {
-            Ok(level) => {
-                // info!("MUX_IO_1 ADC: {}", level);
-                mux_state.zswitch = match level {
-                    level if level < 1000 => ZSwitch::Momentary,
-                    level if level > 3000 => ZSwitch::On,
-                    _ => ZSwitch::Off,
-                };
-            }
-            Err(e) => error!("ADC read failed, while reading Z: {}", e),
-        };
-
-        mux_snd.send(mux_state.clone());
-        audio_snd.send(audio_state.clone());
+        reader.read_and_publish(Instant::now().as_micros()).await;
 
         // Timer::after_millis(20).await;
         Timer::after_millis(1).await;
@@ -318,19 +109,26 @@ async fn main(spawner: Spawner) {
     }
 }
 
-/// Rough LED brightness correction
-fn led_gamma(value: u16) -> u16 {
-    // based on: https://github.com/TomWhitwell/Workshop_Computer/blob/main/Demonstrations%2BHelloWorlds/CircuitPython/mtm_computer.py
-    let temp: u32 = value.into();
-    ((temp * temp) / U12_MAX as u32).clamp(0, u16::MAX.into()) as u16
+/// Accessibility profile from settings, the standard one if flash can't be read
+async fn load_accessibility(flash: peripherals::FLASH) -> Accessibility {
+    let flash = SettingsSectors::new(flash, SETTINGS_SECTORS);
+    match SettingsStore::<_, SETTINGS_SECTORS>::mount(flash).await {
+        Ok(mut settings) => Accessibility::load(&mut settings).await,
+        Err(e) => Err(e),
+    }
+    .unwrap_or_else(|e| {
+        error!("error loading accessibility profile: {}", e);
+        Accessibility::STANDARD
+    })
 }
 
+#[cfg(feature = "stats")]
 #[embassy_executor::task]
 async fn periodic_stats() {
-    let mut mux_rcv = MUX_INPUT.anon_receiver();
+    let mut input_rcv = INPUTS.anon_receiver();
     let mut last_sequence: usize = 0;
     loop {
-        if let Some(mux_state) = mux_rcv.try_get() {
+        if let Some(InputState { mux: mux_state, .. }) = input_rcv.try_get() {
             info!(
                 "main loop rate: {} per sec",
                 mux_state.sequence_counter - last_sequence
@@ -341,47 +139,30 @@ async fn periodic_stats() {
     }
 }
 
-#[allow(clippy::too_many_arguments)]
 #[embassy_executor::task]
 async fn audio_loop(
-    led_pwm_slice: peripherals::PWM_SLICE5,
-    led1_pin: peripherals::PIN_10,
-    led2_pin: peripherals::PIN_11,
-    spi0: peripherals::SPI0,
-    clk: peripherals::PIN_18,
-    mosi: peripherals::PIN_19,
-    dma0: peripherals::DMA_CH0,
-    cs_pin: peripherals::PIN_21,
+    led_channels: [PwmOutput<'static>; 2],
+    mut dac: AudioDac,
+    accessibility: Accessibility,
 ) {
-    let mut mux_rcv = MUX_INPUT.anon_receiver();
-    let mut audio_rcv = AUDIO_INPUT.anon_receiver();
-
-    // LED setup
-    let mut c = pwm::Config::default();
-    // 12 bit PWM * 10. 10x is to increase PWM rate, reducing visible flicker.
-    c.top = 40950;
-
-    let pwm5 = pwm::Pwm::new_output_ab(led_pwm_slice, led1_pin, led2_pin, c.clone());
-    let (Some(mut led1), Some(mut led2)) = pwm5.split() else {
-        error!("Error setting up LED PWM channels for audio_loop");
-        return;
-    };
-
-    // DAC setup
-    let mut spi = spi::Spi::new_txonly(spi0, clk, mosi, dma0, spi::Config::default());
-    let mut cs = Output::new(cs_pin, Level::High);
-
-    // DAC config bits
-    // 0: channel select 0 = A, 1 = B
-    // 1: unused
-    // 2: 0 = 2x gain, 1 = 1x
-    // 3: 0 = shutdown channel
-    let dac_config_a = 0b0011000000000000u16;
-    let dac_config_b = 0b1011000000000000u16;
-    let mut dac_buffer: [u8; 2];
+    let mut input_rcv = INPUTS.anon_receiver();
+
+    // flash the audio LEDs when a cable is plugged into or removed from audio in
+    let loop_rate = Hertz::from_period(Millis::new(20));
+    let mut audio1_flash = PlugFlash::with_accessibility(loop_rate, accessibility);
+    let mut audio2_flash = PlugFlash::with_accessibility(loop_rate, accessibility);
+    let mut leds = Leds::new(led_channels, loop_rate);
+    leds.set_accessibility(accessibility);
 
     loop {
-        if let (Some(mux_state), Some(audio_state)) = (mux_rcv.try_get(), audio_rcv.try_get()) {
+        if let Some(InputState {
+            mux: mux_state,
+            audio: audio_state,
+        }) = input_rcv.try_get()
+        {
+            audio1_flash.update(&audio_state.audio1);
+            audio2_flash.update(&audio_state.audio2);
+
             // write to audio outputs
             let mut output_value = mux_state.main_knob;
             // If cable plugged into audio inputs, mix then attenuvert that signal
@@ -391,52 +172,24 @@ async fn audio_loop(
             ) {
                 (Some(in1), Some(in2)) => {
                     let mix = (*in1 + *in2) / 2;
-                    output_value = (mix * output_value) / Sample::OFFSET;
+                    output_value = mix.attenuvert(output_value);
                 }
                 (Some(input), None) | (None, Some(input)) => {
-                    output_value = (*input * output_value) / Sample::OFFSET;
+                    output_value = input.attenuvert(output_value);
                 }
                 (None, None) => {}
             }
 
-            // the << 4 >> 4 dance clears out the top four bits,
-            // to prepare for setting the config bits
-            dac_buffer =
-                ((output_value.to_output_inverted() << 4 >> 4) | dac_config_a).to_be_bytes();
-            // debug!(
-            //     "audio channel 1: {}, {}: buff: 0x{:08b}{:08b}",
-            //     mux_state.main_knob, output_value, dac_buffer[0], dac_buffer[1]
-            // );
-            cs.set_low();
-            spi.blocking_write(&dac_buffer)
-                .unwrap_or_else(|e| error!("error writing to DAC: {}", e));
-            cs.set_high();
-
-            dac_buffer = ((output_value.to_output() << 4 >> 4) | dac_config_b).to_be_bytes();
-            // debug!(
-            //     "audio channel 2: {}, {}: buff: 0x{:08b}{:08b}",
-            //     mux_state.main_knob, output_value, dac_buffer[0], dac_buffer[1]
-            // );
-            cs.set_low();
-            spi.blocking_write(&dac_buffer)
+            // audio out 2 is an inverted copy of audio out 1
+            dac.write(DacChannel::A, output_value.to_output_inverted())
+                .and_then(|_| dac.write(DacChannel::B, output_value.to_output()))
                 .unwrap_or_else(|e| error!("error writing to DAC: {}", e));
-            cs.set_high();
 
             // audio LEDs
-            led1.set_duty_cycle_fraction(led_gamma(output_value.to_output()), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting LED 1 PWM to : {}",
-                        led_gamma(output_value.to_output())
-                    )
-                });
-            led2.set_duty_cycle_fraction(led_gamma(output_value.to_output_inverted()), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting LED 2 PWM to : {}",
-                        led_gamma(output_value.to_output_inverted())
-                    )
-                });
+            leds.set(0, audio1_flash.apply(output_value.to_output()));
+            leds.set(1, audio2_flash.apply(output_value.to_output_inverted()));
+            leds.update()
+                .unwrap_or_else(|e| error!("error setting LED {} PWM", e.led + 1));
         }
         Timer::after_millis(20).await;
     }
@@ -444,68 +197,38 @@ async fn audio_loop(
 
 #[embassy_executor::task]
 async fn cv_loop(
-    led_pwm_slice: peripherals::PWM_SLICE6,
-    led3_pin: peripherals::PIN_12,
-    led4_pin: peripherals::PIN_13,
-    cv_pwm_slice: peripherals::PWM_SLICE3,
-    cv1_pin: peripherals::PIN_23,
-    cv2_pin: peripherals::PIN_22,
+    led_channels: [PwmOutput<'static>; 2],
+    cv_out: [CvOut<PwmOutput<'static>>; 2],
+    accessibility: Accessibility,
 ) {
-    // If we aim for a specific frequency, here is how we can calculate the top value.
-    // The top value sets the period of the PWM cycle, so a counter goes from 0 to top and then wraps around to 0.
-    // Every such wraparound is one PWM cycle. So here is how we get 60KHz:
-    // 60khz target from Computer docs
-    let desired_freq_hz = 60_000;
-    let clock_freq_hz = embassy_rp::clocks::clk_sys_freq();
-    let divider = 16u8;
-    let period = (clock_freq_hz / (desired_freq_hz * divider as u32)) as u16 - 1;
-
-    // CV PWM setup
-    // Inverted PWM output. Two pole active filtered. Use 12 bit PWM at 60khz.
-    // 4095 = -6v
-    // 2048 =  0v
-    // 0    = +6v
-    let mut cv_pwm_config = pwm::Config::default();
-    cv_pwm_config.top = period;
-    cv_pwm_config.divider = divider.into();
-
-    let pwm3 = pwm::Pwm::new_output_ab(cv_pwm_slice, cv2_pin, cv1_pin, cv_pwm_config.clone());
-    // Yes, cv_2_pwm has the lower GPIO pin.
-    let (Some(mut cv2_pwm), Some(mut cv1_pwm)) = pwm3.split() else {
-        error!("Error setting up CV PWM channels for cv_loop");
-        return;
-    };
-
-    // LED PWM setup
-    let mut led_pwm_config = pwm::Config::default();
-    // 12 bit PWM * 10. 10x is to increase PWM rate, reducing visible flicker.
-    led_pwm_config.top = 40950;
-
-    let pwm6 = pwm::Pwm::new_output_ab(led_pwm_slice, led3_pin, led4_pin, led_pwm_config.clone());
-    let (Some(mut led3), Some(mut led4)) = pwm6.split() else {
-        error!("Error setting up LED PWM channels for cv_loop");
-        return;
-    };
-    let mut mux_rcv = MUX_INPUT.anon_receiver();
+    // calibrated by Computer::init
+    let [mut cv1_out, mut cv2_out] = cv_out;
+    let mut input_rcv = INPUTS.anon_receiver();
+
+    // flash the CV LEDs when a cable is plugged into or removed from CV in
+    let loop_rate = Hertz::from_period(Millis::new(20));
+    let mut cv1_flash = PlugFlash::with_accessibility(loop_rate, accessibility);
+    let mut cv2_flash = PlugFlash::with_accessibility(loop_rate, accessibility);
+    let mut leds = Leds::new(led_channels, loop_rate);
+    leds.set_accessibility(accessibility);
 
     loop {
-        if let Some(mux_state) = mux_rcv.try_get() {
+        if let Some(InputState { mux: mux_state, .. }) = input_rcv.try_get() {
+            cv1_flash.update(&mux_state.cv1);
+            cv2_flash.update(&mux_state.cv2);
+
             // cv1 output
             let mut x_value = mux_state.x_knob;
             // info!("x: {}", x_value);
             // If cable plugged into cv1, attenuvert that signal
             if let Some(input_cv) = mux_state.cv1.plugged_value() {
                 // info!("x: {}, cv: {}", x_value, input_cv);
-                x_value = (*input_cv * x_value) / Sample::OFFSET;
+                x_value = input_cv.attenuvert(x_value);
             }
-            cv1_pwm
-                .set_duty_cycle_fraction(x_value.to_output_inverted(), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting CV1 PWM to : {}",
-                        x_value.to_output_inverted()
-                    )
-                });
+            let cv1_value = power::safe_cv(x_value);
+            cv1_out
+                .set(cv1_value)
+                .unwrap_or_else(|_| error!("error setting CV1 to : {}", cv1_value));
 
             // cv2 output
             let mut y_value = mux_state.y_knob;
@@ -519,32 +242,18 @@ async fn cv_loop(
             // If cable plugged into cv2, attenuvert that signal
             if let Some(input_cv) = mux_state.cv2.plugged_value() {
                 // info!("y: {}, cv: {}", y_value, input_cv);
-                y_value = (*input_cv * y_value) / Sample::OFFSET;
+                y_value = input_cv.attenuvert(y_value);
             }
-            cv2_pwm
-                .set_duty_cycle_fraction(y_value.to_output_inverted(), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting CV2 PWM to : {}",
-                        y_value.to_output_inverted()
-                    )
-                });
+            let cv2_value = power::safe_cv(y_value);
+            cv2_out
+                .set(cv2_value)
+                .unwrap_or_else(|_| error!("error setting CV2 to : {}", cv2_value));
 
             // LEDs
-            led3.set_duty_cycle_fraction(led_gamma(x_value.to_output()), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting LED 3 PWM to : {}",
-                        led_gamma(x_value.to_output())
-                    )
-                });
-            led4.set_duty_cycle_fraction(led_gamma(y_value.to_output()), U12_MAX)
-                .unwrap_or_else(|_| {
-                    error!(
-                        "error setting LED 4 PWM to : {}",
-                        led_gamma(y_value.to_output())
-                    )
-                });
+            leds.set(0, cv1_flash.apply(x_value.to_output()));
+            leds.set(1, cv2_flash.apply(y_value.to_output()));
+            leds.update()
+                .unwrap_or_else(|e| error!("error setting LED {} PWM", e.led + 3));
         }
         Timer::after_millis(20).await;
     }
@@ -552,38 +261,53 @@ async fn cv_loop(
 
 #[embassy_executor::task]
 async fn pulse_loop(
-    led5_pin: peripherals::PIN_14,
-    led6_pin: peripherals::PIN_15,
-    pulse1_pin: peripherals::PIN_8,
-    pulse2_pin: peripherals::PIN_9,
+    led_channels: [PwmOutput<'static>; 2],
+    pulse_out: [PulseOut<Output<'static>>; 2],
+    mut pulse_in1: PulseInput<Input<'static>>,
 ) {
-    let mut led5 = Output::new(led5_pin, Level::Low);
-    let mut led6 = Output::new(led6_pin, Level::Low);
-
-    // pulse outputs are inverted
-    let mut pulse_1_raw_out = Output::new(pulse1_pin, Level::High);
-    let mut pulse_2_raw_out = Output::new(pulse2_pin, Level::High);
+    // fully on or off, following the pulse outputs
+    let mut leds = Leds::new(led_channels, Hertz::from_period(Millis::new(1)));
+    // pulse outputs stay low in USB power mode, LEDs still follow the switch
+    let [mut pulse_1_out, mut pulse_2_out] = pulse_out;
 
-    let mut mux_rcv = MUX_INPUT.anon_receiver();
+    let mut input_rcv = INPUTS.anon_receiver();
+    let mut switch_events = 0;
+    // the latched gate, None while following the switch
+    let mut latch: Option<bool> = None;
 
     loop {
-        if let Some(mux_state) = mux_rcv.try_get() {
+        pulse_in1.poll(Instant::now().as_micros());
+        if let Some(InputState { mux: mux_state, .. }) = input_rcv.try_get() {
+            match (mux_state.switch_event_since(&mut switch_events), latch) {
+                (Some(SwitchEvent::DoubleTap), None) => latch = Some(false),
+                (Some(SwitchEvent::DoubleTap), Some(_)) => latch = None,
+                (Some(SwitchEvent::Press), Some(gate)) => latch = Some(!gate),
+                _ => {}
+            }
+            let switch_high = match latch {
+                Some(gate) => gate,
+                None => mux_state.zswitch != ZSwitch::Off,
+            };
+
             // update pulses
-            match mux_state.zswitch {
-                ZSwitch::On | ZSwitch::Momentary => {
-                    led5.set_high();
-                    pulse_1_raw_out.set_low();
-                    led6.set_low();
-                    pulse_2_raw_out.set_high();
+            match (switch_high, pulse_in1.is_high()) {
+                (_, true) | (true, false) => {
+                    leds.set(0, U12_MAX);
+                    pulse_1_out.gate(true);
+                    leds.set(1, 0);
+                    pulse_2_out.gate(false);
                 }
-                ZSwitch::Off => {
-                    led5.set_low();
-                    pulse_1_raw_out.set_high();
-                    led6.set_high();
-                    pulse_2_raw_out.set_low();
+                (false, false) => {
+                    leds.set(0, 0);
+                    pulse_1_out.gate(false);
+                    leds.set(1, U12_MAX);
+                    pulse_2_out.gate(true);
                 }
             }
+            leds.update()
+                .unwrap_or_else(|e| error!("error setting LED {} PWM", e.led + 5));
         }
-        Timer::after_millis(20).await;
+        // short delay so incoming pulses are followed closely
+        Timer::after_millis(1).await;
     }
 }
@@ -0,0 +1,498 @@
+//! Hello card: a minimal card using each of the wscomp drivers
+//!
+//! Runs on the host against a simulated Computer, so the whole card can be
+//! stepped and tested without hardware:
+//!
+//! ```text
+//! cargo run --example hello_card
+//! cargo test --example hello_card
+//! ```
+//!
+//! A firmware card builds the same drivers from `embassy_rp` peripherals
+//! instead of the `Sim*` types, at the pins in `wscomp::board`, and calls
+//! [`HelloCard::tick`] from a `Ticker`.
+//!
+//! - Main knob: pitch of a tone on audio out 1, audio out 2 is inverted
+//! - Z switch: a press toggles saw or square, stored in settings
+//! - X and Y knobs: CV out 1 and 2, attenuverting CV in 1 and 2 when plugged
+//! - Pulse in 1: copied to pulse out 1, pulse out 2 is a 2Hz clock
+//! - LEDs: audio and CV output levels, flashing on plug changes
+//! - Console: a status line once a second
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use std::rc::Rc;
+
+use embassy_futures::block_on;
+use embedded_hal::digital::{self, InputPin, OutputPin};
+use embedded_hal::pwm::{self, SetDutyCycle};
+use embedded_hal::spi::{self, SpiBus};
+use embedded_hal_async::delay::DelayNs;
+
+use wscomp::board;
+use wscomp::cv::CvOut;
+use wscomp::dac::Dac;
+use wscomp::inputs::{AdcInput, InputAdc, InputReader};
+use wscomp::leds::{Leds, PlugFlash};
+use wscomp::power;
+use wscomp::pulse::{Edge, PulseInput, PulseOut};
+use wscomp::settings::{SettingsFlash, SettingsStore, SECTOR_SIZE};
+use wscomp::switch::SwitchEvent;
+use wscomp::units::{Hertz, Millis};
+use wscomp::{Sample, U12_MAX};
+
+/// Rate of [`HelloCard::tick`]
+const CONTROL_RATE: Hertz = Hertz::new(1_000);
+/// Audio samples written per tick, 48kHz
+const SAMPLES_PER_TICK: u32 = 48;
+const SAMPLE_RATE: u32 = CONTROL_RATE.hz() * SAMPLES_PER_TICK;
+/// Settings key for the waveform
+const WAVE_KEY: u16 = 1;
+const SETTINGS_SECTORS: usize = 2;
+
+/// Front panel and pin levels of the simulated Computer
+struct Panel {
+    /// 12 bit ADC codes
+    main_knob: u16,
+    x_knob: u16,
+    y_knob: u16,
+    switch: u16,
+    /// ADC codes of cables plugged into CV in 1 and 2
+    cv_in: [Option<u16>; 2],
+    /// GPIO levels, by GPIO number
+    pins: [bool; 30],
+    /// PWM duty as a fraction of U12_MAX, by GPIO number
+    duty: [u16; 30],
+    /// Every value written to each DAC channel
+    dac: [Vec<u16>; 2],
+}
+
+type SharedPanel = Rc<RefCell<Panel>>;
+
+impl Panel {
+    fn new() -> SharedPanel {
+        Rc::new(RefCell::new(Panel {
+            main_knob: 2048,
+            x_knob: 2048,
+            y_knob: 2048,
+            switch: 2048,
+            cv_in: [None; 2],
+            // pulse inputs are inverted, so high is no pulse
+            pins: [true; 30],
+            duty: [0; 30],
+            dac: [Vec::new(), Vec::new()],
+        }))
+    }
+
+    fn set_pulse_in(&mut self, high: bool) {
+        self.pins[board::PULSE_IN_1 as usize] = !high;
+    }
+
+    fn pulse_out(&self, gpio: u8) -> bool {
+        !self.pins[gpio as usize]
+    }
+
+    /// ADC code of a CV input, unplugged jacks follow the normalization probe
+    fn cv_code(&self, jack: usize) -> u16 {
+        match self.cv_in[jack] {
+            Some(code) => code,
+            None if self.pins[board::PROBE as usize] => 1000,
+            None => 2048,
+        }
+    }
+}
+
+struct SimAdc(SharedPanel);
+
+impl InputAdc for SimAdc {
+    type Error = Infallible;
+
+    async fn read(&mut self, input: AdcInput) -> Result<u16, Infallible> {
+        let panel = self.0.borrow();
+        let mux = (
+            panel.pins[board::MUX_LOGIC_A as usize],
+            panel.pins[board::MUX_LOGIC_B as usize],
+        );
+        Ok(match (input, mux) {
+            (AdcInput::Audio1 | AdcInput::Audio2, _) => 2048,
+            (AdcInput::MuxIo1, (false, false)) => panel.main_knob,
+            (AdcInput::MuxIo1, (true, false)) => panel.x_knob,
+            (AdcInput::MuxIo1, (false, true)) => panel.y_knob,
+            (AdcInput::MuxIo1, (true, true)) => panel.switch,
+            (AdcInput::MuxIo2, (false, _)) => panel.cv_code(0),
+            (AdcInput::MuxIo2, (true, _)) => panel.cv_code(1),
+        })
+    }
+}
+
+struct SimPin {
+    panel: SharedPanel,
+    gpio: u8,
+}
+
+impl digital::ErrorType for SimPin {
+    type Error = Infallible;
+}
+
+impl OutputPin for SimPin {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.panel.borrow_mut().pins[self.gpio as usize] = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.panel.borrow_mut().pins[self.gpio as usize] = true;
+        Ok(())
+    }
+}
+
+impl InputPin for SimPin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.panel.borrow().pins[self.gpio as usize])
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+struct SimPwm {
+    panel: SharedPanel,
+    gpio: u8,
+}
+
+impl pwm::ErrorType for SimPwm {
+    type Error = Infallible;
+}
+
+impl SetDutyCycle for SimPwm {
+    fn max_duty_cycle(&self) -> u16 {
+        U12_MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+        self.panel.borrow_mut().duty[self.gpio as usize] = duty;
+        Ok(())
+    }
+}
+
+/// MCP4822 on SPI, decodes the command words
+struct SimSpi(SharedPanel);
+
+impl spi::ErrorType for SimSpi {
+    type Error = Infallible;
+}
+
+impl SpiBus for SimSpi {
+    fn read(&mut self, _words: &mut [u8]) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+        for command in words.chunks_exact(2) {
+            let command = u16::from_be_bytes([command[0], command[1]]);
+            let channel = usize::from(command >> 15);
+            self.0.borrow_mut().dac[channel].push(command & 0x0fff);
+        }
+        Ok(())
+    }
+
+    fn transfer(&mut self, _read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+        self.write(write)
+    }
+
+    fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// NOR flash that outlives the card, so settings survive a reboot
+#[derive(Clone)]
+struct SimFlash(Rc<RefCell<Vec<u8>>>);
+
+impl SimFlash {
+    fn new() -> Self {
+        let size = SETTINGS_SECTORS * SECTOR_SIZE as usize;
+        SimFlash(Rc::new(RefCell::new(vec![0xff; size])))
+    }
+}
+
+impl SettingsFlash for SimFlash {
+    type Error = Infallible;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Infallible> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.0.borrow()[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Infallible> {
+        for (memory, byte) in self.0.borrow_mut()[offset as usize..].iter_mut().zip(bytes) {
+            *memory &= byte;
+        }
+        Ok(())
+    }
+
+    async fn erase(&mut self, offset: u32) -> Result<(), Infallible> {
+        let offset = offset as usize;
+        self.0.borrow_mut()[offset..offset + SECTOR_SIZE as usize].fill(0xff);
+        Ok(())
+    }
+}
+
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wave {
+    Saw,
+    Square,
+}
+
+struct HelloCard {
+    inputs: InputReader<SimAdc, SimPin, NoDelay>,
+    /// [`MuxState::switch_events`](wscomp::inputs::MuxState::switch_events) last seen
+    switch_events: u8,
+    pulse_in: PulseInput<SimPin>,
+    pulse_outs: [PulseOut<SimPin>; 2],
+    cv_outs: [CvOut<SimPwm>; 2],
+    leds: Leds<SimPwm, 6>,
+    cv_flashes: [PlugFlash; 2],
+    dac: Dac<SimSpi, SimPin>,
+    settings: SettingsStore<SimFlash, SETTINGS_SECTORS>,
+    wave: Wave,
+    phase: Sample,
+}
+
+impl HelloCard {
+    async fn boot(panel: &SharedPanel, flash: SimFlash) -> Self {
+        let pin = |gpio| SimPin {
+            panel: panel.clone(),
+            gpio,
+        };
+        let pwm = |gpio| SimPwm {
+            panel: panel.clone(),
+            gpio,
+        };
+
+        let mut settings = SettingsStore::mount(flash).await.unwrap();
+        let mut wave = [0];
+        let wave = match settings.get(WAVE_KEY, &mut wave).await.unwrap() {
+            Some(_) if wave[0] == 1 => Wave::Square,
+            _ => Wave::Saw,
+        };
+
+        let mut pulse_outs = [
+            PulseOut::new(pin(board::PULSE_OUT_1), true),
+            PulseOut::new(pin(board::PULSE_OUT_2), true),
+        ];
+        pulse_outs[1].start_clock(Hertz::new(2), Millis::new(10), 0);
+        for pulse_out in pulse_outs.iter_mut() {
+            pulse_out.set_enabled(power::outputs_enabled());
+        }
+
+        HelloCard {
+            inputs: InputReader::new(
+                SimAdc(panel.clone()),
+                pin(board::PROBE),
+                pin(board::MUX_LOGIC_A),
+                pin(board::MUX_LOGIC_B),
+                NoDelay,
+            ),
+            switch_events: 0,
+            pulse_in: PulseInput::new(pin(board::PULSE_IN_1), true),
+            pulse_outs,
+            cv_outs: [
+                CvOut::new(pwm(board::CV_OUT_1)),
+                CvOut::new(pwm(board::CV_OUT_2)),
+            ],
+            leds: Leds::new(board::LEDS.map(pwm), CONTROL_RATE),
+            cv_flashes: [PlugFlash::new(CONTROL_RATE), PlugFlash::new(CONTROL_RATE)],
+            dac: Dac::new(SimSpi(panel.clone()), pin(board::DAC_CS)),
+            settings,
+            wave,
+            phase: Sample::from(0_i32),
+        }
+    }
+
+    /// One control period: read inputs, then update every output
+    async fn tick(&mut self, now_micros: u64) {
+        let state = self.inputs.read(now_micros).await.clone();
+
+        // a firmware card would queue this in a settings::WriteQueue, so
+        // the flash stall doesn't glitch audio
+        let event = state.mux.switch_event_since(&mut self.switch_events);
+        if event == Some(SwitchEvent::Press) {
+            self.wave = match self.wave {
+                Wave::Saw => Wave::Square,
+                Wave::Square => Wave::Saw,
+            };
+            let stored = [(self.wave == Wave::Square).into()];
+            self.settings.set(WAVE_KEY, &stored).await.unwrap();
+        }
+
+        match self.pulse_in.poll(now_micros) {
+            Some(Edge::Rising) => self.pulse_outs[0].gate(true),
+            Some(Edge::Falling) => self.pulse_outs[0].gate(false),
+            None => {}
+        }
+        for pulse_out in self.pulse_outs.iter_mut() {
+            pulse_out.update(now_micros);
+        }
+
+        let jacks = [&state.mux.cv1, &state.mux.cv2];
+        let knobs = [state.mux.x_knob, state.mux.y_knob];
+        for (index, (jack, knob)) in jacks.into_iter().zip(knobs).enumerate() {
+            let value = match jack.plugged_value() {
+                Some(input) => input.attenuvert(knob),
+                None => knob,
+            };
+            self.cv_outs[index].set(power::safe_cv(value)).unwrap();
+            self.cv_flashes[index].update(jack);
+            let level = self.cv_flashes[index].apply(value.to_output_abs() * 2);
+            self.leds.set(2 + index, level);
+        }
+
+        // 55Hz to about 880Hz, in 12 bit counts per sample
+        let hz = 55 + u32::from(state.mux.main_knob.to_output()) * 825 / u32::from(U12_MAX);
+        let step = Sample::from((hz * 4096 / SAMPLE_RATE) as i32);
+        let mut out = Sample::from(0_i32);
+        for _ in 0..SAMPLES_PER_TICK {
+            self.phase = self.phase.wrapping_add(step);
+            out = match self.wave {
+                Wave::Saw => self.phase,
+                Wave::Square if self.phase < Sample::from(0_i32) => Sample::from(Sample::MIN),
+                Wave::Square => Sample::from(Sample::MAX),
+            };
+            self.dac
+                .write_pair(out, Sample::from(-out.to_clamped()))
+                .unwrap();
+        }
+        self.leds.set(0, out.to_output());
+        self.leds
+            .set(1, Sample::from(-out.to_clamped()).to_output());
+        self.leds.update().unwrap();
+    }
+
+    fn status(&self) -> String {
+        let mux = &self.inputs.state().mux;
+        format!(
+            "main: {}, x: {}, y: {}, wave: {:?}",
+            mux.main_knob.as_percent(),
+            mux.x_knob.as_volts(),
+            mux.y_knob.as_volts(),
+            self.wave
+        )
+    }
+}
+
+/// Run the card for `ticks` control periods from `start_micros`
+fn run(card: &mut HelloCard, start_micros: u64, ticks: u32) -> u64 {
+    let period = u64::from(CONTROL_RATE.period().millis()) * 1000;
+    let mut now = start_micros;
+    for _ in 0..ticks {
+        block_on(card.tick(now));
+        now += period;
+    }
+    now
+}
+
+fn main() {
+    let panel = Panel::new();
+    let mut card = block_on(HelloCard::boot(&panel, SimFlash::new()));
+    panel.borrow_mut().main_knob = 3000;
+    panel.borrow_mut().x_knob = 4000;
+    let mut now = 0;
+    for second in 0..3 {
+        // a one second gate into pulse in 1, every other second
+        panel.borrow_mut().set_pulse_in(second % 2 == 0);
+        now = run(&mut card, now, CONTROL_RATE.hz());
+        let pulse_out = panel.borrow().pulse_out(board::PULSE_OUT_1);
+        println!("{}, pulse out 1: {}", card.status(), pulse_out);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{board, run, HelloCard, Panel, SimFlash, Wave, CONTROL_RATE};
+    use embassy_futures::block_on;
+
+    #[test]
+    fn test_hello_card_outputs() {
+        let panel = Panel::new();
+        let mut card = block_on(HelloCard::boot(&panel, SimFlash::new()));
+        panel.borrow_mut().x_knob = 4095;
+        panel.borrow_mut().y_knob = 0;
+        let now = run(&mut card, 0, 100);
+
+        // CV outputs are inverted PWM, so high X is a low duty cycle
+        let duty = |gpio: u8| panel.borrow().duty[gpio as usize];
+        assert!(duty(board::CV_OUT_1) < 100);
+        assert!(duty(board::CV_OUT_2) > 4000);
+        // CV LEDs show the output level either way
+        assert!(duty(board::LEDS[2]) > 2000);
+        assert!(duty(board::LEDS[3]) > 2000);
+
+        // plugging CV in 1 at 0v flashes its LED, and attenuverts it to 0v
+        panel.borrow_mut().cv_in[0] = Some(2048);
+        let now = run(&mut card, now, 20);
+        assert!(card.cv_flashes[0].is_flashing());
+        let now = run(&mut card, now, 200);
+        assert!((2000..2100).contains(&duty(board::CV_OUT_1)));
+
+        // pulse in 1 is copied to pulse out 1, pulse out 2 clocks at 2Hz
+        panel.borrow_mut().set_pulse_in(true);
+        let now = run(&mut card, now, 1);
+        assert!(panel.borrow().pulse_out(board::PULSE_OUT_1));
+        panel.borrow_mut().set_pulse_in(false);
+        run(&mut card, now, 1);
+        assert!(!panel.borrow().pulse_out(board::PULSE_OUT_1));
+        assert!(card.pulse_outs[1].next_change_micros().is_some());
+    }
+
+    #[test]
+    fn test_hello_card_tone() {
+        let panel = Panel::new();
+        let mut card = block_on(HelloCard::boot(&panel, SimFlash::new()));
+        panel.borrow_mut().main_knob = 0;
+        run(&mut card, 0, CONTROL_RATE.hz());
+
+        // a 55Hz saw wraps around about 55 times a second
+        let dac = &panel.borrow().dac;
+        assert_eq!(dac[0].len(), 48_000);
+        let wraps = dac[0]
+            .windows(2)
+            .filter(|pair| pair[1] > pair[0] + 2048)
+            .count();
+        assert!((50..60).contains(&wraps), "{wraps} wraps");
+        // and audio out 2 is inverted, mirrored around mid scale
+        assert!((4094..=4096).contains(&(dac[0][100] + dac[1][100])));
+    }
+
+    #[test]
+    fn test_hello_card_settings() {
+        let panel = Panel::new();
+        let flash = SimFlash::new();
+        let mut card = block_on(HelloCard::boot(&panel, flash.clone()));
+        assert_eq!(card.wave, Wave::Saw);
+
+        // press the Z switch
+        panel.borrow_mut().switch = 0;
+        let now = run(&mut card, 0, 50);
+        panel.borrow_mut().switch = 2048;
+        run(&mut card, now, 50);
+        assert_eq!(card.wave, Wave::Square);
+        assert!(card.status().ends_with("wave: Square"));
+
+        // still square after a reboot
+        let card = block_on(HelloCard::boot(&panel, flash));
+        assert_eq!(card.wave, Wave::Square);
+    }
+}
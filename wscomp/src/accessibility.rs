@@ -0,0 +1,139 @@
+//! Accessibility profile, shared by every card's UI
+//!
+//! One profile, stored in settings, that the shared UI modules apply:
+//!
+//! - high contrast: [`Leds`] skip gamma and keep lit LEDs above a visible
+//!   floor, so dim levels don't disappear
+//! - slow UI: blinks, chases, plug flashes and the Z switch's long hold and
+//!   double tap windows all take [`Accessibility::SLOW_FACTOR`] times longer
+//!
+//! Cards load the profile at boot and hand it to each module, or to their
+//! [`InputReader`](crate::inputs::InputReader) with `set_accessibility`
+//! when it reads the switch:
+//!
+//! ```
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::accessibility::Accessibility;
+//! # use wscomp::leds::{Leds, PlugFlash};
+//! # use wscomp::settings::{SettingsError, SettingsFlash, SettingsStore};
+//! # use wscomp::switch::ZSwitchReader;
+//! # use wscomp::units::Hertz;
+//! # const CONTROL_RATE: Hertz = Hertz::new(1_000);
+//! # async fn card<F: SettingsFlash, P: SetDutyCycle>(
+//! #     mut store: SettingsStore<F, 2>,
+//! #     mut leds: Leds<P, 6>,
+//! # ) -> Result<(), SettingsError<F::Error>> {
+//! let accessibility = Accessibility::load(&mut store).await?;
+//! leds.set_accessibility(accessibility);
+//! let mut flash = PlugFlash::with_accessibility(CONTROL_RATE, accessibility);
+//! let mut switch = ZSwitchReader::with_accessibility(accessibility);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Leds`]: crate::leds::Leds
+
+use crate::settings::{SettingsError, SettingsFlash, SettingsStore};
+use crate::units::Millis;
+
+/// Settings key for the profile, shared settings use keys from 0xff00 down
+pub const SETTINGS_KEY: u16 = 0xff00;
+
+const HIGH_CONTRAST: u8 = 1 << 0;
+const SLOW_UI: u8 = 1 << 1;
+
+/// Which accessibility options are on, all off by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Accessibility {
+    /// Brighter LEDs, with dim levels kept visible
+    pub high_contrast: bool,
+    /// Longer LED feedback and Z switch gesture windows
+    pub slow_ui: bool,
+}
+
+impl Accessibility {
+    /// No options on, the default behaviour
+    pub const STANDARD: Self = Accessibility {
+        high_contrast: false,
+        slow_ui: false,
+    };
+    /// How much longer UI timings are with [`Accessibility::slow_ui`]
+    pub const SLOW_FACTOR: u32 = 2;
+
+    /// A UI timing adjusted for this profile
+    pub fn timing(&self, standard: Millis) -> Millis {
+        if self.slow_ui {
+            Millis::new(standard.millis() * Self::SLOW_FACTOR)
+        } else {
+            standard
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        let mut flags = 0;
+        if self.high_contrast {
+            flags |= HIGH_CONTRAST;
+        }
+        if self.slow_ui {
+            flags |= SLOW_UI;
+        }
+        flags
+    }
+
+    /// From a stored byte, unknown bits are ignored
+    pub fn from_byte(flags: u8) -> Self {
+        Accessibility {
+            high_contrast: flags & HIGH_CONTRAST != 0,
+            slow_ui: flags & SLOW_UI != 0,
+        }
+    }
+
+    /// Profile from settings, [`Accessibility::STANDARD`] if never saved
+    pub async fn load<F: SettingsFlash, const SECTORS: usize>(
+        store: &mut SettingsStore<F, SECTORS>,
+    ) -> Result<Self, SettingsError<F::Error>> {
+        let mut value = [0];
+        Ok(match store.get(SETTINGS_KEY, &mut value).await? {
+            Some(_) => Self::from_byte(value[0]),
+            None => Self::STANDARD,
+        })
+    }
+
+    /// Store the profile, stalls flash like any [`SettingsStore::set`]
+    pub async fn save<F: SettingsFlash, const SECTORS: usize>(
+        &self,
+        store: &mut SettingsStore<F, SECTORS>,
+    ) -> Result<(), SettingsError<F::Error>> {
+        store.set(SETTINGS_KEY, &[self.to_byte()]).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Accessibility;
+    use crate::units::Millis;
+
+    #[test]
+    fn test_accessibility_profile() {
+        let slow = Accessibility {
+            high_contrast: false,
+            slow_ui: true,
+        };
+        assert_eq!(slow.timing(Millis::new(300)), Millis::new(600));
+        assert_eq!(
+            Accessibility::STANDARD.timing(Millis::new(300)),
+            Millis::new(300)
+        );
+        for high_contrast in [false, true] {
+            for slow_ui in [false, true] {
+                let profile = Accessibility {
+                    high_contrast,
+                    slow_ui,
+                };
+                assert_eq!(Accessibility::from_byte(profile.to_byte()), profile);
+            }
+        }
+        assert_eq!(Accessibility::from_byte(0xfc), Accessibility::STANDARD);
+    }
+}
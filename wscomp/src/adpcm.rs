@@ -0,0 +1,422 @@
+//! Seekable IMA ADPCM stream decoder
+//!
+//! [`AdpcmStream`] decodes the mono IMA ADPCM blocks of a [`Wav`] one block
+//! at a time, as the samples are read, so a long file never has to be
+//! decoded up front:
+//!
+//! ```
+//! # use wscomp::adpcm::{AdpcmError, AdpcmStream};
+//! # use wscomp::wav::{Wav, WavError};
+//! # #[derive(Debug)]
+//! # enum Error {
+//! #     Wav(WavError),
+//! #     Adpcm(AdpcmError),
+//! # }
+//! # impl From<WavError> for Error {
+//! #     fn from(error: WavError) -> Self {
+//! #         Error::Wav(error)
+//! #     }
+//! # }
+//! # impl From<AdpcmError> for Error {
+//! #     fn from(error: AdpcmError) -> Self {
+//! #         Error::Adpcm(error)
+//! #     }
+//! # }
+//! # fn play(file: &[u8]) -> Result<(), Error> {
+//! // `file` from `include_bytes!`, or an asset bank
+//! let wav = Wav::parse(file)?;
+//! let mut stream = AdpcmStream::new(&wav)?;
+//! stream.set_looping(true);
+//! stream.seek_to_sample(277)?;
+//! while let Some(sample) = stream.next_sample() {
+//!     if stream.take_wrapped() {
+//!         // back at the start of the loop
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Looping streams play the whole file unless given the loop points the
+//! host asset tool found for it, see [`AdpcmStream::set_loop_points`].
+//!
+//! Blocks are the Microsoft layout written by most tools (`ffmpeg -c:a
+//! adpcm_ima_wav`): a 4 byte header holding the first sample and the step
+//! index, then two samples per byte, low nibble first. Each block decodes
+//! on its own, which is what makes seeking cheap.
+
+use crate::assets::LoopPoints;
+use crate::wav::{Wav, WavCodec};
+
+/// Largest block [`AdpcmStream`] decodes, in bytes
+pub const MAX_BLOCK_SIZE: usize = 1024;
+/// Samples in a [`MAX_BLOCK_SIZE`] block
+const MAX_BLOCK_SAMPLES: usize = (MAX_BLOCK_SIZE - 4) * 2 + 1;
+
+/// Quantizer step sizes
+const STEPS: [i16; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Step index change for each nibble's magnitude
+const INDEX_CHANGES: [i8; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdpcmError {
+    /// The WAV isn't IMA ADPCM
+    NotAdpcm,
+    /// Only mono files are supported
+    NotMono,
+    /// Blocks are bigger than [`MAX_BLOCK_SIZE`], or too small to hold a
+    /// header
+    BlockSize,
+    /// Seek past the last sample
+    SeekOutOfRange,
+    /// Loop points empty, or past the last sample
+    LoopOutOfRange,
+}
+
+/// Predictor state, carried from one nibble to the next within a block
+#[derive(Debug, Clone, Copy, Default)]
+struct Predictor {
+    sample: i16,
+    index: u8,
+}
+
+impl Predictor {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = i32::from(STEPS[usize::from(self.index)]);
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 8 != 0 {
+            diff = -diff;
+        }
+        self.sample =
+            (i32::from(self.sample) + diff).clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        let index = self.index as i8 + INDEX_CHANGES[usize::from(nibble & 7)];
+        self.index = index.clamp(0, STEPS.len() as i8 - 1) as u8;
+        self.sample
+    }
+}
+
+/// Decode one block into `out`, returns the number of samples
+fn decode_block(block: &[u8], out: &mut [i16]) -> usize {
+    let mut predictor = Predictor {
+        sample: i16::from_le_bytes([block[0], block[1]]),
+        index: block[2].min(STEPS.len() as u8 - 1),
+    };
+    out[0] = predictor.sample;
+    let mut count = 1;
+    for &byte in &block[4..] {
+        out[count] = predictor.decode(byte & 0xf);
+        out[count + 1] = predictor.decode(byte >> 4);
+        count += 2;
+    }
+    count
+}
+
+/// Streaming decoder over the data of an IMA ADPCM [`Wav`]
+pub struct AdpcmStream<'a> {
+    data: &'a [u8],
+    block_size: usize,
+    /// Samples in each full block
+    block_samples: usize,
+    /// Total samples, including a short last block
+    len: usize,
+    buffer: [i16; MAX_BLOCK_SAMPLES],
+    /// Block in `buffer`, if any
+    decoded: Option<usize>,
+    /// Next sample to read
+    position: usize,
+    looping: bool,
+    /// Loop region, the whole stream unless set
+    loop_start: usize,
+    loop_end: usize,
+    wrapped: bool,
+}
+
+impl<'a> AdpcmStream<'a> {
+    /// Stream from the start of `wav`, not looping
+    pub fn new(wav: &Wav<'a>) -> Result<Self, AdpcmError> {
+        let format = wav.format;
+        if format.codec != WavCodec::ImaAdpcm {
+            return Err(AdpcmError::NotAdpcm);
+        }
+        if format.channels != 1 {
+            return Err(AdpcmError::NotMono);
+        }
+        let block_size = usize::from(format.block_align);
+        if !(5..=MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(AdpcmError::BlockSize);
+        }
+        let block_samples = format.adpcm_samples_per_block();
+        // a short last block still has its header and first sample
+        let remainder = wav.data.len() % block_size;
+        let last_samples = if remainder >= 4 {
+            (remainder - 4) * 2 + 1
+        } else {
+            0
+        };
+        let len = wav.data.len() / block_size * block_samples + last_samples;
+        Ok(AdpcmStream {
+            data: wav.data,
+            block_size,
+            block_samples,
+            len,
+            buffer: [0; MAX_BLOCK_SAMPLES],
+            decoded: None,
+            position: 0,
+            looping: false,
+            loop_start: 0,
+            loop_end: len,
+            wrapped: false,
+        })
+    }
+
+    /// Go back to the start of the loop after its end, rather than ending
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    /// Loop from `points.end` back to `points.start` instead of over the
+    /// whole stream, once looping
+    ///
+    /// Playback before the loop start plays into the loop, so a lead in is
+    /// only heard once.
+    pub fn set_loop_points(&mut self, points: LoopPoints) -> Result<(), AdpcmError> {
+        let (start, end) = (points.start as usize, points.end as usize);
+        if start >= end || end > self.len {
+            return Err(AdpcmError::LoopOutOfRange);
+        }
+        self.loop_start = start;
+        self.loop_end = end;
+        Ok(())
+    }
+
+    /// Total samples in the stream
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Index of the next sample [`AdpcmStream::next_sample`] returns
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Samples left before the next block has to be decoded
+    ///
+    /// Useful for spreading the decoding of several streams out, so their
+    /// blocks don't all land on the same sample.
+    pub fn block_remaining(&self) -> usize {
+        self.block_samples - self.position % self.block_samples
+    }
+
+    /// Continue from `sample`, decoding only the block it's in
+    pub fn seek_to_sample(&mut self, sample: usize) -> Result<(), AdpcmError> {
+        if sample >= self.len {
+            return Err(AdpcmError::SeekOutOfRange);
+        }
+        self.position = sample;
+        Ok(())
+    }
+
+    /// True once after the stream loops back to the start of the loop
+    pub fn take_wrapped(&mut self) -> bool {
+        core::mem::take(&mut self.wrapped)
+    }
+
+    /// Next sample, `None` at the end unless looping
+    pub fn next_sample(&mut self) -> Option<i16> {
+        let end = if self.looping {
+            self.loop_end
+        } else {
+            self.len
+        };
+        if self.position >= end {
+            if !self.looping || self.is_empty() {
+                return None;
+            }
+            self.position = self.loop_start;
+            self.wrapped = true;
+        }
+        let block = self.position / self.block_samples;
+        if self.decoded != Some(block) {
+            let start = block * self.block_size;
+            let end = (start + self.block_size).min(self.data.len());
+            decode_block(&self.data[start..end], &mut self.buffer);
+            self.decoded = Some(block);
+        }
+        let sample = self.buffer[self.position % self.block_samples];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl Iterator for AdpcmStream<'_> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        self.next_sample()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AdpcmError, AdpcmStream, Predictor, STEPS};
+    use crate::assets::LoopPoints;
+    use crate::wav::{Wav, WavCodec, WavFormat};
+
+    /// Encode `samples` into 36 byte blocks of 65 samples each
+    fn encode(samples: &[i16]) -> Vec<u8> {
+        let mut data = Vec::new();
+        // start at a step size that suits the test signals
+        let mut index = 40;
+        for block in samples.chunks(65) {
+            let mut predictor = Predictor {
+                sample: block[0],
+                index,
+            };
+            data.extend_from_slice(&block[0].to_le_bytes());
+            data.extend_from_slice(&[index, 0]);
+            let mut nibbles = block[1..].iter().map(|&target| {
+                let step = i32::from(STEPS[usize::from(predictor.index)]);
+                let mut diff = i32::from(target) - i32::from(predictor.sample);
+                let mut nibble = 0;
+                if diff < 0 {
+                    nibble = 8;
+                    diff = -diff;
+                }
+                for (bit, threshold) in [(4, step), (2, step >> 1), (1, step >> 2)] {
+                    if diff >= threshold {
+                        nibble |= bit;
+                        diff -= threshold;
+                    }
+                }
+                predictor.decode(nibble);
+                nibble
+            });
+            while let Some(low) = nibbles.next() {
+                data.push(low | (nibbles.next().unwrap_or(0) << 4));
+            }
+            index = predictor.index;
+        }
+        data
+    }
+
+    fn wav(data: &[u8]) -> Wav<'_> {
+        Wav {
+            format: WavFormat {
+                codec: WavCodec::ImaAdpcm,
+                channels: 1,
+                sample_rate: 48_000,
+                block_align: 36,
+                bits_per_sample: 4,
+            },
+            data,
+        }
+    }
+
+    #[test]
+    fn test_adpcm_decode() {
+        let sine: Vec<i16> = (0..650)
+            .map(|n| ((n as f64 * 0.05).sin() * 20_000.0) as i16)
+            .collect();
+        let data = encode(&sine);
+        let mut stream = AdpcmStream::new(&wav(&data)).unwrap();
+        assert_eq!(stream.len(), 650);
+        let decoded: Vec<i16> = stream.by_ref().collect();
+        assert_eq!(decoded.len(), 650);
+        // each block starts exactly on its header sample
+        assert_eq!(decoded[65], sine[65]);
+        for (decoded, original) in decoded.iter().zip(&sine) {
+            assert!((decoded - original).abs() < 600, "{decoded} {original}");
+        }
+        assert_eq!(stream.next_sample(), None);
+
+        let mut not_adpcm = wav(&data);
+        not_adpcm.format.codec = WavCodec::Pcm;
+        assert!(matches!(
+            AdpcmStream::new(&not_adpcm),
+            Err(AdpcmError::NotAdpcm)
+        ));
+    }
+
+    #[test]
+    fn test_adpcm_seek_and_loop() {
+        let ramp: Vec<i16> = (0..149).map(|n| n * 100).collect();
+        let data = encode(&ramp);
+        let mut stream = AdpcmStream::new(&wav(&data)).unwrap();
+        // two full blocks and a short one of 19 samples
+        assert_eq!(stream.len(), 149);
+        let all: Vec<i16> = stream.by_ref().collect();
+
+        stream.seek_to_sample(100).unwrap();
+        assert_eq!(stream.position(), 100);
+        assert_eq!(stream.block_remaining(), 30);
+        assert_eq!(stream.next_sample(), Some(all[100]));
+        assert_eq!(stream.seek_to_sample(149), Err(AdpcmError::SeekOutOfRange));
+
+        stream.set_looping(true);
+        stream.seek_to_sample(148).unwrap();
+        assert_eq!(stream.next_sample(), Some(all[148]));
+        assert!(!stream.take_wrapped());
+        assert_eq!(stream.next_sample(), Some(all[0]));
+        assert!(stream.take_wrapped());
+        assert!(!stream.take_wrapped());
+        assert_eq!(stream.position(), 1);
+    }
+
+    #[test]
+    fn test_adpcm_loop_points() {
+        let ramp: Vec<i16> = (0..149).map(|n| n * 100).collect();
+        let data = encode(&ramp);
+        let mut stream = AdpcmStream::new(&wav(&data)).unwrap();
+        let all: Vec<i16> = stream.by_ref().collect();
+
+        let points = |start, end| LoopPoints { start, end };
+        assert_eq!(
+            stream.set_loop_points(points(10, 10)),
+            Err(AdpcmError::LoopOutOfRange)
+        );
+        assert_eq!(
+            stream.set_loop_points(points(0, 150)),
+            Err(AdpcmError::LoopOutOfRange)
+        );
+        stream.set_loop_points(points(70, 100)).unwrap();
+
+        // not looping, the loop points are ignored
+        stream.seek_to_sample(98).unwrap();
+        let tail: Vec<i16> = stream.by_ref().collect();
+        assert_eq!(tail, all[98..]);
+
+        // the lead in plays once, then the loop repeats
+        stream.set_looping(true);
+        stream.seek_to_sample(0).unwrap();
+        let played: Vec<i16> = stream.by_ref().take(160).collect();
+        assert_eq!(played[..100], all[..100]);
+        assert_eq!(played[100..130], all[70..100]);
+        assert_eq!(played[130..], all[70..100]);
+        assert!(stream.take_wrapped());
+    }
+}
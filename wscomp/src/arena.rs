@@ -0,0 +1,203 @@
+//! Fixed capacity memory arena for DSP buffers
+//!
+//! Cards request delay lines, grain buffers and block buffers from an
+//! [`Arena`] once during init. There is no heap and nothing is ever freed,
+//! the arena only hands out slices until it runs out of room.
+//!
+//! ```
+//! # use wscomp::arena::ArenaStorage;
+//! # use wscomp::Sample;
+//! static ARENA: ArenaStorage<{ 64 * 1024 }> = ArenaStorage::new();
+//!
+//! let mut arena = ARENA.take().expect("arena already taken");
+//! let delay: &'static mut [Sample] = arena
+//!     .alloc(4800, Sample::from(0_i32))
+//!     .expect("not enough RAM for delay line");
+//! ```
+
+use core::cell::UnsafeCell;
+use core::mem::{align_of, size_of, MaybeUninit};
+
+use portable_atomic::{AtomicBool, Ordering};
+
+/// Errors returned when a request doesn't fit in the arena
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ArenaError {
+    /// Not enough bytes left, includes how many were requested and remain
+    OutOfMemory { requested: usize, remaining: usize },
+}
+
+/// Backing storage for an [`Arena`], intended to live in a `static`
+///
+/// The size is a const generic so the linker fails the build if the
+/// storage (plus everything else) doesn't fit in RAM.
+pub struct ArenaStorage<const N: usize> {
+    buffer: UnsafeCell<[MaybeUninit<u8>; N]>,
+    taken: AtomicBool,
+}
+
+// Safety: the buffer is only reachable through `take()`, which hands it out
+// at most once.
+unsafe impl<const N: usize> Sync for ArenaStorage<N> {}
+
+impl<const N: usize> ArenaStorage<N> {
+    pub const fn new() -> Self {
+        ArenaStorage {
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            taken: AtomicBool::new(false),
+        }
+    }
+
+    /// Get the [`Arena`] for this storage, returns `None` after the first call
+    pub fn take(&'static self) -> Option<Arena<'static>> {
+        if self.taken.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        // Safety: guarded by `taken`, so this is the only reference
+        let buffer = unsafe { &mut *self.buffer.get() };
+        Some(Arena::new(buffer))
+    }
+}
+
+impl<const N: usize> Default for ArenaStorage<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bump allocator handing out initialized slices from a byte buffer
+pub struct Arena<'a> {
+    free: &'a mut [MaybeUninit<u8>],
+    used: usize,
+}
+
+impl<'a> Arena<'a> {
+    pub fn new(buffer: &'a mut [MaybeUninit<u8>]) -> Self {
+        Arena {
+            free: buffer,
+            used: 0,
+        }
+    }
+
+    /// Bytes handed out so far, including alignment padding
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Bytes still available (ignoring alignment padding)
+    pub fn remaining(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Allocate a slice of `len` items, each set to `value`
+    pub fn alloc<T: Copy>(&mut self, len: usize, value: T) -> Result<&'a mut [T], ArenaError> {
+        let ptr = self.reserve::<T>(len)?;
+        for i in 0..len {
+            // Safety: reserve() returns room for exactly `len` aligned items
+            unsafe { ptr.add(i).write(value) };
+        }
+        // Safety: all `len` items were initialized above
+        Ok(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    /// Move `value` into the arena, for buffers that aren't `Copy` like a
+    /// [`SampleRing`](crate::ring::SampleRing)
+    pub fn alloc_value<T>(&mut self, value: T) -> Result<&'a mut T, ArenaError> {
+        let ptr = self.reserve::<T>(1)?;
+        // Safety: reserve() returns room for one aligned item
+        unsafe {
+            ptr.write(value);
+            Ok(&mut *ptr)
+        }
+    }
+
+    /// Take room for `len` items of `T` from the front of the free space
+    fn reserve<T>(&mut self, len: usize) -> Result<*mut T, ArenaError> {
+        let free = core::mem::take(&mut self.free);
+        let padding = free.as_ptr().align_offset(align_of::<T>());
+        let bytes = size_of::<T>().checked_mul(len);
+        let needed = bytes.and_then(|b| b.checked_add(padding));
+        let (bytes, needed) = match (bytes, needed) {
+            (Some(bytes), Some(needed)) if needed <= free.len() => (bytes, needed),
+            _ => {
+                let remaining = free.len();
+                self.free = free;
+                return Err(ArenaError::OutOfMemory {
+                    requested: size_of::<T>().saturating_mul(len),
+                    remaining,
+                });
+            }
+        };
+
+        let (_, aligned) = free.split_at_mut(padding);
+        let (chunk, rest) = aligned.split_at_mut(bytes);
+        self.free = rest;
+        self.used += needed;
+        Ok(chunk.as_mut_ptr() as *mut T)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Arena, ArenaError, ArenaStorage};
+    use crate::ring::SampleRing;
+    use crate::Sample;
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn test_arena_alloc() {
+        let mut buffer = [MaybeUninit::uninit(); 64];
+        let mut arena = Arena::new(&mut buffer);
+
+        let a = arena.alloc(4, 7_u8).unwrap();
+        let b = arena.alloc(3, -1_i32).unwrap();
+        assert_eq!(a, &[7, 7, 7, 7]);
+        assert_eq!(b, &[-1, -1, -1]);
+        assert_eq!(b.as_ptr() as usize % core::mem::align_of::<i32>(), 0);
+
+        // both slices are independently writable
+        a[0] = 1;
+        b[2] = 5;
+        assert_eq!(a[0], 1);
+        assert_eq!(b[2], 5);
+        assert!(arena.used() >= 16);
+    }
+
+    #[test]
+    fn test_arena_out_of_memory() {
+        let mut buffer = [MaybeUninit::uninit(); 16];
+        let mut arena = Arena::new(&mut buffer);
+        assert_eq!(
+            arena.alloc(5, 0_u32),
+            Err(ArenaError::OutOfMemory {
+                requested: 20,
+                remaining: 16
+            })
+        );
+        // failed requests don't use up space
+        assert!(arena.alloc(16, 0_u8).is_ok());
+        assert!(arena.alloc(1, 0_u8).is_err());
+    }
+
+    #[test]
+    fn test_arena_storage_take_once() {
+        static STORAGE: ArenaStorage<256> = ArenaStorage::new();
+        let mut arena = STORAGE.take().unwrap();
+        assert!(STORAGE.take().is_none());
+        let samples = arena.alloc(8, Sample::from(0_i32)).unwrap();
+        assert_eq!(samples.len(), 8);
+    }
+
+    #[test]
+    fn test_arena_alloc_value() {
+        static STORAGE: ArenaStorage<256> = ArenaStorage::new();
+        let mut arena = STORAGE.take().unwrap();
+        arena.alloc(1, 0_u8).unwrap();
+        let ring = arena.alloc_value(SampleRing::<32>::new()).unwrap();
+        let (mut producer, mut consumer) = ring.split();
+        assert!(producer.push(1234));
+        assert_eq!(consumer.pop(), Some(1234));
+        assert!(arena.alloc_value(SampleRing::<128>::new()).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! ARMv6-M inner loops for block processing
+//!
+//! The RP2040's Cortex-M0+ multiplies 32 bits by 32 bits into a 32 bit
+//! result and has nothing wider, and no divide instruction, so `i64`
+//! products and divides by a constant become library calls. With the
+//! `armv6m` feature, [`BiquadCascade::process_block`], [`Fir::process_block`]
+//! and the [`SampleBlock`] mix and scale methods run these loops instead,
+//! which:
+//!
+//! - build 32x32 to 64 bit products from four 16 bit multiplies
+//! - run a biquad cascade a stage at a time, with the state in locals
+//! - walk the FIR ring in two straight runs rather than a chained iterator
+//! - divide by [`Sample::MAX`] (2^11 - 1) with shifts
+//!
+//! Results are identical to the portable per sample code, which stays the
+//! reference in the tests below.
+//!
+//! [`BiquadCascade::process_block`]: crate::biquad::BiquadCascade::process_block
+//! [`Fir::process_block`]: crate::fir::Fir::process_block
+//! [`SampleBlock`]: crate::block::SampleBlock
+
+use crate::biquad::{COEFFICIENT_SHIFT, STATE_LIMIT, STATE_SHIFT};
+use crate::Sample;
+
+/// `a * b`, widened to 64 bits from 32 bit multiplies of the 16 bit halves
+#[inline(always)]
+fn mul_wide(a: i32, b: i32) -> i64 {
+    let (a_high, a_low) = (a >> 16, a & 0xffff);
+    let (b_high, b_low) = (b >> 16, b & 0xffff);
+    let high = i64::from(a_high * b_high) << 32;
+    let middle = (i64::from(a_high * b_low) + i64::from(a_low * b_high)) << 16;
+    high + middle + i64::from(a_low as u32 * b_low as u32)
+}
+
+/// `value / Sample::MAX`, truncated like `/`, for products of two clamped
+/// samples
+#[inline(always)]
+fn div_max(value: i32) -> i32 {
+    let plus_one = value.unsigned_abs() + 1;
+    let quotient = ((plus_one + (plus_one >> 11)) >> 11) as i32;
+    if value < 0 {
+        -quotient
+    } else {
+        quotient
+    }
+}
+
+/// One biquad stage over `samples`, see [`Biquad::process`](crate::biquad::Biquad::process)
+pub(crate) fn biquad_block(
+    coefficients: &[i32; 5],
+    inputs: &mut [i64; 2],
+    outputs: &mut [i64; 2],
+    samples: &mut [Sample],
+) {
+    let [b0, b1, b2, a1, a2] = *coefficients;
+    // the state is clamped to STATE_LIMIT, so it fits 32 bits
+    let [mut x1, mut x2] = inputs.map(|input| input as i32);
+    let [mut y1, mut y2] = outputs.map(|output| output as i32);
+    for sample in samples.iter_mut() {
+        let x0 = sample.to_clamped() << STATE_SHIFT;
+        let accumulated = mul_wide(b0, x0) + mul_wide(b1, x1) + mul_wide(b2, x2)
+            - mul_wide(a1, y1)
+            - mul_wide(a2, y2);
+        let y0 = ((accumulated + (1 << (COEFFICIENT_SHIFT - 1))) >> COEFFICIENT_SHIFT)
+            .clamp(-STATE_LIMIT, STATE_LIMIT) as i32;
+        (x2, x1) = (x1, x0);
+        (y2, y1) = (y1, y0);
+
+        let output = (y0 + (1 << (STATE_SHIFT - 1))) >> STATE_SHIFT;
+        *sample = Sample::from(output.clamp(Sample::MIN, Sample::MAX));
+    }
+    *inputs = [x1, x2].map(i64::from);
+    *outputs = [y1, y2].map(i64::from);
+}
+
+/// FIR filter `samples` in place, see [`Fir::process`](crate::fir::Fir::process)
+pub(crate) fn fir_block<const TAPS: usize>(
+    kernel: &[i16; TAPS],
+    history: &mut [i32; TAPS],
+    position: &mut usize,
+    samples: &mut [Sample],
+) {
+    let mut oldest = *position;
+    for sample in samples.iter_mut() {
+        history[oldest] = sample.to_clamped();
+        oldest += 1;
+        if oldest == TAPS {
+            oldest = 0;
+        }
+        // newest input first: back from `oldest` to the start of the ring,
+        // then back from the end, against the rest of the kernel
+        let (recent, older) = history.split_at(oldest);
+        let (recent_taps, older_taps) = kernel.split_at(oldest);
+        let mut sum = 0;
+        for (&input, &coefficient) in recent.iter().rev().zip(recent_taps) {
+            sum += input * i32::from(coefficient);
+        }
+        for (&input, &coefficient) in older.iter().rev().zip(older_taps) {
+            sum += input * i32::from(coefficient);
+        }
+        *sample = Sample::from((sum + (1 << 14)) >> 15);
+    }
+    *position = oldest;
+}
+
+/// `samples[n] += others[n].scale(gain)`, saturating, see
+/// [`SampleBlock::mix_scaled`](crate::block::SampleBlock::mix_scaled)
+pub(crate) fn mix_scaled(samples: &mut [Sample], others: &[Sample], gain: Sample) {
+    let gain = gain.to_clamped();
+    for (sample, other) in samples.iter_mut().zip(others) {
+        let scaled = Sample::new(div_max(other.to_clamped() * gain), other.inverted_source);
+        sample.accumulated_raw = sample
+            .accumulated_raw
+            .saturating_add(scaled.accumulated_raw);
+    }
+}
+
+/// `samples[n] = samples[n].scale(gain)`, see
+/// [`SampleBlock::scale`](crate::block::SampleBlock::scale)
+pub(crate) fn scale(samples: &mut [Sample], gain: Sample) {
+    let gain = gain.to_clamped();
+    for sample in samples.iter_mut() {
+        *sample = Sample::new(div_max(sample.to_clamped() * gain), sample.inverted_source);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{div_max, mul_wide};
+    use crate::biquad::{Biquad, BiquadCascade, BiquadKind};
+    use crate::block::SampleBlock;
+    use crate::fir::{Fir, GENTLE_LOW_PASS, HALF_BAND};
+    use crate::random::Rng;
+    use crate::Sample;
+
+    /// Full scale noise, with a run of each extreme to push the filters to
+    /// their limits
+    fn test_block(rng: &mut Rng) -> SampleBlock<64> {
+        let mut block = SampleBlock::silent();
+        block.map(|_| rng.next_sample());
+        block[8..16].fill(Sample::from(Sample::MAX));
+        block[16..24].fill(Sample::from(Sample::MIN));
+        block
+    }
+
+    #[test]
+    fn test_mul_wide() {
+        let edges = [
+            i32::MIN,
+            i32::MIN + 1,
+            -65_536,
+            -65_535,
+            -1,
+            0,
+            1,
+            65_535,
+            65_536,
+            i32::MAX,
+        ];
+        for a in edges {
+            for b in edges {
+                assert_eq!(mul_wide(a, b), i64::from(a) * i64::from(b), "{a} {b}");
+            }
+        }
+        let mut rng = Rng::new(1754);
+        for _ in 0..10_000 {
+            let (a, b) = (rng.next_u32() as i32, rng.next_u32() as i32);
+            assert_eq!(mul_wide(a, b), i64::from(a) * i64::from(b), "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn test_div_max() {
+        let limit = Sample::MIN * Sample::MIN;
+        for value in -limit..=limit {
+            assert_eq!(div_max(value), value / Sample::MAX, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_biquad_block_matches_portable() {
+        let mut rng = Rng::new(1797);
+        for kind in [
+            BiquadKind::LowPass,
+            BiquadKind::HighPass,
+            BiquadKind::LowShelfCut,
+            BiquadKind::LowShelfBoost,
+            BiquadKind::HighShelfCut,
+            BiquadKind::HighShelfBoost,
+        ] {
+            for cutoff in 0..Biquad::CUTOFFS.len() {
+                let mut portable = BiquadCascade::<3>::new(kind, cutoff);
+                let mut armv6m = portable.clone();
+                for _ in 0..8 {
+                    let mut block = test_block(&mut rng);
+                    let mut expected = block;
+                    expected.map(|sample| portable.process(sample));
+                    armv6m.process_block(&mut block);
+                    assert_eq!(block, expected, "{kind:?} {cutoff}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fir_block_matches_portable() {
+        static KERNEL: [i16; 3] = [32_767, -32_768, 32_767];
+        let mut rng = Rng::new(1829);
+        let mut portable = Fir::new(&HALF_BAND);
+        let mut armv6m = portable.clone();
+        let mut gentle = (Fir::new(&GENTLE_LOW_PASS), Fir::new(&GENTLE_LOW_PASS));
+        let mut extreme = (Fir::new(&KERNEL), Fir::new(&KERNEL));
+        for _ in 0..8 {
+            let mut block = test_block(&mut rng);
+            let mut expected = block;
+            expected.map(|sample| portable.process(sample));
+            armv6m.process_block(&mut block);
+            assert_eq!(block, expected);
+
+            let mut block = test_block(&mut rng);
+            let mut expected = block;
+            expected.map(|sample| gentle.0.process(sample));
+            gentle.1.process_block(&mut block);
+            assert_eq!(block, expected);
+
+            let mut block = test_block(&mut rng);
+            let mut expected = block;
+            expected.map(|sample| extreme.0.process(sample));
+            extreme.1.process_block(&mut block);
+            assert_eq!(block, expected);
+        }
+    }
+
+    #[test]
+    fn test_block_mix_scale_matches_portable() {
+        let mut rng = Rng::new(1754);
+        for gain in [Sample::MIN, -1024, -1, 0, 1, 1000, Sample::MAX] {
+            let gain = Sample::from(gain);
+            let block = test_block(&mut rng);
+            let other = test_block(&mut rng);
+
+            let mut mixed = block;
+            mixed.mix_scaled(&other, gain);
+            for ((sample, other), mixed) in block.iter().zip(other.iter()).zip(mixed.iter()) {
+                let expected = sample.saturating_add(other.scale(gain));
+                assert_eq!(mixed.to_unclamped(), expected.to_unclamped(), "{gain:?}");
+            }
+
+            let mut scaled = block;
+            scaled.scale(gain);
+            for (sample, scaled) in block.iter().zip(scaled.iter()) {
+                assert_eq!(scaled.to_unclamped(), sample.scale(gain).to_unclamped());
+            }
+        }
+        // sums past full scale keep accumulating, like the portable code
+        let mut loud = SampleBlock::<4>::filled(Sample::from(Sample::MAX));
+        loud.mix_scaled(
+            &SampleBlock::filled(Sample::from(Sample::MAX)),
+            Sample::from(Sample::MAX),
+        );
+        assert_eq!(loud[0].to_unclamped(), 2 * Sample::MAX);
+    }
+}
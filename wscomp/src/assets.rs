@@ -0,0 +1,408 @@
+//! Dual bank asset storage with rollback
+//!
+//! Assets (samples, wavetables) live in one of two flash banks. Updates are
+//! written to the inactive bank, and [`Manifest::commit`] only switches to it
+//! once the written data matches the expected CRC, so an interrupted transfer
+//! leaves the previous assets in use. At boot, [`Manifest::validate`] checks
+//! the active bank again and rolls back to the other bank if it's damaged.
+//!
+//! Cards store the [`Manifest`] itself in its own flash sector, see
+//! [`Manifest::to_bytes`].
+//!
+//! Loop points for looping assets travel in a [`LoopManifest`], in the same
+//! format. The host asset tool (`asset_tool` in this repo) finds and checks
+//! them, see [`loops`](crate::loops), and cards pass them on to
+//! [`AdpcmStream::set_loop_points`](crate::adpcm::AdpcmStream::set_loop_points).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AssetError {
+    /// Bank contents don't match the expected CRC
+    CrcMismatch,
+    /// Neither bank holds valid assets
+    NoValidBank,
+    BufferTooSmall,
+    /// Stored manifest is missing or damaged
+    InvalidManifest,
+    /// Asset index past the end of a [`LoopManifest`]
+    UnknownAsset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AssetBank {
+    A,
+    B,
+}
+
+impl AssetBank {
+    pub fn other(&self) -> Self {
+        match self {
+            AssetBank::A => AssetBank::B,
+            AssetBank::B => AssetBank::A,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            AssetBank::A => 0,
+            AssetBank::B => 1,
+        }
+    }
+}
+
+/// Length and CRC of the assets written to a bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BankInfo {
+    pub len: u32,
+    pub crc: u32,
+}
+
+impl BankInfo {
+    /// Do the first `len` bytes of `data` match the CRC?
+    pub fn verify(&self, data: &[u8]) -> bool {
+        data.get(..self.len as usize)
+            .is_some_and(|data| crc32(data) == self.crc)
+    }
+}
+
+/// Bank chosen at boot by [`Manifest::validate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootBank {
+    Active(AssetBank),
+    /// The active bank failed validation, this is the previous one
+    RolledBack(AssetBank),
+}
+
+/// Which bank is active, and what each bank should contain
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Manifest {
+    active: AssetBank,
+    banks: [Option<BankInfo>; 2],
+}
+
+impl Manifest {
+    /// Bytes used by [`Manifest::to_bytes`]
+    pub const SERIALIZED_SIZE: usize = 28;
+    const MARKER: u32 = 0x5753_414d;
+
+    /// Empty manifest, for first boot
+    pub fn new() -> Self {
+        Manifest {
+            active: AssetBank::A,
+            banks: [None; 2],
+        }
+    }
+
+    pub fn active(&self) -> AssetBank {
+        self.active
+    }
+
+    /// Bank the next update should be written to
+    pub fn inactive(&self) -> AssetBank {
+        self.active.other()
+    }
+
+    pub fn bank(&self, bank: AssetBank) -> Option<BankInfo> {
+        self.banks[bank.index()]
+    }
+
+    /// Switch to the inactive bank, once its `data` is checked against `crc`
+    ///
+    /// On a mismatch nothing changes, the active bank stays in use.
+    pub fn commit(&mut self, data: &[u8], crc: u32) -> Result<AssetBank, AssetError> {
+        let info = BankInfo {
+            len: data.len() as u32,
+            crc,
+        };
+        if !info.verify(data) {
+            return Err(AssetError::CrcMismatch);
+        }
+        let bank = self.inactive();
+        self.banks[bank.index()] = Some(info);
+        self.active = bank;
+        Ok(bank)
+    }
+
+    /// Check the active bank at boot, rolling back if it's damaged
+    ///
+    /// `read` gives the flash contents of a bank.
+    pub fn validate<'a>(
+        &mut self,
+        mut read: impl FnMut(AssetBank) -> &'a [u8],
+    ) -> Result<BootBank, AssetError> {
+        let mut valid =
+            |bank: AssetBank| self.banks[bank.index()].is_some_and(|info| info.verify(read(bank)));
+        if valid(self.active) {
+            return Ok(BootBank::Active(self.active));
+        }
+        let previous = self.active.other();
+        if valid(previous) {
+            self.banks[self.active.index()] = None;
+            self.active = previous;
+            return Ok(BootBank::RolledBack(previous));
+        }
+        Err(AssetError::NoValidBank)
+    }
+
+    pub fn to_bytes(&self, buffer: &mut [u8]) -> Result<usize, AssetError> {
+        let size = Self::SERIALIZED_SIZE;
+        if buffer.len() < size {
+            return Err(AssetError::BufferTooSmall);
+        }
+        buffer[..size].fill(0);
+        buffer[0..4].copy_from_slice(&Self::MARKER.to_le_bytes());
+        buffer[4] = self.active.index() as u8;
+        for (index, info) in self.banks.iter().enumerate() {
+            if let Some(info) = info {
+                buffer[5] |= 1 << index;
+                let offset = 8 + 8 * index;
+                buffer[offset..offset + 4].copy_from_slice(&info.len.to_le_bytes());
+                buffer[offset + 4..offset + 8].copy_from_slice(&info.crc.to_le_bytes());
+            }
+        }
+        let crc = crc32(&buffer[..24]);
+        buffer[24..size].copy_from_slice(&crc.to_le_bytes());
+        Ok(size)
+    }
+
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, AssetError> {
+        let buffer = buffer
+            .get(..Self::SERIALIZED_SIZE)
+            .ok_or(AssetError::BufferTooSmall)?;
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            ])
+        };
+        if word(0) != Self::MARKER || word(24) != crc32(&buffer[..24]) {
+            return Err(AssetError::InvalidManifest);
+        }
+        let active = match buffer[4] {
+            0 => AssetBank::A,
+            1 => AssetBank::B,
+            _ => return Err(AssetError::InvalidManifest),
+        };
+        let mut banks = [None; 2];
+        for (index, bank) in banks.iter_mut().enumerate() {
+            if buffer[5] & (1 << index) != 0 {
+                let offset = 8 + 8 * index;
+                *bank = Some(BankInfo {
+                    len: word(offset),
+                    crc: word(offset + 4),
+                });
+            }
+        }
+        Ok(Manifest { active, banks })
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Loop region of an asset, in samples from the start of its audio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoopPoints {
+    /// First sample of the loop
+    pub start: u32,
+    /// Sample after the last one played, playback jumps back to `start` here
+    pub end: u32,
+}
+
+/// Loop points for `N` assets, by asset index
+///
+/// Serialized like [`Manifest`], little endian words between a marker and a
+/// CRC, with a loop of 0 to 0 for assets without loop points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoopManifest<const N: usize> {
+    loops: [Option<LoopPoints>; N],
+}
+
+impl<const N: usize> LoopManifest<N> {
+    /// Bytes used by [`LoopManifest::to_bytes`]
+    pub const SERIALIZED_SIZE: usize = 8 + 8 * N + 4;
+    const MARKER: u32 = 0x504f_4f4c;
+
+    /// No loop points, every asset loops whole
+    pub fn new() -> Self {
+        LoopManifest { loops: [None; N] }
+    }
+
+    pub fn get(&self, asset: usize) -> Option<LoopPoints> {
+        self.loops.get(asset).copied().flatten()
+    }
+
+    pub fn set(&mut self, asset: usize, points: Option<LoopPoints>) -> Result<(), AssetError> {
+        *self.loops.get_mut(asset).ok_or(AssetError::UnknownAsset)? = points;
+        Ok(())
+    }
+
+    pub fn to_bytes(&self, buffer: &mut [u8]) -> Result<usize, AssetError> {
+        let size = Self::SERIALIZED_SIZE;
+        let buffer = buffer.get_mut(..size).ok_or(AssetError::BufferTooSmall)?;
+        buffer[0..4].copy_from_slice(&Self::MARKER.to_le_bytes());
+        buffer[4..8].copy_from_slice(&(N as u32).to_le_bytes());
+        for (index, points) in self.loops.iter().enumerate() {
+            let points = points.unwrap_or(LoopPoints { start: 0, end: 0 });
+            let offset = 8 + 8 * index;
+            buffer[offset..offset + 4].copy_from_slice(&points.start.to_le_bytes());
+            buffer[offset + 4..offset + 8].copy_from_slice(&points.end.to_le_bytes());
+        }
+        let crc = crc32(&buffer[..size - 4]);
+        buffer[size - 4..].copy_from_slice(&crc.to_le_bytes());
+        Ok(size)
+    }
+
+    /// Loop points from bytes written by [`LoopManifest::to_bytes`], for the
+    /// same number of assets
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, AssetError> {
+        let size = Self::SERIALIZED_SIZE;
+        let buffer = buffer.get(..size).ok_or(AssetError::BufferTooSmall)?;
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                buffer[offset],
+                buffer[offset + 1],
+                buffer[offset + 2],
+                buffer[offset + 3],
+            ])
+        };
+        if word(0) != Self::MARKER
+            || word(4) != N as u32
+            || word(size - 4) != crc32(&buffer[..size - 4])
+        {
+            return Err(AssetError::InvalidManifest);
+        }
+        let mut loops = [None; N];
+        for (index, points) in loops.iter_mut().enumerate() {
+            let (start, end) = (word(8 + 8 * index), word(12 + 8 * index));
+            if end > start {
+                *points = Some(LoopPoints { start, end });
+            }
+        }
+        Ok(LoopManifest { loops })
+    }
+}
+
+impl<const N: usize> Default for LoopManifest<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-32 (IEEE, as used by zip and PNG)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::{crc32, AssetBank, AssetError, BootBank, LoopManifest, LoopPoints, Manifest};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_manifest_commit_and_rollback() {
+        let old = b"old assets";
+        let new = b"new assets";
+        let mut flash = [old.as_slice(), new.as_slice()];
+        let mut manifest = Manifest::new();
+        assert_eq!(manifest.commit(old, crc32(old)), Ok(AssetBank::B));
+
+        // interrupted transfer, the bank doesn't match
+        assert_eq!(
+            manifest.commit(b"new ass", crc32(new)),
+            Err(AssetError::CrcMismatch)
+        );
+        assert_eq!(manifest.active(), AssetBank::B);
+        assert_eq!(manifest.commit(new, crc32(new)), Ok(AssetBank::A));
+
+        // round trip through flash
+        let mut buffer = [0_u8; Manifest::SERIALIZED_SIZE];
+        manifest.to_bytes(&mut buffer).unwrap();
+        let mut manifest = Manifest::from_bytes(&buffer).unwrap();
+        buffer[9] ^= 1;
+        assert_eq!(
+            Manifest::from_bytes(&buffer),
+            Err(AssetError::InvalidManifest)
+        );
+
+        // banks: A = new, B = old
+        flash.swap(0, 1);
+        assert_eq!(
+            manifest.validate(|bank| flash[bank as usize]),
+            Ok(BootBank::Active(AssetBank::A))
+        );
+        // new bank damaged after the switch, back to the old one
+        flash[0] = b"new assetz";
+        assert_eq!(
+            manifest.validate(|bank| flash[bank as usize]),
+            Ok(BootBank::RolledBack(AssetBank::B))
+        );
+        assert_eq!(manifest.bank(AssetBank::A), None);
+        flash[1] = b"";
+        assert_eq!(
+            manifest.validate(|bank| flash[bank as usize]),
+            Err(AssetError::NoValidBank)
+        );
+    }
+
+    #[test]
+    fn test_loop_manifest() {
+        let mut loops = LoopManifest::<3>::new();
+        let points = LoopPoints {
+            start: 120,
+            end: 96_000,
+        };
+        loops.set(1, Some(points)).unwrap();
+        assert_eq!(loops.set(3, Some(points)), Err(AssetError::UnknownAsset));
+
+        let mut buffer = [0_u8; LoopManifest::<3>::SERIALIZED_SIZE];
+        assert_eq!(loops.to_bytes(&mut buffer), Ok(36));
+        let read = LoopManifest::<3>::from_bytes(&buffer).unwrap();
+        assert_eq!(read, loops);
+        assert_eq!(read.get(0), None);
+        assert_eq!(read.get(1), Some(points));
+        assert_eq!(read.get(7), None);
+
+        // written for a different number of assets
+        assert_eq!(
+            LoopManifest::<2>::from_bytes(&buffer),
+            Err(AssetError::InvalidManifest)
+        );
+        buffer[16] ^= 1;
+        assert_eq!(
+            LoopManifest::<3>::from_bytes(&buffer),
+            Err(AssetError::InvalidManifest)
+        );
+        assert_eq!(
+            LoopManifest::<3>::from_bytes(&buffer[..20]),
+            Err(AssetError::BufferTooSmall)
+        );
+    }
+}
@@ -0,0 +1,55 @@
+//! Adaptive batch sizes for filling an output buffer
+//!
+//! Rendering audio one sample per loop, as fast as the buffer allows, means
+//! expensive samples (like decoding a new ADPCM block) land whenever the
+//! buffer happens to have room. [`AdaptiveBatch`] picks how many samples to
+//! render before yielding to other tasks, based on how full the buffer is:
+//! big batches when it's running low, small ones when it's nearly full.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdaptiveBatch {
+    min: usize,
+    max: usize,
+}
+
+impl AdaptiveBatch {
+    /// Batches between `min` and `max` samples, `min` is at least 1
+    pub fn new(min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        AdaptiveBatch {
+            min,
+            max: max.max(min),
+        }
+    }
+
+    /// Samples to render, given the buffer's current `len` and `capacity`
+    ///
+    /// Scales linearly from `max` when empty to `min` when full, and never
+    /// more than the free space (unless that's less than `min`).
+    pub fn size(&self, len: usize, capacity: usize) -> usize {
+        if capacity == 0 {
+            return self.min;
+        }
+        let free = capacity.saturating_sub(len);
+        let size = self.min + (self.max - self.min) * free / capacity;
+        size.min(free).max(self.min)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AdaptiveBatch;
+
+    #[test]
+    fn test_adaptive_batch() {
+        let batch = AdaptiveBatch::new(8, 128);
+        assert_eq!(batch.size(0, 1024), 128);
+        assert_eq!(batch.size(512, 1024), 68);
+        assert_eq!(batch.size(1000, 1024), 10);
+        assert_eq!(batch.size(1024, 1024), 8);
+        // never more than fits
+        assert_eq!(batch.size(0, 64), 64);
+        assert_eq!(AdaptiveBatch::new(0, 0).size(0, 1024), 1);
+    }
+}
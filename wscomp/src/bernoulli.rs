@@ -0,0 +1,164 @@
+//! Bernoulli gate, a coin toss for each trigger
+//!
+//! [`BernoulliGate`] routes each incoming trigger to output A or B, with the
+//! chance of B set by a [`Sample`] (usually a knob plus CV):
+//!
+//! ```
+//! # use embedded_hal::digital::{InputPin, OutputPin};
+//! # use wscomp::bernoulli::{BernoulliGate, BernoulliOutput};
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::pulse::{PulseInput, PulseOut};
+//! # use wscomp::random::Rng;
+//! # use wscomp::units::Millis;
+//! # const TRIGGER: Millis = Millis::new(10);
+//! # fn card<I: InputPin, O: OutputPin>(
+//! #     mut pulse_in: PulseInput<I>,
+//! #     mut pulse_out1: PulseOut<O>,
+//! #     mut pulse_out2: PulseOut<O>,
+//! #     mux_state: MuxState,
+//! #     seed: u64,
+//! #     now_micros: u64,
+//! # ) {
+//! let mut gate = BernoulliGate::new(Rng::new(seed));
+//! loop {
+//!     let edge = pulse_in.poll(now_micros);
+//!     match gate.update(edge, mux_state.main_knob) {
+//!         Some(BernoulliOutput::A) => pulse_out1.trigger(TRIGGER, now_micros),
+//!         Some(BernoulliOutput::B) => pulse_out2.trigger(TRIGGER, now_micros),
+//!         None => {}
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::pulse::Edge;
+use crate::random::Rng;
+use crate::Sample;
+
+/// Where a trigger was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BernoulliOutput {
+    A,
+    B,
+}
+
+impl BernoulliOutput {
+    pub fn other(self) -> Self {
+        match self {
+            BernoulliOutput::A => BernoulliOutput::B,
+            BernoulliOutput::B => BernoulliOutput::A,
+        }
+    }
+}
+
+/// What the probability controls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BernoulliMode {
+    /// Chance of each trigger going to B rather than A
+    Route,
+    /// Chance of each trigger going to the other output than the last one,
+    /// so high probabilities alternate and low ones stay put
+    Toggle,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BernoulliGate {
+    rng: Rng,
+    mode: BernoulliMode,
+    last: BernoulliOutput,
+}
+
+impl BernoulliGate {
+    pub fn new(rng: Rng) -> Self {
+        BernoulliGate {
+            rng,
+            mode: BernoulliMode::Route,
+            last: BernoulliOutput::A,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: BernoulliMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> BernoulliMode {
+        self.mode
+    }
+
+    /// Output the last trigger went to
+    pub fn last(&self) -> BernoulliOutput {
+        self.last
+    }
+
+    /// Route one trigger, [`Sample::MIN`] never switches (or picks B),
+    /// [`Sample::MAX`] always does
+    pub fn route(&mut self, probability: Sample) -> BernoulliOutput {
+        let hit = self.rng.chance(probability);
+        self.last = match (self.mode, hit) {
+            (BernoulliMode::Route, true) => BernoulliOutput::B,
+            (BernoulliMode::Route, false) => BernoulliOutput::A,
+            (BernoulliMode::Toggle, true) => self.last.other(),
+            (BernoulliMode::Toggle, false) => self.last,
+        };
+        self.last
+    }
+
+    /// Route a trigger on each rising `edge`
+    pub fn update(&mut self, edge: Option<Edge>, probability: Sample) -> Option<BernoulliOutput> {
+        match edge {
+            Some(Edge::Rising) => Some(self.route(probability)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BernoulliGate, BernoulliMode, BernoulliOutput};
+    use crate::pulse::Edge;
+    use crate::random::Rng;
+    use crate::Sample;
+
+    #[test]
+    fn test_bernoulli_route() {
+        let mut gate = BernoulliGate::new(Rng::new(1));
+        let never = Sample::from(Sample::MIN);
+        let always = Sample::from(Sample::MAX);
+        assert!((0..100).all(|_| gate.route(never) == BernoulliOutput::A));
+        assert!((0..100).all(|_| gate.route(always) == BernoulliOutput::B));
+
+        let even = Sample::from(0_i32);
+        let to_b = (0..1000)
+            .filter(|_| gate.route(even) == BernoulliOutput::B)
+            .count();
+        assert!((400..600).contains(&to_b));
+
+        assert_eq!(gate.update(None, always), None);
+        assert_eq!(gate.update(Some(Edge::Falling), always), None);
+        assert_eq!(
+            gate.update(Some(Edge::Rising), always),
+            Some(BernoulliOutput::B)
+        );
+    }
+
+    #[test]
+    fn test_bernoulli_toggle() {
+        let mut gate = BernoulliGate::new(Rng::new(1));
+        gate.set_mode(BernoulliMode::Toggle);
+        let outputs: [BernoulliOutput; 4] =
+            core::array::from_fn(|_| gate.route(Sample::from(Sample::MAX)));
+        assert_eq!(
+            outputs,
+            [
+                BernoulliOutput::B,
+                BernoulliOutput::A,
+                BernoulliOutput::B,
+                BernoulliOutput::A
+            ]
+        );
+        assert!((0..100).all(|_| gate.route(Sample::from(Sample::MIN)) == BernoulliOutput::A));
+    }
+}
@@ -0,0 +1,341 @@
+//! Biquad filters with precomputed coefficients
+//!
+//! EQ style filtering (low and high pass, shelves) without any trig at
+//! runtime: coefficients for each [`BiquadKind`] at each of
+//! [`Biquad::CUTOFFS`] are tables, worked out ahead of time for 48kHz with
+//! the Audio EQ Cookbook formulas. Chain biquads in a [`BiquadCascade`] for
+//! steeper slopes:
+//!
+//! ```
+//! # use wscomp::biquad::{BiquadCascade, BiquadKind};
+//! # use wscomp::block::SampleBlock;
+//! # let mut block = SampleBlock::<64>::silent();
+//! // 24dB/octave rumble filter
+//! let mut rumble = BiquadCascade::<2>::new(BiquadKind::HighPass, 0);
+//! for sample in block.iter_mut() {
+//!     *sample = rumble.process(*sample);
+//! }
+//! ```
+
+use crate::block::SampleBlock;
+use crate::units::Hertz;
+use crate::Sample;
+
+/// Number of entries in [`Biquad::CUTOFFS`]
+const CUTOFF_COUNT: usize = 9;
+/// Coefficient fraction bits
+pub(crate) const COEFFICIENT_SHIFT: u32 = 28;
+/// State fraction bits, below the [`Sample`] LSB
+pub(crate) const STATE_SHIFT: u32 = 8;
+/// State limit, 8x full scale
+pub(crate) const STATE_LIMIT: i64 = (Sample::OFFSET as i64 * 8) << STATE_SHIFT;
+
+// Coefficients are b0, b1, b2, a1, a2 (normalized so a0 is 1), Q28, one row
+// per cutoff. Shelves have a slope of 1.
+
+/// Butterworth low pass
+const LOW_PASS: [[i32; 5]; CUTOFF_COUNT] = [
+    [4537, 9075, 4537, -533740300, 265322994],
+    [17761, 35522, 17761, -530659584, 262295173],
+    [70237, 140474, 70237, -524449853, 256295344],
+    [274668, 549336, 274668, -512041069, 244704284],
+    [1051227, 2102454, 1051227, -487301911, 223071364],
+    [3865857, 7731714, 3865857, -438353264, 185381237],
+    [13284859, 26569718, 13284859, -343498714, 128202693],
+    [41621193, 83242386, 41621193, -166484771, 64534086],
+    [124863578, 249727157, 124863578, 166484771, 64534086],
+];
+/// Butterworth high pass
+const HIGH_PASS: [[i32; 5]; CUTOFF_COUNT] = [
+    [266874688, -533749375, 266874688, -533740300, 265322994],
+    [265347553, -530695106, 265347553, -530659584, 262295173],
+    [262295163, -524590327, 262295163, -524449853, 256295344],
+    [256295202, -512590405, 256295202, -512041069, 244704284],
+    [244702183, -489404366, 244702183, -487301911, 223071364],
+    [223042489, -446084978, 223042489, -438353264, 185381237],
+    [185034216, -370068431, 185034216, -343498714, 128202693],
+    [124863578, -249727157, 124863578, -166484771, 64534086],
+    [41621193, -83242386, 41621193, 166484771, 64534086],
+];
+/// Low shelf, -6dB
+const LOW_SHELF_CUT: [[i32; 5]; CUTOFF_COUNT] = [
+    [267892677, -533156586, 265276744, -533150199, 264740352],
+    [267359622, -529513909, 262204473, -529488935, 261153613],
+    [266288392, -522208572, 256118210, -522110026, 254069692],
+    [264160721, -507756385, 244366714, -507372687, 240475678],
+    [259970181, -479503786, 222459129, -478047954, 215449686],
+    [251878444, -425669275, 184366855, -420406316, 173072802],
+    [236931534, -328385178, 126623746, -310883439, 112621563],
+    [211446886, -168019839, 60365381, -116369493, 55027158],
+    [170796302, 74041929, 35011899, 213304073, 76634889],
+];
+/// Low shelf, +6dB
+const LOW_SHELF_BOOST: [[i32; 5]; CUTOFF_COUNT] = [
+    [268979335, -534230418, 265276744, -534236818, 265814223],
+    [269515619, -531619555, 262204474, -531644630, 263259562],
+    [270599832, -526319761, 256118237, -526419102, 258183272],
+    [272779366, -515583157, 244367133, -515973065, 248321136],
+    [277176381, -493614383, 222465263, -495117620, 229702950],
+    [286080829, -448041362, 184449593, -453650277, 196486051],
+    [304128339, -352220476, 127596441, -372049357, 143460443],
+    [340783425, -147733071, 69857922, -213304073, 76634889],
+    [421892004, 335243653, 120444770, 116369493, 55027158],
+];
+/// High shelf, -6dB
+const HIGH_SHELF_CUT: [[i32; 5]; CUTOFF_COUNT] = [
+    [134809009, -267749465, 132953317, -534236818, 265814223],
+    [135077787, -266440934, 131413535, -531644630, 263259562],
+    [135621181, -263784745, 128363191, -526419102, 258183272],
+    [136713536, -258403696, 122473688, -515973065, 248321136],
+    [138917264, -247393227, 111496750, -495117620, 229702950],
+    [143380059, -224552611, 92443781, -453650277, 196486051],
+    [152425241, -176528406, 63949707, -372049357, 143460443],
+    [170796302, -74041929, 35011899, -213304073, 76634889],
+    [211446886, 168019839, 60365381, 116369493, 55027158],
+];
+/// High shelf, +6dB
+const HIGH_SHELF_BOOST: [[i32; 5]; CUTOFF_COUNT] = [
+    [534516163, -1063787244, 529296690, -533150199, 264740352],
+    [533452579, -1056519148, 523166703, -529488935, 261153613],
+    [531315194, -1041943084, 511023012, -522110026, 254069692],
+    [527069932, -1013107181, 487575696, -507372687, 240475678],
+    [518708706, -956735834, 443864316, -478047954, 215449686],
+    [502563567, -849321863, 367860238, -420406316, 173072802],
+    [472740562, -655214570, 252647588, -310883439, 112621563],
+    [421892004, -335243653, 120444770, -116369493, 55027158],
+    [340783425, 147733071, 69857922, 213304073, 76634889],
+];
+
+/// Filter response, see [`Biquad::CUTOFFS`] for the frequencies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BiquadKind {
+    LowPass,
+    HighPass,
+    LowShelfCut,
+    LowShelfBoost,
+    HighShelfCut,
+    HighShelfBoost,
+}
+
+impl BiquadKind {
+    fn table(&self) -> &'static [[i32; 5]; CUTOFF_COUNT] {
+        match self {
+            BiquadKind::LowPass => &LOW_PASS,
+            BiquadKind::HighPass => &HIGH_PASS,
+            BiquadKind::LowShelfCut => &LOW_SHELF_CUT,
+            BiquadKind::LowShelfBoost => &LOW_SHELF_BOOST,
+            BiquadKind::HighShelfCut => &HIGH_SHELF_CUT,
+            BiquadKind::HighShelfBoost => &HIGH_SHELF_BOOST,
+        }
+    }
+}
+
+/// Second order filter, direct form I, for audio at 48kHz
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Biquad {
+    coefficients: [i32; 5],
+    /// Last two inputs and outputs, in samples shifted up by STATE_SHIFT
+    inputs: [i64; 2],
+    outputs: [i64; 2],
+}
+
+impl Biquad {
+    /// Cutoff (or shelf midpoint) frequencies with coefficient tables, an
+    /// octave apart
+    pub const CUTOFFS: [Hertz; CUTOFF_COUNT] = [
+        Hertz::new(63),
+        Hertz::new(125),
+        Hertz::new(250),
+        Hertz::new(500),
+        Hertz::new(1_000),
+        Hertz::new(2_000),
+        Hertz::new(4_000),
+        Hertz::new(8_000),
+        Hertz::new(16_000),
+    ];
+
+    /// Filter at [`Biquad::CUTOFFS`]`[cutoff]`, clamped to the last entry
+    pub fn new(kind: BiquadKind, cutoff: usize) -> Self {
+        let mut filter = Self::from_coefficients([0, 0, 0, 0, 0]);
+        filter.set(kind, cutoff);
+        filter
+    }
+
+    /// Filter from custom coefficients: b0, b1, b2, a1, a2 in Q28, with a0
+    /// normalized to 1
+    pub fn from_coefficients(coefficients: [i32; 5]) -> Self {
+        Biquad {
+            coefficients,
+            inputs: [0; 2],
+            outputs: [0; 2],
+        }
+    }
+
+    /// Change response, keeping state so there's no click
+    pub fn set(&mut self, kind: BiquadKind, cutoff: usize) {
+        self.coefficients = kind.table()[cutoff.min(CUTOFF_COUNT - 1)];
+    }
+
+    /// Index of the entry in [`Biquad::CUTOFFS`] closest to `frequency`,
+    /// in octaves
+    pub fn nearest_cutoff(frequency: Hertz) -> usize {
+        Self::CUTOFFS
+            .iter()
+            .position(|cutoff| {
+                // past the geometric midpoint to the next cutoff, ~1.41x
+                u64::from(frequency.millihertz()) * 1000 < u64::from(cutoff.millihertz()) * 1414
+            })
+            .unwrap_or(CUTOFF_COUNT - 1)
+    }
+
+    /// Filter one sample
+    pub fn process(&mut self, input: Sample) -> Sample {
+        let [b0, b1, b2, a1, a2] = self.coefficients.map(i64::from);
+        let input = i64::from(input.to_clamped()) << STATE_SHIFT;
+        let accumulated = b0 * input + b1 * self.inputs[0] + b2 * self.inputs[1]
+            - a1 * self.outputs[0]
+            - a2 * self.outputs[1];
+        // rounded, truncating would bias the output, and low cutoffs have
+        // a DC gain of thousands for that bias
+        let output = ((accumulated + (1 << (COEFFICIENT_SHIFT - 1))) >> COEFFICIENT_SHIFT)
+            .clamp(-STATE_LIMIT, STATE_LIMIT);
+        self.inputs = [input, self.inputs[0]];
+        self.outputs = [output, self.outputs[0]];
+
+        let output = (output + (1 << (STATE_SHIFT - 1))) >> STATE_SHIFT;
+        Sample::from(output.clamp(Sample::MIN.into(), Sample::MAX.into()) as i32)
+    }
+
+    /// Clear the filter state
+    pub fn reset(&mut self) {
+        self.inputs = [0; 2];
+        self.outputs = [0; 2];
+    }
+}
+
+/// `N` biquads in series, each adds 12dB/octave to a low or high pass
+///
+/// Identical Butterworth stages give a slightly softer knee than a true
+/// higher order Butterworth, about -6dB at the cutoff for two stages.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BiquadCascade<const N: usize> {
+    stages: [Biquad; N],
+}
+
+impl<const N: usize> BiquadCascade<N> {
+    /// `N` identical stages
+    pub fn new(kind: BiquadKind, cutoff: usize) -> Self {
+        BiquadCascade {
+            stages: core::array::from_fn(|_| Biquad::new(kind, cutoff)),
+        }
+    }
+
+    /// Stages with different responses, for example a low pass and a shelf
+    pub fn from_stages(stages: [Biquad; N]) -> Self {
+        BiquadCascade { stages }
+    }
+
+    /// Change every stage's response
+    pub fn set(&mut self, kind: BiquadKind, cutoff: usize) {
+        for stage in self.stages.iter_mut() {
+            stage.set(kind, cutoff);
+        }
+    }
+
+    pub fn stages_mut(&mut self) -> &mut [Biquad; N] {
+        &mut self.stages
+    }
+
+    pub fn process(&mut self, input: Sample) -> Sample {
+        self.stages
+            .iter_mut()
+            .fold(input, |sample, stage| stage.process(sample))
+    }
+
+    /// Filter every sample in a block in place
+    pub fn process_block<const M: usize>(&mut self, block: &mut SampleBlock<M>) {
+        #[cfg(feature = "armv6m")]
+        for stage in self.stages.iter_mut() {
+            crate::armv6m::biquad_block(
+                &stage.coefficients,
+                &mut stage.inputs,
+                &mut stage.outputs,
+                block,
+            );
+        }
+        #[cfg(not(feature = "armv6m"))]
+        block.map(|sample| self.process(sample));
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Biquad, BiquadCascade, BiquadKind};
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    /// Settled peak output for a 1000 amplitude sine at `frequency`
+    fn peak(mut process: impl FnMut(Sample) -> Sample, frequency: u32) -> i32 {
+        let period = 48_000.0 / f64::from(frequency);
+        let samples = (period * 40.0) as usize;
+        (0..samples)
+            .map(|index| {
+                let angle = 2.0 * core::f64::consts::PI * index as f64 / period;
+                process(Sample::from((angle.sin() * 1000.0) as i32)).to_clamped()
+            })
+            .skip(samples * 3 / 4)
+            .map(i32::abs)
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_biquad_tables() {
+        let response = |kind, cutoff, frequency| {
+            let mut filter = Biquad::new(kind, cutoff);
+            peak(|sample| filter.process(sample), frequency)
+        };
+        // 1kHz cutoff: passband, -3dB at the cutoff, 12dB/octave beyond
+        assert!(response(BiquadKind::LowPass, 4, 100) > 990);
+        assert!((690..730).contains(&response(BiquadKind::LowPass, 4, 1000)));
+        assert!((230..260).contains(&response(BiquadKind::LowPass, 4, 2000)));
+        assert!(response(BiquadKind::HighPass, 4, 10_000) > 990);
+        assert!(response(BiquadKind::HighPass, 4, 250) < 80);
+        // the lowest cutoff keeps its precision
+        assert!(response(BiquadKind::HighPass, 0, 16) < 80);
+        // +-6dB shelves, half way at the midpoint
+        assert!((1960..2048).contains(&response(BiquadKind::LowShelfBoost, 4, 63)));
+        assert!((480..520).contains(&response(BiquadKind::HighShelfCut, 4, 12_000)));
+        assert!((1370..1450).contains(&response(BiquadKind::HighShelfBoost, 4, 1000)));
+
+        assert_eq!(Biquad::nearest_cutoff(Hertz::new(1200)), 4);
+        assert_eq!(Biquad::nearest_cutoff(Hertz::new(1500)), 5);
+        assert_eq!(Biquad::nearest_cutoff(Hertz::new(10)), 0);
+        assert_eq!(Biquad::nearest_cutoff(Hertz::new(20_000)), 8);
+    }
+
+    #[test]
+    fn test_biquad_cascade() {
+        let mut cascade = BiquadCascade::<2>::new(BiquadKind::LowPass, 4);
+        // about 24dB/octave above the cutoff
+        assert!(peak(|sample| cascade.process(sample), 2000) < 80);
+        cascade.reset();
+        assert!((470..530).contains(&peak(|sample| cascade.process(sample), 1000)));
+
+        let mut eq = BiquadCascade::from_stages([
+            Biquad::new(BiquadKind::HighPass, 0),
+            Biquad::new(BiquadKind::HighShelfCut, 6),
+        ]);
+        assert!(peak(|sample| eq.process(sample), 20_000) < 550);
+        assert!(peak(|sample| eq.process(sample), 500) > 950);
+    }
+}
@@ -0,0 +1,180 @@
+//! Fixed size blocks of samples, for processing audio a block at a time
+//!
+//! Per sample work (a channel send, a function call through a trait object)
+//! adds up at 48kHz. A [`SampleBlock`] lets a task render, process and hand
+//! over `N` samples at once:
+//!
+//! ```
+//! # use wscomp::block::SampleBlock;
+//! # use wscomp::noise::PinkNoise;
+//! # use wscomp::Sample;
+//! # struct Channel;
+//! # impl Channel {
+//! #     async fn send(&self, _block: SampleBlock<64>) {}
+//! # }
+//! # static AUDIO_BLOCKS: Channel = Channel;
+//! # let mut light_stream = PinkNoise::new(1);
+//! # let heavy_block = SampleBlock::<64>::filled(Sample::from(100_i32));
+//! # let (intensity, volume) = (Sample::from(1000_i32), Sample::from(2000_i32));
+//! # embassy_futures::block_on(async {
+//! let mut block = SampleBlock::<64>::silent();
+//! block.fill_from(&mut light_stream);
+//! block.mix_scaled(&heavy_block, intensity);
+//! block.map(|sample| sample.attenuvert(volume));
+//! AUDIO_BLOCKS.send(block).await;
+//! # });
+//! ```
+//!
+//! Blocks deref to `[Sample]`, so they work with [`graph`](crate::graph)
+//! nodes and anything else that takes a slice.
+
+use core::ops::{Deref, DerefMut};
+
+use crate::Sample;
+
+/// `N` samples, copied by value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SampleBlock<const N: usize> {
+    samples: [Sample; N],
+}
+
+impl<const N: usize> SampleBlock<N> {
+    /// Block of 0v samples
+    pub fn silent() -> Self {
+        Self::filled(Sample::from(0_i32))
+    }
+
+    /// Block with every sample set to `value`
+    pub fn filled(value: Sample) -> Self {
+        SampleBlock {
+            samples: [value; N],
+        }
+    }
+
+    pub fn from_array(samples: [Sample; N]) -> Self {
+        SampleBlock { samples }
+    }
+
+    pub fn into_array(self) -> [Sample; N] {
+        self.samples
+    }
+
+    /// Set every sample to `value`
+    pub fn fill(&mut self, value: Sample) {
+        self.samples.fill(value);
+    }
+
+    /// Fill from an iterator, returns how many samples it gave
+    ///
+    /// Samples after the iterator runs out are set to 0v.
+    pub fn fill_from(&mut self, source: &mut impl Iterator<Item = Sample>) -> usize {
+        let mut filled = 0;
+        for sample in self.samples.iter_mut() {
+            match source.next() {
+                Some(value) => {
+                    *sample = value;
+                    filled += 1;
+                }
+                None => *sample = Sample::from(0_i32),
+            }
+        }
+        filled
+    }
+
+    /// Replace each sample with `f(sample)`
+    pub fn map(&mut self, mut f: impl FnMut(Sample) -> Sample) {
+        for sample in self.samples.iter_mut() {
+            *sample = f(*sample);
+        }
+    }
+
+    /// Sum another block onto this one
+    ///
+    /// Uses [`Sample::saturating_add`], so the sum may be out of range until
+    /// it's clamped on output, like any [`Sample`] accumulation.
+    pub fn mix(&mut self, other: &Self) {
+        for (sample, other) in self.samples.iter_mut().zip(other.samples.iter()) {
+            *sample = sample.saturating_add(*other);
+        }
+    }
+
+    /// Sum another block onto this one, scaled by `gain` (see [`Sample::scale`])
+    pub fn mix_scaled(&mut self, other: &Self, gain: Sample) {
+        #[cfg(feature = "armv6m")]
+        crate::armv6m::mix_scaled(&mut self.samples, &other.samples, gain);
+        #[cfg(not(feature = "armv6m"))]
+        for (sample, other) in self.samples.iter_mut().zip(other.samples.iter()) {
+            *sample = sample.saturating_add(other.scale(gain));
+        }
+    }
+
+    /// Scale every sample by `gain` (see [`Sample::scale`])
+    pub fn scale(&mut self, gain: Sample) {
+        #[cfg(feature = "armv6m")]
+        crate::armv6m::scale(&mut self.samples, gain);
+        #[cfg(not(feature = "armv6m"))]
+        self.map(|sample| sample.scale(gain));
+    }
+
+    /// Largest absolute clamped value in the block
+    pub fn peak(&self) -> Sample {
+        self.samples
+            .iter()
+            .map(|sample| sample.abs())
+            .max()
+            .unwrap_or(Sample::from(0_i32))
+    }
+}
+
+impl<const N: usize> Default for SampleBlock<N> {
+    fn default() -> Self {
+        Self::silent()
+    }
+}
+
+impl<const N: usize> Deref for SampleBlock<N> {
+    type Target = [Sample];
+
+    fn deref(&self) -> &[Sample] {
+        &self.samples
+    }
+}
+
+impl<const N: usize> DerefMut for SampleBlock<N> {
+    fn deref_mut(&mut self) -> &mut [Sample] {
+        &mut self.samples
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SampleBlock;
+    use crate::Sample;
+
+    #[test]
+    fn test_sample_block() {
+        let mut block = SampleBlock::<4>::silent();
+        let mut ramp = (1..=3).map(|value| Sample::from(value * 100));
+        assert_eq!(block.fill_from(&mut ramp), 3);
+        assert_eq!(block.into_array(), [100_i32, 200, 300, 0].map(Sample::from));
+
+        block.mix(&SampleBlock::filled(Sample::from(50_i32)));
+        assert_eq!(block[0], Sample::from(150_i32));
+        block.mix_scaled(
+            &SampleBlock::filled(Sample::from(1000_i32)),
+            Sample::from(-1024_i32),
+        );
+        assert_eq!(block[3], Sample::from(-450_i32));
+        assert_eq!(block.peak(), Sample::from(450_i32));
+
+        block.map(|sample| sample * 2);
+        assert_eq!(block[1], Sample::from(-500_i32));
+        // sums past full scale clamp on output
+        block.fill(Sample::from(Sample::MAX));
+        block.mix(&SampleBlock::filled(Sample::from(Sample::MAX)));
+        assert_eq!(block[2].to_clamped(), Sample::MAX);
+        block.scale(Sample::from(0_i32));
+        assert_eq!(block, SampleBlock::silent());
+    }
+}
@@ -0,0 +1,81 @@
+//! Workshop System Computer pin map
+//!
+//! GPIO numbers and PWM slices for every jack, knob and LED, so cards don't
+//! have to rediscover them from the schematic. `wscomp_rp::Computer::init`
+//! sets all of these up on embassy-rp, otherwise cards take the matching
+//! peripherals (`p.PIN_23`, `p.PWM_SLICE3`, ...) and wrap them in the wscomp
+//! drivers:
+//!
+//! ```text
+//! knobs, switch, CV in   InputReader  (ADC, PROBE, MUX_LOGIC_A/B)
+//! pulse in               PulseInput   (inverted)
+//! pulse out              PulseOut     (inverted)
+//! LEDs                   Leds         (PWM, leds::PWM_TOP)
+//! CV out                 CvOut        (PWM, cv::pwm_top)
+//! audio out              Dac          (SPI0 + DAC_CS)
+//! calibration EEPROM     Eeprom       (I2C0)
+//! ```
+
+/// Normalization probe, drives a known signal into unplugged jacks
+pub const PROBE: u8 = 4;
+/// Mux select lines, see [`inputs`](crate::inputs)
+pub const MUX_LOGIC_A: u8 = 24;
+pub const MUX_LOGIC_B: u8 = 25;
+
+/// ADC inputs, the audio inputs are used directly for CV by some cards
+pub const AUDIO_IN_1: u8 = 27;
+pub const AUDIO_IN_2: u8 = 26;
+/// Muxed knobs, switch and CV inputs
+pub const MUX_IO_1: u8 = 28;
+pub const MUX_IO_2: u8 = 29;
+
+/// Inverted, the pin reads low while the pulse is high
+pub const PULSE_IN_1: u8 = 2;
+pub const PULSE_IN_2: u8 = 3;
+/// Inverted, driving the pin low sends the pulse high
+pub const PULSE_OUT_1: u8 = 8;
+pub const PULSE_OUT_2: u8 = 9;
+
+/// LEDs 1 to 6, in pairs on PWM slices 5, 6 and 7 (A then B)
+pub const LEDS: [u8; 6] = [10, 11, 12, 13, 14, 15];
+pub const LED_PWM_SLICES: [u8; 3] = [5, 6, 7];
+
+/// CV outputs, both on PWM slice 3. CV out 2 is channel A.
+pub const CV_OUT_1: u8 = 23;
+pub const CV_OUT_2: u8 = 22;
+pub const CV_PWM_SLICE: u8 = 3;
+
+/// MCP4822 audio DAC on SPI0
+pub const DAC_SCK: u8 = 18;
+pub const DAC_MOSI: u8 = 19;
+pub const DAC_CS: u8 = 21;
+
+/// Calibration EEPROM on I2C0, see [`eeprom`](crate::eeprom)
+pub const EEPROM_SDA: u8 = 16;
+pub const EEPROM_SCL: u8 = 17;
+
+/// PWM slice for a GPIO, from the RP2040 datasheet
+pub const fn pwm_slice(gpio: u8) -> u8 {
+    (gpio >> 1) & 7
+}
+
+/// PWM channel for a GPIO, `false` for A and `true` for B
+pub const fn pwm_channel_b(gpio: u8) -> bool {
+    gpio & 1 == 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pwm_channel_b, pwm_slice, CV_OUT_1, CV_OUT_2, CV_PWM_SLICE, LEDS, LED_PWM_SLICES};
+
+    #[test]
+    fn test_pwm_slices_match_pins() {
+        assert_eq!(pwm_slice(CV_OUT_1), CV_PWM_SLICE);
+        assert_eq!(pwm_slice(CV_OUT_2), CV_PWM_SLICE);
+        assert!(!pwm_channel_b(CV_OUT_2));
+        for (index, led) in LEDS.iter().enumerate() {
+            assert_eq!(pwm_slice(*led), LED_PWM_SLICES[index / 2]);
+            assert_eq!(pwm_channel_b(*led), index % 2 == 1);
+        }
+    }
+}
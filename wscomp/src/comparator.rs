@@ -0,0 +1,167 @@
+//! Comparators, for turning CV into gates
+//!
+//! [`Comparator`] goes high above a threshold, [`WindowComparator`] while
+//! the input is between two. Both have hysteresis, so a slow or noisy input
+//! crossing a threshold gives one clean edge instead of a burst:
+//!
+//! ```
+//! # use embedded_hal::digital::OutputPin;
+//! # use wscomp::comparator::WindowComparator;
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::pulse::PulseOut;
+//! # use wscomp::Sample;
+//! # fn card<O: OutputPin>(mut pulse_out: PulseOut<O>, mux_state: MuxState) {
+//! let mut window = WindowComparator::new(Sample::from(-500_i32), Sample::from(500_i32));
+//! loop {
+//!     window.set_thresholds(mux_state.x_knob, mux_state.y_knob);
+//!     pulse_out.gate(window.update(mux_state.cv1.value()));
+//! }
+//! # }
+//! ```
+
+use crate::Sample;
+
+/// Gate high while the input is above a threshold
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Comparator {
+    threshold: i32,
+    hysteresis: i32,
+    high: bool,
+}
+
+impl Comparator {
+    /// Default counts the input has to drop below the threshold to go low
+    pub const HYSTERESIS: i32 = 16;
+
+    pub fn new(threshold: Sample) -> Self {
+        Comparator {
+            threshold: threshold.to_clamped(),
+            hysteresis: Self::HYSTERESIS,
+            high: false,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold: Sample) {
+        self.threshold = threshold.to_clamped();
+    }
+
+    pub fn threshold(&self) -> Sample {
+        Sample::from(self.threshold)
+    }
+
+    /// Counts below the threshold the input has to go to switch low again
+    pub fn set_hysteresis(&mut self, hysteresis: i32) {
+        self.hysteresis = hysteresis.max(0);
+    }
+
+    /// Compare one input, returns the gate
+    pub fn update(&mut self, input: Sample) -> bool {
+        let value = input.to_clamped();
+        if value > self.threshold {
+            self.high = true;
+        } else if value < self.threshold - self.hysteresis {
+            self.high = false;
+        }
+        self.high
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+}
+
+/// Gate high while the input is between a low and a high threshold
+///
+/// The thresholds can come straight from two knobs, in either order.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WindowComparator {
+    /// High above the bottom of the window
+    above_low: Comparator,
+    /// High above the top of the window
+    above_high: Comparator,
+}
+
+impl WindowComparator {
+    pub fn new(low: Sample, high: Sample) -> Self {
+        let mut window = WindowComparator {
+            above_low: Comparator::new(low),
+            above_high: Comparator::new(high),
+        };
+        window.set_thresholds(low, high);
+        window
+    }
+
+    /// Set both edges of the window, swapped if `low` is above `high`
+    pub fn set_thresholds(&mut self, low: Sample, high: Sample) {
+        let (low, high) = if low.to_clamped() > high.to_clamped() {
+            (high, low)
+        } else {
+            (low, high)
+        };
+        self.above_low.set_threshold(low);
+        self.above_high.set_threshold(high);
+    }
+
+    /// Bottom and top of the window
+    pub fn thresholds(&self) -> (Sample, Sample) {
+        (self.above_low.threshold(), self.above_high.threshold())
+    }
+
+    /// Counts the input has to move back past either edge to switch again
+    pub fn set_hysteresis(&mut self, hysteresis: i32) {
+        self.above_low.set_hysteresis(hysteresis);
+        self.above_high.set_hysteresis(hysteresis);
+    }
+
+    /// Compare one input, returns the gate
+    pub fn update(&mut self, input: Sample) -> bool {
+        self.above_low.update(input);
+        self.above_high.update(input);
+        self.is_inside()
+    }
+
+    /// True while the input is inside the window
+    pub fn is_inside(&self) -> bool {
+        self.above_low.is_high() && !self.above_high.is_high()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Comparator, WindowComparator};
+    use crate::Sample;
+
+    #[test]
+    fn test_comparator() {
+        let mut comparator = Comparator::new(Sample::from(100_i32));
+        assert!(!comparator.update(Sample::from(100_i32)));
+        assert!(comparator.update(Sample::from(101_i32)));
+        // noise just under the threshold doesn't retrigger
+        assert!(comparator.update(Sample::from(90_i32)));
+        assert!(!comparator.update(Sample::from(83_i32)));
+
+        comparator.set_hysteresis(0);
+        assert!(comparator.update(Sample::from(101_i32)));
+        assert!(!comparator.update(Sample::from(99_i32)));
+    }
+
+    #[test]
+    fn test_window_comparator() {
+        // knobs the wrong way round still make a window
+        let mut window = WindowComparator::new(Sample::from(500_i32), Sample::from(-500_i32));
+        assert_eq!(
+            window.thresholds(),
+            (Sample::from(-500_i32), Sample::from(500_i32))
+        );
+        let gates: Vec<bool> = [-1000, -400, 0, 499, 600, 490, 480, 0, -510, -520]
+            .into_iter()
+            .map(|input| window.update(Sample::from(input)))
+            .collect();
+        assert_eq!(
+            gates,
+            [false, true, true, true, false, false, true, true, true, false]
+        );
+    }
+}
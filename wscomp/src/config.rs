@@ -0,0 +1,246 @@
+//! Host configuration protocol, for USB or a web UI
+//!
+//! The host sends a [`Request`] and the card answers each one with a
+//! [`Response`]. Both are [`MESSAGE_LEN`] bytes, so every message fits one
+//! USB packet, laid out little endian as:
+//!
+//! | byte | request                 | response                        |
+//! |------|-------------------------|---------------------------------|
+//! | 0    | command                 | kind, errors have the top bit   |
+//! | 1    | source                  | source                          |
+//! | 2-3  | destination [`ParamId`] | destination [`ParamId`]         |
+//! | 4-5  | depth, `i16`            | depth, `i16`                    |
+//!
+//! Commands are 1 to get a depth, 2 to set one and 3 to clear every
+//! routing. Responses are 1 for a depth, after a get or set, 3 for cleared
+//! and `0x80` plus the [`ConfigError`] for a failure. Unused fields are
+//! zero. Parameters are always [`ParamId`]s, see [`params`](crate::params).
+//! Cards pass decoded requests to
+//! [`ModMatrix::configure`](crate::modmatrix::ModMatrix::configure):
+//!
+//! ```
+//! # use wscomp::config::{Request, Response};
+//! # use wscomp::modmatrix::ModMatrix;
+//! # use wscomp::params::{ParamId, ParamInfo};
+//! # const PARAMS: &[ParamInfo] = &[ParamInfo::new(ParamId(1), 0, 100, 50)];
+//! # let mut matrix = ModMatrix::<2, 1>::new(PARAMS, [ParamId(1)]).unwrap();
+//! # let packet = [3, 0, 0, 0, 0, 0];
+//! let response = match Request::decode(&packet) {
+//!     Ok(request) => matrix.configure(request),
+//!     Err(error) => Response::Error(error),
+//! };
+//! let reply = response.encode();
+//! # assert_eq!(reply, [3, 0, 0, 0, 0, 0]);
+//! ```
+
+use crate::params::ParamId;
+use crate::Sample;
+
+/// Length of every request and response
+pub const MESSAGE_LEN: usize = 6;
+
+const GET_DEPTH: u8 = 1;
+const SET_DEPTH: u8 = 2;
+const CLEAR_ROUTES: u8 = 3;
+/// Set in a response's first byte for an error
+const ERROR: u8 = 0x80;
+
+/// Why a request failed, the low bits of its response's first byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ConfigError {
+    /// Not [`MESSAGE_LEN`] bytes
+    BadLength = 1,
+    UnknownCommand = 2,
+    SourceOutOfRange = 3,
+    UnknownDestination = 4,
+}
+
+impl ConfigError {
+    fn from_code(code: u8) -> Option<Self> {
+        [
+            ConfigError::BadLength,
+            ConfigError::UnknownCommand,
+            ConfigError::SourceOutOfRange,
+            ConfigError::UnknownDestination,
+        ]
+        .into_iter()
+        .find(|error| *error as u8 == code)
+    }
+}
+
+/// Modulation routing command from the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request {
+    /// Read a routing's depth
+    GetDepth { source: u8, destination: ParamId },
+    /// Route a source to a destination, a depth of 0 removes the routing
+    SetDepth {
+        source: u8,
+        destination: ParamId,
+        depth: Sample,
+    },
+    /// Remove every routing
+    ClearRoutes,
+}
+
+impl Request {
+    pub fn decode(bytes: &[u8]) -> Result<Self, ConfigError> {
+        let bytes: &[u8; MESSAGE_LEN] = bytes.try_into().map_err(|_| ConfigError::BadLength)?;
+        let (source, destination, value) = fields(bytes);
+        match bytes[0] {
+            GET_DEPTH => Ok(Request::GetDepth {
+                source,
+                destination,
+            }),
+            SET_DEPTH => Ok(Request::SetDepth {
+                source,
+                destination,
+                depth: Sample::from(value),
+            }),
+            CLEAR_ROUTES => Ok(Request::ClearRoutes),
+            _ => Err(ConfigError::UnknownCommand),
+        }
+    }
+
+    /// Bytes to send, for host tools
+    pub fn encode(&self) -> [u8; MESSAGE_LEN] {
+        match *self {
+            Request::GetDepth {
+                source,
+                destination,
+            } => message(GET_DEPTH, source, destination, 0),
+            Request::SetDepth {
+                source,
+                destination,
+                depth,
+            } => message(SET_DEPTH, source, destination, depth.to_clamped() as i16),
+            Request::ClearRoutes => message(CLEAR_ROUTES, 0, ParamId(0), 0),
+        }
+    }
+}
+
+/// The card's answer to a [`Request`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Response {
+    /// A routing's depth, after a get or set
+    Depth {
+        source: u8,
+        destination: ParamId,
+        depth: Sample,
+    },
+    /// Every routing removed
+    Cleared,
+    Error(ConfigError),
+}
+
+impl Response {
+    /// Bytes to send back to the host
+    pub fn encode(&self) -> [u8; MESSAGE_LEN] {
+        match *self {
+            Response::Depth {
+                source,
+                destination,
+                depth,
+            } => message(GET_DEPTH, source, destination, depth.to_clamped() as i16),
+            Response::Cleared => message(CLEAR_ROUTES, 0, ParamId(0), 0),
+            Response::Error(error) => message(ERROR | error as u8, 0, ParamId(0), 0),
+        }
+    }
+
+    /// Response from the card's bytes, for host tools
+    pub fn decode(bytes: &[u8]) -> Result<Self, ConfigError> {
+        let bytes: &[u8; MESSAGE_LEN] = bytes.try_into().map_err(|_| ConfigError::BadLength)?;
+        let (source, destination, value) = fields(bytes);
+        match bytes[0] {
+            GET_DEPTH => Ok(Response::Depth {
+                source,
+                destination,
+                depth: Sample::from(value),
+            }),
+            CLEAR_ROUTES => Ok(Response::Cleared),
+            kind if kind & ERROR != 0 => ConfigError::from_code(kind & !ERROR)
+                .map(Response::Error)
+                .ok_or(ConfigError::UnknownCommand),
+            _ => Err(ConfigError::UnknownCommand),
+        }
+    }
+}
+
+fn message(kind: u8, source: u8, destination: ParamId, value: i16) -> [u8; MESSAGE_LEN] {
+    let [id_low, id_high] = destination.0.to_le_bytes();
+    let [value_low, value_high] = value.to_le_bytes();
+    [kind, source, id_low, id_high, value_low, value_high]
+}
+
+fn fields(bytes: &[u8; MESSAGE_LEN]) -> (u8, ParamId, i16) {
+    (
+        bytes[1],
+        ParamId(u16::from_le_bytes([bytes[2], bytes[3]])),
+        i16::from_le_bytes([bytes[4], bytes[5]]),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ConfigError, Request, Response, MESSAGE_LEN};
+    use crate::params::ParamId;
+    use crate::Sample;
+
+    #[test]
+    fn test_request_round_trip() {
+        let requests = [
+            Request::GetDepth {
+                source: 1,
+                destination: ParamId(0x1234),
+            },
+            Request::SetDepth {
+                source: 0,
+                destination: ParamId(7),
+                depth: Sample::from(-1024_i32),
+            },
+            Request::ClearRoutes,
+        ];
+        for request in requests {
+            assert_eq!(Request::decode(&request.encode()), Ok(request));
+        }
+        assert_eq!(
+            requests[1].encode(),
+            [2, 0, 7, 0, 0x00, 0xfc],
+            "little endian fields"
+        );
+
+        assert_eq!(Request::decode(&[1, 0, 0]), Err(ConfigError::BadLength));
+        assert_eq!(
+            Request::decode(&[9; MESSAGE_LEN]),
+            Err(ConfigError::UnknownCommand)
+        );
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let responses = [
+            Response::Depth {
+                source: 1,
+                destination: ParamId(3),
+                depth: Sample::from(Sample::MAX),
+            },
+            Response::Cleared,
+            Response::Error(ConfigError::UnknownDestination),
+        ];
+        for response in responses {
+            assert_eq!(Response::decode(&response.encode()), Ok(response));
+        }
+        assert_eq!(
+            Response::Error(ConfigError::SourceOutOfRange).encode(),
+            [0x83, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            Response::decode(&[0x8f, 0, 0, 0, 0, 0]),
+            Err(ConfigError::UnknownCommand)
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Calibrated CV outputs
+//!
+//! The CV outputs are PWM, through a two pole active filter, at 60kHz with
+//! 12 bit resolution. The output is inverted:
+//!
+//! ```text
+//! 4095 = -6v
+//! 2048 =  0v
+//! 0    = +6v
+//! ```
+//!
+//! Cards configure the PWM slice with [`PWM_DIVIDER`] and [`pwm_top`], then
+//! wrap each channel in a [`CvOut`].
+//!
+//! For pitch, [`CvOut::set_note`] outputs a MIDI note at 1v per octave
+//! (see [`midi_note_to_cv`]), [`ZERO_VOLT_NOTE`] at 0v.
+
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::pitch::{NOTE_MAX, ZERO_VOLT_NOTE};
+use crate::units::Hertz;
+use crate::{Sample, U12_MAX};
+
+/// CV PWM frequency, from the Computer docs
+pub const PWM_FREQUENCY: Hertz = Hertz::new(60_000);
+/// CV PWM clock divider
+pub const PWM_DIVIDER: u8 = 16;
+
+/// PWM `top` for [`PWM_FREQUENCY`] with [`PWM_DIVIDER`], given the system clock
+///
+/// The top value sets the period of the PWM cycle, so a counter goes from 0
+/// to top and then wraps around to 0. Every such wraparound is one PWM cycle.
+pub const fn pwm_top(clock_hz: u32) -> u16 {
+    (clock_hz / (PWM_FREQUENCY.hz() * PWM_DIVIDER as u32)) as u16 - 1
+}
+
+/// Per unit offset and gain correction for a CV output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CvCalibration {
+    /// Added after gain, in [`Sample`] counts
+    pub offset: i16,
+    /// Multiplier, [`CvCalibration::UNITY`] is 1.0
+    pub gain: u16,
+}
+
+impl CvCalibration {
+    pub const UNITY: u16 = 1 << 12;
+    /// No correction
+    pub const NONE: CvCalibration = CvCalibration {
+        offset: 0,
+        gain: Self::UNITY,
+    };
+
+    pub fn apply(&self, value: Sample) -> Sample {
+        let scaled = (value.to_clamped() * i32::from(self.gain)) >> 12;
+        Sample::new(scaled + i32::from(self.offset), false)
+    }
+}
+
+impl Default for CvCalibration {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Division rounded to nearest, for the note conversions
+fn div_rounded(numerator: i64, denominator: i64) -> i64 {
+    if (numerator < 0) == (denominator < 0) {
+        (numerator + denominator / 2) / denominator
+    } else {
+        (numerator - denominator / 2) / denominator
+    }
+}
+
+/// Calibrated output value for a MIDI note at 1v per octave
+///
+/// The result already has `calibration` applied, ready for
+/// [`Sample::to_output_inverted`], so don't pass it to [`CvOut::set`] (use
+/// [`CvOut::set_note`]). A semitone is 28.44 counts, so the note and the
+/// calibration gain are applied in one step and rounded once, rather than
+/// rounding to a whole count first and scaling that error with the gain.
+pub fn midi_note_to_cv(note: u8, calibration: &CvCalibration) -> Sample {
+    let semitones = i64::from(note) - i64::from(ZERO_VOLT_NOTE);
+    let scaled = div_rounded(
+        semitones * i64::from(Sample::OFFSET) * 1000 * i64::from(calibration.gain),
+        i64::from(Sample::CV_MILLIVOLTS) * 12 * i64::from(CvCalibration::UNITY),
+    );
+    Sample::new(scaled as i32 + i32::from(calibration.offset), false)
+}
+
+/// Nearest MIDI note for a calibrated output value, the inverse of
+/// [`midi_note_to_cv`]
+///
+/// Saturates at 0 and [`NOTE_MAX`], and at the ends of the output range.
+pub fn cv_to_midi_note(value: Sample, calibration: &CvCalibration) -> u8 {
+    let uncalibrated = i64::from(value.to_clamped() - i32::from(calibration.offset));
+    let semitones = div_rounded(
+        uncalibrated * i64::from(Sample::CV_MILLIVOLTS) * 12 * i64::from(CvCalibration::UNITY),
+        i64::from(Sample::OFFSET) * 1000 * i64::from(calibration.gain.max(1)),
+    );
+    (semitones + i64::from(ZERO_VOLT_NOTE)).clamp(0, i64::from(NOTE_MAX)) as u8
+}
+
+/// A CV output jack, driven by a PWM channel
+///
+/// Handles calibration and the inverted duty cycle. Cards still pass values
+/// through [`power::safe_cv`](crate::power::safe_cv) for the USB-only safe
+/// state.
+pub struct CvOut<P> {
+    pwm: P,
+    calibration: CvCalibration,
+}
+
+impl<P: SetDutyCycle> CvOut<P> {
+    pub fn new(pwm: P) -> Self {
+        Self::with_calibration(pwm, CvCalibration::NONE)
+    }
+
+    pub fn with_calibration(pwm: P, calibration: CvCalibration) -> Self {
+        CvOut { pwm, calibration }
+    }
+
+    pub fn calibration(&self) -> CvCalibration {
+        self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: CvCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// 12 bit duty cycle for `value`, after calibration and inversion
+    pub fn duty(&self, value: Sample) -> u16 {
+        self.calibration.apply(value).to_output_inverted()
+    }
+
+    /// Output `value`
+    pub fn set(&mut self, value: Sample) -> Result<(), P::Error> {
+        let duty = self.duty(value);
+        self.pwm.set_duty_cycle_fraction(duty, U12_MAX)
+    }
+
+    /// Output a voltage, in millivolts
+    pub fn set_millivolts(&mut self, millivolts: i32) -> Result<(), P::Error> {
+        self.set(Sample::from_millivolts(millivolts))
+    }
+
+    /// Output a MIDI note at 1v per octave, see [`midi_note_to_cv`]
+    pub fn set_note(&mut self, note: u8) -> Result<(), P::Error> {
+        let duty = midi_note_to_cv(note, &self.calibration).to_output_inverted();
+        self.pwm.set_duty_cycle_fraction(duty, U12_MAX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+
+    use super::{cv_to_midi_note, midi_note_to_cv, pwm_top, CvCalibration, CvOut};
+    use crate::pitch::NOTE_MAX;
+    use crate::Sample;
+
+    struct FakePwm {
+        duty: u16,
+    }
+
+    impl ErrorType for FakePwm {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for FakePwm {
+        fn max_duty_cycle(&self) -> u16 {
+            4095
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+            self.duty = duty;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cv_out() {
+        // 125MHz system clock
+        assert_eq!(pwm_top(125_000_000), 129);
+
+        let mut cv = CvOut::new(FakePwm { duty: 0 });
+        cv.set(Sample::from(0_i32)).unwrap();
+        assert_eq!(cv.pwm.duty, 2047);
+        // inverted, so positive voltages are low duty cycles
+        cv.set_millivolts(6_000).unwrap();
+        assert_eq!(cv.pwm.duty, 0);
+        cv.set_millivolts(-6_000).unwrap();
+        assert_eq!(cv.pwm.duty, 4095);
+
+        cv.set_calibration(CvCalibration {
+            offset: 10,
+            gain: CvCalibration::UNITY / 2,
+        });
+        cv.set(Sample::from(1000_i32)).unwrap();
+        assert_eq!(cv.pwm.duty, 2047 - 510);
+    }
+
+    #[test]
+    fn test_midi_note_to_cv() {
+        let none = CvCalibration::NONE;
+        assert_eq!(midi_note_to_cv(60, &none), Sample::from(0_i32));
+        assert_eq!(midi_note_to_cv(72, &none), Sample::from_semitones(12));
+        assert_eq!(midi_note_to_cv(0, &none).to_clamped(), -1707);
+
+        // a unit that reads 2% low and 15 counts high, C6 is 682.67 counts
+        // before calibration
+        let calibration = CvCalibration {
+            offset: -15,
+            gain: 4178,
+        };
+        assert_eq!(midi_note_to_cv(84, &calibration).to_clamped(), 681);
+        for note in 0..=NOTE_MAX {
+            let value = midi_note_to_cv(note, &calibration);
+            if value.to_clamped() < Sample::MAX {
+                assert_eq!(cv_to_midi_note(value, &calibration), note);
+            }
+        }
+        assert_eq!(cv_to_midi_note(Sample::from(Sample::MAX), &none), NOTE_MAX);
+        assert_eq!(cv_to_midi_note(Sample::from(Sample::MIN), &none), 0);
+
+        let mut cv = CvOut::with_calibration(FakePwm { duty: 0 }, calibration);
+        cv.set_note(84).unwrap();
+        assert_eq!(cv.pwm.duty, 2047 - 681);
+    }
+}
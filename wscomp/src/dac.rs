@@ -0,0 +1,206 @@
+//! MCP4822 audio output DAC
+//!
+//! Each 16 bit command word is four config bits followed by the 12 bit
+//! value:
+//!
+//! ```text
+//! 15: channel select 0 = A, 1 = B
+//! 14: unused
+//! 13: 0 = 2x gain, 1 = 1x
+//! 12: 0 = shutdown channel
+//! ```
+//!
+//! The audio outputs are inverted, like the CV outputs, so `Sample`s are
+//! written with [`Sample::to_output_inverted`].
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiBus;
+
+use crate::Sample;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DacChannel {
+    /// Audio out 1
+    A,
+    /// Audio out 2
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Gain {
+    /// 0 to 2.048v, what the cards use
+    #[default]
+    X1,
+    X2,
+}
+
+/// DAC command word for a 12 bit `value`
+pub const fn command(channel: DacChannel, value: u16, gain: Gain, active: bool) -> u16 {
+    let channel_bit = match channel {
+        DacChannel::A => 0,
+        DacChannel::B => 1 << 15,
+    };
+    let gain_bit = match gain {
+        Gain::X1 => 1 << 13,
+        Gain::X2 => 0,
+    };
+    let active_bit = if active { 1 << 12 } else { 0 };
+    // clear out the top four bits, to make room for the config bits
+    channel_bit | gain_bit | active_bit | (value & 0x0fff)
+}
+
+/// Command words for both channels, ready to send to the DAC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DacSamplePair {
+    pub a: u16,
+    pub b: u16,
+}
+
+impl DacSamplePair {
+    /// From raw 12 bit values, at 1x gain
+    pub const fn new(a: u16, b: u16) -> Self {
+        DacSamplePair {
+            a: command(DacChannel::A, a, Gain::X1, true),
+            b: command(DacChannel::B, b, Gain::X1, true),
+        }
+    }
+
+    /// From `Sample`s, handling the output inversion
+    pub fn from_samples(a: Sample, b: Sample) -> Self {
+        Self::new(a.to_output_inverted(), b.to_output_inverted())
+    }
+
+    /// Both DAC words packed for a PIO program, channel A in the high half
+    pub const fn to_pio_word(&self) -> u32 {
+        ((self.a as u32) << 16) | self.b as u32
+    }
+}
+
+/// MCP4822 on an SPI bus, with its own chip select pin
+pub struct Dac<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    gain: Gain,
+    active: [bool; 2],
+}
+
+impl<SPI: SpiBus, CS: OutputPin> Dac<SPI, CS> {
+    pub fn new(spi: SPI, cs: CS) -> Self {
+        Dac {
+            spi,
+            cs,
+            gain: Gain::X1,
+            active: [true; 2],
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: Gain) {
+        self.gain = gain;
+    }
+
+    /// Shut down a channel, or wake it up again, takes effect on the next write
+    pub fn set_shutdown(&mut self, channel: DacChannel, shutdown: bool) {
+        self.active[channel as usize] = !shutdown;
+    }
+
+    /// Write a raw 12 bit value to one channel
+    pub fn write(&mut self, channel: DacChannel, value: u16) -> Result<(), SPI::Error> {
+        let word = command(channel, value, self.gain, self.active[channel as usize]);
+        // GPIO writes can't fail on the RP2040
+        let _ = self.cs.set_low();
+        let result = self
+            .spi
+            .write(&word.to_be_bytes())
+            .and_then(|_| self.spi.flush());
+        // raising CS latches the value
+        let _ = self.cs.set_high();
+        result
+    }
+
+    /// Write both outputs
+    pub fn write_pair(&mut self, a: Sample, b: Sample) -> Result<(), SPI::Error> {
+        self.write(DacChannel::A, a.to_output_inverted())?;
+        self.write(DacChannel::B, b.to_output_inverted())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::{ErrorType as PinErrorType, OutputPin};
+    use embedded_hal::spi::{ErrorType, SpiBus};
+
+    use super::{command, Dac, DacChannel, DacSamplePair, Gain};
+    use crate::Sample;
+
+    #[derive(Default)]
+    struct FakeSpi {
+        written: Vec<u8>,
+    }
+
+    impl ErrorType for FakeSpi {
+        type Error = Infallible;
+    }
+
+    impl SpiBus for FakeSpi {
+        fn read(&mut self, _words: &mut [u8]) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            self.written.extend_from_slice(words);
+            Ok(())
+        }
+
+        fn transfer(&mut self, _read: &mut [u8], write: &[u8]) -> Result<(), Infallible> {
+            self.write(write)
+        }
+
+        fn transfer_in_place(&mut self, _words: &mut [u8]) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    struct FakePin;
+
+    impl PinErrorType for FakePin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for FakePin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dac_commands() {
+        assert_eq!(
+            command(DacChannel::A, 0xffff, Gain::X1, true),
+            0b0011_1111_1111_1111
+        );
+        assert_eq!(command(DacChannel::B, 0, Gain::X2, false), 0b1000 << 12);
+
+        let pair = DacSamplePair::new(0x123, 0x456);
+        assert_eq!(pair.to_pio_word(), 0x3123_b456);
+
+        let mut dac = Dac::new(FakeSpi::default(), FakePin);
+        dac.set_shutdown(DacChannel::B, true);
+        dac.write_pair(Sample::from(0_i32), Sample::from(Sample::MIN))
+            .unwrap();
+        // 0v is mid scale, and the output is inverted
+        assert_eq!(dac.spi.written, [0x37, 0xff, 0xaf, 0xff]);
+    }
+}
@@ -0,0 +1,148 @@
+//! Decibel gains in fixed point
+//!
+//! Levels are easier to reason about in decibels: -6dB halves a signal
+//! whatever it is, and a meter reading in dBFS means the same on every
+//! card. These convert without floats, through the exp2 and log2 tables:
+//!
+//! ```
+//! # use wscomp::decibels::{gain_db, level_to_db};
+//! # use wscomp::mixer::Mixer;
+//! # use wscomp::Sample;
+//! # const DRUMS: usize = 0;
+//! # let mut mixer = Mixer::<2>::new();
+//! # let input = Sample::from(500_i32);
+//! mixer.set_level_db(DRUMS, -6);
+//! let boosted = gain_db(input, 3);
+//! let peak_db = level_to_db(Sample::from(1024_i32));
+//! assert_eq!(peak_db, -6);
+//! ```
+//!
+//! Full scale ([`Sample::MAX`]) is 0dBFS. The [`soft_clip`] knee is half of
+//! it, at -6dBFS.
+//!
+//! [`soft_clip`]: crate::shaper::soft_clip
+
+use crate::tables;
+use crate::Sample;
+
+/// Quietest gain, anything at or below this is silence
+///
+/// A full scale signal at -72dB is under one count.
+pub const MIN_DB: i8 = -72;
+/// Loudest gain, a 16x boost
+pub const MAX_DB: i8 = 24;
+
+/// 1.0 in the Q16 gains below
+const ONE: u32 = 1 << 16;
+/// Octaves per decibel, `log2(10) / 20`, in Q16
+const OCTAVES_PER_DB: i32 = 10_885;
+
+/// Linear gain of `db`, Q16 so 0dB is 65536
+///
+/// Clamped to [`MAX_DB`], and 0 at or below [`MIN_DB`].
+pub fn db_to_gain(db: i8) -> u32 {
+    if db <= MIN_DB {
+        return 0;
+    }
+    let octaves = i32::from(db.min(MAX_DB)) * OCTAVES_PER_DB;
+    // 2^fraction then shift by the whole octaves, rounded
+    let ratio = u64::from(tables::exp2((octaves & 0xffff) as u32));
+    let octave = octaves >> 16;
+    let gain = if octave >= 0 {
+        ratio << octave
+    } else {
+        (ratio + (1 << (-octave - 1))) >> -octave
+    };
+    gain as u32
+}
+
+/// Gain of `db` as a level, [`Sample::MAX`] at 0dB, for
+/// [`Sample::scale`] and mixer levels
+///
+/// Cuts only, anything above 0dB is unity.
+pub fn db_to_level(db: i8) -> Sample {
+    let gain = db_to_gain(db.min(0));
+    Sample::from(((gain * Sample::MAX as u32 + ONE / 2) >> 16) as i32)
+}
+
+/// Apply a gain of `db` to `input`
+///
+/// Boosts past full scale are kept in the sample's headroom until it's
+/// clamped, like any other over.
+pub fn gain_db(input: Sample, db: i8) -> Sample {
+    let scaled = i64::from(input.to_unclamped()) * i64::from(db_to_gain(db));
+    let rounded = if scaled < 0 {
+        (scaled - i64::from(ONE / 2)) / i64::from(ONE)
+    } else {
+        (scaled + i64::from(ONE / 2)) / i64::from(ONE)
+    };
+    Sample::from(rounded as i32)
+}
+
+/// Level of `input` in dBFS, rounded to the nearest decibel, for metering
+///
+/// Full scale either way is 0, silence is [`MIN_DB`].
+pub fn level_to_db(input: Sample) -> i8 {
+    let magnitude = input.to_clamped().unsigned_abs();
+    if magnitude == 0 {
+        return MIN_DB;
+    }
+    // log2(magnitude / MAX) in Q16: whole octaves from the top bit, then
+    // the fraction from the table
+    let octave = 31 - magnitude.leading_zeros() as i32;
+    let normalized = (u64::from(magnitude) << 16) >> octave;
+    let log = (octave << 16) + tables::log2(normalized as u32) as i32 - max_log2();
+    let db = (log + log.signum() * OCTAVES_PER_DB / 2) / OCTAVES_PER_DB;
+    db.clamp(i32::from(MIN_DB), 0) as i8
+}
+
+/// `log2(Sample::MAX)` in Q16
+fn max_log2() -> i32 {
+    let max = Sample::MAX as u32;
+    let octave = 31 - max.leading_zeros() as i32;
+    (octave << 16) + tables::log2(((u64::from(max) << 16) >> octave) as u32) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::{db_to_gain, db_to_level, gain_db, level_to_db, MAX_DB, MIN_DB};
+    use crate::shaper::SOFT_CLIP_KNEE;
+    use crate::Sample;
+
+    #[test]
+    fn test_db_gain() {
+        assert_eq!(db_to_gain(0), 1 << 16);
+        for db in MIN_DB + 1..=MAX_DB {
+            let expected = 10_f64.powf(f64::from(db) / 20.0) * 65_536.0;
+            let gain = f64::from(db_to_gain(db));
+            assert!((gain - expected).abs() <= expected / 1_000.0 + 1.0, "{db}");
+        }
+        assert_eq!(db_to_gain(MIN_DB), 0);
+        assert_eq!(db_to_gain(i8::MAX), db_to_gain(MAX_DB));
+
+        let input = Sample::from(1000_i32);
+        assert_eq!(gain_db(input, 0), input);
+        assert_eq!(gain_db(input, -6), Sample::from(501_i32));
+        assert_eq!(gain_db(Sample::from(-1000_i32), 6), Sample::from(-1995_i32));
+        // boosts keep the over
+        assert_eq!(gain_db(input, 12).to_unclamped(), 3981);
+
+        assert_eq!(db_to_level(0), Sample::from(Sample::MAX));
+        assert_eq!(db_to_level(6), Sample::from(Sample::MAX));
+        assert_eq!(db_to_level(-20), Sample::from(205_i32));
+    }
+
+    #[test]
+    fn test_level_to_db() {
+        assert_eq!(level_to_db(Sample::from(Sample::MAX)), 0);
+        assert_eq!(level_to_db(Sample::from(Sample::MIN)), 0);
+        assert_eq!(level_to_db(Sample::from(SOFT_CLIP_KNEE)), -6);
+        assert_eq!(level_to_db(Sample::from(205_i32)), -20);
+        assert_eq!(level_to_db(Sample::from(1_i32)), -66);
+        assert_eq!(level_to_db(Sample::from(0_i32)), MIN_DB);
+        // quieter than this a count is more than a decibel
+        for db in -40..=0 {
+            assert_eq!(level_to_db(db_to_level(db)), db, "{db}");
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Interpolating delay line with feedback
+//!
+//! The building block for delay, chorus, flanger and Karplus-Strong cards.
+//! Samples are stored as `i16` to make the most of the RP2040's 264KB of
+//! RAM, a 32768 sample line takes 64KB and holds 0.68s at 48kHz. A
+//! [`DelayLine`] that size is too big for a task's stack, so it's usually
+//! allocated from an [`Arena`](crate::arena::Arena) once at startup:
+//!
+//! ```
+//! # use wscomp::arena::ArenaStorage;
+//! # use wscomp::delay::DelayLine;
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::Sample;
+//! static ARENA: ArenaStorage<{ 80 * 1024 }> = ArenaStorage::new();
+//!
+//! let mut arena = ARENA.take().expect("arena already taken");
+//! let delay = arena
+//!     .alloc_value(DelayLine::<32768>::new())
+//!     .expect("not enough RAM for the delay line");
+//! # let (mux_state, input) = (MuxState::default(), Sample::from(0_i32));
+//!
+//! let time = DelayLine::<32768>::samples_q16(mux_state.main_knob.to_output() as u32 * 8);
+//! let wet = delay.process(input, time, mux_state.y_knob);
+//! ```
+//!
+//! Delay times are in samples as Q16.16 fixed point, so modulated taps
+//! glide smoothly between samples instead of stepping and clicking.
+
+use crate::Sample;
+
+/// Delay line of `N` samples, `N` must be a power of two
+pub struct DelayLine<const N: usize> {
+    buffer: [i16; N],
+    /// Slot the next sample is written to
+    write: usize,
+}
+
+impl<const N: usize> DelayLine<N> {
+    /// Longest delay, in whole samples
+    pub const MAX_DELAY: usize = N - 1;
+
+    /// New silent delay line
+    pub const fn new() -> Self {
+        assert!(
+            N.is_power_of_two() && N > 1,
+            "DelayLine size must be a power of two"
+        );
+        DelayLine {
+            buffer: [0; N],
+            write: 0,
+        }
+    }
+
+    /// A whole number of samples as a Q16.16 delay time
+    pub const fn samples_q16(samples: u32) -> u32 {
+        samples << 16
+    }
+
+    /// Add a sample, clamped to full scale
+    pub fn write(&mut self, input: Sample) {
+        self.buffer[self.write] = input.to_clamped() as i16;
+        self.write = (self.write + 1) & (N - 1);
+    }
+
+    /// Sample written `delay` writes ago, clamped to 1..=[`Self::MAX_DELAY`]
+    ///
+    /// A delay of 1 is the most recent write.
+    pub fn tap(&self, delay: usize) -> Sample {
+        let delay = delay.clamp(1, Self::MAX_DELAY);
+        Sample::from(self.buffer[self.write.wrapping_sub(delay) & (N - 1)])
+    }
+
+    /// Linearly interpolated sample `delay` writes ago, in Q16.16 samples
+    ///
+    /// Clamped to 1..=[`Self::MAX_DELAY`] samples.
+    pub fn tap_interpolated(&self, delay: u32) -> Sample {
+        let delay = delay.clamp(1 << 16, (Self::MAX_DELAY as u32) << 16);
+        let whole = (delay >> 16) as usize;
+        let fraction = (delay & 0xffff) as i32;
+        let near = i32::from(self.buffer[self.write.wrapping_sub(whole) & (N - 1)]);
+        let far = i32::from(self.buffer[self.write.wrapping_sub(whole + 1) & (N - 1)]);
+        Sample::from(near + (((far - near) * fraction + (1 << 15)) >> 16))
+    }
+
+    /// Read the tap `delay` (Q16.16 samples) back, then write `input` plus
+    /// the tap scaled by `feedback`, returns the tap
+    ///
+    /// [`Sample::MAX`] feedback repeats forever, negative feedback inverts
+    /// each repeat. The sum is clamped before it's written, run it through
+    /// [`soft_clip`] first for a gentler limit on runaway feedback.
+    ///
+    /// [`soft_clip`]: crate::shaper::soft_clip
+    pub fn process(&mut self, input: Sample, delay: u32, feedback: Sample) -> Sample {
+        let output = self.tap_interpolated(delay);
+        let fed_back = output.to_clamped() * feedback.to_clamped() / Sample::MAX;
+        self.write(Sample::from(input.to_unclamped() + fed_back));
+        output
+    }
+
+    /// Silence the whole line
+    pub fn clear(&mut self) {
+        self.buffer = [0; N];
+    }
+}
+
+impl<const N: usize> Default for DelayLine<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DelayLine;
+    use crate::Sample;
+
+    #[test]
+    fn test_delay_taps() {
+        let mut delay = DelayLine::<8>::new();
+        for value in 1..=10_i32 {
+            delay.write(Sample::from(value * 100));
+        }
+        assert_eq!(delay.tap(1), Sample::from(1000_i32));
+        assert_eq!(delay.tap(3), Sample::from(800_i32));
+        assert_eq!(delay.tap(0), Sample::from(1000_i32));
+        assert_eq!(delay.tap(100), Sample::from(400_i32));
+
+        let half = DelayLine::<8>::samples_q16(2) + (1 << 15);
+        assert_eq!(delay.tap_interpolated(half), Sample::from(850_i32));
+        let quarter = DelayLine::<8>::samples_q16(1) + (1 << 14);
+        assert_eq!(delay.tap_interpolated(quarter), Sample::from(975_i32));
+        assert_eq!(
+            delay.tap_interpolated(DelayLine::<8>::samples_q16(4)),
+            delay.tap(4)
+        );
+
+        delay.write(Sample::from(5000_i32));
+        assert_eq!(delay.tap(1).to_clamped(), Sample::MAX);
+        delay.clear();
+        assert_eq!(delay.tap(1), Sample::from(0_i32));
+    }
+
+    #[test]
+    fn test_delay_feedback() {
+        let mut delay = DelayLine::<16>::new();
+        let time = DelayLine::<16>::samples_q16(4);
+        let half = Sample::from(Sample::OFFSET / 2);
+        let outputs: [i32; 13] = core::array::from_fn(|index| {
+            let input = Sample::from(if index == 0 { 1600_i32 } else { 0 });
+            delay.process(input, time, half).to_clamped()
+        });
+        assert_eq!(outputs[4], 1600);
+        assert_eq!(outputs[8], 800);
+        assert_eq!(outputs[12], 400);
+        assert_eq!(outputs.iter().filter(|&&value| value != 0).count(), 3);
+
+        // negative feedback flips each repeat
+        let mut delay = DelayLine::<16>::new();
+        let invert = Sample::from(-Sample::OFFSET / 2);
+        delay.process(Sample::from(1600_i32), time, invert);
+        let repeats: [i32; 8] = core::array::from_fn(|_| {
+            delay
+                .process(Sample::from(0_i32), time, invert)
+                .to_clamped()
+        });
+        assert_eq!(repeats[3], 1600);
+        assert_eq!(repeats[7], -800);
+    }
+}
@@ -0,0 +1,399 @@
+//! Reset cause diagnostics
+//!
+//! Decodes why the RP2040 last reset, so cards can report it at boot. The
+//! chip can't tell a brown-out from a normal power on (both are a POR), so
+//! cards also keep a [`CrashLog`] in RAM that isn't initialized at boot: if
+//! it survived a POR, power only dipped briefly, which points at a marginal
+//! supply rather than the module being switched on.
+//!
+//! The [`CrashLog`] also carries a breadcrumb and which tasks were running
+//! into the next boot, so unexpected resets can be reported with some
+//! context.
+//!
+//! [`LatencyMeter`] measures round trip latency from an output to an input,
+//! with a patch cable between them.
+
+use core::mem::MaybeUninit;
+
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::units::Millis;
+use crate::Sample;
+
+/// Marks a valid [`CrashLog`], anything else is uninitialized RAM
+const CRASH_LOG_MARKER: u32 = 0x5752_4e21;
+
+// VREG_AND_CHIP_RESET.CHIP_RESET bits
+const HAD_POR: u32 = 1 << 8;
+const HAD_RUN: u32 = 1 << 16;
+const HAD_PSM_RESTART: u32 = 1 << 20;
+// WATCHDOG.REASON bits
+const WATCHDOG_TIMER: u32 = 1 << 0;
+const WATCHDOG_FORCE: u32 = 1 << 1;
+
+/// Why the chip last reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetReason {
+    /// Normal power on
+    PowerOn,
+    /// Power on reset while RAM contents survived, likely a supply dip
+    Brownout,
+    /// RUN pin pulled low (reset button)
+    RunPin,
+    /// Reset from the debugger
+    Debugger,
+    /// Watchdog timed out
+    WatchdogTimeout,
+    /// Watchdog reset forced by software (e.g. reboot to bootloader)
+    WatchdogForced,
+    Unknown,
+}
+
+impl ResetReason {
+    /// Decode raw `CHIP_RESET` and watchdog `REASON` register values
+    ///
+    /// `ram_retained` is whether the [`CrashLog`] survived the reset.
+    pub fn from_registers(chip_reset: u32, watchdog_reason: u32, ram_retained: bool) -> Self {
+        if watchdog_reason & WATCHDOG_TIMER != 0 {
+            ResetReason::WatchdogTimeout
+        } else if watchdog_reason & WATCHDOG_FORCE != 0 {
+            ResetReason::WatchdogForced
+        } else if chip_reset & HAD_PSM_RESTART != 0 {
+            ResetReason::Debugger
+        } else if chip_reset & HAD_RUN != 0 {
+            ResetReason::RunPin
+        } else if chip_reset & HAD_POR != 0 {
+            if ram_retained {
+                ResetReason::Brownout
+            } else {
+                ResetReason::PowerOn
+            }
+        } else {
+            ResetReason::Unknown
+        }
+    }
+
+    /// Was this reset unexpected (not power on, reset button or debugger)?
+    pub fn is_unexpected(&self) -> bool {
+        matches!(
+            self,
+            ResetReason::Brownout | ResetReason::WatchdogTimeout | ResetReason::Unknown
+        )
+    }
+}
+
+/// State of the previous run, recovered from a [`CrashLog`] at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CrashReport {
+    /// Boots since the log was last lost (power off)
+    pub boot_count: u32,
+    /// Last breadcrumb recorded before the reset
+    pub breadcrumb: u32,
+    /// Tasks that checked in during the last full stats period
+    pub task_check_ins: u32,
+    /// Tasks that checked in during the period the reset happened in
+    pub partial_check_ins: u32,
+}
+
+/// Diagnostics kept across resets in RAM that isn't initialized at boot
+///
+/// Cards place one in an `.uninit` section and call [`CrashLog::start`] once
+/// at boot, before any tasks use it:
+///
+/// ```no_run
+/// # use core::mem::MaybeUninit;
+/// # use wscomp::diagnostics::CrashLog;
+/// #[link_section = ".uninit.CRASH_LOG"]
+/// static CRASH_LOG: MaybeUninit<CrashLog> = MaybeUninit::uninit();
+///
+/// let (crash_log, report) = CrashLog::start(&CRASH_LOG);
+/// ```
+///
+/// Tasks then call [`CrashLog::check_in`] from their loops, and a periodic
+/// task calls [`CrashLog::end_period`], so the log shows which tasks were
+/// still running when a reset happened.
+pub struct CrashLog {
+    marker: AtomicU32,
+    boot_count: AtomicU32,
+    breadcrumb: AtomicU32,
+    task_check_ins: AtomicU32,
+    partial_check_ins: AtomicU32,
+}
+
+impl CrashLog {
+    /// Recover the previous run's state (if RAM kept it) and start a new log
+    pub fn start(
+        storage: &'static MaybeUninit<CrashLog>,
+    ) -> (&'static CrashLog, Option<CrashReport>) {
+        // SAFETY: CrashLog is all u32 atomics, so any bit pattern left in RAM
+        // is a valid value, stale contents are caught by the marker check.
+        let log = unsafe { storage.assume_init_ref() };
+        let report =
+            (log.marker.load(Ordering::Relaxed) == CRASH_LOG_MARKER).then(|| CrashReport {
+                boot_count: log.boot_count.load(Ordering::Relaxed),
+                breadcrumb: log.breadcrumb.load(Ordering::Relaxed),
+                task_check_ins: log.task_check_ins.load(Ordering::Relaxed),
+                partial_check_ins: log.partial_check_ins.load(Ordering::Relaxed),
+            });
+        let boot_count = report.map_or(0, |report| report.boot_count.wrapping_add(1));
+        log.boot_count.store(boot_count, Ordering::Relaxed);
+        log.breadcrumb.store(0, Ordering::Relaxed);
+        log.task_check_ins.store(0, Ordering::Relaxed);
+        log.partial_check_ins.store(0, Ordering::Relaxed);
+        log.marker.store(CRASH_LOG_MARKER, Ordering::Relaxed);
+        (log, report)
+    }
+
+    /// Record where we are, e.g. a startup stage or mode
+    pub fn breadcrumb(&self, breadcrumb: u32) {
+        self.breadcrumb.store(breadcrumb, Ordering::Relaxed);
+    }
+
+    /// Mark a task (one bit per task) as running this period
+    pub fn check_in(&self, task: u32) {
+        self.partial_check_ins.fetch_or(task, Ordering::Relaxed);
+    }
+
+    /// End a stats period, returns the tasks that checked in during it
+    pub fn end_period(&self) -> u32 {
+        let check_ins = self.partial_check_ins.swap(0, Ordering::Relaxed);
+        self.task_check_ins.store(check_ins, Ordering::Relaxed);
+        check_ins
+    }
+}
+
+/// Round trip latency results from a [`LatencyMeter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LatencyStats {
+    pub count: u32,
+    /// Edges that never arrived, e.g. no cable patched
+    pub timeouts: u32,
+    pub min_micros: u32,
+    pub max_micros: u32,
+    pub total_micros: u64,
+}
+
+impl LatencyStats {
+    pub fn mean_micros(&self) -> Option<u32> {
+        (self.count > 0).then(|| (self.total_micros / u64::from(self.count)) as u32)
+    }
+
+    /// Spread between the fastest and slowest round trip
+    pub fn jitter_micros(&self) -> u32 {
+        self.max_micros - self.min_micros
+    }
+
+    fn record(&mut self, latency: u32) {
+        if self.count == 0 {
+            self.min_micros = latency;
+            self.max_micros = latency;
+        }
+        self.count += 1;
+        self.min_micros = self.min_micros.min(latency);
+        self.max_micros = self.max_micros.max(latency);
+        self.total_micros += u64::from(latency);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum LoopbackState {
+    /// Output low, next edge due at this time
+    Idle { next: u64 },
+    /// Output high since this time, waiting for it to arrive
+    Emitting { since: u64 },
+}
+
+/// Loopback latency measurement
+///
+/// Sends a rising edge on an output and times how long it takes to show up
+/// on an input. Each edge stays high until it arrives (or times out), so
+/// slow input polling can't miss it, then the output goes low until the
+/// next one is due.
+///
+/// Call [`LatencyMeter::update`] with every input reading, and drive the
+/// output from [`LatencyMeter::output`]. Results are only as fine grained as
+/// the input readings.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LatencyMeter {
+    interval_micros: u64,
+    timeout_micros: u64,
+    state: LoopbackState,
+    stats: LatencyStats,
+}
+
+impl LatencyMeter {
+    /// Edge output level, about +3v
+    pub const HIGH: i32 = Sample::MAX / 2;
+    /// Input level counted as the edge arriving
+    pub const THRESHOLD: i32 = Self::HIGH / 2;
+
+    /// Send an edge every `interval`, giving up on each after `timeout`
+    pub fn new(interval: Millis, timeout: Millis) -> Self {
+        LatencyMeter {
+            interval_micros: u64::from(interval.millis()) * 1_000,
+            timeout_micros: u64::from(timeout.millis()) * 1_000,
+            state: LoopbackState::Idle { next: 0 },
+            stats: LatencyStats::default(),
+        }
+    }
+
+    /// Level to send on the output
+    pub fn output(&self) -> Sample {
+        match self.state {
+            LoopbackState::Idle { .. } => Sample::from(0_i32),
+            LoopbackState::Emitting { .. } => Sample::from(Self::HIGH),
+        }
+    }
+
+    /// Update with an input reading, returns the latency when an edge arrives
+    pub fn update(&mut self, input: Sample, now_micros: u64) -> Option<u32> {
+        let arrived = input.to_clamped() >= Self::THRESHOLD;
+        match self.state {
+            // wait for the input to settle low, so the edge is a clean one
+            LoopbackState::Idle { next } if now_micros >= next && !arrived => {
+                self.state = LoopbackState::Emitting { since: now_micros };
+                None
+            }
+            LoopbackState::Idle { .. } => None,
+            LoopbackState::Emitting { since } => {
+                let elapsed = now_micros.saturating_sub(since);
+                if arrived {
+                    let latency = elapsed.min(u32::MAX.into()) as u32;
+                    self.stats.record(latency);
+                    self.state = LoopbackState::Idle {
+                        next: now_micros + self.interval_micros,
+                    };
+                    Some(latency)
+                } else {
+                    if elapsed > self.timeout_micros {
+                        self.stats.timeouts += 1;
+                        self.state = LoopbackState::Idle {
+                            next: now_micros + self.interval_micros,
+                        };
+                    }
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::mem::MaybeUninit;
+
+    use super::{CrashLog, CrashReport, LatencyMeter, LatencyStats, ResetReason, HAD_POR, HAD_RUN};
+    use crate::units::Millis;
+    use crate::Sample;
+
+    #[test]
+    fn test_reset_reason() {
+        assert_eq!(
+            ResetReason::from_registers(HAD_POR, 0, false),
+            ResetReason::PowerOn
+        );
+        assert_eq!(
+            ResetReason::from_registers(HAD_POR, 0, true),
+            ResetReason::Brownout
+        );
+        assert_eq!(
+            ResetReason::from_registers(HAD_RUN, 0, true),
+            ResetReason::RunPin
+        );
+        assert_eq!(
+            ResetReason::from_registers(0, 1, true),
+            ResetReason::WatchdogTimeout
+        );
+        assert_eq!(
+            ResetReason::from_registers(0, 0, false),
+            ResetReason::Unknown
+        );
+
+        assert!(ResetReason::Brownout.is_unexpected());
+        assert!(!ResetReason::PowerOn.is_unexpected());
+    }
+
+    #[test]
+    fn test_crash_log() {
+        // stand in for uninitialized RAM, garbage after power on
+        let storage: &'static mut MaybeUninit<CrashLog> =
+            Box::leak(Box::new(MaybeUninit::uninit()));
+        unsafe {
+            storage
+                .as_mut_ptr()
+                .cast::<[u32; 5]>()
+                .write([0xdead_beef; 5])
+        };
+        let storage = &*storage;
+
+        let (log, report) = CrashLog::start(storage);
+        assert_eq!(report, None);
+        log.breadcrumb(3);
+        log.check_in(0b01);
+        log.check_in(0b10);
+        assert_eq!(log.end_period(), 0b11);
+        log.check_in(0b10);
+
+        // "reset", the log survives
+        let (log, report) = CrashLog::start(storage);
+        assert_eq!(
+            report,
+            Some(CrashReport {
+                boot_count: 0,
+                breadcrumb: 3,
+                task_check_ins: 0b11,
+                partial_check_ins: 0b10,
+            })
+        );
+        assert_eq!(log.end_period(), 0);
+        let (_, report) = CrashLog::start(storage);
+        assert_eq!(report.map(|report| report.boot_count), Some(1));
+    }
+
+    #[test]
+    fn test_latency_meter() {
+        let low = Sample::from(0_i32);
+        let high = Sample::from(LatencyMeter::HIGH);
+        let mut meter = LatencyMeter::new(Millis::new(100), Millis::new(50));
+        assert_eq!(meter.output(), low);
+
+        assert_eq!(meter.update(low, 0), None);
+        assert_eq!(meter.output(), high);
+        assert_eq!(meter.update(low, 1_000), None);
+        assert_eq!(meter.update(high, 3_000), Some(3_000));
+        assert_eq!(meter.output(), low);
+
+        // next edge waits for the interval, and for the input to go low
+        assert_eq!(meter.update(high, 103_000), None);
+        assert_eq!(meter.output(), low);
+        assert_eq!(meter.update(low, 104_000), None);
+        assert_eq!(meter.update(high, 109_000), Some(5_000));
+
+        // unpatched, times out
+        meter.update(low, 209_000);
+        meter.update(low, 260_000);
+        assert_eq!(meter.output(), low);
+
+        assert_eq!(
+            meter.stats(),
+            LatencyStats {
+                count: 2,
+                timeouts: 1,
+                min_micros: 3_000,
+                max_micros: 5_000,
+                total_micros: 8_000,
+            }
+        );
+        assert_eq!(meter.stats().mean_micros(), Some(4_000));
+        assert_eq!(meter.stats().jitter_micros(), 2_000);
+    }
+}
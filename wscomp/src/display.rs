@@ -0,0 +1,169 @@
+//! Human readable `Sample` values for logs
+//!
+//! `Sample`s log as raw counts, so `cv1: 1623` needs mental math to read.
+//! These adapters print a sample in a unit instead, for both defmt and
+//! `core::fmt`:
+//!
+//! ```
+//! # use wscomp::Sample;
+//! # let value = Sample::from(1623_i32);
+//! # let (cv1, main_knob, pitch) = (value, value, value);
+//! assert_eq!(format!("cv1: {}", cv1.as_volts()), "cv1: +4.76V");
+//! assert_eq!(format!("knob: {}", main_knob.as_percent()), "knob: +79.3%");
+//! assert_eq!(format!("pitch: {}", pitch.as_semitones()), "pitch: +57.06st");
+//! ```
+//!
+//! A plain `{}` prints both CV volts and percent, like `+4.76V (+79.3%)`.
+//!
+//! Everything is integer math, no floats are needed to print.
+
+use core::fmt;
+
+use crate::{div_rounded, Sample};
+
+/// What a [`SampleDisplay`] prints a sample as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Unit {
+    /// Volts on the CV path, see [`Sample::to_millivolts`]
+    Volts,
+    /// Volts on the audio path, see [`Sample::to_audio_millivolts`]
+    AudioVolts,
+    /// Percent of full scale
+    Percent,
+    /// Semitones from 0v, at 1v per octave
+    Semitones,
+}
+
+/// A `Sample` printed in a [`Unit`], from [`Sample::display`] and friends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleDisplay {
+    sample: Sample,
+    unit: Unit,
+}
+
+impl SampleDisplay {
+    /// Signed value in fixed point, with the number of decimal places
+    fn fixed_point(&self) -> (i32, u8, &'static str) {
+        let value = self.sample.to_clamped();
+        match self.unit {
+            Unit::Volts => (div_rounded(self.sample.to_millivolts(), 10), 2, "V"),
+            Unit::AudioVolts => (div_rounded(self.sample.to_audio_millivolts(), 10), 2, "V"),
+            Unit::Percent => (div_rounded(value * 1000, Sample::MAX), 1, "%"),
+            // hundredths of a semitone, 1200 per volt
+            Unit::Semitones => (
+                div_rounded(value * (Sample::CV_MILLIVOLTS * 12 / 10), Sample::OFFSET),
+                2,
+                "st",
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SampleDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, decimals, suffix) = self.fixed_point();
+        let sign = if value < 0 { '-' } else { '+' };
+        let value = value.unsigned_abs();
+        match decimals {
+            1 => write!(f, "{}{}.{}{}", sign, value / 10, value % 10, suffix),
+            _ => write!(f, "{}{}.{:02}{}", sign, value / 100, value % 100, suffix),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for SampleDisplay {
+    fn format(&self, f: defmt::Formatter) {
+        let (value, decimals, suffix) = self.fixed_point();
+        let sign = if value < 0 { "-" } else { "+" };
+        let value = value.unsigned_abs();
+        match decimals {
+            1 => defmt::write!(f, "{}{}.{}{}", sign, value / 10, value % 10, suffix),
+            // defmt has no zero padding, so print each decimal digit
+            _ => defmt::write!(
+                f,
+                "{}{}.{}{}{}",
+                sign,
+                value / 100,
+                value % 100 / 10,
+                value % 10,
+                suffix
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.as_volts(), self.as_percent())
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Sample {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{} ({})", self.as_volts(), self.as_percent())
+    }
+}
+
+impl Sample {
+    /// Print this sample in `unit`
+    pub fn display(&self, unit: Unit) -> SampleDisplay {
+        SampleDisplay {
+            sample: *self,
+            unit,
+        }
+    }
+
+    /// Print as CV volts, like `+2.34V`
+    pub fn as_volts(&self) -> SampleDisplay {
+        self.display(Unit::Volts)
+    }
+
+    /// Print as percent of full scale, like `-50.0%`
+    pub fn as_percent(&self) -> SampleDisplay {
+        self.display(Unit::Percent)
+    }
+
+    /// Print as semitones from 0v, like `+7.00st`
+    pub fn as_semitones(&self) -> SampleDisplay {
+        self.display(Unit::Semitones)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Unit;
+    use crate::Sample;
+
+    #[test]
+    fn test_sample_display() {
+        assert_eq!(
+            Sample::from_millivolts(2_340).as_volts().to_string(),
+            "+2.34V"
+        );
+        assert_eq!(
+            Sample::from_millivolts(-50).as_volts().to_string(),
+            "-0.05V"
+        );
+        assert_eq!(
+            Sample::from(0_i32).display(Unit::AudioVolts).to_string(),
+            "+0.00V"
+        );
+        assert_eq!(
+            Sample::from(Sample::MAX).as_percent().to_string(),
+            "+100.0%"
+        );
+        assert_eq!(Sample::from(-1024_i32).as_percent().to_string(), "-50.0%");
+        assert_eq!(
+            Sample::from_semitones(7).as_semitones().to_string(),
+            "+7.00st"
+        );
+        // a little sharp of an octave
+        assert_eq!(Sample::from(350_i32).as_semitones().to_string(), "+12.30st");
+
+        assert_eq!(Sample::from(1024_i32).to_string(), "+3.00V (+50.0%)");
+        assert_eq!(Sample::from(Sample::MIN).to_string(), "-6.00V (-100.0%)");
+    }
+}
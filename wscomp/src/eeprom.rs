@@ -0,0 +1,294 @@
+//! I2C EEPROM and factory calibration data
+//!
+//! The Computer has a small I2C EEPROM (24LC style, 16 bit addresses) which
+//! holds the CV output calibration measured at the factory. The layout is
+//! the one written by the ComputerCard calibration firmware:
+//!
+//! ```text
+//! 0: magic number 2001, u16 big endian
+//! 2: version, u8
+//! 3: CV out 1 points, then CV out 2 points, each:
+//!      count, u8 (up to 10)
+//!      count x (volts, i8 | 19 bit PWM setting, i32 big endian)
+//! ```
+//!
+//! [`FactoryCalibration::parse`] fits a line through each output's points
+//! and turns it into a [`CvCalibration`]. The factory data doesn't include
+//! the ADC inputs, so there is nothing to correct those with yet.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+use crate::cv::CvCalibration;
+
+/// I2C address of the EEPROM
+pub const DEFAULT_ADDRESS: u8 = 0x50;
+/// Bytes to read to be sure of getting all calibration data
+pub const CALIBRATION_SIZE: usize = 3 + 2 * (1 + MAX_POINTS * 5);
+const MAX_POINTS: usize = 10;
+const MAGIC: u16 = 2001;
+/// Bytes in the EEPROM (24LC32)
+pub const SIZE: usize = 4096;
+/// Largest write the EEPROM accepts in one go
+pub const PAGE_SIZE: usize = 32;
+/// Time the EEPROM needs to store a page
+const WRITE_MILLIS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EepromError<E> {
+    I2c(E),
+    /// Read or write past the end of the EEPROM
+    OutOfRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalibrationError {
+    /// No magic number, this unit was never calibrated
+    Missing,
+    /// Data is truncated or the points don't describe a line
+    Invalid,
+}
+
+/// 24LC style EEPROM, with 16 bit memory addresses
+pub struct Eeprom<I2C, D> {
+    i2c: I2C,
+    delay: D,
+    address: u8,
+}
+
+impl<I2C: I2c, D: DelayNs> Eeprom<I2C, D> {
+    pub fn new(i2c: I2C, delay: D) -> Self {
+        Eeprom {
+            i2c,
+            delay,
+            address: DEFAULT_ADDRESS,
+        }
+    }
+
+    pub fn read(&mut self, offset: u16, buffer: &mut [u8]) -> Result<(), EepromError<I2C::Error>> {
+        if usize::from(offset) + buffer.len() > SIZE {
+            return Err(EepromError::OutOfRange);
+        }
+        self.i2c
+            .write_read(self.address, &offset.to_be_bytes(), buffer)
+            .map_err(EepromError::I2c)
+    }
+
+    /// Write `data`, split into pages, waiting for each page to be stored
+    pub fn write(&mut self, offset: u16, data: &[u8]) -> Result<(), EepromError<I2C::Error>> {
+        let mut offset = usize::from(offset);
+        if offset + data.len() > SIZE {
+            return Err(EepromError::OutOfRange);
+        }
+        let mut remaining = data;
+        while !remaining.is_empty() {
+            // writes wrap around within a page, so never cross a page boundary
+            let len = remaining.len().min(PAGE_SIZE - offset % PAGE_SIZE);
+            let mut command = [0_u8; 2 + PAGE_SIZE];
+            command[..2].copy_from_slice(&(offset as u16).to_be_bytes());
+            command[2..2 + len].copy_from_slice(&remaining[..len]);
+            self.i2c
+                .write(self.address, &command[..2 + len])
+                .map_err(EepromError::I2c)?;
+            self.delay.delay_ms(WRITE_MILLIS);
+            offset += len;
+            remaining = &remaining[len..];
+        }
+        Ok(())
+    }
+
+    /// Read and parse the factory calibration
+    pub fn read_calibration(
+        &mut self,
+    ) -> Result<Result<FactoryCalibration, CalibrationError>, EepromError<I2C::Error>> {
+        let mut buffer = [0_u8; CALIBRATION_SIZE];
+        self.read(0, &mut buffer)?;
+        Ok(FactoryCalibration::parse(&buffer))
+    }
+}
+
+/// Calibration for both CV outputs, from the EEPROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FactoryCalibration {
+    pub version: u8,
+    pub cv_out: [CvCalibration; 2],
+}
+
+impl FactoryCalibration {
+    pub fn parse(data: &[u8]) -> Result<Self, CalibrationError> {
+        let magic = data.get(..2).ok_or(CalibrationError::Invalid)?;
+        if u16::from_be_bytes([magic[0], magic[1]]) != MAGIC {
+            return Err(CalibrationError::Missing);
+        }
+        let version = *data.get(2).ok_or(CalibrationError::Invalid)?;
+        let mut rest = &data[3..];
+        let mut cv_out = [CvCalibration::NONE; 2];
+        for calibration in cv_out.iter_mut() {
+            let count = usize::from(*rest.first().ok_or(CalibrationError::Invalid)?);
+            let points = rest
+                .get(1..1 + count * 5)
+                .filter(|_| count <= MAX_POINTS)
+                .ok_or(CalibrationError::Invalid)?;
+            *calibration = fit(points.chunks_exact(5).map(|point| {
+                let volts = i64::from(point[0] as i8);
+                let setting = i32::from_be_bytes([point[1], point[2], point[3], point[4]]);
+                (volts, i64::from(setting))
+            }))?;
+            rest = &rest[1 + count * 5..];
+        }
+        Ok(FactoryCalibration { version, cv_out })
+    }
+}
+
+/// Least squares line through (volts, 19 bit PWM setting) points
+///
+/// The ideal output is inverted, from 2^19 at -6v to 0 at +6v, so mid scale
+/// at 0v. Gain is the measured slope over the ideal slope, and offset is
+/// how far the 0v setting is from mid scale, in 12 bit [`Sample`] counts.
+///
+/// [`Sample`]: crate::Sample
+fn fit(points: impl Iterator<Item = (i64, i64)>) -> Result<CvCalibration, CalibrationError> {
+    let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0_i64, 0, 0, 0, 0);
+    for (x, y) in points {
+        n += 1;
+        sx += x;
+        sy += y;
+        sxx += x * x;
+        sxy += x * y;
+    }
+    let denominator = n * sxx - sx * sx;
+    if n < 2 || denominator == 0 {
+        return Err(CalibrationError::Invalid);
+    }
+    let slope_numerator = n * sxy - sx * sy;
+    // ideal slope is -2^19 / 12v, scaled so the ideal gain is UNITY
+    let gain = -12 * slope_numerator * i64::from(CvCalibration::UNITY) / (denominator << 19);
+    // 0v setting, in 12 bit counts (1 << 7 in 19 bit), relative to mid scale
+    let intercept = (sy * denominator - slope_numerator * sx) / (n * denominator);
+    let offset = ((1 << 18) - intercept) / (1 << 7);
+    Ok(CvCalibration {
+        offset: i16::try_from(offset).map_err(|_| CalibrationError::Invalid)?,
+        gain: u16::try_from(gain).map_err(|_| CalibrationError::Invalid)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_hal::delay::DelayNs;
+    use embedded_hal::i2c::{ErrorType, I2c, Operation};
+
+    use super::{CalibrationError, Eeprom, FactoryCalibration};
+    use crate::cv::CvCalibration;
+
+    struct FakeEeprom {
+        memory: Vec<u8>,
+        writes: usize,
+    }
+
+    impl ErrorType for FakeEeprom {
+        type Error = Infallible;
+    }
+
+    impl I2c for FakeEeprom {
+        fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Infallible> {
+            let mut offset = 0;
+            for operation in operations {
+                match operation {
+                    Operation::Write(bytes) => {
+                        offset = usize::from(u16::from_be_bytes([bytes[0], bytes[1]]));
+                        let data = &bytes[2..];
+                        if !data.is_empty() {
+                            self.writes += 1;
+                            self.memory[offset..offset + data.len()].copy_from_slice(data);
+                        }
+                    }
+                    Operation::Read(buffer) => {
+                        buffer.copy_from_slice(&self.memory[offset..offset + buffer.len()]);
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// Calibration data for one output, from (volts, setting) points
+    fn points(points: &[(i8, i32)]) -> Vec<u8> {
+        let mut data = vec![points.len() as u8];
+        for (volts, setting) in points {
+            data.push(*volts as u8);
+            data.extend_from_slice(&setting.to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_factory_calibration() {
+        let mut data = vec![0x07, 0xd1, 1];
+        // ideal output
+        data.extend(points(&[(-6, 1 << 19), (0, 1 << 18), (6, 0)]));
+        // 0v is 10 counts high, and the range is 1/8 too wide
+        let slope = (1 << 19) * 9 / 8 / 12;
+        data.extend(points(&[
+            (-3, (1 << 18) + 1280 + 3 * slope),
+            (3, (1 << 18) + 1280 - 3 * slope),
+        ]));
+
+        let calibration = FactoryCalibration::parse(&data).unwrap();
+        assert_eq!(calibration.version, 1);
+        assert_eq!(calibration.cv_out[0], CvCalibration::NONE);
+        assert_eq!(
+            calibration.cv_out[1],
+            CvCalibration {
+                offset: -10,
+                gain: CvCalibration::UNITY * 9 / 8,
+            }
+        );
+
+        assert_eq!(
+            FactoryCalibration::parse(&[0xff; 8]),
+            Err(CalibrationError::Missing)
+        );
+        assert_eq!(
+            FactoryCalibration::parse(&data[..20]),
+            Err(CalibrationError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_eeprom_read_write() {
+        let fake = FakeEeprom {
+            memory: vec![0; 4096],
+            writes: 0,
+        };
+        let mut eeprom = Eeprom::new(fake, NoDelay);
+        let data: Vec<u8> = (0..40).collect();
+        // starts mid page, so splits into three writes
+        eeprom.write(30, &data).unwrap();
+        assert_eq!(eeprom.i2c.writes, 3);
+
+        let mut buffer = [0_u8; 40];
+        eeprom.read(30, &mut buffer).unwrap();
+        assert_eq!(buffer.as_slice(), data.as_slice());
+        assert!(eeprom.read(4090, &mut buffer).is_err());
+
+        assert_eq!(
+            eeprom.read_calibration().unwrap(),
+            Err(CalibrationError::Missing)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Gate triggered AD and ADSR envelopes
+//!
+//! The same state machine ticks at any rate, set at construction: the
+//! control loop rate for modulation, or the audio rate for percussive
+//! amplitude envelopes where control rate steps would click.
+//!
+//! ```
+//! # use embedded_hal::digital::InputPin;
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::envelope::{Envelope, EnvelopeShape};
+//! # use wscomp::pulse::PulseInput;
+//! # use wscomp::units::{Hertz, Millis};
+//! # const CONTROL_RATE: Hertz = Hertz::new(1_000);
+//! # fn card<I: InputPin, P: SetDutyCycle>(
+//! #     mut pulse_in: PulseInput<I>,
+//! #     mut cv_out: CvOut<P>,
+//! #     now_micros: u64,
+//! # ) -> Result<(), P::Error> {
+//! let mut envelope = Envelope::new(
+//!     EnvelopeShape::Ad {
+//!         attack: Millis::new(5),
+//!         decay: Millis::new(300),
+//!     },
+//!     CONTROL_RATE,
+//! );
+//! loop {
+//!     pulse_in.poll(now_micros);
+//!     envelope.gate(pulse_in.is_high());
+//!     cv_out.set(envelope.tick())?;
+//! }
+//! # }
+//! ```
+//!
+//! Segments follow an exponential curve, like an RC circuit charging: fast
+//! at the start, settling into the target. Output is unipolar, 0 to
+//! [`Sample::MAX`].
+
+use crate::tables;
+use crate::units::{Hertz, Millis};
+use crate::Sample;
+
+/// Internal level at full scale
+const LEVEL_MAX: i32 = 0xffff;
+
+/// Progress along [`tables::exp_curve`], for `elapsed` of `total` ticks
+fn curve(elapsed: u32, total: u32) -> i32 {
+    let position = ((u64::from(elapsed) << 16) / u64::from(total)).min(1 << 16) as u32;
+    tables::exp_curve(position) as i32
+}
+
+/// Segment times and sustain level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EnvelopeShape {
+    /// Attack then decay to 0 on each gate, however long the gate is
+    Ad { attack: Millis, decay: Millis },
+    /// Decays to `sustain` (0 to [`Sample::MAX`]) while the gate is high,
+    /// releases to 0 when it goes low
+    Adsr {
+        attack: Millis,
+        decay: Millis,
+        sustain: Sample,
+        release: Millis,
+    },
+}
+
+/// Which segment an [`Envelope`] is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Envelope {
+    shape: EnvelopeShape,
+    rate: Hertz,
+    stage: Stage,
+    gate: bool,
+    /// Level when the current segment started, 0..=LEVEL_MAX
+    start: i32,
+    level: i32,
+    elapsed: u32,
+    total: u32,
+}
+
+impl Envelope {
+    /// Envelope ticked `rate` times a second
+    pub fn new(shape: EnvelopeShape, rate: Hertz) -> Self {
+        Envelope {
+            shape,
+            rate,
+            stage: Stage::Idle,
+            gate: false,
+            start: 0,
+            level: 0,
+            elapsed: 0,
+            total: 1,
+        }
+    }
+
+    /// Change times or sustain, for knob control
+    ///
+    /// A running segment keeps its length, new times apply from the next
+    /// segment. A new sustain level applies straight away.
+    pub fn set_shape(&mut self, shape: EnvelopeShape) {
+        self.shape = shape;
+    }
+
+    pub fn shape(&self) -> EnvelopeShape {
+        self.shape
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// False once the envelope has finished and is back at 0
+    pub fn is_active(&self) -> bool {
+        self.stage != Stage::Idle
+    }
+
+    /// Update the gate, call every tick or on each change
+    ///
+    /// A rising gate (re)starts the attack from the current level, so
+    /// retriggers don't click. A falling gate releases an ADSR, AD envelopes
+    /// ignore it.
+    pub fn gate(&mut self, high: bool) {
+        match (self.gate, high) {
+            (false, true) => self.enter(Stage::Attack),
+            (true, false)
+                if matches!(self.shape, EnvelopeShape::Adsr { .. })
+                    && self.stage != Stage::Idle =>
+            {
+                self.enter(Stage::Release)
+            }
+            _ => {}
+        }
+        self.gate = high;
+    }
+
+    /// Start the attack without a gate, an ADSR goes straight on to release
+    /// after its decay
+    pub fn trigger(&mut self) {
+        self.gate = false;
+        self.enter(Stage::Attack);
+    }
+
+    /// Advance one tick and return the new level
+    pub fn tick(&mut self) -> Sample {
+        match self.stage {
+            Stage::Idle => self.level = 0,
+            Stage::Sustain => self.level = self.sustain(),
+            Stage::Attack | Stage::Decay | Stage::Release => {
+                self.elapsed += 1;
+                let target = match self.stage {
+                    Stage::Attack => LEVEL_MAX,
+                    Stage::Decay => self.sustain(),
+                    _ => 0,
+                };
+                let progress = i64::from(curve(self.elapsed, self.total));
+                self.level = self.start
+                    + (i64::from(target - self.start) * progress / i64::from(LEVEL_MAX)) as i32;
+                if self.elapsed >= self.total {
+                    self.finish();
+                }
+            }
+        }
+        self.value()
+    }
+
+    /// Level after the last tick
+    pub fn value(&self) -> Sample {
+        Sample::from((self.level * Sample::MAX + LEVEL_MAX / 2) / LEVEL_MAX)
+    }
+
+    fn sustain(&self) -> i32 {
+        match self.shape {
+            EnvelopeShape::Ad { .. } => 0,
+            EnvelopeShape::Adsr { sustain, .. } => {
+                sustain.to_clamped().clamp(0, Sample::MAX) * LEVEL_MAX / Sample::MAX
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        match (self.stage, self.shape) {
+            (Stage::Attack, _) => self.enter(Stage::Decay),
+            (Stage::Decay, EnvelopeShape::Adsr { .. }) if self.gate => self.stage = Stage::Sustain,
+            (Stage::Decay, EnvelopeShape::Adsr { .. }) => self.enter(Stage::Release),
+            _ => self.stage = Stage::Idle,
+        }
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        let time = match (stage, self.shape) {
+            (Stage::Attack, EnvelopeShape::Ad { attack, .. })
+            | (Stage::Attack, EnvelopeShape::Adsr { attack, .. }) => attack,
+            (Stage::Decay, EnvelopeShape::Ad { decay, .. })
+            | (Stage::Decay, EnvelopeShape::Adsr { decay, .. }) => decay,
+            (Stage::Release, EnvelopeShape::Adsr { release, .. }) => release,
+            _ => Millis::new(0),
+        };
+        self.stage = stage;
+        self.start = self.level;
+        self.elapsed = 0;
+        self.total = time.ticks(self.rate).max(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Envelope, EnvelopeShape, Stage};
+    use crate::units::{Hertz, Millis};
+    use crate::Sample;
+
+    #[test]
+    fn test_ad_envelope() {
+        let shape = EnvelopeShape::Ad {
+            attack: Millis::new(10),
+            decay: Millis::new(20),
+        };
+        let mut envelope = Envelope::new(shape, Hertz::new(1000));
+        assert_eq!(envelope.tick(), Sample::from(0_i32));
+
+        envelope.gate(true);
+        // exponential, more than half way at half the attack time
+        let mut levels = [Sample::from(0_i32); 10];
+        for level in levels.iter_mut() {
+            *level = envelope.tick();
+        }
+        assert!(levels[4] > Sample::from(Sample::MAX / 2));
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(levels[9], Sample::from(Sample::MAX));
+        assert_eq!(envelope.stage(), Stage::Decay);
+
+        // AD runs to the end whatever the gate does
+        envelope.gate(false);
+        for _ in 0..20 {
+            envelope.tick();
+        }
+        assert_eq!(envelope.value(), Sample::from(0_i32));
+        assert!(!envelope.is_active());
+    }
+
+    #[test]
+    fn test_adsr_envelope() {
+        let shape = EnvelopeShape::Adsr {
+            attack: Millis::new(1),
+            decay: Millis::new(1),
+            sustain: Sample::from(1000_i32),
+            release: Millis::new(100),
+        };
+        // audio rate, 48 ticks per millisecond
+        let mut envelope = Envelope::new(shape, Hertz::new(48_000));
+        envelope.gate(true);
+        for _ in 0..200 {
+            envelope.tick();
+        }
+        assert_eq!(envelope.stage(), Stage::Sustain);
+        assert_eq!(envelope.value(), Sample::from(1000_i32));
+
+        envelope.gate(false);
+        let released = envelope.tick();
+        assert!(released < Sample::from(1000_i32) && released > Sample::from(900_i32));
+        // retrigger starts the attack from where the release got to
+        envelope.gate(true);
+        assert_eq!(envelope.stage(), Stage::Attack);
+        assert!(envelope.tick() > released);
+
+        envelope.gate(false);
+        for _ in 0..4800 {
+            envelope.tick();
+        }
+        assert!(!envelope.is_active());
+        assert_eq!(envelope.value(), Sample::from(0_i32));
+    }
+}
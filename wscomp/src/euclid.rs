@@ -0,0 +1,235 @@
+//! Euclidean rhythms
+//!
+//! [`Euclid`] spreads `fills` hits as evenly as possible over `steps` steps
+//! with Bjorklund's algorithm, then rotates the result. Many traditional
+//! rhythms fall out of it, like the tresillo (3 in 8, `x..x..x.`) or the
+//! cinquillo (5 in 8, `x.xx.xx.`). Tick it once per clock step:
+//!
+//! ```
+//! # use embedded_hal::digital::{InputPin, OutputPin};
+//! # use wscomp::euclid::Euclid;
+//! # use wscomp::pulse::{Edge, PulseInput, PulseOut};
+//! # use wscomp::units::Millis;
+//! # fn card<I: InputPin, O: OutputPin>(
+//! #     mut clock_in: PulseInput<I>,
+//! #     mut pulse_out: PulseOut<O>,
+//! #     now_micros: u64,
+//! # ) {
+//! let mut euclid = Euclid::new(16, 5, 0);
+//! loop {
+//!     if clock_in.poll(now_micros) == Some(Edge::Rising) && euclid.tick() {
+//!         pulse_out.trigger(Millis::new(10), now_micros);
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::sequence::Pattern;
+
+/// Bjorklund's algorithm, bit 0 is the first step
+fn bjorklund(steps: u8, fills: u8) -> u32 {
+    let steps = usize::from(steps);
+    let fills = usize::from(fills);
+    if fills == 0 {
+        return 0;
+    }
+    if fills >= steps {
+        return u32::MAX >> (Euclid::MAX_STEPS - steps);
+    }
+
+    // groups of steps as (bits, length), the first `heads` start with a hit,
+    // the `tails` after them are the remainder still to be spread out
+    let mut groups = [(0_u32, 0_u8); Euclid::MAX_STEPS];
+    for (index, group) in groups.iter_mut().enumerate().take(steps) {
+        *group = (u32::from(index < fills), 1);
+    }
+    let mut heads = fills;
+    let mut tails = steps - fills;
+    while tails > 1 {
+        // append one tail to each head
+        let pairs = heads.min(tails);
+        for index in 0..pairs {
+            let (bits, length) = groups[index];
+            let (tail_bits, tail_length) = groups[heads + index];
+            groups[index] = (bits | tail_bits << length, length + tail_length);
+        }
+        // whichever is left over becomes the new tails, straight after the
+        // heads, leftover heads are already there
+        if tails > heads {
+            groups.copy_within(heads + pairs..heads + tails, pairs);
+        }
+        (heads, tails) = (pairs, heads.abs_diff(tails));
+    }
+
+    let mut pattern = 0;
+    let mut offset = 0;
+    for &(bits, length) in &groups[..heads + tails] {
+        pattern |= bits << offset;
+        offset += length;
+    }
+    pattern
+}
+
+/// Euclidean rhythm, with steps, fills and rotation settable at any time
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Euclid {
+    steps: u8,
+    fills: u8,
+    rotation: u8,
+    /// Rotated gates, bit 0 is the first step
+    gates: u32,
+    position: u8,
+}
+
+impl Euclid {
+    pub const MAX_STEPS: usize = Pattern::MAX_STEPS;
+
+    /// `fills` hits over `steps` steps, rotated later by `rotation` steps
+    ///
+    /// Steps are clamped to 1..=32, fills to the number of steps.
+    pub fn new(steps: u8, fills: u8, rotation: u8) -> Self {
+        let mut euclid = Euclid {
+            steps: 1,
+            fills,
+            rotation,
+            gates: 0,
+            position: 0,
+        };
+        euclid.set_steps(steps);
+        euclid
+    }
+
+    /// Change the number of steps, keeping the position if it still fits
+    ///
+    /// Fills are remembered, so shrinking below them and back is undone.
+    pub fn set_steps(&mut self, steps: u8) {
+        self.steps = steps.clamp(1, Self::MAX_STEPS as u8);
+        self.position %= self.steps;
+        self.update();
+    }
+
+    pub fn set_fills(&mut self, fills: u8) {
+        self.fills = fills;
+        self.update();
+    }
+
+    pub fn set_rotation(&mut self, rotation: u8) {
+        self.rotation = rotation;
+        self.update();
+    }
+
+    pub fn steps(&self) -> u8 {
+        self.steps
+    }
+
+    /// Fills played, at most the number of steps
+    pub fn fills(&self) -> u8 {
+        self.fills.min(self.steps)
+    }
+
+    pub fn rotation(&self) -> u8 {
+        self.rotation
+    }
+
+    /// Gates as a bitmask, bit 0 is the first step
+    pub fn gates(&self) -> u32 {
+        self.gates
+    }
+
+    /// Gate for a step, wraps around the number of steps
+    pub fn gate(&self, step: usize) -> bool {
+        self.gates & (1 << (step % usize::from(self.steps))) != 0
+    }
+
+    /// The step the next [`Euclid::tick`] plays
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    /// Gate for the current step, then advance, call once per clock step
+    pub fn tick(&mut self) -> bool {
+        let gate = self.gate(self.position.into());
+        self.position = (self.position + 1) % self.steps;
+        gate
+    }
+
+    /// Go back to the first step
+    pub fn reset(&mut self) {
+        self.position = 0;
+    }
+
+    /// The rhythm as a [`Pattern`], for cards that edit or store it
+    pub fn to_pattern(&self) -> Pattern {
+        Pattern::from_gates(self.steps, self.gates)
+    }
+
+    fn update(&mut self) {
+        let gates = bjorklund(self.steps, self.fills());
+        let steps = u32::from(self.steps);
+        let rotation = u32::from(self.rotation) % steps;
+        let mask = u32::MAX >> (Self::MAX_STEPS as u32 - steps);
+        // rotate within the low `steps` bits, the shift right is split in
+        // two so a rotation of 0 doesn't shift by 32
+        self.gates = (gates << rotation | gates >> (steps - rotation - 1) >> 1) & mask;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Euclid;
+
+    /// Gates as a string, `x` for a hit
+    fn rhythm(euclid: &Euclid) -> [u8; 16] {
+        let mut text = [b' '; 16];
+        for (step, char) in text.iter_mut().enumerate().take(euclid.steps().into()) {
+            *char = if euclid.gate(step) { b'x' } else { b'.' };
+        }
+        text
+    }
+
+    #[test]
+    fn test_euclid_patterns() {
+        let cases: [(u8, u8, &[u8; 16]); 7] = [
+            (8, 3, b"x..x..x.        "),
+            (8, 5, b"x.xx.xx.        "),
+            (13, 5, b"x..x.x..x.x..   "),
+            (16, 4, b"x...x...x...x..."),
+            (9, 4, b"x.x.x.x..       "),
+            (4, 0, b"....            "),
+            (4, 9, b"xxxx            "),
+        ];
+        for (steps, fills, expected) in cases {
+            assert_eq!(&rhythm(&Euclid::new(steps, fills, 0)), expected);
+        }
+        assert_eq!(Euclid::new(32, 32, 0).gates(), u32::MAX);
+        assert_eq!(Euclid::new(4, 9, 0).fills(), 4);
+    }
+
+    #[test]
+    fn test_euclid_rotation_and_ticks() {
+        let mut euclid = Euclid::new(8, 3, 1);
+        assert_eq!(&rhythm(&euclid), b".x..x..x        ");
+        euclid.set_rotation(9);
+        assert_eq!(euclid.gates(), 0b1001_0010);
+        euclid.set_rotation(0);
+
+        let ticks: [bool; 10] = core::array::from_fn(|_| euclid.tick());
+        assert_eq!(
+            ticks,
+            [true, false, false, true, false, false, true, false, true, false]
+        );
+        assert_eq!(euclid.position(), 2);
+        // shrinking keeps the position in range
+        euclid.set_steps(2);
+        assert_eq!(euclid.position(), 0);
+        euclid.set_steps(8);
+        assert_eq!(euclid.fills(), 3);
+        euclid.reset();
+        assert!(euclid.tick());
+
+        let pattern = euclid.to_pattern();
+        assert_eq!(pattern.len(), 8);
+        assert_eq!(pattern.gates(), euclid.gates());
+    }
+}
@@ -0,0 +1,321 @@
+//! Audio and control rate filters
+//!
+//! [`OnePole`] is a gentle 6dB/octave filter for tone controls, and for lag
+//! on CV (slew, or smoothing a stepped source):
+//!
+//! ```
+//! # use wscomp::block::SampleBlock;
+//! # use wscomp::filter::{OnePole, PoleMode};
+//! # use wscomp::inputs::MuxState;
+//! # let mux_state = MuxState::default();
+//! # let mut block = SampleBlock::<64>::silent();
+//! let mut tone = OnePole::new(PoleMode::LowPass, mux_state.x_knob);
+//! for sample in block.iter_mut() {
+//!     *sample = tone.process(*sample);
+//! }
+//! ```
+//!
+//! [`Svf`] is a resonant 12dB/octave state variable filter for audio at
+//! 48kHz, with low, band, high pass and notch outputs all at once.
+//!
+//! Cutoffs come from a [`Sample`] (a knob or CV), or for [`OnePole`] from a
+//! frequency when it needs to be exact.
+
+use crate::units::Hertz;
+use crate::{Sample, SampleUpdate};
+
+/// Fixed point one, for coefficients
+const ONE: i64 = 1 << 16;
+
+/// [`Svf`] frequency coefficient, `2 sin(pi fc / 48kHz)` in Q16, for
+/// cutoffs from 20Hz to 6.75kHz spaced evenly in pitch (8.4 octaves)
+const SVF_CUTOFFS: [u16; 65] = [
+    172, 188, 206, 225, 247, 270, 296, 324, 355, 389, 426, 467, 511, 560, 613, 672, 736, 806, 882,
+    966, 1058, 1159, 1270, 1391, 1523, 1668, 1827, 2001, 2191, 2400, 2629, 2879, 3153, 3453, 3782,
+    4142, 4537, 4968, 5441, 5959, 6526, 7147, 7827, 8572, 9387, 10279, 11256, 12325, 13495, 14775,
+    16175, 17707, 19381, 21212, 23211, 25395, 27779, 30378, 33211, 36294, 39646, 43285, 47228,
+    51491, 56086,
+];
+/// [`Svf`] damping (1/Q) at no resonance, Q16, a Butterworth response
+const SVF_DAMPING_MAX: i64 = 92_682;
+/// [`Svf`] damping at full resonance, Q16, a Q of 16, close to ringing
+const SVF_DAMPING_MIN: i64 = 4_096;
+/// [`Svf`] state fraction bits, below the [`Sample`] LSB
+const SVF_SHIFT: u32 = 8;
+/// [`Svf`] state limit, 8x full scale, so resonant peaks have headroom but
+/// can't run away
+const SVF_LIMIT: i64 = (Sample::OFFSET as i64 * 8) << SVF_SHIFT;
+
+/// Which side of the cutoff a filter passes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PoleMode {
+    LowPass,
+    HighPass,
+}
+
+/// First order low or high pass filter
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OnePole {
+    mode: PoleMode,
+    /// How far the low pass state moves towards the input each sample, Q16
+    coefficient: i64,
+    /// Low pass output, Q16
+    state: i64,
+    output: Sample,
+}
+
+impl OnePole {
+    pub fn new(mode: PoleMode, cutoff: Sample) -> Self {
+        let mut filter = OnePole {
+            mode,
+            coefficient: ONE,
+            state: 0,
+            output: Sample::from(0_i32),
+        };
+        filter.set_cutoff(cutoff);
+        filter
+    }
+
+    pub fn set_mode(&mut self, mode: PoleMode) {
+        self.mode = mode;
+    }
+
+    /// Cutoff from a knob or CV, [`Sample::MIN`] is the lowest
+    ///
+    /// Squared, so the knob spends more of its travel on low cutoffs, where
+    /// ears (and CV lag times) are more sensitive. At 48kHz the range is
+    /// about 0.1Hz to fully open.
+    pub fn set_cutoff(&mut self, cutoff: Sample) {
+        let position = i64::from(cutoff.to_clamped() - Sample::MIN + 1);
+        self.coefficient = (position * position * ONE / (1 << 24)).max(1);
+    }
+
+    /// Cutoff at `frequency` for a filter run `rate` times a second
+    pub fn set_cutoff_hertz(&mut self, frequency: Hertz, rate: Hertz) {
+        // w = 2 pi fc / fs, then w / (1 + w) keeps the coefficient below one
+        // and close to the exact 1 - e^-w at low cutoffs
+        let w = 411_775 * i64::from(frequency.millihertz()) / i64::from(rate.millihertz());
+        self.coefficient = (w * ONE / (w + ONE)).max(1);
+    }
+
+    /// Filter one sample
+    pub fn process(&mut self, input: Sample) -> Sample {
+        let input = i64::from(input.to_clamped()) << 16;
+        self.state += (input - self.state) * self.coefficient / ONE;
+        let low = self.state;
+        let output = match self.mode {
+            PoleMode::LowPass => low,
+            PoleMode::HighPass => input - low,
+        };
+        self.output = Sample::from(((output + (1 << 15)) >> 16) as i32);
+        self.output
+    }
+
+    /// Output from the last [`OnePole::process`]
+    pub fn value(&self) -> Sample {
+        self.output
+    }
+
+    /// Jump straight to settled at `value`, with no lag
+    pub fn reset(&mut self, value: Sample) {
+        self.state = i64::from(value.to_clamped()) << 16;
+        self.output = match self.mode {
+            PoleMode::LowPass => value,
+            PoleMode::HighPass => Sample::from(0_i32),
+        };
+    }
+}
+
+impl SampleUpdate<Sample> for OnePole {
+    /// Filter a new value, for use as a CV smoother
+    fn update(&mut self, value: Sample) {
+        self.process(value);
+    }
+}
+
+/// All four outputs of an [`Svf`] for one sample, clamped to full scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SvfOutput {
+    pub low: Sample,
+    pub band: Sample,
+    pub high: Sample,
+    pub notch: Sample,
+}
+
+/// Chamberlin state variable filter, for audio at 48kHz
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Svf {
+    /// Frequency coefficient, Q16
+    frequency: i64,
+    /// 1/Q, Q16
+    damping: i64,
+    /// Low and band pass state, in samples shifted up by SVF_SHIFT
+    low: i64,
+    band: i64,
+}
+
+impl Svf {
+    pub fn new(cutoff: Sample, resonance: Sample) -> Self {
+        let mut filter = Svf {
+            frequency: 0,
+            damping: SVF_DAMPING_MAX,
+            low: 0,
+            band: 0,
+        };
+        filter.set_cutoff(cutoff);
+        filter.set_resonance(resonance);
+        filter
+    }
+
+    /// Cutoff from a knob or CV, [`Sample::MIN`] is 20Hz and
+    /// [`Sample::MAX`] 6.75kHz, with equal steps in pitch
+    pub fn set_cutoff(&mut self, cutoff: Sample) {
+        // 12 bit position, top 6 bits pick the table step
+        let position = (cutoff.to_clamped() - Sample::MIN) as usize;
+        let index = position >> 6;
+        let fraction = (position & 0x3f) as i64;
+        let low = i64::from(SVF_CUTOFFS[index]);
+        let high = i64::from(SVF_CUTOFFS[index + 1]);
+        self.frequency = low + (high - low) * fraction / 0x40;
+    }
+
+    /// Resonance from a knob or CV, [`Sample::MIN`] is none (a Q of 0.7)
+    /// and [`Sample::MAX`] is a Q of 16
+    pub fn set_resonance(&mut self, resonance: Sample) {
+        let position = i64::from(resonance.to_clamped() - Sample::MIN);
+        let span = i64::from(Sample::MAX - Sample::MIN);
+        self.damping = SVF_DAMPING_MAX - (SVF_DAMPING_MAX - SVF_DAMPING_MIN) * position / span;
+    }
+
+    /// Filter one sample
+    pub fn process(&mut self, input: Sample) -> SvfOutput {
+        let input = i64::from(input.to_clamped()) << SVF_SHIFT;
+        self.low = (self.low + self.frequency * self.band / ONE).clamp(-SVF_LIMIT, SVF_LIMIT);
+        let high = input - self.low - self.damping * self.band / ONE;
+        self.band = (self.band + self.frequency * high / ONE).clamp(-SVF_LIMIT, SVF_LIMIT);
+
+        let output = |value: i64| {
+            let value = (value + (1 << (SVF_SHIFT - 1))) >> SVF_SHIFT;
+            Sample::from(value.clamp(Sample::MIN.into(), Sample::MAX.into()) as i32)
+        };
+        SvfOutput {
+            low: output(self.low),
+            band: output(self.band),
+            high: output(high),
+            notch: output(high + self.low),
+        }
+    }
+
+    /// Clear the filter state, silencing any ringing
+    pub fn reset(&mut self) {
+        self.low = 0;
+        self.band = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OnePole, PoleMode, Svf};
+    use crate::random::Rng;
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    /// Peak output for an input alternating between +1000 and -1000
+    fn nyquist_peak(filter: &mut OnePole) -> i32 {
+        (0..400)
+            .map(|index| {
+                filter.process(Sample::from(if index % 2 == 0 { 1000_i32 } else { -1000 }))
+            })
+            .skip(200)
+            .map(|sample| sample.to_clamped().abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_one_pole_low_pass() {
+        let mut low = OnePole::new(PoleMode::LowPass, Sample::from(0_i32));
+        for _ in 0..200 {
+            low.process(Sample::from(1000_i32));
+        }
+        assert_eq!(low.value(), Sample::from(1000_i32));
+        assert!(nyquist_peak(&mut low) < 150);
+
+        low.set_cutoff(Sample::from(Sample::MAX));
+        assert_eq!(nyquist_peak(&mut low), 1000);
+
+        // 1kHz at 48kHz moves about 12% of the way per sample
+        low.set_cutoff_hertz(Hertz::new(1000), Hertz::new(48_000));
+        low.reset(Sample::from(0_i32));
+        assert_eq!(low.process(Sample::from(1000_i32)), Sample::from(116_i32));
+    }
+
+    #[test]
+    fn test_one_pole_high_pass() {
+        let mut high = OnePole::new(PoleMode::HighPass, Sample::from(-1024_i32));
+        let first = high.process(Sample::from(1000_i32));
+        assert!(first > Sample::from(900_i32));
+        for _ in 0..2000 {
+            high.process(Sample::from(1000_i32));
+        }
+        // DC blocked
+        assert_eq!(high.value(), Sample::from(0_i32));
+        assert!(nyquist_peak(&mut high) > 950);
+    }
+
+    /// Peak output of each filter response, after settling, for a sine at
+    /// `period` samples per cycle
+    fn svf_peaks(filter: &mut Svf, period: usize) -> [i32; 4] {
+        let mut peaks = [0; 4];
+        for index in 0..(period * 40) {
+            let angle = 2.0 * core::f64::consts::PI * index as f64 / period as f64;
+            let output = filter.process(Sample::from((angle.sin() * 1000.0) as i32));
+            if index >= period * 30 {
+                let levels = [output.low, output.band, output.high, output.notch];
+                for (peak, level) in peaks.iter_mut().zip(levels) {
+                    *peak = (*peak).max(level.to_clamped().abs());
+                }
+            }
+        }
+        peaks
+    }
+
+    #[test]
+    fn test_svf_responses() {
+        // 0 is a cutoff of 367Hz, about 131 samples per cycle at 48kHz
+        let mut svf = Svf::new(Sample::from(0_i32), Sample::from(Sample::MIN));
+        let [low, band, high, notch] = svf_peaks(&mut svf, 1300);
+        assert!(low > 950 && band < 150 && high < 50 && notch > 950);
+        let [low, band, high, notch] = svf_peaks(&mut svf, 131);
+        // -3dB for low and high at the cutoff, no resonant peak
+        assert!((650..760).contains(&low) && (650..760).contains(&high));
+        assert!(band < 1000 && notch < 100);
+        let [low, band, high, _] = svf_peaks(&mut svf, 13);
+        assert!(low < 50 && band < 150 && high > 950);
+
+        // a Q of 16 at the cutoff, held within the state limit
+        svf.set_resonance(Sample::from(Sample::MAX));
+        let [low, band, high, _] = svf_peaks(&mut svf, 131);
+        assert!([low, band, high].iter().all(|&peak| peak >= Sample::MAX));
+    }
+
+    #[test]
+    fn test_svf_stays_stable() {
+        // top cutoff, full resonance, full scale noise
+        let mut svf = Svf::new(Sample::from(Sample::MAX), Sample::from(Sample::MAX));
+        let mut rng = Rng::new(1);
+        for _ in 0..48_000 {
+            svf.process(rng.next_sample());
+        }
+        // and rings down to silence, rather than getting stuck
+        for _ in 0..4800 {
+            svf.process(Sample::from(0_i32));
+        }
+        let output = svf.process(Sample::from(0_i32));
+        assert!(output.low.to_clamped().abs() <= 1 && output.band.to_clamped().abs() <= 1);
+    }
+}
@@ -0,0 +1,185 @@
+//! Small fixed coefficient FIR filters
+//!
+//! [`Fir`] convolves a [`Sample`] stream with a kernel of up to
+//! [`MAX_TAPS`] Q15 coefficients. FIR filters have no feedback, so they
+//! can't ring or go unstable, and symmetric kernels delay every frequency
+//! by the same amount. That makes them the filter for decimation before
+//! dropping samples, and for smoothing without smearing the timing:
+//!
+//! ```
+//! # use wscomp::fir::{Fir, HALF_BAND};
+//! # use wscomp::Sample;
+//! # let oversampled = [Sample::from(0_i32); 64];
+//! # let mut output = Vec::new();
+//! let mut anti_alias = Fir::new(&HALF_BAND);
+//! for pair in oversampled.chunks_exact(2) {
+//!     output.push(anti_alias.decimate(pair));
+//! }
+//! ```
+//!
+//! The canned kernels have unity gain at DC. Custom kernels are `i16`
+//! arrays where 32768 is a gain of 1.
+
+use crate::block::SampleBlock;
+use crate::Sample;
+
+/// Most taps in a kernel
+///
+/// Each product of a clamped input and a coefficient is at most 2^26, from
+/// -2048 × -32768, so 31 of them still fit the `i32` accumulator and 32
+/// could overflow it.
+pub const MAX_TAPS: usize = 31;
+
+/// Half-band low pass, -6dB at a quarter of the sample rate and below -70dB
+/// from 0.4 of it, for 2x decimation
+///
+/// Every other tap is zero, a property of half-band filters.
+pub const HALF_BAND: [i16; 19] = [
+    11, 0, -151, 0, 709, 0, -2397, 0, 10018, 16388, 10018, 0, -2397, 0, 709, 0, -151, 0, 11,
+];
+
+/// Binomial low pass, [1, 4, 6, 4, 1] / 16, -3dB at about 0.13 of the
+/// sample rate and no ripple, for smoothing control signals
+pub const GENTLE_LOW_PASS: [i16; 5] = [2048, 8192, 12288, 8192, 2048];
+
+/// FIR filter over a `TAPS` long kernel
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Fir<const TAPS: usize> {
+    kernel: &'static [i16; TAPS],
+    /// Inputs, as a ring with the oldest at `position`
+    history: [i32; TAPS],
+    position: usize,
+}
+
+impl<const TAPS: usize> Fir<TAPS> {
+    /// New filter over `kernel`, starting from silence
+    pub fn new(kernel: &'static [i16; TAPS]) -> Self {
+        const { assert!(TAPS > 0 && TAPS <= MAX_TAPS, "FIR kernel too long") };
+        Fir {
+            kernel,
+            history: [0; TAPS],
+            position: 0,
+        }
+    }
+
+    /// Delay through the filter in samples, exact for symmetric kernels
+    pub fn delay(&self) -> usize {
+        (TAPS - 1) / 2
+    }
+
+    /// Add an input without working out an output, for the samples a
+    /// decimator drops
+    pub fn push(&mut self, input: Sample) {
+        self.history[self.position] = input.to_clamped();
+        self.position = (self.position + 1) % TAPS;
+    }
+
+    /// Output for the inputs so far
+    pub fn output(&self) -> Sample {
+        // newest input first, running back through the ring
+        let (recent, oldest) = self.history.split_at(self.position);
+        let sum: i32 = recent
+            .iter()
+            .rev()
+            .chain(oldest.iter().rev())
+            .zip(self.kernel.iter())
+            .map(|(&input, &coefficient)| input * i32::from(coefficient))
+            .sum();
+        Sample::from((sum + (1 << 14)) >> 15)
+    }
+
+    /// Filter one sample
+    pub fn process(&mut self, input: Sample) -> Sample {
+        self.push(input);
+        self.output()
+    }
+
+    /// Filter every sample in a block in place
+    pub fn process_block<const N: usize>(&mut self, block: &mut SampleBlock<N>) {
+        #[cfg(feature = "armv6m")]
+        crate::armv6m::fir_block(self.kernel, &mut self.history, &mut self.position, block);
+        #[cfg(not(feature = "armv6m"))]
+        block.map(|sample| self.process(sample));
+    }
+
+    /// Filter `inputs` and keep only the last output, decimating by
+    /// `inputs.len()`
+    pub fn decimate(&mut self, inputs: &[Sample]) -> Sample {
+        for &input in inputs {
+            self.push(input);
+        }
+        self.output()
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [0; TAPS];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Fir, GENTLE_LOW_PASS, HALF_BAND, MAX_TAPS};
+    use crate::Sample;
+
+    #[test]
+    fn test_fir_impulse() {
+        // an asymmetric kernel comes out in order
+        static KERNEL: [i16; 3] = [16384, 8192, -4096];
+        let mut fir = Fir::new(&KERNEL);
+        let impulse = [1000, 0, 0, 0].map(|value| fir.process(Sample::from(value)));
+        assert_eq!(impulse, [500, 250, -125, 0].map(Sample::from));
+
+        for kernel in [&HALF_BAND[..], &GENTLE_LOW_PASS[..]] {
+            let gain: i32 = kernel.iter().map(|&tap| i32::from(tap)).sum();
+            assert_eq!(gain, 1 << 15);
+        }
+        // settles to DC unchanged, with extremes that don't overflow
+        let mut fir = Fir::new(&GENTLE_LOW_PASS);
+        for _ in 0..5 {
+            fir.process(Sample::from(Sample::MIN));
+        }
+        assert_eq!(fir.output(), Sample::from(Sample::MIN));
+        assert_eq!(fir.delay(), 2);
+    }
+
+    #[test]
+    fn test_half_band_decimation() {
+        let mut fir = Fir::new(&HALF_BAND);
+        // a tone at 0.45 of the rate aliases when decimated, so it's removed
+        let mut peak = 0;
+        for index in 0..200 {
+            let phase = |tick: f64| (tick * 0.45 * core::f64::consts::TAU).cos();
+            let pair = [2 * index, 2 * index + 1]
+                .map(|tick| Sample::from((phase(f64::from(tick)) * 2000.0) as i32));
+            let output = fir.decimate(&pair);
+            if index > 20 {
+                peak = peak.max(output.to_clamped().abs());
+            }
+        }
+        assert!(peak <= 1, "{peak}");
+
+        // while a low tone passes
+        fir.reset();
+        let mut peak = 0;
+        for index in 0..200 {
+            let phase = |tick: f64| (tick * 0.05 * core::f64::consts::TAU).cos();
+            let pair = [2 * index, 2 * index + 1]
+                .map(|tick| Sample::from((phase(f64::from(tick)) * 2000.0) as i32));
+            peak = peak.max(fir.decimate(&pair).to_clamped().abs());
+        }
+        assert!(peak.abs_diff(2000) <= 5, "{peak}");
+    }
+
+    #[test]
+    fn test_fir_max_taps_full_scale() {
+        // the largest products on every tap, just inside the accumulator
+        static KERNEL: [i16; MAX_TAPS] = [i16::MIN; MAX_TAPS];
+        let mut fir = Fir::new(&KERNEL);
+        let mut output = Sample::from(0_i32);
+        for _ in 0..MAX_TAPS {
+            output = fir.process(Sample::from(Sample::MIN));
+        }
+        assert_eq!(output.to_unclamped(), 31 * 2048);
+    }
+}
@@ -0,0 +1,49 @@
+//! Logging that compiles away without the `defmt` feature
+//!
+//! Same idea as embassy's `fmt.rs`: the macros forward to defmt when it's
+//! enabled, and otherwise only borrow their arguments, so there are no
+//! unused variable warnings.
+
+#![allow(unused_macros)]
+
+macro_rules! debug {
+    ($($arg:expr),* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::debug!($($arg),*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $arg ),*);
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:expr),* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::error!($($arg),*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $arg ),*);
+        }
+    };
+}
+
+/// `defmt::Format` with the `defmt` feature, otherwise any type
+///
+/// Used to bound error types that wscomp logs, like [`InputAdc::Error`].
+///
+/// [`InputAdc::Error`]: crate::inputs::InputAdc::Error
+#[cfg(feature = "defmt")]
+pub trait MaybeFormat: defmt::Format {}
+#[cfg(feature = "defmt")]
+impl<T: defmt::Format> MaybeFormat for T {}
+
+/// `defmt::Format` with the `defmt` feature, otherwise any type
+///
+/// Used to bound error types that wscomp logs, like [`InputAdc::Error`].
+///
+/// [`InputAdc::Error`]: crate::inputs::InputAdc::Error
+#[cfg(not(feature = "defmt"))]
+pub trait MaybeFormat {}
+#[cfg(not(feature = "defmt"))]
+impl<T> MaybeFormat for T {}
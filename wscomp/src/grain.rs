@@ -0,0 +1,224 @@
+//! Grains, short windowed reads from a sample buffer
+//!
+//! A [`Grain`] plays `length` samples from a start point in a buffer, at
+//! its own rate, faded in and out by a [`Window`]. Granular clouds,
+//! freezes and stutters are all grains: many overlapping ones scattered
+//! over a recording, or one repeated from the same spot.
+//!
+//! ```
+//! # use embedded_hal::digital::OutputPin;
+//! # use embedded_hal::spi::SpiBus;
+//! # use wscomp::dac::Dac;
+//! # use wscomp::grain::{Grain, Window};
+//! # use wscomp::random::Rng;
+//! # use wscomp::Sample;
+//! # fn card<S: SpiBus, C: OutputPin>(
+//! #     mut dac: Dac<S, C>,
+//! #     buffer: &[Sample],
+//! #     mut rng: Rng,
+//! # ) -> Result<(), S::Error> {
+//! let mut grains = [Grain::new(0, 2_400, Grain::UNITY_RATE, Window::Hann); 4];
+//! loop {
+//!     let mut mixed = Sample::from(0_i32);
+//!     for grain in grains.iter_mut() {
+//!         match grain.next(buffer) {
+//!             Some(sample) => mixed += sample,
+//!             None => grain.restart(rng.below(buffer.len() as u32) as usize),
+//!         }
+//!     }
+//!     dac.write_pair(mixed, mixed)?;
+//! }
+//! # }
+//! ```
+//!
+//! The buffer is treated as a loop, so grains can read straight from a
+//! ring buffer that's still recording, and run off either end.
+
+use crate::tables;
+use crate::Sample;
+
+/// Window shape faded over each grain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Window {
+    /// Raised cosine, smooth at the ends, see [`tables::hann`]
+    Hann,
+    /// Straight fades in and out, see [`tables::triangle`]
+    Triangular,
+    /// No fade, for stutters where the click is the point
+    Rectangular,
+}
+
+impl Window {
+    /// Gain, 0 to 65535, at a Q16 `position` from 0 to 1 across the window
+    pub fn gain(self, position: u32) -> u32 {
+        match self {
+            Window::Hann => tables::hann(position),
+            Window::Triangular => tables::triangle(position),
+            Window::Rectangular => 65_535,
+        }
+    }
+}
+
+/// One grain playing from a buffer
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Grain {
+    /// Read position in the buffer, Q16
+    position: i64,
+    /// Buffer samples per output sample, Q16, negative plays backwards
+    rate: i32,
+    length: u32,
+    elapsed: u32,
+    window: Window,
+}
+
+impl Grain {
+    /// Playback rate at the recorded pitch
+    pub const UNITY_RATE: i32 = 1 << 16;
+
+    /// New grain of `length` output samples from buffer index `start`
+    ///
+    /// `rate` is Q16 buffer samples per output sample, so
+    /// [`Grain::UNITY_RATE`] plays at pitch, twice that an octave up, and
+    /// negative rates play backwards.
+    pub fn new(start: usize, length: usize, rate: i32, window: Window) -> Self {
+        Grain {
+            position: (start as i64) << 16,
+            rate,
+            length: length as u32,
+            elapsed: 0,
+            window,
+        }
+    }
+
+    /// Start again from buffer index `start`, keeping the length, rate and
+    /// window
+    pub fn restart(&mut self, start: usize) {
+        self.position = (start as i64) << 16;
+        self.elapsed = 0;
+    }
+
+    /// Change the rate, from the next sample
+    pub fn set_rate(&mut self, rate: i32) {
+        self.rate = rate;
+    }
+
+    /// Change the rate by `semitones`, from the next sample
+    pub fn set_rate_semitones(&mut self, semitones: i32) {
+        let octave = semitones.div_euclid(12);
+        let ratio = tables::exp2((semitones.rem_euclid(12) << 16) as u32 / 12) as i32;
+        self.rate = if octave >= 0 {
+            ratio << octave
+        } else {
+            ratio >> -octave
+        };
+    }
+
+    pub fn rate(&self) -> i32 {
+        self.rate
+    }
+
+    pub fn set_window(&mut self, window: Window) {
+        self.window = window;
+    }
+
+    pub fn window(&self) -> Window {
+        self.window
+    }
+
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.length
+    }
+
+    /// Next windowed sample from `buffer`, `None` once the grain is over
+    ///
+    /// Reads between buffer samples are linearly interpolated.
+    pub fn next(&mut self, buffer: &[Sample]) -> Option<Sample> {
+        if self.is_finished() || buffer.is_empty() {
+            return None;
+        }
+        let len = buffer.len() as i64;
+        let index = (self.position >> 16).rem_euclid(len) as usize;
+        let fraction = (self.position & 0xffff) as i32;
+        let next = if index + 1 == buffer.len() {
+            0
+        } else {
+            index + 1
+        };
+        let sample = Sample::lerp_fraction(buffer[index], buffer[next], fraction, 1 << 16);
+
+        let progress = (u64::from(self.elapsed) << 16) / u64::from(self.length.max(2) - 1);
+        let gain = i64::from(self.window.gain(progress as u32));
+        let scaled = i64::from(sample.to_clamped()) * gain;
+        // round half away from zero, so the window is symmetric about 0v
+        let windowed = if scaled < 0 {
+            (scaled - 32_767) / 65_535
+        } else {
+            (scaled + 32_767) / 65_535
+        };
+
+        self.position += i64::from(self.rate);
+        self.elapsed += 1;
+        Some(Sample::from(windowed as i32))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Grain, Window};
+    use crate::Sample;
+
+    #[test]
+    fn test_grain_playback() {
+        let buffer: Vec<Sample> = (0..100).map(|i| Sample::from(i * 10)).collect();
+        let mut grain = Grain::new(10, 5, Grain::UNITY_RATE, Window::Rectangular);
+        let played: Vec<i32> =
+            core::iter::from_fn(|| grain.next(&buffer).map(|s| s.to_clamped())).collect();
+        assert_eq!(played, [100, 110, 120, 130, 140]);
+        assert!(grain.is_finished());
+
+        // an octave up, half speed, and backwards off the start of the loop
+        grain.restart(10);
+        grain.set_rate_semitones(12);
+        assert_eq!(grain.rate(), 2 * Grain::UNITY_RATE);
+        assert_eq!(grain.next(&buffer), Some(Sample::from(100_i32)));
+        assert_eq!(grain.next(&buffer), Some(Sample::from(120_i32)));
+        grain.restart(10);
+        grain.set_rate(Grain::UNITY_RATE / 2);
+        grain.next(&buffer);
+        assert_eq!(grain.next(&buffer), Some(Sample::from(105_i32)));
+        grain.restart(1);
+        grain.set_rate(-Grain::UNITY_RATE);
+        let played: Vec<i32> =
+            core::iter::from_fn(|| grain.next(&buffer).map(|s| s.to_clamped())).collect();
+        assert_eq!(played, [10, 0, 990, 980, 970]);
+        assert_eq!(grain.next(&[]), None);
+        let mut grain = Grain::new(0, 1, Grain::UNITY_RATE, Window::Rectangular);
+        assert_eq!(
+            grain.next(&[Sample::from(-1000_i32)]),
+            Some(Sample::from(-1000_i32))
+        );
+    }
+
+    #[test]
+    fn test_grain_window() {
+        let buffer = [Sample::from(1000_i32); 64];
+        for window in [Window::Hann, Window::Triangular] {
+            let mut grain = Grain::new(0, 9, Grain::UNITY_RATE, window);
+            let played: Vec<i32> =
+                core::iter::from_fn(|| grain.next(&buffer).map(|s| s.to_clamped())).collect();
+            // silent at both ends, full level in the middle
+            assert_eq!(played[0], 0, "{window:?}");
+            assert_eq!(played[4], 1000, "{window:?}");
+            assert_eq!(played[8], 0, "{window:?}");
+            // and both windows are at half level a quarter of the way in
+            assert_eq!(played[2], 500, "{window:?}");
+            assert_eq!(played[6], 500, "{window:?}");
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Block based audio graphs, declared with [`audio_graph!`]
+//!
+//! A graph is a set of chains, each a [`Source`] followed by any number of
+//! [`Effect`]s, summed onto a bus which can have its own effects. The macro
+//! generates a struct holding every node, and a `render()` which runs the
+//! whole graph one block at a time:
+//!
+//! ```
+//! # use wscomp::audio_graph;
+//! # use wscomp::filter::{OnePole, PoleMode};
+//! # use wscomp::graph::{Effect, IterSource};
+//! # use wscomp::noise::{PinkNoise, WhiteNoise};
+//! # use wscomp::shaper::soft_clip;
+//! # use wscomp::Sample;
+//! /// Fixed gain
+//! struct Level(Sample);
+//!
+//! impl Effect for Level {
+//!     fn process(&mut self, block: &mut [Sample]) {
+//!         for sample in block.iter_mut() {
+//!             *sample = sample.scale(self.0);
+//!         }
+//!     }
+//! }
+//! # struct Tone(OnePole);
+//! # impl Effect for Tone {
+//! #     fn process(&mut self, block: &mut [Sample]) {
+//! #         for sample in block.iter_mut() {
+//! #             *sample = self.0.process(*sample);
+//! #         }
+//! #     }
+//! # }
+//! # struct Clip;
+//! # impl Effect for Clip {
+//! #     fn process(&mut self, block: &mut [Sample]) {
+//! #         for sample in block.iter_mut() {
+//! #             *sample = soft_clip(*sample);
+//! #         }
+//! #     }
+//! # }
+//!
+//! audio_graph! {
+//!     /// Two rain layers, clipped after mixing
+//!     struct Rain {
+//!         chains {
+//!             light: IterSource<WhiteNoise> => light_level: Level;
+//!             heavy: IterSource<PinkNoise> => tone: Tone => heavy_level: Level;
+//!         }
+//!         bus => clip: Clip;
+//!     }
+//! }
+//!
+//! let mut rain = Rain {
+//!     light: IterSource(WhiteNoise::new(1)),
+//!     light_level: Level(Sample::from(500_i32)),
+//!     heavy: IterSource(PinkNoise::new(2)),
+//!     tone: Tone(OnePole::new(PoleMode::LowPass, Sample::from(0_i32))),
+//!     heavy_level: Level(Sample::from(1500_i32)),
+//!     clip: Clip,
+//! };
+//! let mut scratch = [Sample::from(0_i32); 64];
+//! let mut block = [Sample::from(0_i32); 64];
+//! rain.render(&mut block, &mut scratch);
+//! ```
+//!
+//! Every chain renders into the same scratch block before being mixed onto
+//! the output block, so a graph only ever needs those two buffers, however
+//! many chains it has. Nothing is allocated, callers own both blocks.
+
+use crate::Sample;
+
+/// Start of a chain, fills a block with new samples
+pub trait Source {
+    fn render(&mut self, block: &mut [Sample]);
+}
+
+/// Processes a block in place
+pub trait Effect {
+    fn process(&mut self, block: &mut [Sample]);
+}
+
+/// [`Source`] from any iterator of samples, silence once it runs out
+pub struct IterSource<I>(pub I);
+
+impl<I: Iterator<Item = Sample>> Source for IterSource<I> {
+    fn render(&mut self, block: &mut [Sample]) {
+        for sample in block.iter_mut() {
+            *sample = self.0.next().unwrap_or(Sample::from(0_i32));
+        }
+    }
+}
+
+/// Sum `block` onto `bus`
+pub fn mix_into(bus: &mut [Sample], block: &[Sample]) {
+    for (bus, sample) in bus.iter_mut().zip(block) {
+        *bus = bus.saturating_add(*sample);
+    }
+}
+
+/// Declare an audio graph struct and its block render function
+///
+/// Each line in `chains` is a chain: `field: Type` for the source, then
+/// `=> field: Type` for each effect in order. The `bus` line lists effects
+/// applied after all chains are mixed, and may be just `bus;`. All fields
+/// are public, graphs are constructed with a struct literal.
+///
+/// The generated `render(&mut self, out, scratch)` overwrites `out` with
+/// one block of output. `scratch` must be at least as long as `out`.
+#[macro_export]
+macro_rules! audio_graph {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            chains {
+                $( $source:ident : $source_ty:ty $( => $effect:ident : $effect_ty:ty )* ; )+
+            }
+            bus $( => $bus_effect:ident : $bus_effect_ty:ty )* ;
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                pub $source: $source_ty,
+                $( pub $effect: $effect_ty, )*
+            )+
+            $( pub $bus_effect: $bus_effect_ty, )*
+        }
+
+        impl $name {
+            /// Render one block into `out`, using `scratch` for each chain
+            #[allow(dead_code)]
+            $vis fn render(
+                &mut self,
+                out: &mut [$crate::Sample],
+                scratch: &mut [$crate::Sample],
+            ) {
+                let scratch = &mut scratch[..out.len()];
+                out.fill($crate::Sample::from(0_i32));
+                $(
+                    $crate::graph::Source::render(&mut self.$source, scratch);
+                    $( $crate::graph::Effect::process(&mut self.$effect, scratch); )*
+                    $crate::graph::mix_into(out, scratch);
+                )+
+                $( $crate::graph::Effect::process(&mut self.$bus_effect, out); )*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Effect, IterSource};
+    use crate::Sample;
+
+    /// Multiplies by a fixed amount
+    struct Gain(i32);
+
+    impl Effect for Gain {
+        fn process(&mut self, block: &mut [Sample]) {
+            for sample in block.iter_mut() {
+                *sample = *sample * self.0;
+            }
+        }
+    }
+
+    type Ramp = core::iter::Map<core::ops::RangeFrom<i32>, fn(i32) -> Sample>;
+
+    audio_graph! {
+        struct TestGraph {
+            chains {
+                ramp: IterSource<Ramp> => double: Gain;
+                constant: IterSource<core::iter::Repeat<Sample>>;
+            }
+            bus => negate: Gain;
+        }
+    }
+
+    #[test]
+    fn test_audio_graph() {
+        let mut graph = TestGraph {
+            ramp: IterSource((0..).map(Sample::from as fn(i32) -> Sample)),
+            double: Gain(2),
+            constant: IterSource(core::iter::repeat(Sample::from(100_i32))),
+            negate: Gain(-1),
+        };
+        let mut scratch = [Sample::from(0_i32); 8];
+        let mut block = [Sample::from(0_i32); 4];
+
+        graph.render(&mut block, &mut scratch);
+        assert_eq!(block.map(|s| s.to_clamped()), [-100, -102, -104, -106]);
+        // chains keep their state between blocks
+        graph.render(&mut block, &mut scratch);
+        assert_eq!(block.map(|s| s.to_clamped()), [-108, -110, -112, -114]);
+    }
+}
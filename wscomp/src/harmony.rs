@@ -0,0 +1,290 @@
+//! Scales and chords
+//!
+//! The one set of note data the quantizer, chord and arpeggiator cards
+//! share. A [`Scale`] is a 12 bit mask of the notes in an octave, a
+//! [`Chord`] the semitones above its root, in order:
+//!
+//! ```
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::harmony::{Chord, Scale};
+//! # use wscomp::quantizer::Quantizer;
+//! # use wscomp::Sample;
+//! # fn card<P: SetDutyCycle>(
+//! #     mut outputs: [CvOut<P>; 2],
+//! #     knob_index: usize,
+//! #     root: i32,
+//! # ) -> Result<(), P::Error> {
+//! let scale = Scale::PRESETS[knob_index];
+//! let mut quantizer = Quantizer::new(scale);
+//!
+//! let chord = Chord::MINOR_7.inversion(1);
+//! for (output, semitones) in outputs.iter_mut().zip(chord.notes(root)) {
+//!     output.set(Sample::from_semitones(semitones))?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Users can save their own scales to a few slots in settings flash, see
+//! [`Scale::save`].
+
+use crate::settings::{SettingsError, SettingsFlash, SettingsStore};
+
+/// Settings key of the first user scale, the slots count down from here
+pub const USER_SCALE_KEY: u16 = 0xfef0;
+/// User scale slots in settings
+pub const USER_SCALE_SLOTS: u8 = 8;
+
+/// A set of notes within an octave, as a 12 bit mask
+///
+/// Bit 0 is the root, bit 11 is the major seventh. An empty mask is treated
+/// as the root note only.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Scale {
+    mask: u16,
+}
+
+impl Scale {
+    pub const CHROMATIC: Scale = Scale::from_mask(0b1111_1111_1111);
+    /// Ionian mode
+    pub const MAJOR: Scale = Scale::from_mask(0b1010_1011_0101);
+    /// Natural minor, the aeolian mode
+    pub const MINOR: Scale = Scale::from_mask(0b0101_1010_1101);
+    pub const HARMONIC_MINOR: Scale = Scale::from_mask(0b1001_1010_1101);
+    /// Ascending melodic minor
+    pub const MELODIC_MINOR: Scale = Scale::from_mask(0b1010_1010_1101);
+    pub const DORIAN: Scale = Scale::from_mask(0b0110_1010_1101);
+    pub const PHRYGIAN: Scale = Scale::from_mask(0b0101_1010_1011);
+    pub const LYDIAN: Scale = Scale::from_mask(0b1010_1101_0101);
+    pub const MIXOLYDIAN: Scale = Scale::from_mask(0b0110_1011_0101);
+    pub const LOCRIAN: Scale = Scale::from_mask(0b0101_0110_1011);
+    /// Major pentatonic
+    pub const PENTATONIC: Scale = Scale::from_mask(0b0010_1001_0101);
+    pub const MINOR_PENTATONIC: Scale = Scale::from_mask(0b0100_1010_1001);
+    /// Minor pentatonic with the flat fifth
+    pub const BLUES: Scale = Scale::from_mask(0b0100_1110_1001);
+    pub const WHOLE_TONE: Scale = Scale::from_mask(0b0101_0101_0101);
+
+    /// Built in scales, in knob order
+    pub const PRESETS: [Scale; 14] = [
+        Scale::CHROMATIC,
+        Scale::MAJOR,
+        Scale::MINOR,
+        Scale::HARMONIC_MINOR,
+        Scale::MELODIC_MINOR,
+        Scale::DORIAN,
+        Scale::PHRYGIAN,
+        Scale::LYDIAN,
+        Scale::MIXOLYDIAN,
+        Scale::LOCRIAN,
+        Scale::PENTATONIC,
+        Scale::MINOR_PENTATONIC,
+        Scale::BLUES,
+        Scale::WHOLE_TONE,
+    ];
+
+    /// New `Scale` from a user supplied mask, bits above 11 are ignored
+    pub const fn from_mask(mask: u16) -> Self {
+        let mask = mask & 0b1111_1111_1111;
+        Scale {
+            mask: if mask == 0 { 1 } else { mask },
+        }
+    }
+
+    pub fn mask(&self) -> u16 {
+        self.mask
+    }
+
+    /// Is this semitone (relative to the root, any octave) in the scale?
+    pub fn contains(&self, semitone: i32) -> bool {
+        self.mask & (1 << semitone.rem_euclid(12)) != 0
+    }
+
+    /// Notes in each octave
+    pub fn len(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+
+    /// Always false, a scale has at least its root
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Semitones above the root of each note in the first octave
+    pub fn notes(&self) -> impl Iterator<Item = i32> + '_ {
+        (0..12).filter(|&semitone| self.contains(semitone))
+    }
+
+    /// User scale from settings `slot`, `None` if it was never saved
+    ///
+    /// Panics if `slot` isn't below [`USER_SCALE_SLOTS`].
+    pub async fn load<F: SettingsFlash, const SECTORS: usize>(
+        store: &mut SettingsStore<F, SECTORS>,
+        slot: u8,
+    ) -> Result<Option<Self>, SettingsError<F::Error>> {
+        let mut value = [0; 2];
+        Ok(match store.get(user_scale_key(slot), &mut value).await? {
+            Some(2) => Some(Self::from_mask(u16::from_le_bytes(value))),
+            _ => None,
+        })
+    }
+
+    /// Store this scale in settings `slot`, stalls flash like any
+    /// [`SettingsStore::set`]
+    ///
+    /// Panics if `slot` isn't below [`USER_SCALE_SLOTS`].
+    pub async fn save<F: SettingsFlash, const SECTORS: usize>(
+        &self,
+        store: &mut SettingsStore<F, SECTORS>,
+        slot: u8,
+    ) -> Result<(), SettingsError<F::Error>> {
+        store
+            .set(user_scale_key(slot), &self.mask.to_le_bytes())
+            .await
+    }
+}
+
+fn user_scale_key(slot: u8) -> u16 {
+    assert!(slot < USER_SCALE_SLOTS, "user scale slot out of range");
+    USER_SCALE_KEY - u16::from(slot)
+}
+
+/// Notes of a chord, as rising semitones above its root
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Chord {
+    intervals: [u8; Chord::MAX_NOTES],
+    len: u8,
+}
+
+impl Chord {
+    /// Most notes in a chord
+    pub const MAX_NOTES: usize = 4;
+
+    pub const MAJOR: Chord = Chord::from_intervals(&[0, 4, 7]);
+    pub const MINOR: Chord = Chord::from_intervals(&[0, 3, 7]);
+    pub const DIMINISHED: Chord = Chord::from_intervals(&[0, 3, 6]);
+    pub const AUGMENTED: Chord = Chord::from_intervals(&[0, 4, 8]);
+    pub const SUS2: Chord = Chord::from_intervals(&[0, 2, 7]);
+    pub const SUS4: Chord = Chord::from_intervals(&[0, 5, 7]);
+    pub const MAJOR_7: Chord = Chord::from_intervals(&[0, 4, 7, 11]);
+    pub const MINOR_7: Chord = Chord::from_intervals(&[0, 3, 7, 10]);
+    pub const DOMINANT_7: Chord = Chord::from_intervals(&[0, 4, 7, 10]);
+    /// Minor 7 flat 5
+    pub const HALF_DIMINISHED_7: Chord = Chord::from_intervals(&[0, 3, 6, 10]);
+    pub const DIMINISHED_7: Chord = Chord::from_intervals(&[0, 3, 6, 9]);
+
+    /// Built in chords, in knob order
+    pub const PRESETS: [Chord; 11] = [
+        Chord::MAJOR,
+        Chord::MINOR,
+        Chord::DIMINISHED,
+        Chord::AUGMENTED,
+        Chord::SUS2,
+        Chord::SUS4,
+        Chord::MAJOR_7,
+        Chord::MINOR_7,
+        Chord::DOMINANT_7,
+        Chord::HALF_DIMINISHED_7,
+        Chord::DIMINISHED_7,
+    ];
+
+    /// Chord from rising semitones above the root, notes past
+    /// [`Chord::MAX_NOTES`] are dropped
+    pub const fn from_intervals(intervals: &[u8]) -> Self {
+        let mut chord = Chord {
+            intervals: [0; Chord::MAX_NOTES],
+            len: 0,
+        };
+        while (chord.len as usize) < intervals.len() && (chord.len as usize) < Chord::MAX_NOTES {
+            chord.intervals[chord.len as usize] = intervals[chord.len as usize];
+            chord.len += 1;
+        }
+        chord
+    }
+
+    pub fn intervals(&self) -> &[u8] {
+        &self.intervals[..usize::from(self.len)]
+    }
+
+    pub fn len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The chord with its lowest `inversion` notes moved up an octave,
+    /// still rising from its new lowest note
+    ///
+    /// Intervals stay relative to the original root, so the first inversion
+    /// of C major is E, G, C as `[4, 7, 12]`.
+    pub fn inversion(&self, inversion: usize) -> Self {
+        let mut chord = *self;
+        for _ in 0..inversion % self.len().max(1) {
+            let notes = &mut chord.intervals[..usize::from(chord.len)];
+            notes[0] += 12;
+            notes.rotate_left(1);
+        }
+        chord
+    }
+
+    /// Semitones from 0v of each note, for a chord on `root`
+    pub fn notes(&self, root: i32) -> impl Iterator<Item = i32> + '_ {
+        self.intervals()
+            .iter()
+            .map(move |&interval| root + i32::from(interval))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chord, Scale, USER_SCALE_SLOTS};
+    use crate::settings::test::FakeFlash;
+    use crate::settings::SettingsStore;
+
+    #[test]
+    fn test_scales() {
+        let modes = [
+            (Scale::DORIAN, [0, 2, 3, 5, 7, 9, 10]),
+            (Scale::PHRYGIAN, [0, 1, 3, 5, 7, 8, 10]),
+            (Scale::LYDIAN, [0, 2, 4, 6, 7, 9, 11]),
+            (Scale::MIXOLYDIAN, [0, 2, 4, 5, 7, 9, 10]),
+            (Scale::LOCRIAN, [0, 1, 3, 5, 6, 8, 10]),
+            (Scale::HARMONIC_MINOR, [0, 2, 3, 5, 7, 8, 11]),
+            (Scale::MELODIC_MINOR, [0, 2, 3, 5, 7, 9, 11]),
+        ];
+        for (scale, notes) in modes {
+            assert!(scale.notes().eq(notes), "{scale:?}");
+        }
+        assert!(Scale::BLUES.notes().eq([0, 3, 5, 6, 7, 10]));
+        assert!(Scale::MINOR_PENTATONIC.notes().eq([0, 3, 5, 7, 10]));
+        assert_eq!(Scale::WHOLE_TONE.len(), 6);
+
+        let mut flash = FakeFlash::new(2);
+        let mut store: SettingsStore<_, 2> =
+            embassy_futures::block_on(SettingsStore::mount(&mut flash)).unwrap();
+        let load = |store: &mut _, slot| embassy_futures::block_on(Scale::load(store, slot));
+        assert_eq!(load(&mut store, 0).unwrap(), None);
+        embassy_futures::block_on(Scale::BLUES.save(&mut store, USER_SCALE_SLOTS - 1)).unwrap();
+        assert_eq!(
+            load(&mut store, USER_SCALE_SLOTS - 1).unwrap(),
+            Some(Scale::BLUES)
+        );
+    }
+
+    #[test]
+    fn test_chords() {
+        assert_eq!(Chord::MAJOR.intervals(), &[0, 4, 7]);
+        assert_eq!(Chord::DOMINANT_7.len(), 4);
+        assert_eq!(Chord::MAJOR.inversion(1).intervals(), &[4, 7, 12]);
+        assert_eq!(Chord::MAJOR.inversion(2).intervals(), &[7, 12, 16]);
+        // a full turn is back in root position
+        assert_eq!(Chord::MAJOR.inversion(3), Chord::MAJOR);
+        assert!(Chord::MINOR_7.inversion(3).notes(-12).eq([-2, 0, 3, 7]));
+        assert_eq!(Chord::from_intervals(&[0, 1, 2, 3, 4]).len(), 4);
+    }
+}
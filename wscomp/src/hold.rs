@@ -0,0 +1,182 @@
+//! Sample & hold and track & hold, clocked by pulse edges
+//!
+//! Both take the edges from [`PulseInput::poll`] (or any other
+//! [`PulseDetector`]) and a [`Sample`] to capture:
+//!
+//! ```
+//! # use embedded_hal::digital::InputPin;
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::hold::SampleAndHold;
+//! # use wscomp::pulse::PulseInput;
+//! # use wscomp::random::Rng;
+//! # fn card<I: InputPin, P: SetDutyCycle>(
+//! #     mut pulse_in: PulseInput<I>,
+//! #     mut cv_out: CvOut<P>,
+//! #     mut noise: Rng,
+//! #     now_micros: u64,
+//! # ) -> Result<(), P::Error> {
+//! let mut hold = SampleAndHold::new();
+//! loop {
+//!     let edge = pulse_in.poll(now_micros);
+//!     cv_out.set(hold.update(edge, noise.next_sample()))?;
+//! }
+//! # }
+//! ```
+//!
+//! The captured value is the input's clamped value, as a fresh [`Sample`]:
+//! a smoothed input's accumulator history (or a sum that's out of range)
+//! isn't carried into the held value, so it reads the same as the input
+//! did at the edge.
+//!
+//! [`PulseInput::poll`]: crate::pulse::PulseInput::poll
+//! [`PulseDetector`]: crate::pulse::PulseDetector
+
+use crate::pulse::Edge;
+use crate::Sample;
+
+/// Capture the input on each rising edge, hold it until the next
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SampleAndHold {
+    held: Sample,
+}
+
+impl SampleAndHold {
+    pub fn new() -> Self {
+        SampleAndHold {
+            held: Sample::from(0_i32),
+        }
+    }
+
+    /// Capture `input` now, for triggers that don't come from a pulse input
+    pub fn sample(&mut self, input: Sample) {
+        self.held = Sample::from(input.to_clamped());
+    }
+
+    /// Capture `input` if `edge` is rising, returns the held value
+    pub fn update(&mut self, edge: Option<Edge>, input: Sample) -> Sample {
+        if edge == Some(Edge::Rising) {
+            self.sample(input);
+        }
+        self.held
+    }
+
+    pub fn value(&self) -> Sample {
+        self.held
+    }
+}
+
+impl Default for SampleAndHold {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Follow the input while the gate is high, hold it while low
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrackAndHold {
+    held: Sample,
+    tracking: bool,
+}
+
+impl TrackAndHold {
+    pub fn new() -> Self {
+        TrackAndHold {
+            held: Sample::from(0_i32),
+            tracking: false,
+        }
+    }
+
+    /// Track from a rising `edge` until a falling one, returns the output
+    pub fn update(&mut self, edge: Option<Edge>, input: Sample) -> Sample {
+        match edge {
+            Some(Edge::Rising) => self.tracking = true,
+            // the value at the falling edge is the one held
+            Some(Edge::Falling) => {
+                self.held = Sample::from(input.to_clamped());
+                self.tracking = false;
+            }
+            None => {}
+        }
+        if self.tracking {
+            self.held = Sample::from(input.to_clamped());
+        }
+        self.held
+    }
+
+    pub fn is_tracking(&self) -> bool {
+        self.tracking
+    }
+
+    pub fn value(&self) -> Sample {
+        self.held
+    }
+}
+
+impl Default for TrackAndHold {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SampleAndHold, TrackAndHold};
+    use crate::pulse::Edge;
+    use crate::{Sample, SampleUpdate};
+
+    #[test]
+    fn test_sample_and_hold() {
+        let mut hold = SampleAndHold::new();
+        assert_eq!(
+            hold.update(None, Sample::from(500_i32)),
+            Sample::from(0_i32)
+        );
+        assert_eq!(
+            hold.update(Some(Edge::Rising), Sample::from(500_i32)),
+            Sample::from(500_i32)
+        );
+        assert_eq!(
+            hold.update(Some(Edge::Falling), Sample::from(-300_i32)),
+            Sample::from(500_i32)
+        );
+
+        // a sum past full scale holds at full scale
+        let sum = Sample::from(2000_i32) + Sample::from(2000_i32);
+        hold.update(Some(Edge::Rising), sum);
+        assert_eq!(hold.value().to_clamped(), Sample::MAX);
+        assert_eq!((hold.value() - Sample::from(47_i32)).to_clamped(), 2000);
+
+        // a smoothed input holds what it reads, not its accumulator
+        let mut knob = Sample::from(0_i32);
+        knob.update(1000_i32);
+        hold.sample(knob);
+        assert_eq!(hold.value(), knob);
+        let mut held = hold.value();
+        held.update(knob);
+        assert_eq!(held, knob);
+    }
+
+    #[test]
+    fn test_track_and_hold() {
+        let mut hold = TrackAndHold::new();
+        assert_eq!(
+            hold.update(None, Sample::from(100_i32)),
+            Sample::from(0_i32)
+        );
+        hold.update(Some(Edge::Rising), Sample::from(100_i32));
+        assert!(hold.is_tracking());
+        assert_eq!(
+            hold.update(None, Sample::from(200_i32)),
+            Sample::from(200_i32)
+        );
+        hold.update(Some(Edge::Falling), Sample::from(300_i32));
+        assert!(!hold.is_tracking());
+        assert_eq!(
+            hold.update(None, Sample::from(-900_i32)),
+            Sample::from(300_i32)
+        );
+    }
+}
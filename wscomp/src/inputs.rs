@@ -0,0 +1,578 @@
+//! Reading the knobs, switch, CV and audio inputs
+//!
+//! Every card reads its inputs the same way: audio inputs directly from the
+//! ADC, everything else through the mux, with the normalization probe toggled
+//! to check which jacks have cables plugged in. [`InputReader`] does one full
+//! pass over all of them and keeps the results in an [`InputState`].
+//!
+//! The reader works through embedded-hal traits plus [`InputAdc`], so it's
+//! host testable. With the `embassy` feature, cards build one over
+//! embassy-rp's ADC with [`rp_input_reader!`](crate::rp_input_reader), run
+//! it from their own task, and publish each new [`InputState`] to the shared
+//! [`INPUTS`] watch:
+//!
+//! ```text
+//! let mut reader = wscomp::rp_input_reader!(p.ADC, Irqs, ...);
+//! loop {
+//!     reader.read_and_publish(Instant::now().as_micros()).await;
+//!     ticker.next().await;
+//! }
+//! ```
+//!
+//! Knobs and CV are smoothed by [`Sample`]'s IIR by default. Inputs that
+//! need something else get another [`Smoothing`] with
+//! [`InputReader::set_smoothing`].
+
+#[cfg(feature = "embassy")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, watch::Watch};
+use embedded_hal::digital::{OutputPin, PinState};
+use embedded_hal_async::delay::DelayNs;
+
+use crate::accessibility::Accessibility;
+use crate::smooth::{Smoother, Smoothing};
+use crate::switch::{SwitchEvent, ZSwitch, ZSwitchReader};
+use crate::{JackSample, MaybeFormat, Sample, SampleUpdate};
+
+/// Time for the mux outputs to settle after switching, before reading
+const MUX_SETTLE_MICROS: u32 = 20;
+/// Time for CV inputs to settle after toggling the normalization probe
+const PROBE_SETTLE_MICROS: u32 = 200;
+
+/// Most recent [`InputState`], published by the card's input task with
+/// [`InputReader::read_and_publish`]
+///
+/// Single writer, multiple readers. Tasks read it through
+/// `INPUTS.anon_receiver()`.
+#[cfg(feature = "embassy")]
+pub static INPUTS: Watch<CriticalSectionRawMutex, InputState, 2> = Watch::new();
+
+/// The ADC inputs read by [`InputReader`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdcInput {
+    Audio1,
+    Audio2,
+    /// Knobs and the Z switch, selected by the mux
+    MuxIo1,
+    /// CV inputs, selected by the mux
+    MuxIo2,
+}
+
+/// ADC access for [`InputReader`]
+///
+/// Cards implement this for a struct holding their ADC and the four channels.
+// cards run on single threaded executors, so the future not being Send is fine
+#[allow(async_fn_in_trait)]
+pub trait InputAdc {
+    type Error: MaybeFormat;
+
+    async fn read(&mut self, input: AdcInput) -> Result<u16, Self::Error>;
+}
+
+/// The knob and CV inputs read through the mux, for per-input settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MuxInput {
+    MainKnob,
+    XKnob,
+    YKnob,
+    Cv1,
+    Cv2,
+}
+
+impl MuxInput {
+    pub const ALL: [MuxInput; 5] = [
+        MuxInput::MainKnob,
+        MuxInput::XKnob,
+        MuxInput::YKnob,
+        MuxInput::Cv1,
+        MuxInput::Cv2,
+    ];
+}
+
+/// State of inputs collected via the ADC mux device.
+#[derive(Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MuxState {
+    pub main_knob: Sample,
+    pub x_knob: Sample,
+    pub y_knob: Sample,
+    pub zswitch: ZSwitch,
+    /// The most recent Z switch event, see [`MuxState::switch_event_since`]
+    pub switch_event: Option<SwitchEvent>,
+    /// Wrapping count of Z switch events
+    pub switch_events: u8,
+    pub cv1: JackSample,
+    pub cv2: JackSample,
+    pub sequence_counter: usize,
+}
+
+impl MuxState {
+    /// The latest Z switch event, if there's been one since `seen`
+    ///
+    /// `seen` is the caller's copy of [`MuxState::switch_events`], and is
+    /// updated here. Like [`JackSample::plug_changes`], this works from
+    /// copies of the state, but several events between two looks at it
+    /// only return the last.
+    pub fn switch_event_since(&self, seen: &mut u8) -> Option<SwitchEvent> {
+        if *seen == self.switch_events {
+            return None;
+        }
+        *seen = self.switch_events;
+        self.switch_event
+    }
+
+    /// The current value of a knob, or a CV input's raw value
+    pub fn value(&self, input: MuxInput) -> Sample {
+        match input {
+            MuxInput::MainKnob => self.main_knob,
+            MuxInput::XKnob => self.x_knob,
+            MuxInput::YKnob => self.y_knob,
+            MuxInput::Cv1 => self.cv1.raw,
+            MuxInput::Cv2 => self.cv2.raw,
+        }
+    }
+
+    fn value_mut(&mut self, input: MuxInput) -> &mut Sample {
+        match input {
+            MuxInput::MainKnob => &mut self.main_knob,
+            MuxInput::XKnob => &mut self.x_knob,
+            MuxInput::YKnob => &mut self.y_knob,
+            MuxInput::Cv1 => &mut self.cv1.raw,
+            MuxInput::Cv2 => &mut self.cv2.raw,
+        }
+    }
+}
+
+impl Default for MuxState {
+    fn default() -> Self {
+        MuxState {
+            main_knob: Sample::new(Sample::CENTER, false),
+            x_knob: Sample::new(Sample::CENTER, false),
+            y_knob: Sample::new(Sample::CENTER, false),
+            zswitch: ZSwitch::default(),
+            switch_event: None,
+            switch_events: 0,
+            // CV inputs are not inverted according to docs.  0V reads ~ 2030
+            // NOTE: I get inverted data, and ~2060 as 0v
+            cv1: JackSample::new(
+                Sample::new(Sample::CENTER, true),
+                Sample::new(Sample::CENTER, true),
+            ),
+            cv2: JackSample::new(
+                Sample::new(Sample::CENTER, true),
+                Sample::new(Sample::CENTER, true),
+            ),
+            sequence_counter: 0,
+        }
+    }
+}
+
+/// State of audio inputs collected via direct ADC read.
+#[derive(Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AudioState {
+    pub audio1: JackSample,
+    pub audio2: JackSample,
+}
+
+impl Default for AudioState {
+    fn default() -> Self {
+        AudioState {
+            audio1: JackSample::new(
+                Sample::new(Sample::CENTER, true),
+                Sample::new(Sample::CENTER, true),
+            ),
+            audio2: JackSample::new(
+                Sample::new(Sample::CENTER, true),
+                Sample::new(Sample::CENTER, true),
+            ),
+        }
+    }
+}
+
+/// Most recent values of all inputs except pulses
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InputState {
+    pub mux: MuxState,
+    pub audio: AudioState,
+}
+
+/// Reads all knob, switch, CV and audio inputs, see the module docs
+pub struct InputReader<A, P, D> {
+    adc: A,
+    probe: P,
+    muxlogic_a: P,
+    muxlogic_b: P,
+    delay: D,
+    zswitch: ZSwitchReader,
+    /// One per [`MuxInput`], in [`MuxInput::ALL`] order
+    smoothers: [Smoother; 5],
+    state: InputState,
+}
+
+impl<A: InputAdc, P: OutputPin, D: DelayNs> InputReader<A, P, D> {
+    pub fn new(adc: A, probe: P, muxlogic_a: P, muxlogic_b: P, delay: D) -> Self {
+        let state = InputState::default();
+        InputReader {
+            adc,
+            probe,
+            muxlogic_a,
+            muxlogic_b,
+            delay,
+            zswitch: ZSwitchReader::new(),
+            smoothers: MuxInput::ALL
+                .map(|input| Smoother::new(Smoothing::Iir, state.mux.value(input))),
+            state,
+        }
+    }
+
+    /// Apply an accessibility profile to the Z switch's long hold and double
+    /// tap windows, takes effect on the next read
+    pub fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.zswitch.set_accessibility(accessibility);
+    }
+
+    /// Change how one knob or CV input is smoothed
+    ///
+    /// The new filter starts from the input's current value, and keeps its
+    /// inverted source setting.
+    pub fn set_smoothing(&mut self, input: MuxInput, smoothing: Smoothing) {
+        self.smoothers[input as usize] = Smoother::new(smoothing, self.state.mux.value(input));
+    }
+
+    pub fn smoothing(&self, input: MuxInput) -> Smoothing {
+        self.smoothers[input as usize].smoothing()
+    }
+
+    pub fn state(&self) -> &InputState {
+        &self.state
+    }
+
+    /// Read every input once, returns the updated state
+    ///
+    /// `now_micros` is used for Z switch event timing, events are kept in
+    /// the state for [`MuxState::switch_event_since`]. ADC read errors are
+    /// logged and leave that input's previous value.
+    pub async fn read(&mut self, now_micros: u64) -> &InputState {
+        let mux = &mut self.state.mux;
+        mux.sequence_counter = mux.sequence_counter.wrapping_add(1);
+
+        // read audio inputs and their normalization probe inputs
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::Audio1, "audio1").await {
+            self.state.audio.audio1.raw.update(level);
+        }
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::Audio2, "audio2").await {
+            self.state.audio.audio2.raw.update(level);
+        }
+        set_pin(&mut self.probe, PinState::High);
+        self.delay.delay_us(MUX_SETTLE_MICROS).await;
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::Audio1, "audio1").await {
+            self.state.audio.audio1.update_probe(level);
+        }
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::Audio2, "audio2").await {
+            self.state.audio.audio2.update_probe(level);
+        }
+        set_pin(&mut self.probe, PinState::Low);
+
+        // read Main knob & cv1
+        self.select(PinState::Low, PinState::Low).await;
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::MuxIo1, "Main").await {
+            self.smooth(MuxInput::MainKnob, level);
+        }
+        self.read_cv(CvInput::Cv1).await;
+
+        // read X knob & cv2
+        // NOTE: X and Y appear to be swapped compared to how I read the logic table
+        // not sure why.... :/
+        self.select(PinState::High, PinState::Low).await;
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::MuxIo1, "X").await {
+            self.smooth(MuxInput::XKnob, level);
+        }
+        self.read_cv(CvInput::Cv2).await;
+
+        // read Y knob
+        self.select(PinState::Low, PinState::High).await;
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::MuxIo1, "Y").await {
+            self.smooth(MuxInput::YKnob, level);
+        }
+
+        // read Z switch
+        self.select(PinState::High, PinState::High).await;
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::MuxIo1, "Z").await {
+            let mux = &mut self.state.mux;
+            if let Some(event) = self.zswitch.update_adc(level, now_micros) {
+                debug!("Z switch: {}", event);
+                mux.switch_event = Some(event);
+                mux.switch_events = mux.switch_events.wrapping_add(1);
+            }
+            mux.zswitch = self.zswitch.state();
+        }
+
+        &self.state
+    }
+
+    /// [`InputReader::read`], then send the state to [`INPUTS`]
+    #[cfg(feature = "embassy")]
+    pub async fn read_and_publish(&mut self, now_micros: u64) -> &InputState {
+        let state = self.read(now_micros).await;
+        INPUTS.sender().send(state.clone());
+        state
+    }
+
+    /// Update an input's smoother and copy its value into the state
+    fn smooth(&mut self, input: MuxInput, level: u16) {
+        let smoother = &mut self.smoothers[input as usize];
+        smoother.update(level);
+        *self.state.mux.value_mut(input) = smoother.value();
+    }
+
+    /// Switch the mux, then wait for it to settle
+    async fn select(&mut self, a: PinState, b: PinState) {
+        set_pin(&mut self.muxlogic_a, a);
+        set_pin(&mut self.muxlogic_b, b);
+        self.delay.delay_us(MUX_SETTLE_MICROS).await;
+    }
+
+    /// Read a CV input (inverted data) and its normalization probe
+    async fn read_cv(&mut self, input: CvInput) {
+        let (name, smoothed) = match input {
+            CvInput::Cv1 => ("CV1", MuxInput::Cv1),
+            CvInput::Cv2 => ("CV2", MuxInput::Cv2),
+        };
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::MuxIo2, name).await {
+            self.smooth(smoothed, level);
+        }
+        set_pin(&mut self.probe, PinState::High);
+        self.delay.delay_us(PROBE_SETTLE_MICROS).await;
+        if let Some(level) = read_adc(&mut self.adc, AdcInput::MuxIo2, name).await {
+            self.cv(input).update_probe(level);
+        }
+        set_pin(&mut self.probe, PinState::Low);
+        self.delay.delay_us(PROBE_SETTLE_MICROS).await;
+    }
+
+    fn cv(&mut self, input: CvInput) -> &mut JackSample {
+        match input {
+            CvInput::Cv1 => &mut self.state.mux.cv1,
+            CvInput::Cv2 => &mut self.state.mux.cv2,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum CvInput {
+    Cv1,
+    Cv2,
+}
+
+async fn read_adc<A: InputAdc>(adc: &mut A, input: AdcInput, name: &str) -> Option<u16> {
+    match adc.read(input).await {
+        Ok(level) => Some(level),
+        Err(e) => {
+            error!("ADC read failed, while reading {}: {}", name, e);
+            None
+        }
+    }
+}
+
+fn set_pin<P: OutputPin>(pin: &mut P, state: PinState) {
+    // GPIO writes can't fail on the RP2040
+    let _ = pin.set_state(state);
+}
+
+/// Build an [`InputReader`] over embassy-rp's ADC and GPIO
+///
+/// Takes the ADC peripheral, the interrupt bindings for `ADC_IRQ_FIFO`, then
+/// the pins every card uses, by name. Expands in the card, which needs
+/// `embassy-rp` and `embassy-time` dependencies of its own.
+///
+/// ```text
+/// let mut reader = wscomp::rp_input_reader!(p.ADC, Irqs,
+///     probe: p.PIN_4,
+///     mux_logic: (p.PIN_24, p.PIN_25),
+///     mux_io: (p.PIN_28, p.PIN_29),
+///     audio: (p.PIN_27, p.PIN_26),
+/// );
+/// ```
+///
+/// The reader's ADC type is local to the expansion. Where it needs a name,
+/// to pass the reader to a task, `rp_input_reader!(adc: pub struct Name)`
+/// declares it as `Name<'d>` with fields `adc`, `audio1`, `audio2`,
+/// `mux_io_1` and `mux_io_2`, for the caller to build.
+#[cfg(feature = "embassy")]
+#[macro_export]
+macro_rules! rp_input_reader {
+    (adc: $vis:vis struct $name:ident) => {
+        /// embassy-rp ADC and the four channels read by an `InputReader`
+        $vis struct $name<'d> {
+            adc: ::embassy_rp::adc::Adc<'d, ::embassy_rp::adc::Async>,
+            audio1: ::embassy_rp::adc::Channel<'d>,
+            audio2: ::embassy_rp::adc::Channel<'d>,
+            mux_io_1: ::embassy_rp::adc::Channel<'d>,
+            mux_io_2: ::embassy_rp::adc::Channel<'d>,
+        }
+
+        impl $crate::inputs::InputAdc for $name<'_> {
+            type Error = ::embassy_rp::adc::Error;
+
+            async fn read(
+                &mut self,
+                input: $crate::inputs::AdcInput,
+            ) -> Result<u16, ::embassy_rp::adc::Error> {
+                use $crate::inputs::AdcInput;
+                let channel = match input {
+                    AdcInput::Audio1 => &mut self.audio1,
+                    AdcInput::Audio2 => &mut self.audio2,
+                    AdcInput::MuxIo1 => &mut self.mux_io_1,
+                    AdcInput::MuxIo2 => &mut self.mux_io_2,
+                };
+                self.adc.read(channel).await
+            }
+        }
+    };
+    (
+        $adc:expr, $irqs:expr,
+        probe: $probe:expr,
+        mux_logic: ($mux_a:expr, $mux_b:expr),
+        mux_io: ($mux_io_1:expr, $mux_io_2:expr),
+        audio: ($audio1:expr, $audio2:expr) $(,)?
+    ) => {{
+        use ::embassy_rp::{adc, gpio};
+
+        $crate::rp_input_reader!(adc: struct InputChannels);
+
+        let channels = InputChannels {
+            adc: adc::Adc::new($adc, $irqs, adc::Config::default()),
+            audio1: adc::Channel::new_pin($audio1, gpio::Pull::None),
+            audio2: adc::Channel::new_pin($audio2, gpio::Pull::None),
+            mux_io_1: adc::Channel::new_pin($mux_io_1, gpio::Pull::None),
+            mux_io_2: adc::Channel::new_pin($mux_io_2, gpio::Pull::None),
+        };
+        // the mux starts on the Z switch, and the probe low
+        $crate::inputs::InputReader::new(
+            channels,
+            gpio::Output::new($probe, gpio::Level::Low),
+            gpio::Output::new($mux_a, gpio::Level::Low),
+            gpio::Output::new($mux_b, gpio::Level::Low),
+            ::embassy_time::Delay,
+        )
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::{ErrorType, OutputPin};
+    use embedded_hal_async::delay::DelayNs;
+
+    use super::{AdcInput, InputAdc, InputReader, MuxInput};
+    use crate::smooth::Smoothing;
+    use crate::switch::{SwitchEvent, ZSwitch};
+    use crate::Sample;
+
+    struct FakeAdc;
+
+    impl InputAdc for FakeAdc {
+        type Error = Infallible;
+
+        async fn read(&mut self, input: AdcInput) -> Result<u16, Infallible> {
+            Ok(match input {
+                AdcInput::Audio1 | AdcInput::Audio2 => 2048,
+                AdcInput::MuxIo1 => 4095,
+                AdcInput::MuxIo2 => 1000,
+            })
+        }
+    }
+
+    struct FakePin;
+
+    impl ErrorType for FakePin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for FakePin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn test_input_reader() {
+        let mut reader = InputReader::new(FakeAdc, FakePin, FakePin, FakePin, NoDelay);
+        let state = embassy_futures::block_on(reader.read(0)).clone();
+        assert_eq!(state.mux.sequence_counter, 1);
+        assert_eq!(state.mux.zswitch, ZSwitch::On);
+        assert!(state.mux.main_knob > Sample::new(Sample::CENTER, false));
+        assert!(state.mux.y_knob > Sample::new(Sample::CENTER, false));
+        // probe reads the same as the input, so the CV jacks look plugged
+        assert!(state.mux.cv1.is_plugged());
+
+        embassy_futures::block_on(reader.read(1_000));
+        assert_eq!(reader.state().mux.sequence_counter, 2);
+    }
+
+    #[test]
+    fn test_input_reader_switch_events() {
+        let mut reader = InputReader::new(FakeAdc, FakePin, FakePin, FakePin, NoDelay);
+        let mut seen = 0;
+        let state = embassy_futures::block_on(reader.read(0)).clone();
+        // the switch starts Off, and the fake reads On
+        let event = state.mux.switch_event_since(&mut seen);
+        assert_eq!(event, Some(SwitchEvent::Changed(ZSwitch::On)));
+        assert_eq!(state.mux.switch_event_since(&mut seen), None);
+
+        let state = embassy_futures::block_on(reader.read(1_000));
+        assert_eq!(state.mux.switch_events, 1);
+        assert_eq!(state.mux.switch_event_since(&mut seen), None);
+    }
+
+    #[test]
+    fn test_input_reader_smoothing() {
+        let mut reader = InputReader::new(FakeAdc, FakePin, FakePin, FakePin, NoDelay);
+        for input in MuxInput::ALL {
+            assert_eq!(reader.smoothing(input), Smoothing::Iir);
+        }
+        reader.set_smoothing(MuxInput::MainKnob, Smoothing::MovingAverage);
+        reader.set_smoothing(MuxInput::Cv1, Smoothing::Median);
+        assert_eq!(reader.smoothing(MuxInput::Cv1), Smoothing::Median);
+        assert_eq!(reader.smoothing(MuxInput::Cv2), Smoothing::Iir);
+
+        for _ in 0..8 {
+            embassy_futures::block_on(reader.read(0));
+        }
+        let mux = &reader.state().mux;
+        // the moving average has fully settled, the IIR is still rising
+        assert_eq!(mux.main_knob, Sample::from(Sample::MAX));
+        assert!(mux.x_knob < Sample::from(Sample::MAX));
+        // CV1 is inverted, 1000 reads 1048 above center, after 3 median updates
+        assert_eq!(mux.cv1.raw, Sample::from(1048_i32));
+        assert!(mux.cv2.raw < mux.cv1.raw);
+    }
+
+    #[cfg(feature = "embassy")]
+    #[test]
+    fn test_read_and_publish() {
+        use super::INPUTS;
+
+        let mut receiver = INPUTS.anon_receiver();
+        assert!(receiver.try_get().is_none());
+        let mut reader = InputReader::new(FakeAdc, FakePin, FakePin, FakePin, NoDelay);
+        embassy_futures::block_on(reader.read_and_publish(0));
+        let state = receiver.try_get().unwrap();
+        assert_eq!(state.mux.sequence_counter, 1);
+        assert_eq!(state.mux.zswitch, ZSwitch::On);
+    }
+}
@@ -0,0 +1,199 @@
+//! RP2040 SIO interpolator blends and clamps
+//!
+//! Each RP2040 core has two interpolators in its SIO block. INTERP0 has a
+//! blend mode, `low + (high - low) * alpha / 256` for an 8 bit `alpha`, and
+//! INTERP1 a clamp mode. [`Interpolator`] is those two operations, with
+//! exact wider blends built from them, so table lookups and crossfades can
+//! run on either implementation:
+//!
+//! - [`SoftInterp`], portable, a model of the hardware
+//! - `SioInterp`, the hardware, with the `sio-interp` feature on an
+//!   ARM target without an OS
+//!
+//! ```
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::interp::SoftInterp;
+//! # use wscomp::tables;
+//! # use wscomp::units::Hertz;
+//! # use wscomp::wavetable::{Wavetable, WavetableOsc};
+//! # let mux_state = MuxState::default();
+//! # let table = Wavetable::from_bytes(&[0; 512]).unwrap();
+//! # let mut osc = WavetableOsc::new(table, Hertz::new(110), Hertz::new(48_000));
+//! // `unsafe { SioInterp::new() }` on the card
+//! let mut interp = SoftInterp;
+//! let (fade_out, fade_in) = tables::equal_power_with(&mut interp, mux_state.main_knob);
+//! let sample = osc.tick_with(&mut interp);
+//! assert_eq!((fade_out, fade_in), tables::equal_power(mux_state.main_knob));
+//! ```
+//!
+//! The `_with` versions of [`tables::equal_power`](crate::tables::equal_power)
+//! and [`WavetableOsc::tick`](crate::wavetable::WavetableOsc::tick) give
+//! the same results as the portable code, which the tests check through
+//! [`SoftInterp`].
+
+/// Blend and clamp, the operations of the RP2040 interpolators
+pub trait Interpolator {
+    /// `low + ((high - low) * alpha) >> 8`, rounded down
+    fn blend(&mut self, low: i32, high: i32, alpha: u8) -> i32;
+
+    /// `value` limited to `min..=max`
+    fn clamp(&mut self, value: i32, min: i32, max: i32) -> i32;
+
+    /// [`Interpolator::blend`] rounded to nearest, half up
+    ///
+    /// Blends twice the values, offset by a half, then halves the result.
+    fn blend_rounded(&mut self, low: i32, high: i32, alpha: u8) -> i32 {
+        self.blend(2 * low + 1, 2 * high + 1, alpha) >> 1
+    }
+
+    /// `low + ((high - low) * fraction) >> 16`, a Q16 fraction, rounded down
+    ///
+    /// The top 8 bits of `fraction` blend exactly between `low` and `high`
+    /// shifted up 8 bits, the bottom 8 blend on to the next step.
+    fn lerp_q16(&mut self, low: i32, high: i32, fraction: u16) -> i32 {
+        let step = high - low;
+        let coarse = self.blend(low << 8, high << 8, (fraction >> 8) as u8);
+        self.blend(coarse, coarse + step, fraction as u8) >> 8
+    }
+}
+
+/// Portable [`Interpolator`], the reference for the hardware
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SoftInterp;
+
+impl Interpolator for SoftInterp {
+    fn blend(&mut self, low: i32, high: i32, alpha: u8) -> i32 {
+        low + (((high - low) * i32::from(alpha)) >> 8)
+    }
+
+    fn clamp(&mut self, value: i32, min: i32, max: i32) -> i32 {
+        value.max(min).min(max)
+    }
+}
+
+#[cfg(all(feature = "sio-interp", target_arch = "arm", target_os = "none"))]
+pub use sio::SioInterp;
+
+#[cfg(all(feature = "sio-interp", target_arch = "arm", target_os = "none"))]
+mod sio {
+    use core::marker::PhantomData;
+    use core::ptr::{read_volatile, write_volatile};
+
+    use super::Interpolator;
+
+    /// INTERP0 and INTERP1 in this core's SIO block
+    const INTERP0: usize = 0xd000_0080;
+    const INTERP1: usize = 0xd000_00c0;
+
+    // register offsets within an interpolator
+    const ACCUM0: usize = 0x00;
+    const ACCUM1: usize = 0x04;
+    const BASE0: usize = 0x08;
+    const BASE1: usize = 0x0c;
+    const PEEK_LANE0: usize = 0x20;
+    const PEEK_LANE1: usize = 0x24;
+    const CTRL_LANE0: usize = 0x2c;
+    const CTRL_LANE1: usize = 0x30;
+
+    // CTRL_LANEx fields
+    const MASK_MSB_SHIFT: u32 = 10;
+    const SIGNED: u32 = 1 << 15;
+    const BLEND: u32 = 1 << 21;
+    const CLAMP: u32 = 1 << 22;
+
+    fn write(interp: usize, offset: usize, value: u32) {
+        // SAFETY: SIO registers, always mapped, single cycle and side
+        // effect free to write
+        unsafe { write_volatile((interp + offset) as *mut u32, value) }
+    }
+
+    fn read(interp: usize, offset: usize) -> u32 {
+        // SAFETY: as for `write`, and PEEK registers don't pop
+        unsafe { read_volatile((interp + offset) as *const u32) }
+    }
+
+    /// The current core's interpolators, set up for [`Interpolator`]
+    ///
+    /// Not `Send`, each core has its own interpolators.
+    #[derive(Debug)]
+    pub struct SioInterp {
+        _core_local: PhantomData<*mut ()>,
+    }
+
+    impl SioInterp {
+        /// Configure INTERP0 for signed blends and INTERP1 for signed clamps
+        ///
+        /// # Safety
+        ///
+        /// At most one `SioInterp` per core, and nothing else on the core,
+        /// including interrupt handlers, may use its interpolators.
+        pub unsafe fn new() -> Self {
+            // lane 1 passes the 8 bit alpha through, lane 0 isn't used
+            write(INTERP0, CTRL_LANE0, BLEND | (31 << MASK_MSB_SHIFT));
+            write(INTERP0, CTRL_LANE1, SIGNED | (7 << MASK_MSB_SHIFT));
+            write(INTERP1, CTRL_LANE0, CLAMP | SIGNED | (31 << MASK_MSB_SHIFT));
+            SioInterp {
+                _core_local: PhantomData,
+            }
+        }
+    }
+
+    impl Interpolator for SioInterp {
+        fn blend(&mut self, low: i32, high: i32, alpha: u8) -> i32 {
+            write(INTERP0, BASE0, low as u32);
+            write(INTERP0, BASE1, high as u32);
+            write(INTERP0, ACCUM1, alpha.into());
+            read(INTERP0, PEEK_LANE1) as i32
+        }
+
+        fn clamp(&mut self, value: i32, min: i32, max: i32) -> i32 {
+            write(INTERP1, BASE0, min as u32);
+            write(INTERP1, BASE1, max as u32);
+            write(INTERP1, ACCUM0, value as u32);
+            read(INTERP1, PEEK_LANE0) as i32
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Interpolator, SoftInterp};
+
+    #[test]
+    fn test_soft_interp() {
+        let mut interp = SoftInterp;
+        assert_eq!(interp.blend(100, 200, 0), 100);
+        assert_eq!(interp.blend(100, 200, 128), 150);
+        assert_eq!(interp.blend(100, 200, 255), 199);
+        // rounded down, for falling blends too
+        assert_eq!(interp.blend(0, -1, 128), -1);
+        assert_eq!(interp.clamp(5000, -2048, 2047), 2047);
+        assert_eq!(interp.clamp(-5000, -2048, 2047), -2048);
+        assert_eq!(interp.clamp(7, -2048, 2047), 7);
+    }
+
+    #[test]
+    fn test_wider_blends() {
+        let mut interp = SoftInterp;
+        // sample levels, and table values up to 1.0 in Q16
+        let ends = [-2048, -1000, -1, 0, 1, 999, 2047, 65_535, 65_536];
+        for low in ends {
+            for high in ends {
+                for alpha in 0..=255_u8 {
+                    let expected = low + ((high - low) * i32::from(alpha) + 128).div_euclid(256);
+                    assert_eq!(interp.blend_rounded(low, high, alpha), expected);
+                }
+            }
+        }
+        for low in &ends[..7] {
+            for high in &ends[..7] {
+                let (low, high) = (*low, *high);
+                for fraction in (0..=u16::MAX).step_by(97).chain([u16::MAX]) {
+                    let expected = low + (((high - low) * i32::from(fraction)) >> 16);
+                    assert_eq!(interp.lerp_q16(low, high, fraction), expected);
+                }
+            }
+        }
+    }
+}
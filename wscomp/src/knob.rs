@@ -0,0 +1,313 @@
+//! Helpers for reading knobs
+//!
+//! Times and frequencies are heard in ratios, so a knob sweeping them
+//! linearly crams the useful end of the range into a few degrees of travel.
+//! [`map_exp`] spreads them evenly instead:
+//!
+//! ```
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::knob::map_exp;
+//! # use wscomp::units::{Hertz, Millis};
+//! # let mux_state = MuxState::default();
+//! let decay = Millis::new(map_exp(mux_state.x_knob, 1, 10_000));
+//! let cutoff = Hertz::from_millihertz(map_exp(mux_state.main_knob, 20_000, 20_000_000));
+//! ```
+
+use crate::tables;
+use crate::Sample;
+
+/// 1.0 in the Q16 positions and logs below
+const ONE: u32 = 1 << 16;
+
+/// Knob position, Q16 from 0 at [`Sample::MIN`] to 1 at [`Sample::MAX`]
+fn position(value: Sample) -> u32 {
+    (value.to_clamped() - Sample::MIN) as u32 * ONE / (Sample::MAX - Sample::MIN) as u32
+}
+
+/// `log2(value)` in Q16, `value` at least 1
+fn log2(value: u32) -> i64 {
+    let octave = 31 - value.leading_zeros();
+    let normalized = (u64::from(value) << 16) >> octave;
+    (i64::from(octave) << 16) + i64::from(tables::log2(normalized as u32))
+}
+
+/// `2^log` for a Q16 `log`, rounded, at most `u32::MAX`
+fn exp2(log: i64) -> u32 {
+    let ratio = u64::from(tables::exp2((log & 0xffff) as u32));
+    (((ratio << (log >> 16)) + (1 << 15)) >> 16).min(u32::MAX.into()) as u32
+}
+
+/// Map a knob or CV exponentially, [`Sample::MIN`] is `min` and
+/// [`Sample::MAX`] is `max`
+///
+/// Each step of travel multiplies the result by the same ratio, so 1ms to
+/// 10s puts 10ms, 100ms and 1s evenly a quarter of the way apart. `min` is
+/// treated as at least 1, and `max` can be below it for a knob that turns
+/// down.
+pub fn map_exp(value: Sample, min: u32, max: u32) -> u32 {
+    let (min, max) = (min.max(1), max.max(1));
+    match position(value) {
+        0 => min,
+        ONE => max,
+        position => {
+            let span = log2(max) - log2(min);
+            // the tables are coarser than the steps of a narrow range
+            exp2(log2(min) + ((span * i64::from(position)) >> 16)).clamp(min.min(max), min.max(max))
+        }
+    }
+}
+
+/// Map a knob or CV logarithmically, [`Sample::MIN`] is `min` and
+/// [`Sample::MAX`] is `max`
+///
+/// The mirror of [`map_exp`], like a reverse log pot: the result moves
+/// fast at the start of travel and finely at the end, for controls that
+/// mostly live near `max` such as feedback or resonance.
+pub fn map_log(value: Sample, min: u32, max: u32) -> u32 {
+    let (min, max) = (min.max(1), max.max(1));
+    let mirrored = Sample::from(Sample::MAX + Sample::MIN - value.to_clamped());
+    // min + max - exp, without the sum, which can overflow
+    let (low, high) = (min.min(max), min.max(max));
+    high - (map_exp(mirrored, min, max) - low)
+}
+
+/// Soft takeover (pickup) for a knob shared between several values
+///
+/// After [`PickupKnob::set_value`], the physical knob position is ignored
+/// until it reaches or crosses the stored value, then the knob controls the
+/// value directly. This avoids jumps when one knob controls different values
+/// on different pages or modes.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PickupKnob {
+    value: Sample,
+    picked_up: bool,
+    last_position: Option<Sample>,
+}
+
+impl PickupKnob {
+    /// How close (in counts) the knob needs to get to count as reaching the value
+    pub const THRESHOLD: i32 = 16;
+
+    /// New `PickupKnob` holding `value`, waiting for the knob to reach it
+    pub fn new(value: Sample) -> Self {
+        PickupKnob {
+            value,
+            picked_up: false,
+            last_position: None,
+        }
+    }
+
+    /// Current logical value
+    pub fn value(&self) -> Sample {
+        self.value
+    }
+
+    /// Is the physical knob currently controlling the value?
+    pub fn is_picked_up(&self) -> bool {
+        self.picked_up
+    }
+
+    /// Store a new logical value and wait for the knob to reach it
+    pub fn set_value(&mut self, value: Sample) {
+        self.value = value;
+        self.picked_up = false;
+    }
+
+    /// Update with the physical knob position, returns the logical value
+    pub fn update(&mut self, position: Sample) -> Sample {
+        if !self.picked_up {
+            let target = self.value.to_clamped();
+            let offset = position.to_clamped() - target;
+            let crossed = match self.last_position {
+                Some(last) => (last.to_clamped() - target).signum() * offset.signum() < 0,
+                None => false,
+            };
+            self.picked_up = crossed || offset.abs() <= Self::THRESHOLD;
+        }
+        if self.picked_up {
+            self.value = position;
+        }
+        self.last_position = Some(position);
+        self.value
+    }
+}
+
+/// Detects knob movement, for "knob touched" and idle behaviors
+///
+/// Movement smaller than the threshold (ADC noise) is ignored. Timestamps
+/// are in microseconds, e.g. `Instant::now().as_micros()`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KnobTracker {
+    reference: Sample,
+    threshold: i32,
+    moved: bool,
+    last_moved: Option<u64>,
+}
+
+impl KnobTracker {
+    /// Default movement (in counts) needed to count as the knob being moved
+    pub const THRESHOLD: i32 = 32;
+
+    pub fn new(position: Sample) -> Self {
+        Self::with_threshold(position, Self::THRESHOLD)
+    }
+
+    pub fn with_threshold(position: Sample, threshold: i32) -> Self {
+        KnobTracker {
+            reference: position,
+            threshold: threshold.max(1),
+            moved: false,
+            last_moved: None,
+        }
+    }
+
+    /// Update with the knob position, returns true if it moved past the threshold
+    pub fn update(&mut self, position: Sample, now_micros: u64) -> bool {
+        if (position.to_clamped() - self.reference.to_clamped()).abs() < self.threshold {
+            return false;
+        }
+        self.reference = position;
+        self.moved = true;
+        self.last_moved = Some(now_micros);
+        true
+    }
+
+    /// Has the knob moved since the last call?
+    pub fn take_moved(&mut self) -> bool {
+        core::mem::take(&mut self.moved)
+    }
+
+    /// When the knob last moved, `None` if it hasn't since startup
+    pub fn last_moved_micros(&self) -> Option<u64> {
+        self.last_moved
+    }
+
+    /// Time since the knob last moved, `None` if it hasn't since startup
+    pub fn idle_micros(&self, now_micros: u64) -> Option<u64> {
+        self.last_moved
+            .map(|last_moved| now_micros.saturating_sub(last_moved))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{map_exp, map_log, KnobTracker, PickupKnob};
+    use crate::Sample;
+
+    #[test]
+    fn test_map_exp() {
+        let knob = |fraction: f64| {
+            let range = f64::from(Sample::MAX - Sample::MIN);
+            Sample::from(Sample::MIN + (fraction * range).round() as i32)
+        };
+        // 1ms to 10s, a decade each quarter of travel
+        assert_eq!(map_exp(knob(0.0), 1, 10_000), 1);
+        for (fraction, expected) in [(0.25, 10), (0.5, 100), (0.75, 1_000)] {
+            let millis = map_exp(knob(fraction), 1, 10_000);
+            assert!(millis.abs_diff(expected) <= expected / 100, "{millis}");
+        }
+        assert_eq!(map_exp(knob(1.0), 1, 10_000), 10_000);
+
+        // 20Hz to 20kHz in millihertz, rising all the way
+        let sweep: Vec<u32> = (Sample::MIN..=Sample::MAX)
+            .map(|value| map_exp(Sample::from(value), 20_000, 20_000_000))
+            .collect();
+        assert!(sweep.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(sweep[sweep.len() - 1], 20_000_000);
+        let middle = sweep[sweep.len() / 2];
+        assert!(middle.abs_diff(632_456) < 1_000, "{middle}");
+
+        // a knob that turns down
+        assert!(map_exp(knob(0.5), 1_000, 10).abs_diff(100) <= 1);
+
+        // log is mirrored, fast then fine
+        assert_eq!(map_log(knob(0.0), 1, 10_000), 1);
+        assert_eq!(map_log(knob(1.0), 1, 10_000), 10_000);
+        let log = map_log(knob(0.25), 1, 10_000);
+        assert!(log.abs_diff(9_001) <= 10, "{log}");
+
+        // the whole u32 range either way round, and a narrow one at the top
+        for (min, max) in [(1, u32::MAX), (u32::MAX, 1), (u32::MAX - 1, u32::MAX)] {
+            for map in [map_exp, map_log] {
+                let sweep: Vec<u32> = (Sample::MIN..=Sample::MAX)
+                    .map(|value| map(Sample::from(value), min, max))
+                    .collect();
+                assert_eq!((sweep[0], sweep[sweep.len() - 1]), (min, max));
+                assert!(sweep.windows(2).all(|pair| if min < max {
+                    pair[0] <= pair[1]
+                } else {
+                    pair[0] >= pair[1]
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pickup_reaching_value() {
+        let mut knob = PickupKnob::new(Sample::from(1000_i32));
+        // far away, value held
+        assert_eq!(knob.update(Sample::from(-500_i32)), Sample::from(1000_i32));
+        assert_eq!(knob.update(Sample::from(500_i32)), Sample::from(1000_i32));
+        assert!(!knob.is_picked_up());
+        // within threshold, picked up
+        assert_eq!(knob.update(Sample::from(990_i32)), Sample::from(990_i32));
+        assert!(knob.is_picked_up());
+        // then tracks the knob freely
+        assert_eq!(
+            knob.update(Sample::from(-2000_i32)),
+            Sample::from(-2000_i32)
+        );
+    }
+
+    #[test]
+    fn test_pickup_crossing_value() {
+        let mut knob = PickupKnob::new(Sample::from(0_i32));
+        assert_eq!(knob.update(Sample::from(-300_i32)), Sample::from(0_i32));
+        // a fast move jumps past the value between updates
+        assert_eq!(knob.update(Sample::from(400_i32)), Sample::from(400_i32));
+        assert!(knob.is_picked_up());
+    }
+
+    #[test]
+    fn test_pickup_set_value() {
+        let mut knob = PickupKnob::new(Sample::from(0_i32));
+        knob.update(Sample::from(0_i32));
+        assert!(knob.is_picked_up());
+
+        // switching pages releases the knob
+        knob.set_value(Sample::from(-1500_i32));
+        assert!(!knob.is_picked_up());
+        assert_eq!(knob.update(Sample::from(10_i32)), Sample::from(-1500_i32));
+        assert_eq!(
+            knob.update(Sample::from(-1000_i32)),
+            Sample::from(-1500_i32)
+        );
+        assert_eq!(
+            knob.update(Sample::from(-1600_i32)),
+            Sample::from(-1600_i32)
+        );
+    }
+
+    #[test]
+    fn test_knob_tracker() {
+        let mut knob = KnobTracker::new(Sample::from(0_i32));
+        // noise is ignored
+        assert!(!knob.update(Sample::from(20_i32), 1_000));
+        assert!(!knob.update(Sample::from(-20_i32), 2_000));
+        assert!(!knob.take_moved());
+        assert_eq!(knob.idle_micros(5_000), None);
+
+        assert!(knob.update(Sample::from(100_i32), 3_000));
+        assert_eq!(knob.last_moved_micros(), Some(3_000));
+        assert_eq!(knob.idle_micros(10_000), Some(7_000));
+        assert!(knob.take_moved());
+        assert!(!knob.take_moved());
+
+        // threshold is relative to the last movement
+        assert!(!knob.update(Sample::from(120_i32), 4_000));
+        assert!(knob.update(Sample::from(60_i32), 5_000));
+        assert!(knob.take_moved());
+    }
+}
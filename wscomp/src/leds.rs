@@ -0,0 +1,358 @@
+//! LED helpers shared by cards
+//!
+//! [`Leds`] drives a set of LED PWM channels with gamma correction, a global
+//! brightness, and per LED levels or blink patterns, plus a chase animation
+//! across all of them. Both follow the card's [`Accessibility`] profile.
+
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::accessibility::Accessibility;
+use crate::units::{Hertz, Millis};
+use crate::{JackSample, U12_MAX};
+
+/// LED PWM `top`: 12 bit PWM * 10. 10x is to increase PWM rate, reducing
+/// visible flicker.
+pub const PWM_TOP: u16 = 40950;
+
+/// Rough LED brightness correction, for 12 bit levels
+pub fn gamma(value: u16) -> u16 {
+    // based on: https://github.com/TomWhitwell/Workshop_Computer/blob/main/Demonstrations%2BHelloWorlds/CircuitPython/mtm_computer.py
+    let temp: u32 = value.min(U12_MAX).into();
+    (temp * temp / U12_MAX as u32) as u16
+}
+
+/// Lowest level a lit LED shows with [`Accessibility::high_contrast`]
+pub const HIGH_CONTRAST_FLOOR: u16 = U12_MAX / 4;
+
+/// What an LED shows, levels are 12 bit (0..=[`U12_MAX`]) before gamma
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LedPattern {
+    Level(u16),
+    /// Alternate between `level` and off, starting on
+    Blink {
+        level: u16,
+        on: Millis,
+        off: Millis,
+    },
+}
+
+/// Setting an LED's PWM duty cycle failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LedError {
+    /// Index of the LED in [`Leds`]
+    pub led: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct Chase {
+    level: u16,
+    step_ticks: u32,
+    start: u32,
+}
+
+/// A group of LED PWM channels, updated once per loop
+///
+/// Usually all six LEDs, but cards that drive LEDs from several tasks can
+/// give each task its own `Leds`.
+pub struct Leds<P, const N: usize> {
+    channels: [P; N],
+    patterns: [LedPattern; N],
+    brightness: u16,
+    rate: Hertz,
+    ticks: u32,
+    chase: Option<Chase>,
+    accessibility: Accessibility,
+}
+
+impl<P: SetDutyCycle, const N: usize> Leds<P, N> {
+    /// New `Leds`, all off, for a loop calling [`Leds::update`] at `rate`
+    pub fn new(channels: [P; N], rate: Hertz) -> Self {
+        Leds {
+            channels,
+            patterns: [LedPattern::Level(0); N],
+            brightness: U12_MAX,
+            rate,
+            ticks: 0,
+            chase: None,
+            accessibility: Accessibility::STANDARD,
+        }
+    }
+
+    /// Apply an accessibility profile, takes effect on the next update
+    pub fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.accessibility = accessibility;
+    }
+
+    /// Set a fixed level, out of range LEDs are ignored
+    pub fn set(&mut self, led: usize, level: u16) {
+        self.set_pattern(led, LedPattern::Level(level));
+    }
+
+    pub fn set_pattern(&mut self, led: usize, pattern: LedPattern) {
+        if let Some(current) = self.patterns.get_mut(led) {
+            // keep blink phase when re-setting the same pattern each loop
+            if *current != pattern {
+                *current = pattern;
+            }
+        }
+    }
+
+    /// Scale all LEDs, 12 bit
+    pub fn set_brightness(&mut self, brightness: u16) {
+        self.brightness = brightness.min(U12_MAX);
+    }
+
+    /// Light one LED at a time, starting from the first and moving every
+    /// `step`, over any patterns
+    pub fn start_chase(&mut self, level: u16, step: Millis) {
+        self.chase = Some(Chase {
+            level,
+            step_ticks: self.accessibility.timing(step).ticks(self.rate).max(1),
+            start: self.ticks,
+        });
+    }
+
+    pub fn stop_chase(&mut self) {
+        self.chase = None;
+    }
+
+    /// Current 12 bit level of an LED, before gamma and brightness
+    pub fn level(&self, led: usize) -> u16 {
+        if let Some(chase) = self.chase {
+            let lit = (self.ticks.wrapping_sub(chase.start) / chase.step_ticks) as usize % N;
+            return if lit == led { chase.level } else { 0 };
+        }
+        match self.patterns.get(led) {
+            Some(LedPattern::Level(level)) => *level,
+            Some(LedPattern::Blink { level, on, off }) => {
+                let on = self.accessibility.timing(*on).ticks(self.rate).max(1);
+                let period = on + self.accessibility.timing(*off).ticks(self.rate);
+                if self.ticks % period < on {
+                    *level
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        }
+    }
+
+    /// Write all LEDs, call once per loop
+    ///
+    /// Every LED is written even if one fails, the error is for the first
+    /// that failed.
+    pub fn update(&mut self) -> Result<(), LedError> {
+        let mut result = Ok(());
+        for led in 0..N {
+            let level = self.level(led);
+            let level = match (self.accessibility.high_contrast, level) {
+                (true, 0) => 0,
+                // no gamma, so mid levels are brighter
+                (true, level) => level.clamp(HIGH_CONTRAST_FLOOR, U12_MAX),
+                (false, level) => gamma(level),
+            };
+            let value = level as u32 * self.brightness as u32 / U12_MAX as u32;
+            if self.channels[led]
+                .set_duty_cycle_fraction(value as u16, U12_MAX)
+                .is_err()
+                && result.is_ok()
+            {
+                result = Err(LedError { led });
+            }
+        }
+        self.ticks = self.ticks.wrapping_add(1);
+        result
+    }
+}
+
+/// Briefly lights an LED when a cable is plugged into or removed from a jack
+///
+/// Convention across cards: flash the LED nearest the function whose
+/// normalization switched, so users get confirmation the cable was detected.
+/// Works from copies of a [`JackSample`] (as received from a `Watch`), by
+/// comparing [`JackSample::plug_changes`] between updates.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlugFlash {
+    seen: Option<u8>,
+    remaining: u32,
+    duration: u32,
+}
+
+impl PlugFlash {
+    /// How long the LED stays lit after a plug change
+    pub const FLASH_TIME: Millis = Millis::new(150);
+
+    /// New `PlugFlash` for a loop running at `rate`
+    pub fn new(rate: Hertz) -> Self {
+        Self::with_accessibility(rate, Accessibility::STANDARD)
+    }
+
+    /// New `PlugFlash`, flashing for longer with [`Accessibility::slow_ui`]
+    pub fn with_accessibility(rate: Hertz, accessibility: Accessibility) -> Self {
+        PlugFlash {
+            seen: None,
+            remaining: 0,
+            duration: accessibility.timing(Self::FLASH_TIME).ticks(rate).max(1),
+        }
+    }
+
+    /// Check the jack for plug changes, call once per loop, returns true while flashing
+    ///
+    /// The first update only records the state, so cables already plugged at
+    /// startup don't flash.
+    pub fn update(&mut self, jack: &JackSample) -> bool {
+        let changes = jack.plug_changes();
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.seen.is_some_and(|seen| seen != changes) {
+            self.remaining = self.duration;
+        }
+        self.seen = Some(changes);
+        self.is_flashing()
+    }
+
+    pub fn is_flashing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// LED brightness: full while flashing, otherwise `value`
+    pub fn apply(&self, value: u16) -> u16 {
+        if self.is_flashing() {
+            U12_MAX
+        } else {
+            value
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+
+    use super::{gamma, LedPattern, Leds, PlugFlash, HIGH_CONTRAST_FLOOR};
+    use crate::accessibility::Accessibility;
+    use crate::units::{Hertz, Millis};
+    use crate::{JackSample, Sample, U12_MAX};
+
+    struct FakePwm {
+        duty: u16,
+    }
+
+    impl ErrorType for FakePwm {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for FakePwm {
+        fn max_duty_cycle(&self) -> u16 {
+            U12_MAX
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Infallible> {
+            self.duty = duty;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_leds() {
+        assert_eq!(gamma(0), 0);
+        assert_eq!(gamma(2048), 1024);
+        assert_eq!(gamma(U12_MAX), U12_MAX);
+
+        // 100Hz loop
+        let mut leds = Leds::new([FakePwm { duty: 1 }, FakePwm { duty: 1 }], Hertz::new(100));
+        leds.set(0, U12_MAX);
+        leds.set_pattern(
+            1,
+            LedPattern::Blink {
+                level: U12_MAX,
+                on: Millis::new(20),
+                off: Millis::new(30),
+            },
+        );
+        let mut blink = [0; 5];
+        for on in blink.iter_mut() {
+            leds.update().unwrap();
+            *on = leds.channels[1].duty;
+        }
+        assert_eq!(leds.channels[0].duty, U12_MAX);
+        assert_eq!(blink, [U12_MAX, U12_MAX, 0, 0, 0]);
+
+        leds.set_brightness(2048);
+        leds.update().unwrap();
+        assert_eq!(leds.channels[0].duty, 2048);
+
+        // 10ms per step, one tick each
+        leds.start_chase(U12_MAX, Millis::new(10));
+        assert_eq!([leds.level(0), leds.level(1)], [U12_MAX, 0]);
+        leds.update().unwrap();
+        assert_eq!([leds.level(0), leds.level(1)], [0, U12_MAX]);
+        leds.update().unwrap();
+        assert_eq!([leds.level(0), leds.level(1)], [U12_MAX, 0]);
+        leds.stop_chase();
+        assert_eq!(leds.level(0), U12_MAX);
+    }
+
+    #[test]
+    fn test_leds_accessibility() {
+        let mut leds = Leds::new([FakePwm { duty: 1 }, FakePwm { duty: 1 }], Hertz::new(100));
+        leds.set_accessibility(Accessibility {
+            high_contrast: true,
+            slow_ui: true,
+        });
+        leds.set(0, 100);
+        leds.set_pattern(
+            1,
+            LedPattern::Blink {
+                level: 2048,
+                on: Millis::new(20),
+                off: Millis::new(30),
+            },
+        );
+        let mut blink = [0; 10];
+        for on in blink.iter_mut() {
+            leds.update().unwrap();
+            *on = leds.channels[1].duty;
+        }
+        // dim levels stay visible, and mid levels skip gamma
+        assert_eq!(leds.channels[0].duty, HIGH_CONTRAST_FLOOR);
+        assert_eq!(blink, [2048, 2048, 2048, 2048, 0, 0, 0, 0, 0, 0]);
+        leds.set(0, 0);
+        leds.update().unwrap();
+        assert_eq!(leds.channels[0].duty, 0);
+
+        let slow = Accessibility {
+            high_contrast: false,
+            slow_ui: true,
+        };
+        let flash = PlugFlash::with_accessibility(Hertz::new(100), slow);
+        assert_eq!(flash.duration, 30);
+    }
+
+    #[test]
+    fn test_plug_flash() {
+        let mut jack = JackSample::new(Sample::from(0_i32), Sample::from(0_i32));
+        jack.set_debounce(1);
+        // 100Hz loop, 150ms flash
+        let mut flash = PlugFlash::new(Hertz::new(100));
+        assert!(!flash.update(&jack));
+        assert_eq!(flash.apply(100), 100);
+
+        // unplug
+        jack.probe = Sample::from(1000_i32);
+        jack.update_probe(Sample::from(1000_i32));
+        let snapshot = jack.clone();
+        assert!(flash.update(&snapshot));
+        assert_eq!(flash.apply(100), U12_MAX);
+        for _ in 1..15 {
+            assert!(flash.update(&snapshot));
+        }
+        assert!(!flash.update(&snapshot));
+        assert_eq!(flash.apply(100), 100);
+    }
+}
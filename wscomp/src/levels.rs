@@ -0,0 +1,124 @@
+//! Gain staging between 16 bit PCM, `Sample`, DAC codes and PWM duty
+//!
+//! One set of conversions, so every card lands on the same levels:
+//!
+//! ```text
+//! 16 bit PCM   -32768..32767   from_pcm16 / to_pcm16      (4 bits of shift)
+//! Sample       -2048..2047
+//! DAC code     0..4095         from_dac_code / to_output  (offset by 2048)
+//! PWM duty     0..max_duty     to_duty / to_duty_inverted (scaled to 0..4095)
+//! f32          -1.0..1.0       from_f32 / to_f32          (f32 feature only)
+//! ```
+//!
+//! Full scale maps to full scale in every direction, and narrowing
+//! conversions round to nearest, so a PCM file and a `Sample` at the same
+//! level produce the same DAC code.
+
+use crate::{Sample, U12_MAX};
+
+impl Sample {
+    /// From a 16 bit PCM sample, rounded to nearest
+    pub fn from_pcm16(value: i16) -> Self {
+        // +8 rounds, and can push 32767 over the top, so clamp
+        Self::new(((i32::from(value) + 8) >> 4).min(Self::MAX), false)
+    }
+
+    /// To a 16 bit PCM sample, saturating
+    pub fn to_pcm16(&self) -> i16 {
+        (self.to_clamped() << 4) as i16
+    }
+
+    /// From a 12 bit DAC or ADC code, 2048 is 0v
+    pub fn from_dac_code(code: u16) -> Self {
+        Self::from_u16(code.min(U12_MAX), false)
+    }
+
+    /// PWM duty cycle for this value, 0 at [`Sample::MIN`] and `max_duty` at [`Sample::MAX`]
+    pub fn to_duty(&self, max_duty: u16) -> u16 {
+        let code = u32::from(self.to_output());
+        let max_duty = u32::from(max_duty);
+        ((code * max_duty + u32::from(U12_MAX) / 2) / u32::from(U12_MAX)) as u16
+    }
+
+    /// PWM duty cycle for inverted outputs, like the CV jacks
+    pub fn to_duty_inverted(&self, max_duty: u16) -> u16 {
+        max_duty - self.to_duty(max_duty)
+    }
+}
+
+#[cfg(feature = "f32")]
+impl Sample {
+    /// To a float, [`Sample::MIN`] is -1.0 and [`Sample::MAX`] is just under 1.0
+    pub fn to_f32(&self) -> f32 {
+        self.to_clamped() as f32 / Self::OFFSET as f32
+    }
+
+    /// From a float, rounded to nearest and saturating, NaN is 0
+    pub fn from_f32(value: f32) -> Self {
+        let scaled = value * Self::OFFSET as f32;
+        // `as` saturates, and core has no f32::round
+        let rounded = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        } as i32;
+        Self::new(rounded.clamp(Self::MIN, Self::MAX), false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Sample, U12_MAX};
+
+    #[test]
+    fn test_pcm_and_dac_levels() {
+        assert_eq!(Sample::from_pcm16(i16::MAX).to_clamped(), Sample::MAX);
+        assert_eq!(Sample::from_pcm16(i16::MIN).to_clamped(), Sample::MIN);
+        assert_eq!(Sample::from_pcm16(0).to_clamped(), 0);
+        // rounds, where a plain >> 4 would give -1
+        assert_eq!(Sample::from_pcm16(-8).to_clamped(), 0);
+        assert_eq!(Sample::from_pcm16(24).to_clamped(), 2);
+        for value in [Sample::MIN, -1, 0, 1000, Sample::MAX] {
+            let sample = Sample::from(value);
+            assert_eq!(Sample::from_pcm16(sample.to_pcm16()), sample);
+            assert_eq!(Sample::from_dac_code(sample.to_output()), sample);
+        }
+        assert_eq!(Sample::from_dac_code(0).to_clamped(), Sample::MIN);
+        assert_eq!(Sample::from_dac_code(u16::MAX).to_clamped(), Sample::MAX);
+    }
+
+    #[test]
+    fn test_duty() {
+        let max = Sample::from(Sample::MAX);
+        let min = Sample::from(Sample::MIN);
+        assert_eq!(max.to_duty(U12_MAX), U12_MAX);
+        assert_eq!(min.to_duty(1000), 0);
+        assert_eq!(max.to_duty(1000), 1000);
+        assert_eq!(Sample::from(0_i32).to_duty(1000), 500);
+        assert_eq!(max.to_duty_inverted(1000), 0);
+        // same as the 12 bit output at a 12 bit top
+        let sample = Sample::from(-700_i32);
+        assert_eq!(sample.to_duty(U12_MAX), sample.to_output());
+        assert_eq!(
+            sample.to_duty_inverted(U12_MAX),
+            sample.to_output_inverted()
+        );
+    }
+
+    #[cfg(feature = "f32")]
+    #[test]
+    fn test_f32_levels() {
+        assert_eq!(Sample::from(Sample::MIN).to_f32(), -1.0);
+        assert_eq!(Sample::from(1024_i32).to_f32(), 0.5);
+        assert_eq!(Sample::from_f32(1.0).to_clamped(), Sample::MAX);
+        assert_eq!(Sample::from_f32(-2.0).to_clamped(), Sample::MIN);
+        assert_eq!(Sample::from_f32(f32::NAN).to_clamped(), 0);
+        assert_eq!(Sample::from_f32(-0.25).to_clamped(), -512);
+        // rounds to nearest
+        assert_eq!(Sample::from_f32(0.7 / 2048.0).to_clamped(), 1);
+        for value in [Sample::MIN, -1, 0, 1000, Sample::MAX] {
+            let sample = Sample::from(value);
+            assert_eq!(Sample::from_f32(sample.to_f32()), sample);
+        }
+    }
+}
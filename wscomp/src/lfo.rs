@@ -0,0 +1,209 @@
+//! Low frequency oscillator for modulation
+//!
+//! A 32 bit phase accumulator, ticked at a fixed rate (usually the control
+//! loop rate), read out through one of several [`LfoShape`]s:
+//!
+//! ```
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::lfo::{Lfo, LfoShape};
+//! # use wscomp::units::Hertz;
+//! # use wscomp::Sample;
+//! # const CONTROL_RATE: Hertz = Hertz::new(1_000);
+//! # fn card<P: SetDutyCycle>(mut cv_out: CvOut<P>, rate_knob: Sample) -> Result<(), P::Error> {
+//! let mut lfo = Lfo::new(LfoShape::Triangle, Hertz::from_millihertz(500), CONTROL_RATE);
+//! loop {
+//!     lfo.set_frequency_from(rate_knob, Hertz::from_millihertz(50), Hertz::new(20));
+//!     cv_out.set(lfo.tick())?;
+//! }
+//! # }
+//! ```
+//!
+//! Output is bipolar, the full [`Sample`] range. Sine and triangle start at
+//! 0 heading up, saw ramps up from [`Sample::MIN`] and square starts high, so
+//! [`Lfo::reset`] syncs all of them to the start of a cycle.
+
+use crate::random::Rng;
+use crate::tables;
+use crate::units::Hertz;
+use crate::Sample;
+
+/// Bits to shift a 32 bit phase down to a [`Sample`] range (4096 steps)
+const SAMPLE_SHIFT: u32 = 32 - 12;
+
+/// LFO waveform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    /// Rising ramp
+    Saw,
+    Square,
+    /// A new random level each cycle, stepped sample and hold
+    Random,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Lfo {
+    shape: LfoShape,
+    rate: Hertz,
+    phase: u32,
+    increment: u32,
+    /// Level held by [`LfoShape::Random`]
+    held: i32,
+    rng: Rng,
+}
+
+impl Lfo {
+    /// LFO at `frequency`, ticked `rate` times a second
+    pub fn new(shape: LfoShape, frequency: Hertz, rate: Hertz) -> Self {
+        Lfo {
+            shape,
+            rate,
+            phase: 0,
+            increment: frequency.phase_increment(rate),
+            held: 0,
+            rng: Rng::new(0),
+        }
+    }
+
+    pub fn set_shape(&mut self, shape: LfoShape) {
+        self.shape = shape;
+    }
+
+    pub fn shape(&self) -> LfoShape {
+        self.shape
+    }
+
+    /// Seed [`LfoShape::Random`], it repeats the same levels every boot
+    /// otherwise
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn set_frequency(&mut self, frequency: Hertz) {
+        self.increment = frequency.phase_increment(self.rate);
+    }
+
+    pub fn set_millihertz(&mut self, millihertz: u32) {
+        self.set_frequency(Hertz::from_millihertz(millihertz));
+    }
+
+    /// Set frequency from a knob or CV, [`Sample::MIN`] is `min` and
+    /// [`Sample::MAX`] is `max`
+    pub fn set_frequency_from(&mut self, value: Sample, min: Hertz, max: Hertz) {
+        let position = (value.to_clamped() - Sample::MIN) as u64;
+        let span = u64::from(max.millihertz().saturating_sub(min.millihertz()));
+        let millihertz = span * position / (Sample::MAX - Sample::MIN) as u64;
+        self.set_millihertz(min.millihertz() + millihertz as u32);
+    }
+
+    /// Restart the cycle, for syncing to a clock or trigger
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+
+    /// Advance one tick and return the new level
+    pub fn tick(&mut self) -> Sample {
+        let (phase, wrapped) = self.phase.overflowing_add(self.increment);
+        self.phase = phase;
+        if wrapped && self.shape == LfoShape::Random {
+            self.held = self.rng.next_sample().to_clamped();
+        }
+        self.current()
+    }
+
+    /// Level at the current phase
+    pub fn current(&self) -> Sample {
+        let phase = self.phase;
+        Sample::from(match self.shape {
+            LfoShape::Sine => tables::sine(phase).to_clamped(),
+            LfoShape::Triangle => {
+                // a quarter cycle ahead, so phase 0 is the middle of the rise
+                let shifted = phase.wrapping_add(1 << 30);
+                // fold the second half back down, no abs() to overflow
+                let folded = if shifted < 1 << 31 {
+                    u64::from(shifted) << 1
+                } else {
+                    ((1 << 32) - u64::from(shifted)) << 1
+                };
+                (folded.min(u64::from(u32::MAX)) >> SAMPLE_SHIFT) as i32 + Sample::MIN
+            }
+            LfoShape::Saw => (phase >> SAMPLE_SHIFT) as i32 + Sample::MIN,
+            LfoShape::Square => {
+                if phase < 1 << 31 {
+                    Sample::MAX
+                } else {
+                    Sample::MIN
+                }
+            }
+            LfoShape::Random => self.held,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Lfo, LfoShape};
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    #[test]
+    fn test_lfo_shapes() {
+        // 4 ticks per cycle, so each tick lands on a quarter
+        let quarters = |shape| {
+            let mut lfo = Lfo::new(shape, Hertz::new(1), Hertz::new(4));
+            let first = lfo.current().to_clamped();
+            let mut levels = [first; 4];
+            for level in levels.iter_mut().skip(1) {
+                *level = lfo.tick().to_clamped();
+            }
+            levels
+        };
+        assert_eq!(quarters(LfoShape::Sine), [0, 2047, 0, -2047]);
+        assert_eq!(quarters(LfoShape::Triangle), [0, 2047, 0, -2048]);
+        assert_eq!(quarters(LfoShape::Saw), [-2048, -1024, 0, 1024]);
+        assert_eq!(quarters(LfoShape::Square), [2047, 2047, -2048, -2048]);
+    }
+
+    #[test]
+    fn test_lfo_triangle_is_smooth() {
+        // backyard_rain's intensity LFO flickered when its triangle wrapped
+        let mut lfo = Lfo::new(LfoShape::Triangle, Hertz::new(1), Hertz::new(8192));
+        let mut last = lfo.current();
+        for _ in 0..(8192 * 2) {
+            let level = lfo.tick();
+            assert!((level - last).abs() <= Sample::from(2_i32));
+            last = level;
+        }
+        lfo.set_shape(LfoShape::Sine);
+        for _ in 0..8192 {
+            let level = lfo.tick();
+            assert!((level - last).abs() <= Sample::from(4_i32));
+            last = level;
+        }
+    }
+
+    #[test]
+    fn test_lfo_random_and_rate() {
+        let mut lfo = Lfo::new(LfoShape::Random, Hertz::new(1), Hertz::new(4));
+        let mut held = [0; 8];
+        for level in held.iter_mut() {
+            for _ in 0..4 {
+                *level = lfo.tick().to_clamped();
+            }
+        }
+        // steps once per cycle, to different levels
+        assert!(held.windows(2).all(|pair| pair[0] != pair[1]));
+
+        lfo.set_shape(LfoShape::Saw);
+        lfo.reset();
+        assert_eq!(lfo.current(), Sample::from(Sample::MIN));
+        // knob at max is the top of the range, 2Hz is 2 ticks per cycle
+        lfo.set_frequency_from(Sample::from(Sample::MAX), Hertz::new(1), Hertz::new(2));
+        lfo.tick();
+        assert_eq!(lfo.tick(), Sample::from(Sample::MIN));
+    }
+}
@@ -1,9 +1,80 @@
 #![cfg_attr(not(test), no_std)]
 
+use core::cmp::Ordering;
 use core::fmt::Debug;
-use core::ops::{Add, Div, Mul, Sub};
+use core::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
 
-use defmt::*;
+// first, so the logging macros are visible in every module
+#[macro_use]
+mod fmt;
+
+pub mod accessibility;
+pub mod adpcm;
+pub mod arena;
+#[cfg(feature = "armv6m")]
+mod armv6m;
+pub mod assets;
+pub mod batch;
+pub mod bernoulli;
+pub mod biquad;
+pub mod block;
+pub mod board;
+pub mod comparator;
+pub mod config;
+pub mod cv;
+pub mod dac;
+pub mod decibels;
+pub mod delay;
+pub mod diagnostics;
+pub mod display;
+pub mod eeprom;
+pub mod envelope;
+pub mod euclid;
+pub mod filter;
+pub mod fir;
+pub mod grain;
+pub mod graph;
+pub mod harmony;
+pub mod hold;
+pub mod inputs;
+pub mod interp;
+pub mod knob;
+pub mod leds;
+pub mod levels;
+pub mod lfo;
+pub mod lofi;
+pub mod logic;
+pub mod loops;
+pub mod meter;
+pub mod mixer;
+pub mod modmatrix;
+pub mod noise;
+pub mod osc;
+pub mod params;
+pub mod pitch;
+pub mod pitchdetect;
+pub mod power;
+pub mod pulse;
+pub mod quantizer;
+pub mod random;
+pub mod resample;
+pub mod reverb;
+pub mod ring;
+pub mod sequence;
+pub mod settings;
+pub mod shaper;
+pub mod smooth;
+pub mod source;
+pub mod switch;
+pub mod tables;
+pub mod tempo;
+pub mod trace;
+pub mod units;
+pub mod vca;
+pub mod wav;
+pub mod wavetable;
+
+pub use fmt::MaybeFormat;
 
 // Sample todos
 //
@@ -12,22 +83,39 @@ use defmt::*;
 
 pub const U12_MAX: u16 = 2u16.pow(12) - 1;
 
-/// A 12 bit value representing input from a knob or input jack's ADC
+/// A `BITS` bit signed value, with clamping, smoothing and fixed point math
+///
+/// [`Sample`] (12 bits) is the one cards use for inputs and outputs. Other
+/// widths share the same machinery, like 16 bit intermediate mixes or 8 bit
+/// table indices, and [`Value::convert`] moves between them at the same
+/// level, so full scale stays full scale. `BITS` is 2 to 16.
 ///
-/// Normalized to the range -2048 to 2047 inclusive. Stored as i32 to give
+/// Normalized to the range -2^(BITS-1) to 2^(BITS-1)-1 inclusive. Stored as i32 to give
 /// room for integer math without needing allocations and the rp2040 is 32bit.
 /// Conversions from this type saturate (clamp) - they stop at the min/max
-/// values without giving errors. Before converting, raw internal value will be
-/// outside of 12 bit range (allowing for math & accumulations, etc).
+/// values without giving errors. Before converting, the raw internal value can
+/// be outside of the `BITS` bit range (allowing for math & accumulations, etc).
 ///
 /// Values are smoothed over recent updates (count based on `ACCUM_BITS`).
-#[derive(Format, PartialEq, Copy, Clone, PartialOrd)]
-pub struct Sample {
+///
+/// Equality and ordering compare the clamped values (see [`Sample::to_clamped`]),
+/// so out of range accumulations and the inversion flag don't affect
+/// comparisons.
+///
+/// `Sample`s print as CV volts and percent of full scale, like
+/// `+2.34V (+39.0%)`, see [`display`].
+#[derive(Copy, Clone)]
+pub struct Value<const BITS: u8> {
     accumulated_raw: i32,
     inverted_source: bool,
 }
 
-impl Debug for Sample {
+/// A 12 bit value representing input from a knob or input jack's ADC
+///
+/// Normalized to the range -2048 to 2047 inclusive, see [`Value`].
+pub type Sample = Value<12>;
+
+impl<const BITS: u8> Debug for Value<BITS> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::write!(
             f,
@@ -38,20 +126,44 @@ impl Debug for Sample {
     }
 }
 
-impl Sample {
-    // CONST for min/max values (12 bit limits, 11 on each positive/negative)
-    pub const MIN: i32 = -2_i32.pow(11);
-    pub const MAX: i32 = 2_i32.pow(11) - 1;
+impl<const BITS: u8> PartialEq for Value<BITS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_clamped() == other.to_clamped()
+    }
+}
+
+impl<const BITS: u8> Eq for Value<BITS> {}
+
+impl<const BITS: u8> PartialOrd for Value<BITS> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const BITS: u8> Ord for Value<BITS> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_clamped().cmp(&other.to_clamped())
+    }
+}
+
+impl<const BITS: u8> Value<BITS> {
+    // CONST for min/max values (BITS bit limits, BITS - 1 on each positive/negative)
+    pub const MIN: i32 = -(1 << (BITS - 1));
+    pub const MAX: i32 = (1 << (BITS - 1)) - 1;
     pub const CENTER: i32 = 0;
-    pub const OFFSET: i32 = 2_i32.pow(11);
+    pub const OFFSET: i32 = 1 << (BITS - 1);
+    /// Largest value from [`Value::to_output`], [`U12_MAX`] for a [`Sample`]
+    pub const OUTPUT_MAX: u16 = ((1_u32 << BITS) - 1) as u16;
     const ACCUM_BITS: u8 = 3;
+    const VALID_BITS: () = assert!(BITS >= 2 && BITS <= 16, "Value is 2 to 16 bits");
 
     /// New `InputValue` from i32
     ///
-    /// Values are expected to already be 12bit (-2048..2048), but this
-    /// is not checked.
+    /// Values are expected to already be in range (-2048..2048 for 12
+    /// bits), but this is not checked.
     pub fn new(raw_value: i32, invert: bool) -> Self {
-        Sample {
+        let () = Self::VALID_BITS;
+        Value {
             accumulated_raw: match invert {
                 false => raw_value << Self::ACCUM_BITS,
                 true => -raw_value << Self::ACCUM_BITS,
@@ -62,15 +174,15 @@ impl Sample {
 
     /// New `InputValue` from u16 and offset value so center is at zero
     ///
-    /// Values are expected to already be 12bit (0..4096), but this
-    /// is not checked.
+    /// Values are expected to already be in range (0..4096 for 12 bits),
+    /// but this is not checked.
     pub fn from_u16(value: u16, invert: bool) -> Self {
         let mut output = i32::from(value);
         output -= Self::OFFSET;
         Self::new(output, invert)
     }
 
-    /// Saturating conversion into 12 bit safe u16 for output
+    /// Saturating conversion into `BITS` bit safe u16 for output
     pub fn to_output(&self) -> u16 {
         // clamp self and convert to u16
         (self.to_clamped() + Self::OFFSET) as u16
@@ -78,7 +190,7 @@ impl Sample {
 
     /// Saturating conversion into 12 bit safe u16 for output, inverted
     pub fn to_output_inverted(&self) -> u16 {
-        U12_MAX.saturating_sub(self.to_output())
+        Self::OUTPUT_MAX.saturating_sub(self.to_output())
     }
 
     /// Saturating conversion into 12 bit safe u16 for output, absolute value.
@@ -90,13 +202,20 @@ impl Sample {
 
     /// Saturating conversion into 12 bit safe u16 for output, inverted
     pub fn to_output_abs_inverted(&self) -> u16 {
-        U12_MAX.saturating_sub(self.to_output_abs())
+        Self::OUTPUT_MAX.saturating_sub(self.to_output_abs())
     }
 
     pub fn to_clamped(&self) -> i32 {
         (self.accumulated_raw >> Self::ACCUM_BITS).clamp(Self::MIN, Self::MAX)
     }
 
+    /// Value before clamping, out of range after sums past full scale
+    ///
+    /// For code that shapes overs itself, like [`shaper::soft_clip`].
+    pub fn to_unclamped(&self) -> i32 {
+        self.accumulated_raw >> Self::ACCUM_BITS
+    }
+
     pub fn to_inverted(&self) -> Self {
         Self::new(-self.accumulated_raw, self.inverted_source)
     }
@@ -125,6 +244,218 @@ impl Sample {
             self.inverted_source,
         )
     }
+
+    /// Linear interpolation from `a` to `b`, `t` from 0 (all `a`) to [`MAX`] (all `b`)
+    ///
+    /// Negative `t` is treated as 0. Used for crossfades, wavetables and
+    /// fractional delays.
+    pub fn lerp(a: Self, b: Self, t: Self) -> Self {
+        Self::lerp_fraction(a, b, t.to_clamped().max(0), Self::MAX)
+    }
+
+    /// Linear interpolation from `a` to `b` by `numerator / denominator`
+    ///
+    /// Rounded to nearest, so both ends are exact. The fraction is clamped
+    /// to 0..=1, a `denominator` of 0 or less gives `a`.
+    pub fn lerp_fraction(a: Self, b: Self, numerator: i32, denominator: i32) -> Self {
+        if denominator <= 0 {
+            return a;
+        }
+        let numerator = numerator.clamp(0, denominator);
+        let a_value = a.to_clamped();
+        let delta = i64::from(b.to_clamped() - a_value) * i64::from(numerator);
+        let denominator = i64::from(denominator);
+        // round half away from zero, like div_rounded
+        let step = if delta < 0 {
+            (delta - denominator / 2) / denominator
+        } else {
+            (delta + denominator / 2) / denominator
+        };
+        Self::new(a_value + step as i32, a.inverted_source)
+    }
+
+    /// Map this value from `in_min..=in_max` onto `out_min..=out_max`
+    ///
+    /// Values outside the input range clamp to its ends, and either range
+    /// can run backwards, so `cv.map_range(0, Self::MAX, Self::MAX, 0)`
+    /// inverts the positive half of a CV. See [`Value::map_range_i32`] and
+    /// [`Value::map_range_u16`] for counts that aren't samples.
+    pub fn map_range(&self, in_min: i32, in_max: i32, out_min: i32, out_max: i32) -> Self {
+        Self::new(
+            self.map_range_i32(in_min, in_max, out_min, out_max),
+            self.inverted_source,
+        )
+    }
+
+    /// Map this value from `in_min..=in_max` onto any `i32` range, such as
+    /// a delay in samples
+    ///
+    /// Rounded to nearest, so both ends are exact. An empty input range
+    /// gives `out_min`.
+    pub fn map_range_i32(&self, in_min: i32, in_max: i32, out_min: i32, out_max: i32) -> i32 {
+        let (mut numerator, mut denominator) = (
+            i64::from(self.to_clamped()) - i64::from(in_min),
+            i64::from(in_max) - i64::from(in_min),
+        );
+        if denominator == 0 {
+            return out_min;
+        }
+        if denominator < 0 {
+            (numerator, denominator) = (-numerator, -denominator);
+        }
+        let delta = (i64::from(out_max) - i64::from(out_min)) * numerator.clamp(0, denominator);
+        // round half away from zero, like div_rounded
+        let step = if delta < 0 {
+            (delta - denominator / 2) / denominator
+        } else {
+            (delta + denominator / 2) / denominator
+        };
+        (i64::from(out_min) + step) as i32
+    }
+
+    /// Map this value from `in_min..=in_max` onto a `u16` range, such as a
+    /// PWM top or duty cycle
+    pub fn map_range_u16(&self, in_min: i32, in_max: i32, out_min: u16, out_max: u16) -> u16 {
+        self.map_range_i32(in_min, in_max, out_min.into(), out_max.into()) as u16
+    }
+
+    /// Add without overflowing the internal accumulator
+    ///
+    /// Useful in feedback loops where repeated `+` could eventually wrap.
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        Value {
+            accumulated_raw: self.accumulated_raw.saturating_add(rhs.accumulated_raw),
+            inverted_source: self.inverted_source,
+        }
+    }
+
+    /// Subtract without overflowing the internal accumulator
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        Value {
+            accumulated_raw: self.accumulated_raw.saturating_sub(rhs.accumulated_raw),
+            inverted_source: self.inverted_source,
+        }
+    }
+
+    /// Wrap around into the `BITS` bit range, like an oscillator phase
+    ///
+    /// [`MAX`] + 1 wraps to [`MIN`]. The accumulator's fractional bits are
+    /// kept, so slow phase increments don't lose precision.
+    pub fn wrapped(self) -> Self {
+        // the range is a power of two, so sign extending from the top bit of
+        // the range wraps
+        let shift = i32::BITS - u32::from(BITS) - u32::from(Self::ACCUM_BITS);
+        Value {
+            accumulated_raw: (self.accumulated_raw << shift) >> shift,
+            inverted_source: self.inverted_source,
+        }
+    }
+
+    /// Add, wrapping around the `BITS` bit range instead of saturating
+    pub fn wrapping_add(self, rhs: Self) -> Self {
+        Value {
+            accumulated_raw: self.accumulated_raw.wrapping_add(rhs.accumulated_raw),
+            inverted_source: self.inverted_source,
+        }
+        .wrapped()
+    }
+
+    /// Subtract, wrapping around the `BITS` bit range instead of saturating
+    pub fn wrapping_sub(self, rhs: Self) -> Self {
+        Value {
+            accumulated_raw: self.accumulated_raw.wrapping_sub(rhs.accumulated_raw),
+            inverted_source: self.inverted_source,
+        }
+        .wrapped()
+    }
+
+    /// The same level at another bit width, rounded to nearest
+    ///
+    /// Full scale maps to full scale, so a 16 bit mix converts to a 12 bit
+    /// output without halving or doubling it. Negative values shift, so
+    /// [`Value::MIN`] is `MIN` at any width, and positive ones are rescaled
+    /// so [`Value::MAX`] is `MAX`. Widening and narrowing back gives the
+    /// value you started with.
+    pub fn convert<const OTHER: u8>(&self) -> Value<OTHER> {
+        let value = self.to_clamped();
+        let value = if value >= 0 {
+            // at most 2^30, so the product fits
+            (value * Value::<OTHER>::MAX + Self::MAX / 2) / Self::MAX
+        } else if OTHER >= BITS {
+            value << (OTHER - BITS)
+        } else {
+            let shift = BITS - OTHER;
+            (value + (1 << (shift - 1))) >> shift
+        };
+        let mut converted = Value::new(value, false);
+        converted.inverted_source = self.inverted_source;
+        converted
+    }
+}
+
+impl Sample {
+    /// Approximate voltage of CV jacks at full scale (±6v), in millivolts
+    pub const CV_MILLIVOLTS: i32 = 6_000;
+    /// Approximate voltage of audio jacks at full scale (±6v), in millivolts
+    ///
+    /// Kept separate from [`Sample::CV_MILLIVOLTS`] because the audio path
+    /// (DAC and direct ADC) is calibrated independently of the CV path.
+    pub const AUDIO_MILLIVOLTS: i32 = 6_000;
+    /// Half width of the region around center where [`Sample::attenuvert`]
+    /// gives exactly 0, about 2% of the knob's travel each side
+    pub const ATTENUVERT_SNAP: i32 = 40;
+
+    /// Attenuvert this sample by a bipolar `amount`, like a knob
+    ///
+    /// [`MIN`]..[`MAX`] maps to -100%..+100% gain. Amounts within
+    /// [`Sample::ATTENUVERT_SNAP`] of center give exactly 0, so a centered
+    /// knob doesn't leak signal, and gain ramps up from the edge of that region.
+    pub fn attenuvert(&self, amount: Self) -> Self {
+        let amount = amount.to_clamped();
+        let range = Self::MAX - Self::ATTENUVERT_SNAP;
+        let gain = (amount.abs() - Self::ATTENUVERT_SNAP).max(0) * amount.signum();
+        Self::new(
+            div_rounded(self.to_clamped() * gain.max(-range), range),
+            self.inverted_source,
+        )
+    }
+
+    /// Approximate voltage on the CV path, in millivolts
+    ///
+    /// [`OFFSET`](Sample::OFFSET) counts ≈ [`CV_MILLIVOLTS`](Sample::CV_MILLIVOLTS).
+    pub fn to_millivolts(&self) -> i32 {
+        div_rounded(self.to_clamped() * Self::CV_MILLIVOLTS, Self::OFFSET)
+    }
+
+    /// New `Sample` from a voltage on the CV path, in millivolts
+    pub fn from_millivolts(millivolts: i32) -> Self {
+        Self::new(
+            div_rounded(millivolts * Self::OFFSET, Self::CV_MILLIVOLTS),
+            false,
+        )
+    }
+
+    /// Approximate voltage on the audio path, in millivolts
+    pub fn to_audio_millivolts(&self) -> i32 {
+        div_rounded(self.to_clamped() * Self::AUDIO_MILLIVOLTS, Self::OFFSET)
+    }
+
+    /// New `Sample` from a voltage on the audio path, in millivolts
+    pub fn from_audio_millivolts(millivolts: i32) -> Self {
+        Self::new(
+            div_rounded(millivolts * Self::OFFSET, Self::AUDIO_MILLIVOLTS),
+            false,
+        )
+    }
+}
+
+/// Integer division rounded to nearest, instead of towards zero
+fn div_rounded(numerator: i32, denominator: i32) -> i32 {
+    if (numerator < 0) == (denominator < 0) {
+        (numerator + denominator / 2) / denominator
+    } else {
+        (numerator - denominator / 2) / denominator
+    }
 }
 
 pub trait SampleUpdate<V> {
@@ -132,7 +463,7 @@ pub trait SampleUpdate<V> {
     fn update(&mut self, value: V);
 }
 
-impl SampleUpdate<u16> for Sample {
+impl<const BITS: u8> SampleUpdate<u16> for Value<BITS> {
     /// Update with new value from 12 bit u16
     ///
     /// Expecting 12 bit number between 0..4096, from various Computer
@@ -148,7 +479,7 @@ impl SampleUpdate<u16> for Sample {
     }
 }
 
-impl SampleUpdate<Self> for Sample {
+impl<const BITS: u8> SampleUpdate<Self> for Value<BITS> {
     /// Update with new value from another [`Sample`]
     fn update(&mut self, value: Self) {
         let value = value.to_clamped();
@@ -157,7 +488,7 @@ impl SampleUpdate<Self> for Sample {
     }
 }
 
-impl SampleUpdate<i32> for Sample {
+impl<const BITS: u8> SampleUpdate<i32> for Value<BITS> {
     /// Update with new value from i32
     ///
     /// Unchecked update, assuming value within -2048..2048
@@ -169,18 +500,18 @@ impl SampleUpdate<i32> for Sample {
     }
 }
 
-impl From<i32> for Sample {
+impl<const BITS: u8> From<i32> for Value<BITS> {
     fn from(value: i32) -> Self {
         Self::new(value, false)
     }
 }
-impl From<i16> for Sample {
+impl<const BITS: u8> From<i16> for Value<BITS> {
     fn from(value: i16) -> Self {
         Self::new(value.into(), false)
     }
 }
 
-impl Add for Sample {
+impl<const BITS: u8> Add for Value<BITS> {
     type Output = Self;
 
     fn add(mut self, rhs: Self) -> Self::Output {
@@ -189,7 +520,7 @@ impl Add for Sample {
     }
 }
 
-impl Sub for Sample {
+impl<const BITS: u8> Sub for Value<BITS> {
     type Output = Self;
 
     fn sub(mut self, rhs: Self) -> Self::Output {
@@ -198,7 +529,19 @@ impl Sub for Sample {
     }
 }
 
-impl Mul for Sample {
+impl<const BITS: u8> AddAssign for Value<BITS> {
+    fn add_assign(&mut self, rhs: Self) {
+        self.accumulated_raw += rhs.accumulated_raw;
+    }
+}
+
+impl<const BITS: u8> SubAssign for Value<BITS> {
+    fn sub_assign(&mut self, rhs: Self) {
+        self.accumulated_raw -= rhs.accumulated_raw;
+    }
+}
+
+impl<const BITS: u8> Mul for Value<BITS> {
     type Output = Self;
 
     fn mul(mut self, rhs: Self) -> Self::Output {
@@ -209,7 +552,7 @@ impl Mul for Sample {
     }
 }
 
-impl Mul<i32> for Sample {
+impl<const BITS: u8> Mul<i32> for Value<BITS> {
     type Output = Self;
 
     fn mul(mut self, rhs: i32) -> Self::Output {
@@ -219,7 +562,7 @@ impl Mul<i32> for Sample {
     }
 }
 
-impl Div<i32> for Sample {
+impl<const BITS: u8> Div<i32> for Value<BITS> {
     type Output = Self;
 
     fn div(mut self, rhs: i32) -> Self::Output {
@@ -243,26 +586,149 @@ impl Div<i32> for Sample {
 /// be smoothed to avoid false negatives from short term voltages on the cable
 /// which happen to have the right voltage difference between them from a single
 /// sample.
-#[derive(Format, Clone)]
+///
+/// Plugged state uses separate plug and unplug thresholds (hysteresis), so a
+/// difference hovering near one threshold doesn't flip the state back and
+/// forth. State is re-evaluated on each [`JackSample::update_probe`], and only
+/// changes after several consecutive probe comparisons agree (see
+/// [`JackSample::set_debounce`]), so a single glitched reading doesn't cause a
+/// momentary unplug.
+#[derive(Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct JackSample {
     pub raw: Sample,
     pub probe: Sample,
+    plugged: bool,
+    plug_threshold: i32,
+    unplug_threshold: i32,
+    debounce: u8,
+    pending: u8,
+    plug_changes: u8,
+    normalled: Sample,
 }
 
-// TODO: implement probe logic
 impl JackSample {
+    /// Default difference below which an unplugged jack becomes plugged
+    ///
+    /// Determined through testing my unit, may need adjusting, see
+    /// [`JackSample::with_thresholds`].
+    pub const PLUG_THRESHOLD: i32 = 250;
+    /// Default difference above which a plugged jack becomes unplugged
+    pub const UNPLUG_THRESHOLD: i32 = 350;
+    /// Default consecutive probe comparisons needed to change state
+    pub const DEBOUNCE: u8 = 4;
+
     pub fn new(raw: Sample, probe: Sample) -> JackSample {
-        JackSample { raw, probe }
+        Self::with_thresholds(raw, probe, Self::PLUG_THRESHOLD, Self::UNPLUG_THRESHOLD)
+    }
+
+    /// New `JackSample` with custom probe thresholds, in counts
+    ///
+    /// `plug_threshold` should be at or below `unplug_threshold`, the gap
+    /// between them is the hysteresis.
+    pub fn with_thresholds(
+        raw: Sample,
+        probe: Sample,
+        plug_threshold: i32,
+        unplug_threshold: i32,
+    ) -> JackSample {
+        let mut jack = JackSample {
+            raw,
+            probe,
+            plugged: true,
+            plug_threshold,
+            unplug_threshold: unplug_threshold.max(plug_threshold),
+            debounce: Self::DEBOUNCE,
+            pending: 0,
+            plug_changes: 0,
+            normalled: Sample::from(0_i32),
+        };
+        jack.plugged = jack.probe_difference() <= jack.unplug_threshold;
+        jack
+    }
+
+    /// Set how many consecutive probe comparisons must agree before the
+    /// plugged state changes, 1 changes immediately
+    pub fn set_debounce(&mut self, count: u8) {
+        self.debounce = count.max(1);
+        self.pending = 0;
+    }
+
+    /// Update the probe value and re-evaluate the plugged state
+    ///
+    /// Call after updating `raw`, while the probe is enabled.
+    pub fn update_probe<V>(&mut self, value: V)
+    where
+        Sample: SampleUpdate<V>,
+    {
+        self.probe.update(value);
+        self.update_plugged();
+    }
+
+    pub fn is_plugged(&self) -> bool {
+        self.plugged
+    }
+
+    /// Wrapping count of plug/unplug events
+    ///
+    /// Compare with a previous value to detect changes from copies of this
+    /// state, see [`leds::PlugFlash`].
+    pub fn plug_changes(&self) -> u8 {
+        self.plug_changes
     }
 
     pub fn plugged_value(&self) -> Option<&Sample> {
-        let mut diff = self.probe.accumulated_raw - self.raw.accumulated_raw;
-        diff >>= Sample::ACCUM_BITS;
-        // determined through testing my unit, may need adjusting
-        if diff > 300 {
+        if self.plugged {
+            Some(&self.raw)
+        } else {
             None
+        }
+    }
+
+    /// Input value if a cable is plugged, otherwise `fallback`
+    ///
+    /// For "use CV1 if plugged, else the internal LFO" style normalling.
+    pub fn value_or(&self, fallback: Sample) -> Sample {
+        match self.plugged_value() {
+            Some(value) => *value,
+            None => fallback,
+        }
+    }
+
+    /// Set the internal source used by [`JackSample::value`] when unplugged
+    ///
+    /// Defaults to 0v. Update it whenever the source changes (e.g. each LFO
+    /// step).
+    pub fn set_normalled(&mut self, source: Sample) {
+        self.normalled = source;
+    }
+
+    /// Input value if a cable is plugged, otherwise the normalled source
+    pub fn value(&self) -> Sample {
+        self.value_or(self.normalled)
+    }
+
+    /// Difference between probe and raw values, in counts
+    fn probe_difference(&self) -> i32 {
+        (self.probe.accumulated_raw - self.raw.accumulated_raw) >> Sample::ACCUM_BITS
+    }
+
+    fn update_plugged(&mut self) {
+        let diff = self.probe_difference();
+        let changed = if self.plugged {
+            diff > self.unplug_threshold
         } else {
-            Some(&self.raw)
+            diff < self.plug_threshold
+        };
+        if !changed {
+            self.pending = 0;
+            return;
+        }
+        self.pending += 1;
+        if self.pending >= self.debounce {
+            self.plugged = !self.plugged;
+            self.pending = 0;
+            self.plug_changes = self.plug_changes.wrapping_add(1);
         }
     }
 }
@@ -270,7 +736,7 @@ impl JackSample {
 #[cfg(test)]
 mod test {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
-    use super::{Sample, SampleUpdate, U12_MAX};
+    use super::{JackSample, Sample, SampleUpdate, Value, U12_MAX};
 
     #[test]
     fn test_input_value_basics() {
@@ -379,6 +845,251 @@ mod test {
         assert_eq!(Sample::new(123, false) / -1, Sample::new(-123, false));
     }
 
+    #[test]
+    fn test_input_value_assign_math() {
+        let mut sample = Sample::new(123, false);
+        sample += Sample::new(456, false);
+        assert_eq!(sample, Sample::new(579, false));
+        sample -= Sample::new(79, false);
+        assert_eq!(sample, Sample::new(500, false));
+    }
+
+    #[test]
+    fn test_input_value_saturating_math() {
+        let big = Sample::new(i32::MAX >> 3, false);
+        let mut sample = big;
+        for _ in 0..4 {
+            sample = sample.saturating_add(big);
+        }
+        assert_eq!(sample.to_clamped(), Sample::MAX);
+
+        let mut sample = Sample::new(i32::MIN >> 3, false);
+        for _ in 0..4 {
+            sample = sample.saturating_sub(big);
+        }
+        assert_eq!(sample.to_clamped(), Sample::MIN);
+
+        assert_eq!(
+            Sample::new(100, false).saturating_sub(Sample::new(30, false)),
+            Sample::new(70, false)
+        );
+    }
+
+    #[test]
+    fn test_input_value_attenuvert() {
+        let signal = Sample::from(1000_i32);
+        // full scale either way, without the 2047/2048 loss of dividing by OFFSET
+        assert_eq!(signal.attenuvert(Sample::from(Sample::MAX)), signal);
+        assert_eq!(
+            signal.attenuvert(Sample::from(Sample::MIN)),
+            Sample::from(-1000_i32)
+        );
+        // centered, or nearly, is silent
+        for amount in [0, 1, -1, Sample::ATTENUVERT_SNAP, -Sample::ATTENUVERT_SNAP] {
+            assert_eq!(signal.attenuvert(Sample::from(amount)).to_clamped(), 0);
+        }
+        // and ramps up from the edge of the snap region
+        assert_eq!(
+            signal
+                .attenuvert(Sample::from(Sample::ATTENUVERT_SNAP + 1))
+                .to_clamped(),
+            0
+        );
+        assert_eq!(
+            Sample::from(Sample::MAX)
+                .attenuvert(Sample::from(Sample::ATTENUVERT_SNAP + 1))
+                .to_clamped(),
+            1
+        );
+        let half = (Sample::MAX + Sample::ATTENUVERT_SNAP) / 2;
+        assert_eq!(
+            signal.attenuvert(Sample::from(-half)),
+            Sample::from(-500_i32)
+        );
+    }
+
+    #[test]
+    fn test_value_bit_widths() {
+        assert_eq!((Value::<8>::MIN, Value::<8>::MAX), (-128, 127));
+        assert_eq!(Value::<16>::OUTPUT_MAX, u16::MAX);
+        assert_eq!(Sample::OUTPUT_MAX, U12_MAX);
+        assert_eq!(Value::<16>::from(40_000_i32).to_clamped(), Value::<16>::MAX);
+        assert_eq!(Value::<8>::from(-100_i32).to_output(), 28);
+
+        // full scale stays full scale
+        let loud: Value<16> = Value::from(Value::<16>::MAX);
+        assert_eq!(loud.convert::<12>(), Sample::from(Sample::MAX));
+        assert_eq!(
+            Value::<16>::from(Value::<16>::MIN).convert::<12>(),
+            Sample::from(Sample::MIN)
+        );
+        assert_eq!(
+            Sample::from(Sample::MAX).convert::<16>(),
+            Value::from(Value::<16>::MAX)
+        );
+        assert_eq!(
+            Sample::from(Sample::MIN).convert::<16>(),
+            Value::from(Value::<16>::MIN)
+        );
+        assert_eq!(
+            Value::<8>::from(Value::<8>::MAX).convert::<12>(),
+            Sample::from(Sample::MAX)
+        );
+        assert_eq!(
+            Sample::from(1000_i32).convert::<16>(),
+            Value::from(16_007_i32)
+        );
+        assert_eq!(
+            Sample::from(-1000_i32).convert::<16>(),
+            Value::from(-16_000_i32)
+        );
+        // narrowing rounds to nearest
+        assert_eq!(Value::<16>::from(-24_i32).convert::<12>().to_clamped(), -1);
+        assert_eq!(Value::<16>::from(23_i32).convert::<12>().to_clamped(), 1);
+        assert_eq!(Sample::from(1000_i32).convert::<8>().to_clamped(), 62);
+        // and undoes widening
+        for value in Sample::MIN..=Sample::MAX {
+            let sample = Sample::from(value);
+            assert_eq!(sample.convert::<16>().convert::<12>(), sample);
+        }
+
+        // phases wrap at their own width
+        let phase = Value::<8>::from(127_i32).wrapping_add(Value::from(2_i32));
+        assert_eq!(phase.to_clamped(), -127);
+    }
+
+    #[test]
+    fn test_input_value_wrapping_math() {
+        let one = Sample::from(1_i32);
+        assert_eq!(
+            Sample::from(Sample::MAX).wrapping_add(one).to_clamped(),
+            Sample::MIN
+        );
+        assert_eq!(
+            Sample::from(Sample::MIN).wrapping_sub(one).to_clamped(),
+            Sample::MAX
+        );
+        assert_eq!(Sample::new(5000, false).wrapped().to_clamped(), 904);
+        assert_eq!(Sample::new(-5000, false).wrapped().to_clamped(), -904);
+
+        // a phase stepping by 3/8 of a count, fractions carry across the wrap
+        let step = Sample {
+            accumulated_raw: 3,
+            inverted_source: false,
+        };
+        let mut phase = Sample::from(Sample::MIN);
+        for _ in 0..(4096 * 8) {
+            phase = phase.wrapping_add(step);
+        }
+        assert_eq!(phase.to_clamped(), Sample::MIN);
+        assert_eq!(phase.accumulated_raw, Sample::MIN << 3);
+    }
+
+    #[test]
+    fn test_input_value_lerp() {
+        let a = Sample::from(-1000_i32);
+        let b = Sample::from(1000_i32);
+        assert_eq!(Sample::lerp(a, b, Sample::from(0_i32)), a);
+        assert_eq!(Sample::lerp(a, b, Sample::from(Sample::MAX)), b);
+        assert_eq!(Sample::lerp(a, b, Sample::from(-500_i32)), a);
+        assert_eq!(
+            Sample::lerp(a, b, Sample::from(1024_i32)),
+            Sample::from(0_i32)
+        );
+
+        // 1/3 of the way, rounded in both directions
+        assert_eq!(
+            Sample::lerp_fraction(Sample::from(0_i32), Sample::from(100_i32), 1, 3),
+            Sample::from(33_i32)
+        );
+        assert_eq!(
+            Sample::lerp_fraction(Sample::from(0_i32), Sample::from(-200_i32), 1, 3),
+            Sample::from(-67_i32)
+        );
+        assert_eq!(Sample::lerp_fraction(a, b, 1 << 16, 1 << 16), b);
+        // no fraction without a positive denominator
+        assert_eq!(Sample::lerp_fraction(a, b, 1, 0), a);
+        assert_eq!(Sample::lerp_fraction(a, b, -1, -2), a);
+    }
+
+    #[test]
+    fn test_input_value_map_range() {
+        let knob = |value: i32| Sample::from(value);
+        // positive half of a CV onto a bipolar range
+        assert_eq!(
+            knob(0).map_range(0, Sample::MAX, -500, 500),
+            Sample::from(-500_i32)
+        );
+        assert_eq!(
+            knob(Sample::MAX).map_range(0, Sample::MAX, -500, 500),
+            Sample::from(500_i32)
+        );
+        assert_eq!(
+            knob(-1000).map_range(0, Sample::MAX, -500, 500),
+            Sample::from(-500_i32)
+        );
+        // backwards output range inverts, halfway is 1023.5 rounded down
+        assert_eq!(
+            knob(500).map_range(0, 1000, Sample::MAX, 0),
+            Sample::from(1023_i32)
+        );
+        // and a backwards input range maps the same way
+        assert_eq!(
+            knob(250).map_range_i32(1000, 0, 0, 100),
+            knob(750).map_range_i32(0, 1000, 0, 100)
+        );
+        assert_eq!(knob(100).map_range_i32(5, 5, 7, 9), 7);
+
+        // raw counts, full knob travel onto a PWM top
+        let full = |value| knob(value).map_range_u16(Sample::MIN, Sample::MAX, 0, u16::MAX);
+        assert_eq!(full(Sample::MIN), 0);
+        assert_eq!(full(Sample::MAX), u16::MAX);
+        assert_eq!(full(0), 32_776);
+        assert_eq!(
+            knob(Sample::MAX).map_range_i32(0, Sample::MAX, 48, 48_000),
+            48_000
+        );
+    }
+
+    #[test]
+    fn test_input_value_ordering() {
+        // inversion flag doesn't affect comparisons
+        assert_eq!(Sample::new(100, false), Sample::new(-100, true));
+        assert!(Sample::new(100, true) < Sample::from(0_i32));
+
+        // out of range accumulations compare as clamped
+        let above_range = Sample::new(Sample::MAX, false) + Sample::new(500, false);
+        assert_eq!(above_range, Sample::new(Sample::MAX, false));
+        assert!(above_range > Sample::new(2000, false));
+
+        assert_eq!(
+            Sample::new(5, false).max(Sample::new(-5, false)),
+            Sample::new(5, false)
+        );
+    }
+
+    #[test]
+    fn test_input_value_millivolts() {
+        assert_eq!(Sample::from(0_i32).to_millivolts(), 0);
+        assert_eq!(Sample::from(1024_i32).to_millivolts(), 3000);
+        assert_eq!(Sample::from(-1024_i32).to_millivolts(), -3000);
+        assert_eq!(Sample::from(Sample::MIN).to_millivolts(), -6000);
+        assert_eq!(Sample::from(Sample::MAX).to_millivolts(), 5997);
+
+        assert_eq!(Sample::from_millivolts(3000).to_clamped(), 1024);
+        assert_eq!(Sample::from_millivolts(-6000).to_clamped(), Sample::MIN);
+        assert_eq!(Sample::from_millivolts(1000).to_clamped(), 341);
+        // saturates beyond the ±6v range
+        assert_eq!(Sample::from_millivolts(10_000).to_clamped(), Sample::MAX);
+
+        for mv in [-5000, -1234, -1, 0, 1, 42, 2500, 5000] {
+            let round_trip = Sample::from_millivolts(mv).to_millivolts();
+            assert!((round_trip - mv).abs() <= 2, "{mv} -> {round_trip}");
+            let round_trip = Sample::from_audio_millivolts(mv).to_audio_millivolts();
+            assert!((round_trip - mv).abs() <= 2, "{mv} -> {round_trip}");
+        }
+    }
+
     #[test]
     fn test_input_value_update() {
         let mut sample = Sample::from(0_i32);
@@ -394,4 +1105,85 @@ mod test {
         }
         assert_eq!(sample.to_clamped(), Sample::MIN, "should converge to MIN");
     }
+
+    #[test]
+    fn test_jack_sample_hysteresis() {
+        let mut jack = JackSample::new(Sample::from(100_i32), Sample::from(100_i32));
+        jack.set_debounce(1);
+        assert_eq!(jack.plugged_value(), Some(&Sample::from(100_i32)));
+
+        // probe well above raw, unplugged
+        jack.probe = Sample::from(1000_i32);
+        jack.update_probe(Sample::from(1000_i32));
+        assert!(!jack.is_plugged());
+        assert_eq!(jack.plugged_value(), None);
+
+        // between thresholds, stays unplugged
+        jack.probe = Sample::from(400_i32);
+        jack.update_probe(Sample::from(400_i32));
+        assert!(!jack.is_plugged());
+
+        // below the plug threshold
+        jack.probe = Sample::from(300_i32);
+        jack.update_probe(Sample::from(300_i32));
+        assert!(jack.is_plugged());
+
+        // between thresholds again, stays plugged
+        jack.probe = Sample::from(400_i32);
+        jack.update_probe(Sample::from(400_i32));
+        assert!(jack.is_plugged());
+    }
+
+    #[test]
+    fn test_jack_sample_custom_thresholds() {
+        let mut jack =
+            JackSample::with_thresholds(Sample::from(0_i32), Sample::from(200_i32), 100, 150);
+        assert!(!jack.is_plugged());
+        jack.set_debounce(1);
+        jack.probe = Sample::from(50_i32);
+        jack.update_probe(Sample::from(50_i32));
+        assert!(jack.is_plugged());
+    }
+
+    #[test]
+    fn test_jack_sample_debounce() {
+        let mut jack = JackSample::new(Sample::from(0_i32), Sample::from(0_i32));
+        let unplugged = Sample::from(1000_i32);
+        let plugged = Sample::from(0_i32);
+
+        // a single glitched reading doesn't unplug
+        jack.probe = unplugged;
+        jack.update_probe(unplugged);
+        jack.probe = plugged;
+        jack.update_probe(plugged);
+        assert!(jack.is_plugged());
+
+        // and the count restarts after a consistent reading
+        jack.probe = unplugged;
+        for _ in 1..JackSample::DEBOUNCE {
+            jack.update_probe(unplugged);
+            assert!(jack.is_plugged());
+        }
+        jack.update_probe(unplugged);
+        assert!(!jack.is_plugged());
+        assert_eq!(jack.plug_changes(), 1);
+    }
+
+    #[test]
+    fn test_jack_sample_normalled() {
+        let mut jack = JackSample::new(Sample::from(500_i32), Sample::from(500_i32));
+        jack.set_debounce(1);
+        assert_eq!(jack.value_or(Sample::from(-100_i32)), Sample::from(500_i32));
+        assert_eq!(jack.value(), Sample::from(500_i32));
+
+        jack.probe = Sample::from(1500_i32);
+        jack.update_probe(Sample::from(1500_i32));
+        assert_eq!(
+            jack.value_or(Sample::from(-100_i32)),
+            Sample::from(-100_i32)
+        );
+        assert_eq!(jack.value(), Sample::from(0_i32));
+        jack.set_normalled(Sample::from(42_i32));
+        assert_eq!(jack.value(), Sample::from(42_i32));
+    }
 }
@@ -0,0 +1,172 @@
+//! Lo-fi effects: bit depth and sample rate reduction
+//!
+//! ```
+//! # use wscomp::block::SampleBlock;
+//! # use wscomp::lofi::{BitCrush, RateReduce};
+//! # let mut block = SampleBlock::<64>::silent();
+//! let mut crush = BitCrush::new(6);
+//! let mut reduce = RateReduce::new(4);
+//! for sample in block.iter_mut() {
+//!     *sample = crush.process(reduce.process(*sample));
+//! }
+//! ```
+
+use crate::{div_rounded, Sample};
+
+/// Reduce bit depth to `2^bits` evenly spaced levels
+///
+/// The levels span the full range, so 1 bit is a full scale square wave, and
+/// are symmetric like the range itself. Masking off low bits instead would
+/// round everything down, adding a DC offset of half a step. An even number
+/// of levels has none at 0v, silence sits on the level just above it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BitCrush {
+    bits: u8,
+}
+
+impl BitCrush {
+    /// Most bits, a [`Sample`]'s full resolution, which passes audio
+    /// through unchanged
+    pub const MAX_BITS: u8 = 12;
+
+    /// Crush to `bits` bits, clamped to 1..=12
+    pub fn new(bits: u8) -> Self {
+        let mut crush = BitCrush {
+            bits: Self::MAX_BITS,
+        };
+        crush.set_bits(bits);
+        crush
+    }
+
+    pub fn set_bits(&mut self, bits: u8) {
+        self.bits = bits.clamp(1, Self::MAX_BITS);
+    }
+
+    /// Bits from a knob or CV, [`Sample::MIN`] is 1 bit and [`Sample::MAX`]
+    /// is full resolution
+    pub fn set_bits_from(&mut self, value: Sample) {
+        let position = value.to_clamped() - Sample::MIN;
+        let bits = 1 + position * i32::from(Self::MAX_BITS) / (Sample::MAX - Sample::MIN + 1);
+        self.set_bits(bits as u8);
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+
+    pub fn process(&self, input: Sample) -> Sample {
+        let step = 1 << (Self::MAX_BITS - self.bits);
+        let level = (input.to_clamped() - Sample::MIN) / step;
+        let levels = 1 << self.bits;
+        let range = Sample::MAX - Sample::MIN;
+        Sample::from(Sample::MIN + div_rounded(level * range, levels - 1))
+    }
+}
+
+/// Reduce sample rate by holding every `N`th sample for `N` samples
+///
+/// No filtering, so the aliasing is part of the sound.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RateReduce {
+    factor: u32,
+    count: u32,
+    held: Sample,
+}
+
+impl RateReduce {
+    /// Hold each sample for `factor` samples, 1 passes audio through
+    pub fn new(factor: u32) -> Self {
+        RateReduce {
+            factor: factor.max(1),
+            count: 0,
+            held: Sample::from(0_i32),
+        }
+    }
+
+    /// Change the hold, starting over from the next input
+    pub fn set_factor(&mut self, factor: u32) {
+        self.factor = factor.max(1);
+        self.count = 0;
+    }
+
+    pub fn factor(&self) -> u32 {
+        self.factor
+    }
+
+    pub fn process(&mut self, input: Sample) -> Sample {
+        if self.count == 0 {
+            self.held = Sample::from(input.to_clamped());
+        }
+        self.count += 1;
+        if self.count >= self.factor {
+            self.count = 0;
+        }
+        self.held
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitCrush, RateReduce};
+    use crate::Sample;
+
+    #[test]
+    fn test_bit_crush() {
+        let full = BitCrush::new(12);
+        for value in [Sample::MIN, -1, 0, 1, 1234, Sample::MAX] {
+            assert_eq!(full.process(Sample::from(value)), Sample::from(value));
+        }
+
+        // 4 bits: inputs in steps of 256, onto 16 levels 273 apart
+        let crush = BitCrush::new(4);
+        let crushed = |value: i32| crush.process(Sample::from(value)).to_clamped();
+        assert_eq!(crushed(0), 136);
+        assert_eq!(crushed(255), 136);
+        assert_eq!(crushed(-1), -137);
+        assert_eq!(crushed(256), 409);
+        assert_eq!(crushed(Sample::MAX), Sample::MAX);
+        assert_eq!(crushed(Sample::MIN), Sample::MIN);
+
+        // 1 bit is two full scale levels, with none at 0
+        let crush = BitCrush::new(1);
+        let crushed = |value: i32| crush.process(Sample::from(value)).to_clamped();
+        assert_eq!(crushed(-1), Sample::MIN);
+        assert_eq!(crushed(0), Sample::MAX);
+
+        for bits in 1..=BitCrush::MAX_BITS {
+            let crush = BitCrush::new(bits);
+            let ramp = Sample::MIN..=Sample::MAX;
+            let mut levels: Vec<i32> = ramp
+                .clone()
+                .map(|value| crush.process(Sample::from(value)).to_clamped())
+                .collect();
+            // no DC offset from the rounding over a full scale ramp
+            assert_eq!(levels.iter().sum::<i32>(), ramp.sum::<i32>(), "{bits}");
+            levels.dedup();
+            assert_eq!(levels.len(), 1 << bits);
+        }
+
+        let mut crush = BitCrush::new(0);
+        assert_eq!(crush.bits(), 1);
+        crush.set_bits_from(Sample::from(Sample::MAX));
+        assert_eq!(crush.bits(), 12);
+        crush.set_bits_from(Sample::from(0_i32));
+        assert_eq!(crush.bits(), 7);
+    }
+
+    #[test]
+    fn test_rate_reduce() {
+        let mut reduce = RateReduce::new(3);
+        let output: [i32; 7] =
+            core::array::from_fn(|index| reduce.process(Sample::from(index as i32)).to_clamped());
+        assert_eq!(output, [0, 0, 0, 3, 3, 3, 6]);
+
+        reduce.set_factor(0);
+        assert_eq!(reduce.factor(), 1);
+        // a new factor starts from the next input
+        assert_eq!(reduce.process(Sample::from(5_i32)).to_clamped(), 5);
+        assert_eq!(reduce.process(Sample::from(9_i32)).to_clamped(), 9);
+    }
+}
@@ -0,0 +1,249 @@
+//! Boolean logic on gates
+//!
+//! Gates come from anywhere that gives a level: a
+//! [`PulseInput`](crate::pulse::PulseInput), a
+//! [`Comparator`](crate::comparator::Comparator), a switch. [`LogicOp`]
+//! combines two of them, and the flip-flops remember state between clock
+//! edges:
+//!
+//! ```
+//! # use embedded_hal::digital::{InputPin, OutputPin};
+//! # use wscomp::comparator::Comparator;
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::logic::{LogicOp, ToggleFlipFlop};
+//! # use wscomp::pulse::{PulseInput, PulseOut};
+//! # fn card<I: InputPin, O: OutputPin>(
+//! #     pulse_in_1: PulseInput<I>,
+//! #     mut pulse_out_1: PulseOut<O>,
+//! #     mut pulse_out_2: PulseOut<O>,
+//! #     mut comparator: Comparator,
+//! #     mux_state: MuxState,
+//! # ) {
+//! let op = LogicOp::from_sample(mux_state.main_knob);
+//! let mut divider = ToggleFlipFlop::new();
+//! loop {
+//!     let a = pulse_in_1.is_high();
+//!     let b = comparator.update(mux_state.cv1.value());
+//!     pulse_out_1.gate(op.apply(a, b));
+//!     pulse_out_2.gate(divider.update(a));
+//! }
+//! # }
+//! ```
+//!
+//! NOT is [`LogicOp::inverted`] for the two input operations, or plain `!`.
+
+use crate::Sample;
+
+/// Two input logic operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LogicOp {
+    And,
+    Or,
+    Xor,
+    Nand,
+    Nor,
+    Xnor,
+}
+
+impl LogicOp {
+    /// Every operation, in knob order
+    pub const ALL: [LogicOp; 6] = [
+        LogicOp::And,
+        LogicOp::Or,
+        LogicOp::Xor,
+        LogicOp::Nand,
+        LogicOp::Nor,
+        LogicOp::Xnor,
+    ];
+
+    pub fn apply(self, a: bool, b: bool) -> bool {
+        match self {
+            LogicOp::And => a && b,
+            LogicOp::Or => a || b,
+            LogicOp::Xor => a != b,
+            LogicOp::Nand => !(a && b),
+            LogicOp::Nor => !(a || b),
+            LogicOp::Xnor => a == b,
+        }
+    }
+
+    /// The same operation with its output inverted, AND to NAND and so on
+    pub fn inverted(self) -> Self {
+        match self {
+            LogicOp::And => LogicOp::Nand,
+            LogicOp::Or => LogicOp::Nor,
+            LogicOp::Xor => LogicOp::Xnor,
+            LogicOp::Nand => LogicOp::And,
+            LogicOp::Nor => LogicOp::Or,
+            LogicOp::Xnor => LogicOp::Xor,
+        }
+    }
+
+    /// Pick an operation from a knob or CV, spread evenly over [`LogicOp::ALL`]
+    pub fn from_sample(value: Sample) -> Self {
+        let position = (value.to_clamped() - Sample::MIN) as usize;
+        let range = (Sample::MAX - Sample::MIN + 1) as usize;
+        Self::ALL[position * Self::ALL.len() / range]
+    }
+}
+
+/// Rising edges of a gate
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct RisingEdge {
+    previous: bool,
+}
+
+impl RisingEdge {
+    fn update(&mut self, gate: bool) -> bool {
+        let rising = gate && !self.previous;
+        self.previous = gate;
+        rising
+    }
+}
+
+/// Output flips on each rising edge of the clock, so it divides by two
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ToggleFlipFlop {
+    clock: RisingEdge,
+    output: bool,
+}
+
+impl ToggleFlipFlop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the clock level, returns the output
+    pub fn update(&mut self, clock: bool) -> bool {
+        if self.clock.update(clock) {
+            self.output = !self.output;
+        }
+        self.output
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    pub fn reset(&mut self) {
+        self.output = false;
+    }
+}
+
+/// Output takes the data level at each rising edge of the clock, and holds
+/// it until the next
+///
+/// Chain a few for a shift register, each one's output as the next one's
+/// data.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DFlipFlop {
+    clock: RisingEdge,
+    output: bool,
+}
+
+impl DFlipFlop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the data and clock levels, returns the output
+    pub fn update(&mut self, data: bool, clock: bool) -> bool {
+        if self.clock.update(clock) {
+            self.output = data;
+        }
+        self.output
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+}
+
+/// Set-reset latch, set goes high and stays there until reset
+///
+/// Reset wins while both are high.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SrLatch {
+    output: bool,
+}
+
+impl SrLatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the set and reset levels, returns the output
+    pub fn update(&mut self, set: bool, reset: bool) -> bool {
+        if reset {
+            self.output = false;
+        } else if set {
+            self.output = true;
+        }
+        self.output
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DFlipFlop, LogicOp, SrLatch, ToggleFlipFlop};
+    use crate::Sample;
+
+    #[test]
+    fn test_logic_ops() {
+        let inputs = [(false, false), (false, true), (true, false), (true, true)];
+        let table = |op: LogicOp| inputs.map(|(a, b)| op.apply(a, b));
+        assert_eq!(table(LogicOp::And), [false, false, false, true]);
+        assert_eq!(table(LogicOp::Or), [false, true, true, true]);
+        assert_eq!(table(LogicOp::Xor), [false, true, true, false]);
+        for op in LogicOp::ALL {
+            let inverted = table(op.inverted());
+            assert_eq!(table(op).map(|gate| !gate), inverted);
+            assert_eq!(op.inverted().inverted(), op);
+        }
+
+        assert_eq!(
+            LogicOp::from_sample(Sample::from(Sample::MIN)),
+            LogicOp::And
+        );
+        assert_eq!(LogicOp::from_sample(Sample::from(0_i32)), LogicOp::Nand);
+        assert_eq!(
+            LogicOp::from_sample(Sample::from(Sample::MAX)),
+            LogicOp::Xnor
+        );
+    }
+
+    #[test]
+    fn test_flip_flops() {
+        let clock = [false, true, true, false, true, false, true, false];
+        let mut toggle = ToggleFlipFlop::new();
+        let divided = clock.map(|clock| toggle.update(clock));
+        assert_eq!(divided, [false, true, true, true, false, false, true, true]);
+
+        let data = [true, false, true, true, false, false, true, true];
+        let mut d = DFlipFlop::new();
+        let latched: Vec<bool> = data
+            .iter()
+            .zip(clock)
+            .map(|(&data, clock)| d.update(data, clock))
+            .collect();
+        assert_eq!(
+            latched,
+            [false, false, false, false, false, false, true, true]
+        );
+
+        let mut latch = SrLatch::new();
+        assert!(latch.update(true, false));
+        assert!(latch.update(false, false));
+        assert!(!latch.update(true, true));
+        assert!(!latch.output());
+    }
+}
@@ -0,0 +1,215 @@
+//! Seamless loop points for recordings
+//!
+//! The host asset tool uses these to find where a looping recording should
+//! jump back to, check how cleanly it joins, and bake a crossfade into the
+//! end of the loop when no join is clean enough. They work on decoded 16
+//! bit samples, and the loop points go to the card in a
+//! [`LoopManifest`](crate::assets::LoopManifest):
+//!
+//! ```
+//! # use wscomp::loops::{self, LoopError};
+//! # fn prepare(samples: &mut [i16]) -> Result<(), LoopError> {
+//! // search the last second, at 48kHz
+//! let Some(points) = loops::find_loop(samples, 48_000) else {
+//!     return Ok(());
+//! };
+//! if !loops::check_loop(samples, points)?.is_seamless() {
+//!     // 50ms, long enough to hide the join in noisy recordings
+//!     loops::bake_crossfade(samples, points, 2_400)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::assets::LoopPoints;
+use crate::tables;
+use crate::Sample;
+
+/// Samples either side of a join that loops are compared and checked over
+pub const JOIN_WINDOW: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoopError {
+    /// Loop points empty, or past the end of the samples
+    OutOfRange,
+    /// Crossfade longer than the loop, or than the audio before it
+    CrossfadeTooLong,
+}
+
+/// How cleanly a loop joins, from [`check_loop`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LoopJoin {
+    /// Step from the last sample of the loop back to its first
+    pub jump: u32,
+    /// Largest step between neighbouring samples around the join, within
+    /// [`JOIN_WINDOW`] of it
+    pub local_step: u32,
+}
+
+impl LoopJoin {
+    /// Is the jump no bigger than the steps the audio around it already has?
+    pub fn is_seamless(&self) -> bool {
+        self.jump <= self.local_step
+    }
+}
+
+/// Loop from the first rising zero crossing to the rising zero crossing in
+/// the last `search` samples whose audio best matches the loop start's
+///
+/// `None` without a crossing for each end.
+pub fn find_loop(samples: &[i16], search: usize) -> Option<LoopPoints> {
+    let rising = |index: usize| samples[index - 1] < 0 && samples[index] >= 0;
+    let start = (1..samples.len()).find(|&index| rising(index))?;
+    // the audio after the end is compared with the audio after the start
+    let last = samples.len().checked_sub(JOIN_WINDOW)?;
+    let first = samples
+        .len()
+        .saturating_sub(search)
+        .max(start + JOIN_WINDOW);
+    let mismatch = |end: usize| -> u32 {
+        samples[start..start + JOIN_WINDOW]
+            .iter()
+            .zip(&samples[end..end + JOIN_WINDOW])
+            .map(|(&a, &b)| i32::from(a).abs_diff(i32::from(b)))
+            .sum()
+    };
+    let end = (first..=last)
+        .filter(|&index| rising(index))
+        .min_by_key(|&index| mismatch(index))?;
+    Some(LoopPoints {
+        start: start as u32,
+        end: end as u32,
+    })
+}
+
+/// How cleanly the loop at `points` joins
+///
+/// Compares the jump from the last sample back to the first against the
+/// steps just before the end and around the start.
+pub fn check_loop(samples: &[i16], points: LoopPoints) -> Result<LoopJoin, LoopError> {
+    let (start, end) = range(samples, points)?;
+    let step = |index: usize| i32::from(samples[index]).abs_diff(i32::from(samples[index - 1]));
+    let before_end = (end - JOIN_WINDOW).max(start + 1)..end;
+    let around_start = start.max(1)..(start + JOIN_WINDOW).min(end);
+    Ok(LoopJoin {
+        jump: i32::from(samples[start]).abs_diff(i32::from(samples[end - 1])),
+        local_step: before_end.chain(around_start).map(step).max().unwrap_or(0),
+    })
+}
+
+/// Crossfade the last `len` samples of the loop into the `len` samples
+/// before its start, so the loop's end runs straight on into its start
+///
+/// Equal power, for uncorrelated audio like field recordings. Needs `len`
+/// samples of audio before the loop start.
+pub fn bake_crossfade(
+    samples: &mut [i16],
+    points: LoopPoints,
+    len: usize,
+) -> Result<(), LoopError> {
+    let (start, end) = range(samples, points)?;
+    if len > start || len > end - start {
+        return Err(LoopError::CrossfadeTooLong);
+    }
+    for index in 0..len {
+        // fully faded in on the last sample, which then joins the start
+        let position =
+            Sample::MIN + ((index + 1) * (Sample::MAX - Sample::MIN) as usize / len) as i32;
+        let (fade_out, fade_in) = tables::equal_power(Sample::from(position));
+        let outgoing = i32::from(samples[end - len + index]) * fade_out.to_clamped();
+        let incoming = i32::from(samples[start - len + index]) * fade_in.to_clamped();
+        samples[end - len + index] =
+            ((outgoing + incoming) / Sample::MAX).clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+    }
+    Ok(())
+}
+
+/// Loop points as indices, checked against the samples
+fn range(samples: &[i16], points: LoopPoints) -> Result<(usize, usize), LoopError> {
+    let (start, end) = (points.start as usize, points.end as usize);
+    if start >= end || end > samples.len() {
+        return Err(LoopError::OutOfRange);
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bake_crossfade, check_loop, find_loop, LoopError, JOIN_WINDOW};
+    use crate::assets::LoopPoints;
+
+    /// Sine with a period of 96.5 samples, so not every cycle lines up
+    fn sine(len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|n| ((n as f64 * core::f64::consts::TAU / 96.5).sin() * 20_000.0) as i16)
+            .collect()
+    }
+
+    /// Repeatable noise, like a rain recording
+    fn noise(len: usize) -> Vec<i16> {
+        let mut state = 12_345_u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 16) as i16 / 4
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_loop() {
+        let samples = sine(10_000);
+        let points = find_loop(&samples, 1_000).unwrap();
+        // starts on the first rising crossing, ends on whole cycles
+        assert_eq!(points.start, 97);
+        assert_eq!((points.end - points.start) % 193, 0, "{points:?}");
+        assert!(points.end as usize > 9_000 - JOIN_WINDOW);
+        let join = check_loop(&samples, points).unwrap();
+        assert!(join.is_seamless(), "{join:?}");
+
+        assert_eq!(find_loop(&[100; 500], 100), None);
+        assert_eq!(find_loop(&samples[..20], 100), None);
+    }
+
+    #[test]
+    fn test_check_loop() {
+        let samples = sine(1_000);
+        // from a trough back to a peak, a big click
+        let click = LoopPoints { start: 24, end: 73 };
+        let join = check_loop(&samples, click).unwrap();
+        assert!(!join.is_seamless(), "{join:?}");
+
+        for points in [
+            LoopPoints { start: 5, end: 5 },
+            LoopPoints {
+                start: 0,
+                end: 1_001,
+            },
+        ] {
+            assert_eq!(check_loop(&samples, points), Err(LoopError::OutOfRange));
+        }
+    }
+
+    #[test]
+    fn test_bake_crossfade() {
+        let original = noise(20_000);
+        let mut samples = original.clone();
+        let points = LoopPoints {
+            start: 3_000,
+            end: 17_777,
+        };
+        assert_eq!(
+            bake_crossfade(&mut samples, points, 3_001),
+            Err(LoopError::CrossfadeTooLong)
+        );
+        bake_crossfade(&mut samples, points, 2_400).unwrap();
+        // the loop end now runs on into the start, as the lead in does
+        assert_eq!(samples[17_776], original[2_999]);
+        assert!(check_loop(&samples, points).unwrap().is_seamless());
+        // and nothing outside the crossfade moved
+        assert_eq!(samples[..15_377], original[..15_377]);
+        assert_eq!(samples[17_777..], original[17_777..]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Level meters, from audio rate samples to control rate levels
+//!
+//! [`PeakMeter`] jumps up to each peak and falls back slowly, like the
+//! LEDs on a mixing desk, and notices clipping. [`RmsMeter`] averages power
+//! over a window, closer to how loud something sounds, for VU displays and
+//! envelope following:
+//!
+//! ```
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::block::SampleBlock;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::leds::{LedPattern, Leds};
+//! # use wscomp::meter::{PeakMeter, RmsMeter};
+//! # use wscomp::units::{Hertz, Millis};
+//! # fn card<P: SetDutyCycle>(
+//! #     mut leds: Leds<P, 6>,
+//! #     mut cv_out: CvOut<P>,
+//! #     block: SampleBlock<64>,
+//! # ) -> Result<(), P::Error> {
+//! let mut peak = PeakMeter::new(Millis::new(300), Hertz::new(48_000));
+//! let mut rms = RmsMeter::new(Millis::new(50), Hertz::new(48_000));
+//! loop {
+//!     peak.process_block(&block);
+//!     rms.process_block(&block);
+//!     leds.set(0, peak.level().to_clamped() as u16 * 2);
+//!     cv_out.set(rms.level())?;
+//!     if peak.take_clipped() {
+//!         leds.set_pattern(5, LedPattern::Level(4095));
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Levels are magnitudes, 0 to [`Sample::MAX`]. See
+//! [`level_to_db`](crate::decibels::level_to_db) to show them in dBFS.
+
+use crate::block::SampleBlock;
+use crate::units::{Hertz, Millis};
+use crate::Sample;
+
+/// Peak level, falling back exponentially after each peak
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PeakMeter {
+    /// Q16
+    peak: u32,
+    /// Ticks to fall to about a third, at least 1
+    decay_ticks: u32,
+    clipped: bool,
+}
+
+impl PeakMeter {
+    /// Meter for `rate` samples, falling to about a third (-9dB) of a peak
+    /// in `decay`
+    pub fn new(decay: Millis, rate: Hertz) -> Self {
+        let mut meter = PeakMeter {
+            peak: 0,
+            decay_ticks: 1,
+            clipped: false,
+        };
+        meter.set_decay(decay, rate);
+        meter
+    }
+
+    pub fn set_decay(&mut self, decay: Millis, rate: Hertz) {
+        self.decay_ticks = decay.ticks(rate).max(1);
+    }
+
+    /// Meter one sample
+    pub fn process(&mut self, input: Sample) {
+        // an over is clipping even before it's clamped
+        let value = input.to_unclamped();
+        if value >= Sample::MAX || value <= Sample::MIN {
+            self.clipped = true;
+        }
+        let magnitude = value.unsigned_abs().min(Sample::MAX as u32) << 16;
+        self.peak -= self.peak / self.decay_ticks;
+        self.peak = self.peak.max(magnitude);
+    }
+
+    /// Meter every sample in a block
+    pub fn process_block<const N: usize>(&mut self, block: &SampleBlock<N>) {
+        for &sample in block.iter() {
+            self.process(sample);
+        }
+    }
+
+    /// Current level, 0 to [`Sample::MAX`]
+    pub fn level(&self) -> Sample {
+        Sample::from((self.peak >> 16) as i32)
+    }
+
+    /// Has any input reached full scale since the last call?
+    pub fn take_clipped(&mut self) -> bool {
+        core::mem::take(&mut self.clipped)
+    }
+
+    pub fn reset(&mut self) {
+        self.peak = 0;
+        self.clipped = false;
+    }
+}
+
+/// Root mean square level over a moving window
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RmsMeter {
+    /// Mean of the squares, Q8
+    mean_square: u64,
+    /// Averaging time constant in ticks, at least 1
+    window_ticks: u32,
+}
+
+impl RmsMeter {
+    /// Meter for `rate` samples, averaging over about `window`
+    ///
+    /// 300ms is the classic VU response, 10 to 50ms follows an envelope.
+    pub fn new(window: Millis, rate: Hertz) -> Self {
+        let mut meter = RmsMeter {
+            mean_square: 0,
+            window_ticks: 1,
+        };
+        meter.set_window(window, rate);
+        meter
+    }
+
+    pub fn set_window(&mut self, window: Millis, rate: Hertz) {
+        self.window_ticks = window.ticks(rate).max(1);
+    }
+
+    /// Meter one sample
+    pub fn process(&mut self, input: Sample) {
+        let magnitude = u64::from(input.to_clamped().unsigned_abs());
+        let square = (magnitude * magnitude) << 8;
+        // one pole average, moving 1/window of the way each tick
+        let window = u64::from(self.window_ticks);
+        self.mean_square = (self.mean_square * (window - 1) + square) / window;
+    }
+
+    /// Meter every sample in a block
+    pub fn process_block<const N: usize>(&mut self, block: &SampleBlock<N>) {
+        for &sample in block.iter() {
+            self.process(sample);
+        }
+    }
+
+    /// Current level, 0 to [`Sample::MAX`]
+    ///
+    /// A full scale sine reads about 0.707 of full scale, a full scale
+    /// square wave full scale.
+    pub fn level(&self) -> Sample {
+        Sample::from(((self.mean_square >> 8).isqrt()) as i32)
+    }
+
+    pub fn reset(&mut self) {
+        self.mean_square = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PeakMeter, RmsMeter};
+    use crate::block::SampleBlock;
+    use crate::tables;
+    use crate::units::{Hertz, Millis};
+    use crate::Sample;
+
+    #[test]
+    fn test_peak_meter() {
+        // 10 ticks to fall to a third
+        let mut meter = PeakMeter::new(Millis::new(10), Hertz::new(1_000));
+        meter.process(Sample::from(-1000_i32));
+        assert_eq!(meter.level(), Sample::from(1000_i32));
+        // smaller peaks don't pull it down
+        meter.process(Sample::from(500_i32));
+        assert_eq!(meter.level(), Sample::from(900_i32));
+        for _ in 0..9 {
+            meter.process(Sample::from(0_i32));
+        }
+        assert!(meter.level().to_clamped().abs_diff(1000 / 3) < 20);
+        assert!(!meter.take_clipped());
+
+        meter.process_block(&SampleBlock::<4>::filled(Sample::from(Sample::MAX)));
+        assert_eq!(meter.level(), Sample::from(Sample::MAX));
+        assert!(meter.take_clipped());
+        assert!(!meter.take_clipped());
+        // overs from a mix count, before they're clamped
+        meter.process(Sample::from(1500_i32) + Sample::from(1500_i32));
+        assert!(meter.take_clipped());
+    }
+
+    #[test]
+    fn test_rms_meter() {
+        let rate = Hertz::new(48_000);
+        let mut meter = RmsMeter::new(Millis::new(10), rate);
+        let mut square = SampleBlock::<48>::silent();
+        for (index, sample) in square.iter_mut().enumerate() {
+            *sample = Sample::from(if index < 24 { 1000_i32 } else { -1000 });
+        }
+        for _ in 0..100 {
+            meter.process_block(&square);
+        }
+        assert!(meter.level().to_clamped().abs_diff(1000) <= 5);
+
+        // a sine reads 1/sqrt(2) of its peak
+        meter.reset();
+        for tick in 0..4_800_u32 {
+            meter.process(tables::sine(tick.wrapping_mul(u32::MAX / 480)));
+        }
+        let level = meter.level().to_clamped();
+        assert!(level.abs_diff(Sample::MAX * 707 / 1000) <= 10, "{level}");
+    }
+}
@@ -0,0 +1,206 @@
+//! N channel mixer with a shared summing bus
+//!
+//! [`Mixer`] scales each channel by its own level and sums them on a wide
+//! bus, so intermediate sums never wrap or clamp. The bus can then give up
+//! some gain as headroom before the final stage, which either clamps or
+//! bends the peaks over with [`soft_clip`]:
+//!
+//! ```
+//! # use wscomp::mixer::Mixer;
+//! # use wscomp::Sample;
+//! # const HEAVY: usize = 2;
+//! # fn card(light: Sample, medium: Sample, heavy: Sample, intensity: Sample) {
+//! let mut mixer = Mixer::<3>::new();
+//! mixer.set_limiter(true);
+//! loop {
+//!     mixer.set_level(HEAVY, intensity);
+//!     let mixed = mixer.mix([light, medium, heavy]);
+//! }
+//! # }
+//! ```
+//!
+//! [`pan`] places a mono source between the two audio outputs.
+
+use crate::block::SampleBlock;
+use crate::decibels;
+use crate::shaper::soft_clip;
+use crate::tables;
+use crate::Sample;
+
+/// Split a mono `input` into `(left, right)` with an equal power law
+///
+/// `position` runs from [`Sample::MIN`], all left, to [`Sample::MAX`], all
+/// right. In the middle each side is at -3dB, so a source keeps the same
+/// loudness as it moves across.
+pub fn pan(input: Sample, position: Sample) -> (Sample, Sample) {
+    let (left, right) = tables::equal_power(position);
+    (input.scale(left), input.scale(right))
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Mixer<const N: usize> {
+    levels: [Sample; N],
+    headroom: u8,
+    limiter: bool,
+}
+
+impl<const N: usize> Mixer<N> {
+    /// Most bus attenuation, in 6dB steps
+    pub const MAX_HEADROOM: u8 = 4;
+
+    /// All channels at full level, no headroom and no limiter
+    pub fn new() -> Self {
+        Mixer {
+            levels: [Sample::from(Sample::MAX); N],
+            headroom: 0,
+            limiter: false,
+        }
+    }
+
+    /// Set a channel's level, 0 (or below) is silent and [`Sample::MAX`]
+    /// unity gain
+    ///
+    /// Panics if `channel` is out of range.
+    pub fn set_level(&mut self, channel: usize, level: Sample) {
+        self.levels[channel] = Sample::from(level.to_clamped().max(0));
+    }
+
+    /// Set a channel's level in decibels, 0 is unity gain and
+    /// [`MIN_DB`](crate::decibels::MIN_DB) or below silent
+    ///
+    /// Panics if `channel` is out of range.
+    pub fn set_level_db(&mut self, channel: usize, db: i8) {
+        self.set_level(channel, decibels::db_to_level(db));
+    }
+
+    pub fn level(&self, channel: usize) -> Sample {
+        self.levels[channel]
+    }
+
+    /// Set every channel's level at once
+    pub fn set_levels(&mut self, levels: [Sample; N]) {
+        for (channel, level) in levels.into_iter().enumerate() {
+            self.set_level(channel, level);
+        }
+    }
+
+    /// Attenuate the bus by 6dB per step, up to [`Mixer::MAX_HEADROOM`]
+    ///
+    /// `N` full scale channels need about `log2(N)` steps to never reach
+    /// the output stage.
+    pub fn set_headroom(&mut self, steps: u8) {
+        self.headroom = steps.min(Self::MAX_HEADROOM);
+    }
+
+    pub fn headroom(&self) -> u8 {
+        self.headroom
+    }
+
+    /// Bend bus peaks over with [`soft_clip`] rather than clamping them
+    pub fn set_limiter(&mut self, limiter: bool) {
+        self.limiter = limiter;
+    }
+
+    pub fn is_limiting(&self) -> bool {
+        self.limiter
+    }
+
+    /// Mix one sample from each channel, the result is always in range
+    pub fn mix(&self, inputs: [Sample; N]) -> Sample {
+        let sum: i32 = inputs
+            .iter()
+            .zip(self.levels.iter())
+            .map(|(input, level)| input.to_clamped() * level.to_clamped() / Sample::MAX)
+            .sum();
+        let bus = Sample::from(sum / (1 << self.headroom));
+        if self.limiter {
+            soft_clip(bus)
+        } else {
+            Sample::from(bus.to_clamped())
+        }
+    }
+
+    /// Mix a block from each channel into `out`
+    pub fn mix_blocks<const B: usize>(
+        &self,
+        inputs: &[SampleBlock<B>; N],
+        out: &mut SampleBlock<B>,
+    ) {
+        for (index, sample) in out.iter_mut().enumerate() {
+            *sample = self.mix(core::array::from_fn(|channel| inputs[channel][index]));
+        }
+    }
+}
+
+impl<const N: usize> Default for Mixer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pan, Mixer};
+    use crate::block::SampleBlock;
+    use crate::Sample;
+
+    #[test]
+    fn test_mixer() {
+        let mut mixer = Mixer::<3>::new();
+        let inputs = [1000_i32, 500, -200].map(Sample::from);
+        assert_eq!(mixer.mix(inputs), Sample::from(1300_i32));
+
+        mixer.set_levels([Sample::MAX, 1023, -500].map(Sample::from));
+        assert_eq!(mixer.level(2), Sample::from(0_i32));
+        assert_eq!(mixer.mix(inputs), Sample::from(1249_i32));
+
+        // the bus is wide, so out of range inputs cancel before clamping
+        mixer.set_levels([Sample::from(Sample::MAX); 3]);
+        let loud = [Sample::MAX, Sample::MAX, -Sample::MAX].map(Sample::from);
+        assert_eq!(mixer.mix(loud).to_clamped(), Sample::MAX);
+        let all_loud = [Sample::from(Sample::MAX); 3];
+        assert_eq!(mixer.mix(all_loud).to_unclamped(), Sample::MAX);
+        mixer.set_headroom(2);
+        assert_eq!(mixer.mix(all_loud), Sample::from(3 * Sample::MAX / 4));
+
+        let blocks = inputs.map(SampleBlock::<4>::filled);
+        let mut out = SampleBlock::silent();
+        mixer.mix_blocks(&blocks, &mut out);
+        assert_eq!(out, SampleBlock::filled(Sample::from(1300_i32 / 4)));
+    }
+
+    #[test]
+    fn test_mixer_limiter() {
+        let mut mixer = Mixer::<2>::new();
+        mixer.set_limiter(true);
+        // below the knee nothing changes
+        let quiet = [Sample::from(500_i32), Sample::from(400_i32)];
+        assert_eq!(mixer.mix(quiet), Sample::from(900_i32));
+        // peaks bend over rather than flattening at full scale
+        let hot = [Sample::from(1500_i32), Sample::from(500_i32)];
+        assert_eq!(mixer.mix(hot).to_unclamped(), 1768);
+        let loud = [Sample::from(Sample::MAX); 2];
+        assert_eq!(mixer.mix(loud).to_unclamped(), Sample::MAX);
+    }
+
+    #[test]
+    fn test_pan() {
+        let input = Sample::from(-2000_i32);
+        let (left, right) = pan(input, Sample::from(Sample::MIN));
+        assert_eq!(
+            (left, right),
+            (Sample::from(-2000_i32), Sample::from(0_i32))
+        );
+        let (left, right) = pan(input, Sample::from(Sample::MAX));
+        assert_eq!(
+            (left, right),
+            (Sample::from(0_i32), Sample::from(-2000_i32))
+        );
+        // centre is -3dB each side, the power adds back up to the input's
+        let (left, right) = pan(input, Sample::from(0_i32));
+        assert!(left.to_clamped().abs_diff(right.to_clamped()) <= 1);
+        let power = left.to_clamped().pow(2) + right.to_clamped().pow(2);
+        assert!(power.abs_diff(2000 * 2000) < 2000 * 2000 / 100, "{power}");
+    }
+}
@@ -0,0 +1,307 @@
+//! Modulation matrix, routing sources (CV, LFOs, knobs) to card parameters
+//!
+//! Destinations are parameters from the card's [`params`] table, addressed
+//! by [`ParamId`], so a host can route CV 1 to a filter or an LFO to a level
+//! over the [`config`](crate::config) protocol without a code path for each. Each source/destination pair has a bipolar depth.
+//! Cards read their sources and modulate each parameter's base value:
+//!
+//! ```
+//! # use wscomp::modmatrix::ModMatrix;
+//! # use wscomp::params::{ParamId, ParamInfo};
+//! # use wscomp::Sample;
+//! # let (cv1, lfo) = (Sample::from(500_i32), Sample::from(0_i32));
+//! const CUTOFF: ParamId = ParamId(1);
+//! const LEVEL: ParamId = ParamId(2);
+//! const PARAMS: &[ParamInfo] = &[
+//!     ParamInfo::new(CUTOFF, 20, 20_000, 1_000).named("cutoff"),
+//!     ParamInfo::new(LEVEL, 0, Sample::MAX, Sample::MAX).named("level"),
+//! ];
+//!
+//! // sources: CV 1 and the LFO
+//! let mut matrix = ModMatrix::<2, 2>::new(PARAMS, [CUTOFF, LEVEL]).unwrap();
+//! matrix.set_depth(0, CUTOFF, Sample::from(Sample::MAX)).unwrap();
+//! let cutoff = matrix.modulate(&[cv1, lfo], CUTOFF, 1_000);
+//! ```
+
+use crate::config::{ConfigError, Request, Response};
+use crate::params::{self, ParamId, ParamInfo};
+use crate::Sample;
+
+/// Errors from configuring a [`ModMatrix`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ModMatrixError {
+    SourceOutOfRange,
+    /// Not one of the matrix's destinations, or not in the parameter table
+    UnknownDestination,
+}
+
+impl From<ModMatrixError> for ConfigError {
+    fn from(error: ModMatrixError) -> Self {
+        match error {
+            ModMatrixError::SourceOutOfRange => ConfigError::SourceOutOfRange,
+            ModMatrixError::UnknownDestination => ConfigError::UnknownDestination,
+        }
+    }
+}
+
+/// `S` sources by `D` destination parameters, each pair with a depth
+///
+/// Depth is a [`Sample`], [`Sample::MAX`] is full depth, negative depths
+/// invert the source and zero disconnects it. A full scale source at full
+/// depth moves a parameter by half its range either way, as a bipolar CV
+/// would a [`Sample`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ModMatrix<const S: usize, const D: usize> {
+    destinations: [ParamInfo; D],
+    depths: [[i16; D]; S],
+}
+
+impl<const S: usize, const D: usize> ModMatrix<S, D> {
+    /// New matrix with nothing routed, to `destinations` from the card's
+    /// parameter table
+    pub fn new(params: &[ParamInfo], destinations: [ParamId; D]) -> Result<Self, ModMatrixError> {
+        let mut infos = [ParamInfo::new(ParamId(0), 0, 0, 0); D];
+        for (info, id) in infos.iter_mut().zip(destinations) {
+            *info = *params::find(params, id).ok_or(ModMatrixError::UnknownDestination)?;
+        }
+        Ok(ModMatrix {
+            destinations: infos,
+            depths: [[0; D]; S],
+        })
+    }
+
+    /// Destination parameters, in the order of [`ModMatrix::apply`]'s results
+    pub fn destinations(&self) -> &[ParamInfo; D] {
+        &self.destinations
+    }
+
+    pub fn depth(&self, source: usize, destination: ParamId) -> Result<Sample, ModMatrixError> {
+        let destination = self.check(source, destination)?;
+        Ok(Sample::from(self.depths[source][destination]))
+    }
+
+    pub fn set_depth(
+        &mut self,
+        source: usize,
+        destination: ParamId,
+        depth: Sample,
+    ) -> Result<(), ModMatrixError> {
+        let destination = self.check(source, destination)?;
+        self.depths[source][destination] = depth.to_clamped() as i16;
+        Ok(())
+    }
+
+    /// Remove all routings
+    pub fn clear(&mut self) {
+        self.depths = [[0; D]; S];
+    }
+
+    /// Modulation for each destination from the current source values, as
+    /// [`Sample`]s before scaling to the parameter ranges
+    pub fn apply(&self, sources: &[Sample; S]) -> [Sample; D] {
+        let mut outputs = [Sample::from(0_i32); D];
+        for (source, depths) in sources.iter().zip(self.depths.iter()) {
+            for (output, &depth) in outputs.iter_mut().zip(depths.iter()) {
+                if depth != 0 {
+                    *output += source.scale(Sample::from(depth));
+                }
+            }
+        }
+        outputs
+    }
+
+    /// Apply modulation to a destination parameter's base value, scaled to
+    /// and clamped within its range
+    ///
+    /// Parameters that aren't destinations pass the base value through.
+    pub fn modulate(&self, sources: &[Sample; S], destination: ParamId, base: i32) -> i32 {
+        let Some(index) = self.index(destination) else {
+            return base;
+        };
+        let info = &self.destinations[index];
+        let amount: i32 = sources
+            .iter()
+            .zip(self.depths.iter())
+            .map(|(source, depths)| source.scale(Sample::from(depths[index])).to_clamped())
+            .sum();
+        // a Sample's range onto the parameter's, wide enough for any i32 range
+        let span = i64::from(info.max) - i64::from(info.min);
+        let offset = i64::from(amount) * span / i64::from(Sample::MAX - Sample::MIN);
+        info.clamp((i64::from(base) + offset).clamp(i32::MIN.into(), i32::MAX.into()) as i32)
+    }
+
+    /// Carry out a request from the host's [`config`](crate::config)
+    /// protocol
+    ///
+    /// Depth changes are answered with the depth as stored, after clamping.
+    pub fn configure(&mut self, request: Request) -> Response {
+        let (source, destination) = match request {
+            Request::GetDepth {
+                source,
+                destination,
+            } => (source, destination),
+            Request::SetDepth {
+                source,
+                destination,
+                depth,
+            } => {
+                if let Err(error) = self.set_depth(source.into(), destination, depth) {
+                    return Response::Error(error.into());
+                }
+                (source, destination)
+            }
+            Request::ClearRoutes => {
+                self.clear();
+                return Response::Cleared;
+            }
+        };
+        match self.depth(source.into(), destination) {
+            Ok(depth) => Response::Depth {
+                source,
+                destination,
+                depth,
+            },
+            Err(error) => Response::Error(error.into()),
+        }
+    }
+
+    fn index(&self, destination: ParamId) -> Option<usize> {
+        self.destinations
+            .iter()
+            .position(|info| info.id == destination)
+    }
+
+    fn check(&self, source: usize, destination: ParamId) -> Result<usize, ModMatrixError> {
+        if source >= S {
+            return Err(ModMatrixError::SourceOutOfRange);
+        }
+        self.index(destination)
+            .ok_or(ModMatrixError::UnknownDestination)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ModMatrix, ModMatrixError};
+    use crate::config::{ConfigError, Request, Response};
+    use crate::params::{ParamId, ParamInfo};
+    use crate::Sample;
+
+    const CUTOFF: ParamId = ParamId(10);
+    const LEVEL: ParamId = ParamId(11);
+    const PAN: ParamId = ParamId(12);
+    const PARAMS: [ParamInfo; 3] = [
+        ParamInfo::new(CUTOFF, 0, 8190, 4095),
+        ParamInfo::new(LEVEL, Sample::MIN, Sample::MAX, 0),
+        ParamInfo::new(PAN, Sample::MIN, Sample::MAX, 0),
+    ];
+
+    #[test]
+    fn test_mod_matrix_routing() {
+        let mut matrix = ModMatrix::<2, 3>::new(&PARAMS, [CUTOFF, LEVEL, PAN]).unwrap();
+        let sources = [Sample::from(1000_i32), Sample::from(-500_i32)];
+        assert_eq!(matrix.apply(&sources), [Sample::from(0_i32); 3]);
+
+        // cv1 -> cutoff at full depth, cv2 -> pan inverted at half
+        matrix
+            .set_depth(0, CUTOFF, Sample::from(Sample::MAX))
+            .unwrap();
+        matrix.set_depth(1, PAN, Sample::from(-1024_i32)).unwrap();
+        let outputs = matrix.apply(&sources);
+        assert_eq!(outputs[0], Sample::from(1000_i32));
+        assert_eq!(outputs[1], Sample::from(0_i32));
+        assert_eq!(outputs[2], Sample::from(250_i32));
+
+        // both sources into one destination sum
+        matrix
+            .set_depth(1, CUTOFF, Sample::from(Sample::MAX))
+            .unwrap();
+        assert_eq!(matrix.apply(&sources)[0], Sample::from(500_i32));
+
+        matrix.clear();
+        assert_eq!(matrix.apply(&sources), [Sample::from(0_i32); 3]);
+    }
+
+    #[test]
+    fn test_mod_matrix_modulate() {
+        let mut matrix = ModMatrix::<1, 2>::new(&PARAMS, [LEVEL, CUTOFF]).unwrap();
+        matrix
+            .set_depth(0, LEVEL, Sample::from(Sample::MAX))
+            .unwrap();
+        matrix
+            .set_depth(0, CUTOFF, Sample::from(Sample::MAX))
+            .unwrap();
+        let sources = [Sample::from(1500_i32)];
+        assert_eq!(matrix.modulate(&sources, LEVEL, -200), 1300);
+        // clamped to the parameter's range
+        assert_eq!(matrix.modulate(&sources, LEVEL, 1000), Sample::MAX);
+        // scaled to it, cutoff's range is twice a Sample's
+        assert_eq!(matrix.modulate(&sources, CUTOFF, 4095), 4095 + 3000);
+        assert_eq!(matrix.modulate(&[Sample::from(-2000_i32)], CUTOFF, 100), 0);
+        // parameters that aren't destinations pass the base value through
+        assert_eq!(matrix.modulate(&sources, PAN, 1000), 1000);
+    }
+
+    #[test]
+    fn test_mod_matrix_errors() {
+        assert_eq!(
+            ModMatrix::<1, 1>::new(&PARAMS, [ParamId(99)]).unwrap_err(),
+            ModMatrixError::UnknownDestination
+        );
+        let mut matrix = ModMatrix::<2, 2>::new(&PARAMS, [CUTOFF, LEVEL]).unwrap();
+        assert_eq!(
+            matrix.set_depth(2, CUTOFF, Sample::from(0_i32)),
+            Err(ModMatrixError::SourceOutOfRange)
+        );
+        assert_eq!(
+            matrix.depth(0, PAN),
+            Err(ModMatrixError::UnknownDestination)
+        );
+        matrix.set_depth(1, LEVEL, Sample::from(-3000_i32)).unwrap();
+        assert_eq!(matrix.depth(1, LEVEL), Ok(Sample::from(Sample::MIN)));
+    }
+
+    #[test]
+    fn test_mod_matrix_configure() {
+        let mut matrix = ModMatrix::<2, 2>::new(&PARAMS, [CUTOFF, LEVEL]).unwrap();
+        let response = matrix.configure(Request::SetDepth {
+            source: 1,
+            destination: LEVEL,
+            depth: Sample::from(5000_i32),
+        });
+        // answered with the depth as clamped
+        let routed = Response::Depth {
+            source: 1,
+            destination: LEVEL,
+            depth: Sample::from(Sample::MAX),
+        };
+        assert_eq!(response, routed);
+        let get = Request::GetDepth {
+            source: 1,
+            destination: LEVEL,
+        };
+        assert_eq!(matrix.configure(get), routed);
+
+        assert_eq!(matrix.configure(Request::ClearRoutes), Response::Cleared);
+        assert_eq!(matrix.depth(1, LEVEL), Ok(Sample::from(0_i32)));
+
+        let unknown = Request::GetDepth {
+            source: 0,
+            destination: PAN,
+        };
+        assert_eq!(
+            matrix.configure(unknown),
+            Response::Error(ConfigError::UnknownDestination)
+        );
+        let out_of_range = Request::GetDepth {
+            source: 2,
+            destination: CUTOFF,
+        };
+        assert_eq!(
+            matrix.configure(out_of_range),
+            Response::Error(ConfigError::SourceOutOfRange)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! White and pink noise, integer only
+//!
+//! Both generators are infinite [`Iterator`]s of [`Sample`]s, so they fill
+//! [`SampleBlock`](crate::block::SampleBlock)s directly:
+//!
+//! ```
+//! # use wscomp::block::SampleBlock;
+//! # use wscomp::noise::PinkNoise;
+//! # let mut block = SampleBlock::<64>::silent();
+//! let mut wind = PinkNoise::new(0x1234);
+//! block.fill_from(&mut wind);
+//! ```
+//!
+//! The same seed always gives the same stream, different generators should
+//! be given different seeds.
+
+use crate::random::Rng;
+use crate::Sample;
+
+/// Flat spectrum noise
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WhiteNoise {
+    rng: Rng,
+}
+
+impl WhiteNoise {
+    pub fn new(seed: u64) -> Self {
+        Self::from_rng(Rng::new(seed))
+    }
+
+    /// Noise from an existing generator, for a hardware seeded [`Rng`]
+    pub fn from_rng(rng: Rng) -> Self {
+        WhiteNoise { rng }
+    }
+
+    /// Full scale noise, [`Sample::MIN`] to [`Sample::MAX`]
+    pub fn next_sample(&mut self) -> Sample {
+        self.rng.next_sample()
+    }
+}
+
+impl Iterator for WhiteNoise {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        Some(self.next_sample())
+    }
+}
+
+/// Rows summed by [`PinkNoise`], each an octave lower than the last
+const PINK_ROWS: usize = 8;
+
+/// Noise falling 3dB per octave, softer and darker than white
+///
+/// Voss-McCartney: [`PINK_ROWS`] white values summed, row `n` replaced every
+/// 2^`n` samples, plus a fresh white value each sample.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PinkNoise {
+    white: WhiteNoise,
+    rows: [i32; PINK_ROWS],
+    sum: i32,
+    counter: u32,
+}
+
+impl PinkNoise {
+    pub fn new(seed: u64) -> Self {
+        Self::from_rng(Rng::new(seed))
+    }
+
+    pub fn from_rng(rng: Rng) -> Self {
+        PinkNoise {
+            white: WhiteNoise::from_rng(rng),
+            rows: [0; PINK_ROWS],
+            sum: 0,
+            counter: 0,
+        }
+    }
+
+    /// One row's worth of white noise, 1/8 of full scale
+    fn row_value(&mut self) -> i32 {
+        self.white.next_sample().to_clamped() >> 3
+    }
+
+    /// Nearly full scale noise, peaks within 7/8 of [`Sample::MAX`]
+    pub fn next_sample(&mut self) -> Sample {
+        // counter's lowest set bit picks the row, so row n changes every
+        // 2^n samples, and only one row changes per sample
+        self.counter = self.counter.wrapping_add(1);
+        let row = (self.counter.trailing_zeros() as usize).min(PINK_ROWS - 1);
+        let value = self.row_value();
+        self.sum += value - self.rows[row];
+        self.rows[row] = value;
+
+        let total = self.sum + self.row_value();
+        // PINK_ROWS + 1 sources at 1/8 scale, brought back into range
+        Sample::from(total * 7 / (PINK_ROWS as i32 + 1))
+    }
+}
+
+impl Iterator for PinkNoise {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        Some(self.next_sample())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PinkNoise, WhiteNoise};
+    use crate::Sample;
+
+    /// Mean and mean absolute difference between neighbouring samples
+    fn stats(noise: impl Iterator<Item = Sample>) -> (i32, i32) {
+        let samples = noise.take(4096).map(|sample| sample.to_clamped());
+        let (mut sum, mut step, mut last) = (0, 0, 0);
+        for sample in samples {
+            sum += sample;
+            step += (sample - last).abs();
+            last = sample;
+        }
+        (sum / 4096, step / 4096)
+    }
+
+    #[test]
+    fn test_white_noise() {
+        // full scale, even from a 0 seed
+        let noise = WhiteNoise::new(0).take(4096);
+        let (low, high) = noise.fold((0, 0), |(low, high), sample| {
+            (sample.to_clamped().min(low), sample.to_clamped().max(high))
+        });
+        assert!(low < Sample::MIN + 50 && high > Sample::MAX - 50);
+        let (mean, step) = stats(WhiteNoise::new(1));
+        assert!(mean.abs() < 100);
+        // uncorrelated samples, about a third of the full range apart
+        assert!((1200..1600).contains(&step));
+        // repeatable from a seed
+        assert!(WhiteNoise::new(7).take(16).eq(WhiteNoise::new(7).take(16)));
+    }
+
+    #[test]
+    fn test_pink_noise() {
+        let (mean, step) = stats(PinkNoise::new(1));
+        assert!(mean.abs() < 200);
+        // low frequencies dominate, so neighbours are much closer than white
+        assert!(step < 600);
+        assert!(PinkNoise::new(1)
+            .take(4096)
+            .all(|sample| sample.to_clamped().abs() <= Sample::MAX * 7 / 8));
+    }
+}
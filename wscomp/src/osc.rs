@@ -0,0 +1,256 @@
+//! Band limited audio oscillators
+//!
+//! A naive saw or square jumps between one sample and the next, and
+//! everything above Nyquist in that jump folds back down as inharmonic
+//! aliasing, audible from a few hundred hertz up. [`Oscillator`] smooths
+//! each jump over the two samples around it with a polynomial band limited
+//! step (polyBLEP), and each corner of the triangle with its integral
+//! (polyBLAMP), which removes most of it for a few multiplies per sample:
+//!
+//! ```
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::osc::{OscShape, Oscillator};
+//! # use wscomp::pitch::ZERO_VOLT_FREQUENCY;
+//! # use wscomp::units::Hertz;
+//! # const SAMPLE_RATE: Hertz = Hertz::new(48_000);
+//! # fn card(mux_state: MuxState) {
+//! let mut osc = Oscillator::new(OscShape::Saw, ZERO_VOLT_FREQUENCY, SAMPLE_RATE);
+//! loop {
+//!     osc.set_pitch(mux_state.main_knob + mux_state.cv1.value());
+//!     let sample = osc.tick();
+//! }
+//! # }
+//! ```
+//!
+//! Shapes start at the same points as [`Lfo`](crate::lfo::Lfo)'s, so
+//! [`Oscillator::reset`] hard syncs any of them.
+
+use crate::units::Hertz;
+use crate::Sample;
+
+/// 1.0 in the Q16 levels used internally
+const ONE: i64 = 1 << 16;
+/// Half a cycle of a 32 bit phase
+const HALF: u32 = 1 << 31;
+
+/// Correction for a falling step of 2 at phase 0, Q16
+///
+/// `dt` is the phase increment, the correction covers one sample either
+/// side of the step.
+fn blep(phase: u32, dt: u32) -> i64 {
+    let dt = u64::from(dt);
+    if dt == 0 {
+        return 0;
+    }
+    let before = (1_u64 << 32) - u64::from(phase);
+    if u64::from(phase) < dt {
+        // just after the step, -(1 - x)^2
+        let x = (u64::from(phase) << 16) / dt;
+        let rest = ONE - x as i64;
+        -((rest * rest) >> 16)
+    } else if before <= dt {
+        // just before it, (1 - x)^2
+        let x = (before << 16) / dt;
+        let rest = ONE - x as i64;
+        (rest * rest) >> 16
+    } else {
+        0
+    }
+}
+
+/// Correction for a corner at phase 0 where the slope rises by one level
+/// per sample, Q16
+fn blamp(phase: u32, dt: u32) -> i64 {
+    let dt = u64::from(dt);
+    if dt == 0 {
+        return 0;
+    }
+    // distance from the corner in samples, either side, (1 - x)^3 / 6
+    let distance = u64::from(phase.wrapping_neg().min(phase));
+    if distance >= dt {
+        return 0;
+    }
+    let rest = ONE - ((distance << 16) / dt) as i64;
+    ((rest * rest) >> 16) * rest / (6 * ONE)
+}
+
+/// Oscillator waveform
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OscShape {
+    /// Rising ramp
+    Saw,
+    Square,
+    Triangle,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Oscillator {
+    shape: OscShape,
+    rate: Hertz,
+    phase: u32,
+    increment: u32,
+}
+
+impl Oscillator {
+    /// Oscillator at `frequency`, ticked `rate` times a second
+    ///
+    /// Frequencies should stay below half the rate, the corrections assume
+    /// at most one step per sample.
+    pub fn new(shape: OscShape, frequency: Hertz, rate: Hertz) -> Self {
+        Oscillator {
+            shape,
+            rate,
+            phase: 0,
+            increment: frequency.phase_increment(rate),
+        }
+    }
+
+    pub fn set_shape(&mut self, shape: OscShape) {
+        self.shape = shape;
+    }
+
+    pub fn shape(&self) -> OscShape {
+        self.shape
+    }
+
+    pub fn set_frequency(&mut self, frequency: Hertz) {
+        self.increment = frequency.phase_increment(self.rate);
+    }
+
+    /// Set frequency from a 1v per octave pitch, see
+    /// [`Sample::to_frequency`]
+    pub fn set_pitch(&mut self, pitch: Sample) {
+        self.set_frequency(pitch.to_frequency());
+    }
+
+    /// Restart the cycle, for hard sync
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+
+    /// Level at the current phase
+    pub fn current(&self) -> Sample {
+        let (phase, dt) = (self.phase, self.increment);
+        let level = match self.shape {
+            OscShape::Saw => i64::from(phase >> 15) - ONE - blep(phase, dt),
+            OscShape::Square => {
+                let naive = if phase < HALF { ONE } else { -ONE };
+                naive + blep(phase, dt) - blep(phase.wrapping_add(HALF), dt)
+            }
+            OscShape::Triangle => {
+                // a quarter cycle ahead, so phase 0 is the middle of the rise
+                let shifted = phase.wrapping_add(1 << 30);
+                let naive = if shifted < HALF {
+                    i64::from(shifted >> 14) - ONE
+                } else {
+                    3 * ONE - i64::from(shifted >> 14)
+                };
+                // the slope turns by 8 * dt levels per sample at each corner
+                let turn = (8 * i64::from(dt)) >> 16;
+                let corners = blamp(shifted, dt) - blamp(shifted.wrapping_add(HALF), dt);
+                naive + ((turn * corners) >> 16)
+            }
+        };
+        Sample::from(((level * i64::from(Sample::MAX)) >> 16) as i32)
+    }
+
+    /// Return the level at the current phase, then advance one tick
+    pub fn tick(&mut self) -> Sample {
+        let sample = self.current();
+        self.phase = self.phase.wrapping_add(self.increment);
+        sample
+    }
+}
+
+/// Endless, for use as a graph source through
+/// [`IterSource`](crate::graph::IterSource)
+impl Iterator for Oscillator {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        Some(self.tick())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OscShape, Oscillator};
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    const RATE: Hertz = Hertz::new(48_000);
+
+    /// Fraction of the energy that isn't at a harmonic of `frequency`,
+    /// which must complete a whole number of cycles in `samples`
+    fn alias_ratio(samples: &[f64], frequency: f64) -> f64 {
+        let len = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / len;
+        let total: f64 = samples.iter().map(|x| (x - mean).powi(2)).sum();
+        let mut harmonic = 0.0;
+        let mut k = 1.0;
+        while k * frequency < 24_000.0 {
+            let w = 2.0 * core::f64::consts::PI * k * frequency / 48_000.0;
+            let (mut re, mut im) = (0.0, 0.0);
+            for (n, x) in samples.iter().enumerate() {
+                re += x * (w * n as f64).cos();
+                im -= x * (w * n as f64).sin();
+            }
+            harmonic += 2.0 * (re * re + im * im) / len;
+            k += 1.0;
+        }
+        (total - harmonic) / total
+    }
+
+    #[test]
+    fn test_osc_shapes() {
+        // slow enough that the corrections only touch the edges
+        let mut osc = Oscillator::new(OscShape::Saw, Hertz::new(480), RATE);
+        let saw: [i32; 100] = core::array::from_fn(|_| osc.tick().to_clamped());
+        assert!(saw[50].abs() <= 1);
+        assert!(saw[1..99].windows(2).all(|pair| pair[0] < pair[1]));
+
+        osc.set_shape(OscShape::Square);
+        osc.reset();
+        let square: [i32; 100] = core::array::from_fn(|_| osc.tick().to_clamped());
+        assert_eq!(square[25], Sample::MAX);
+        assert_eq!(square[75], -Sample::MAX);
+        // the step is split across the two samples either side of it
+        assert!(square[0].abs() < Sample::MAX && square[50].abs() < Sample::MAX);
+
+        osc.set_shape(OscShape::Triangle);
+        osc.reset();
+        let triangle: [i32; 100] = core::array::from_fn(|_| osc.tick().to_clamped());
+        assert_eq!(triangle[0], 0);
+        assert_eq!(triangle[50], 0);
+        assert!(triangle[20] > 1600 && triangle[25] < Sample::MAX);
+        assert!(triangle[75] > -Sample::MAX);
+    }
+
+    #[test]
+    fn test_osc_aliasing() {
+        // 311 whole cycles in 4800 samples, aliases land between harmonics
+        let frequency = 3_110;
+        for shape in [OscShape::Saw, OscShape::Square, OscShape::Triangle] {
+            let mut osc = Oscillator::new(shape, Hertz::new(frequency), RATE);
+            let blep: Vec<f64> = (0..4800).map(|_| osc.tick().to_clamped().into()).collect();
+            let naive: Vec<f64> = (0..4800_u64)
+                .map(|n| {
+                    let phase = (n * frequency as u64 % 48_000) as f64 / 48_000.0;
+                    match shape {
+                        OscShape::Saw => 2.0 * phase - 1.0,
+                        OscShape::Square if phase < 0.5 => 1.0,
+                        OscShape::Square => -1.0,
+                        OscShape::Triangle => {
+                            1.0 - 4.0 * ((phase + 0.25).rem_euclid(1.0) - 0.5).abs()
+                        }
+                    }
+                })
+                .collect();
+            let blep = alias_ratio(&blep, f64::from(frequency));
+            let naive = alias_ratio(&naive, f64::from(frequency));
+            assert!(blep < naive / 10.0, "{shape:?} {blep} {naive}");
+        }
+    }
+}
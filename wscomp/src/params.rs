@@ -0,0 +1,102 @@
+//! Card parameters, identified by stable numeric IDs
+//!
+//! Anything a host configures over the [`config`](crate::config) protocol
+//! (USB, or a web UI) refers to parameters by [`ParamId`], never by name or
+//! position, so host side mappings survive firmware upgrades that rename or
+//! reorder parameters. Names are an optional English fallback for hosts
+//! without their own (localized) string table:
+//!
+//! ```
+//! # use wscomp::params::{self, ParamId, ParamInfo};
+//! # use wscomp::Sample;
+//! const RAIN: ParamId = ParamId(1);
+//! const WIND: ParamId = ParamId(2);
+//!
+//! const PARAMS: &[ParamInfo] = &[
+//!     ParamInfo::new(RAIN, Sample::MIN, Sample::MAX, 0).named("rain"),
+//!     ParamInfo::new(WIND, 0, Sample::MAX, 0),
+//! ];
+//! # assert!(params::is_valid(PARAMS));
+//! ```
+//!
+//! Once released, an ID keeps its meaning and range. Retired IDs aren't
+//! reused.
+
+/// Stable identifier for a card parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParamId(pub u16);
+
+/// Description of a parameter, for hosts to build their UI from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ParamInfo {
+    pub id: ParamId,
+    pub min: i32,
+    pub max: i32,
+    pub default: i32,
+    /// English name, hosts should prefer their own string for `id`
+    pub name: Option<&'static str>,
+}
+
+impl ParamInfo {
+    pub const fn new(id: ParamId, min: i32, max: i32, default: i32) -> Self {
+        ParamInfo {
+            id,
+            min,
+            max,
+            default,
+            name: None,
+        }
+    }
+
+    pub const fn named(self, name: &'static str) -> Self {
+        ParamInfo {
+            name: Some(name),
+            ..self
+        }
+    }
+
+    /// Clamp a value from the host to this parameter's range
+    pub fn clamp(&self, value: i32) -> i32 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// Find a parameter by ID
+pub fn find(params: &[ParamInfo], id: ParamId) -> Option<&ParamInfo> {
+    params.iter().find(|param| param.id == id)
+}
+
+/// Check a parameter table has no duplicate IDs and sane ranges, for tests
+/// and debug asserts
+pub fn is_valid(params: &[ParamInfo]) -> bool {
+    params.iter().enumerate().all(|(index, param)| {
+        param.min <= param.default
+            && param.default <= param.max
+            && params[..index].iter().all(|other| other.id != param.id)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find, is_valid, ParamId, ParamInfo};
+    use crate::Sample;
+
+    #[test]
+    fn test_param_table() {
+        let params = [
+            ParamInfo::new(ParamId(1), Sample::MIN, Sample::MAX, 0).named("rain"),
+            ParamInfo::new(ParamId(7), 0, 100, 50),
+        ];
+        assert!(is_valid(&params));
+        assert_eq!(find(&params, ParamId(1)).unwrap().name, Some("rain"));
+        assert_eq!(find(&params, ParamId(7)).unwrap().name, None);
+        assert_eq!(find(&params, ParamId(7)).unwrap().clamp(500), 100);
+        assert!(find(&params, ParamId(2)).is_none());
+
+        let duplicate = [params[0], params[0]];
+        assert!(!is_valid(&duplicate));
+        assert!(!is_valid(&[ParamInfo::new(ParamId(3), 0, 10, 20)]));
+    }
+}
@@ -0,0 +1,444 @@
+//! Volt per octave pitch helpers
+//!
+//! Pitch is 1v per octave on the CV scale (see [`Sample::CV_MILLIVOLTS`]),
+//! so one semitone is 1/12 of a volt, roughly 28.44 counts. Conversions round
+//! to the nearest count or semitone rather than truncating, because
+//! truncation makes every other note land a count flat.
+//!
+//! Other tunings go through a [`Tuning`], a table of cents for each degree of
+//! a repeating scale, which can be stored in settings flash:
+//!
+//! ```
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::pitch::Tuning;
+//! # use wscomp::settings::{SettingsError, SettingsFlash, SettingsStore};
+//! # async fn card<F: SettingsFlash, P: SetDutyCycle<Error = F::Error>>(
+//! #     mut store: SettingsStore<F, 2>,
+//! #     mut cv_out: CvOut<P>,
+//! #     mux_state: MuxState,
+//! # ) -> Result<(), SettingsError<F::Error>> {
+//! let tuning = Tuning::load(&mut store).await?.unwrap_or(Tuning::EQUAL);
+//! loop {
+//!     let degree = tuning.nearest_degree(mux_state.cv1.value());
+//!     cv_out.set(tuning.pitch(degree))?;
+//! }
+//! # }
+//! ```
+
+use crate::settings::{SettingsError, SettingsFlash, SettingsStore, MAX_VALUE_LEN};
+use crate::tables;
+use crate::units::Hertz;
+use crate::{div_rounded, Sample};
+
+/// MIDI note number at 0v (C4)
+pub const ZERO_VOLT_NOTE: u8 = 60;
+
+/// Frequency at 0v, [`ZERO_VOLT_NOTE`] in equal temperament with A4 at 440Hz
+pub const ZERO_VOLT_FREQUENCY: Hertz = Hertz::from_millihertz(261_626);
+
+/// Highest MIDI note number
+pub const NOTE_MAX: u8 = 127;
+
+impl Sample {
+    /// New `Sample` offset from 0v by a number of semitones
+    pub fn from_semitones(semitones: i32) -> Self {
+        Self::new(
+            div_rounded(semitones * Self::OFFSET * 1000, Self::CV_MILLIVOLTS * 12),
+            false,
+        )
+    }
+
+    /// Nearest semitone offset from 0v
+    pub fn to_semitones(&self) -> i32 {
+        div_rounded(
+            self.to_clamped() * Self::CV_MILLIVOLTS * 12,
+            Self::OFFSET * 1000,
+        )
+    }
+
+    /// New `Sample` offset from 0v by a number of cents
+    pub fn from_cents(cents: i32) -> Self {
+        Self::new(
+            div_rounded(cents * Self::OFFSET * 10, Self::CV_MILLIVOLTS * 12),
+            false,
+        )
+    }
+
+    /// Nearest cent offset from 0v, one count is about 3.5 cents
+    pub fn to_cents(&self) -> i32 {
+        div_rounded(
+            self.to_clamped() * Self::CV_MILLIVOLTS * 12,
+            Self::OFFSET * 10,
+        )
+    }
+
+    /// New `Sample` from a MIDI note number, [`ZERO_VOLT_NOTE`] is 0v
+    pub fn from_note(note: u8) -> Self {
+        Self::from_semitones(i32::from(note) - i32::from(ZERO_VOLT_NOTE))
+    }
+
+    /// Nearest MIDI note number, saturating at 0 and [`NOTE_MAX`]
+    pub fn to_note(&self) -> u8 {
+        (self.to_semitones() + i32::from(ZERO_VOLT_NOTE)).clamp(0, i32::from(NOTE_MAX)) as u8
+    }
+
+    /// Frequency of this pitch, [`ZERO_VOLT_FREQUENCY`] at 0v
+    ///
+    /// Not rounded to a semitone, so it tracks fine tuning and vibrato.
+    pub fn to_frequency(&self) -> Hertz {
+        // octaves in Q16, 1v is 341.33 counts
+        let octaves = (i64::from(self.to_clamped()) << 16) * i64::from(Self::CV_MILLIVOLTS)
+            / i64::from(Self::OFFSET * 1000);
+        let octave = octaves >> 16;
+        let ratio = u64::from(tables::exp2((octaves & 0xffff) as u32));
+        let millihertz = u64::from(ZERO_VOLT_FREQUENCY.millihertz()) * ratio;
+        let millihertz = if octave >= 0 {
+            millihertz << octave
+        } else {
+            millihertz >> -octave
+        };
+        Hertz::from_millihertz((millihertz >> 16) as u32)
+    }
+
+    /// Pitch of a frequency, the inverse of [`Sample::to_frequency`]
+    ///
+    /// Frequencies more than 6 octaves from [`ZERO_VOLT_FREQUENCY`] clamp to
+    /// the ends of the range.
+    pub fn from_frequency(frequency: Hertz) -> Self {
+        let ratio = (u64::from(frequency.millihertz().max(1)) << 16)
+            / u64::from(ZERO_VOLT_FREQUENCY.millihertz());
+        if ratio == 0 {
+            return Self::from(Self::MIN);
+        }
+        // whole octaves from the top bit, then normalize into 1 to 2
+        let octave = 63 - ratio.leading_zeros() as i64 - 16;
+        let normalized = if octave >= 0 {
+            ratio >> octave
+        } else {
+            ratio << -octave
+        };
+        let octaves = (octave << 16) + i64::from(tables::log2(normalized as u32));
+        let counts = (octaves * i64::from(Self::OFFSET) * 1000 / i64::from(Self::CV_MILLIVOLTS)
+            + (1 << 15))
+            >> 16;
+        Self::new(
+            counts.clamp(Self::MIN.into(), Self::MAX.into()) as i32,
+            false,
+        )
+    }
+}
+
+/// Settings key of the tuning header, the degrees are in the keys below it
+pub const TUNING_KEY: u16 = 0xfee0;
+
+/// Degrees per cent value in each tuning settings record
+const DEGREES_PER_KEY: usize = MAX_VALUE_LEN / 2;
+
+/// A tuning table, the pitch of each degree of a scale that repeats every
+/// period
+///
+/// Degree 0 sits at 0v plus its cents, so 12 tone equal temperament is 12
+/// degrees of 0, 100, 200 and so on cents, repeating every 1200. The period
+/// doesn't have to be an octave, the Bohlen-Pierce scale repeats every
+/// 1902 cents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Tuning {
+    cents: [i16; Tuning::MAX_DEGREES],
+    len: u8,
+    period: i16,
+}
+
+impl Tuning {
+    /// Most degrees in a tuning
+    pub const MAX_DEGREES: usize = 16;
+
+    /// 12 tone equal temperament, the same pitches as
+    /// [`Sample::from_semitones`]
+    pub const EQUAL: Tuning = Tuning {
+        cents: [
+            0, 100, 200, 300, 400, 500, 600, 700, 800, 900, 1000, 1100, 0, 0, 0, 0,
+        ],
+        len: 12,
+        period: 1200,
+    };
+
+    /// New `Tuning` from the cents of each degree and the period
+    ///
+    /// `None` unless there are 1 to [`Tuning::MAX_DEGREES`] degrees, rising,
+    /// and all within one period of the first.
+    pub fn from_cents(cents: &[i16], period: i16) -> Option<Self> {
+        let (&first, &last) = (cents.first()?, cents.last()?);
+        if cents.len() > Self::MAX_DEGREES
+            || period <= 0
+            || cents.windows(2).any(|pair| pair[0] >= pair[1])
+            || i32::from(last) >= i32::from(first) + i32::from(period)
+        {
+            return None;
+        }
+        let mut tuning = Tuning {
+            cents: [0; Self::MAX_DEGREES],
+            len: cents.len() as u8,
+            period,
+        };
+        tuning.cents[..cents.len()].copy_from_slice(cents);
+        Some(tuning)
+    }
+
+    /// Twelve note octave tuning, as cents offsets from equal temperament
+    ///
+    /// `None` if the offsets are wide enough to reorder the notes.
+    pub fn from_offsets(offsets: &[i16; 12]) -> Option<Self> {
+        let mut cents = [0; 12];
+        for (degree, (cents, offset)) in cents.iter_mut().zip(offsets).enumerate() {
+            *cents = degree as i16 * 100 + offset;
+        }
+        Self::from_cents(&cents, 1200)
+    }
+
+    /// Degrees in each period
+    pub fn len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    /// Always false, a tuning has at least one degree
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Cents of each degree in the first period
+    pub fn cents(&self) -> &[i16] {
+        &self.cents[..self.len()]
+    }
+
+    /// Period the degrees repeat at, in cents
+    pub fn period(&self) -> i16 {
+        self.period
+    }
+
+    /// Cents from 0v of a degree, negative degrees count down from 0v
+    pub fn degree_cents(&self, degree: i32) -> i32 {
+        let len = i32::from(self.len);
+        degree.div_euclid(len) * i32::from(self.period)
+            + i32::from(self.cents[degree.rem_euclid(len) as usize])
+    }
+
+    /// Pitch of a degree
+    pub fn pitch(&self, degree: i32) -> Sample {
+        Sample::from_cents(self.degree_cents(degree))
+    }
+
+    /// Degree closest in pitch to `sample`
+    pub fn nearest_degree(&self, sample: Sample) -> i32 {
+        let cents = sample.to_cents();
+        let first = i32::from(self.cents[0]);
+        let len = i32::from(self.len);
+        // the period at or below, then the degrees up to the next one's first
+        let start = (cents - first).div_euclid(i32::from(self.period)) * len;
+        (start..=start + len)
+            .min_by_key(|&degree| (self.degree_cents(degree) - cents).abs())
+            .unwrap_or(start)
+    }
+
+    /// Snap `sample` to the nearest degree
+    pub fn quantize(&self, sample: Sample) -> Sample {
+        self.pitch(self.nearest_degree(sample))
+    }
+
+    /// The tuning in settings, `None` if there isn't a valid one
+    pub async fn load<F: SettingsFlash, const SECTORS: usize>(
+        store: &mut SettingsStore<F, SECTORS>,
+    ) -> Result<Option<Self>, SettingsError<F::Error>> {
+        let mut header = [0; 3];
+        if store.get(TUNING_KEY, &mut header).await? != Some(header.len()) {
+            return Ok(None);
+        }
+        let len = usize::from(header[0]);
+        let period = i16::from_le_bytes([header[1], header[2]]);
+        if len == 0 || len > Self::MAX_DEGREES {
+            return Ok(None);
+        }
+        let mut cents = [0; Self::MAX_DEGREES];
+        for (key, chunk) in (0..).zip(cents[..len].chunks_mut(DEGREES_PER_KEY)) {
+            let mut value = [0; MAX_VALUE_LEN];
+            if store.get(TUNING_KEY - 1 - key, &mut value).await? != Some(chunk.len() * 2) {
+                return Ok(None);
+            }
+            for (cents, bytes) in chunk.iter_mut().zip(value.chunks(2)) {
+                *cents = i16::from_le_bytes([bytes[0], bytes[1]]);
+            }
+        }
+        Ok(Self::from_cents(&cents[..len], period))
+    }
+
+    /// Store this tuning in settings, stalls flash like any
+    /// [`SettingsStore::set`]
+    pub async fn save<F: SettingsFlash, const SECTORS: usize>(
+        &self,
+        store: &mut SettingsStore<F, SECTORS>,
+    ) -> Result<(), SettingsError<F::Error>> {
+        for (key, chunk) in (0..).zip(self.cents().chunks(DEGREES_PER_KEY)) {
+            let mut value = [0; MAX_VALUE_LEN];
+            for (bytes, cents) in value.chunks_mut(2).zip(chunk) {
+                bytes.copy_from_slice(&cents.to_le_bytes());
+            }
+            store
+                .set(TUNING_KEY - 1 - key, &value[..chunk.len() * 2])
+                .await?;
+        }
+        let period = self.period.to_le_bytes();
+        store
+            .set(TUNING_KEY, &[self.len, period[0], period[1]])
+            .await
+    }
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self::EQUAL
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Tuning, NOTE_MAX, ZERO_VOLT_FREQUENCY, ZERO_VOLT_NOTE};
+    use crate::settings::test::FakeFlash;
+    use crate::settings::SettingsStore;
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    #[test]
+    fn test_semitones() {
+        assert_eq!(Sample::from_semitones(0).to_clamped(), 0);
+        assert_eq!(Sample::from_semitones(1).to_clamped(), 28);
+        // one octave is 1v, 341.33 counts
+        assert_eq!(Sample::from_semitones(12).to_clamped(), 341);
+        assert_eq!(Sample::from_semitones(-24).to_clamped(), -683);
+        // ±6v is ±6 octaves
+        assert_eq!(Sample::from_semitones(-72).to_clamped(), Sample::MIN);
+        assert_eq!(Sample::from_semitones(72).to_clamped(), Sample::MAX);
+
+        for semitones in -72..72 {
+            assert_eq!(Sample::from_semitones(semitones).to_semitones(), semitones);
+        }
+    }
+
+    #[test]
+    fn test_notes() {
+        assert_eq!(Sample::from_note(ZERO_VOLT_NOTE).to_clamped(), 0);
+        assert_eq!(Sample::from_note(72), Sample::from_semitones(12));
+        assert_eq!(Sample::from_note(48), Sample::from_semitones(-12));
+
+        for note in 0..=NOTE_MAX {
+            let sample = Sample::from_note(note);
+            if sample.to_clamped() < Sample::MAX {
+                assert_eq!(sample.to_note(), note);
+            }
+        }
+
+        // saturating at the ends of the MIDI range
+        assert_eq!(Sample::from(Sample::MIN).to_note(), 0);
+        assert_eq!(Sample::from(Sample::MAX).to_note(), NOTE_MAX);
+    }
+
+    #[test]
+    fn test_frequency() {
+        assert_eq!(Sample::from(0_i32).to_frequency(), ZERO_VOLT_FREQUENCY);
+        // 3v is exactly 1024 counts
+        assert_eq!(
+            Sample::from(1024_i32).to_frequency(),
+            Hertz::from_millihertz(2_093_008)
+        );
+        // A4 and A2, within 2 cents (about 0.12%), a count is 3.5 cents
+        for (semitones, millihertz) in [(9, 440_000), (-15, 110_000)] {
+            let frequency = Sample::from_semitones(semitones)
+                .to_frequency()
+                .millihertz();
+            assert!(
+                frequency.abs_diff(millihertz) < millihertz / 850,
+                "{frequency}"
+            );
+        }
+        // rising all the way from -6v to +6v (C-2 to C10)
+        let frequencies: Vec<u32> = (Sample::MIN..=Sample::MAX)
+            .map(|value| Sample::from(value).to_frequency().millihertz())
+            .collect();
+        assert!(frequencies.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(frequencies[0], 4_087);
+        assert!(frequencies[frequencies.len() - 1] < 16_744_064);
+
+        for (value, &millihertz) in (Sample::MIN..=Sample::MAX).zip(&frequencies) {
+            let pitch = Sample::from_frequency(Hertz::from_millihertz(millihertz));
+            assert!((pitch.to_clamped() - value).abs() <= 1, "{value}");
+        }
+        assert_eq!(
+            Sample::from_frequency(Hertz::new(440)),
+            Sample::from_semitones(9)
+        );
+        assert_eq!(
+            Sample::from_frequency(Hertz::new(1)).to_clamped(),
+            Sample::MIN
+        );
+        assert_eq!(
+            Sample::from_frequency(Hertz::new(40_000)).to_clamped(),
+            Sample::MAX
+        );
+    }
+
+    #[test]
+    fn test_tuning() {
+        let equal = Tuning::EQUAL;
+        for semitones in -72..72 {
+            assert_eq!(equal.pitch(semitones), Sample::from_semitones(semitones));
+            assert_eq!(
+                equal.nearest_degree(Sample::from_semitones(semitones)),
+                semitones
+            );
+        }
+        assert_eq!(Sample::from_cents(1200).to_clamped(), 341);
+        assert_eq!(Sample::from_cents(-50).to_cents(), -49);
+
+        // quarter comma meantone has a narrow fifth and a pure major third
+        let meantone =
+            Tuning::from_offsets(&[0, -24, -7, 10, -14, 3, -21, -3, -27, -10, 7, -17]).unwrap();
+        assert_eq!(meantone.degree_cents(4), 386);
+        assert_eq!(meantone.degree_cents(-5), -503);
+
+        // Bohlen-Pierce, 13 steps to a tritave instead of 12 to an octave
+        let cents: Vec<i16> = (0..13).map(|step| (step * 1902 / 13) as i16).collect();
+        let bohlen_pierce = Tuning::from_cents(&cents, 1902).unwrap();
+        assert_eq!(bohlen_pierce.degree_cents(13), 1902);
+        assert_eq!(bohlen_pierce.nearest_degree(Sample::from_cents(1900)), 13);
+        assert_eq!(bohlen_pierce.nearest_degree(Sample::from_cents(-140)), -1);
+        assert_eq!(
+            bohlen_pierce.quantize(Sample::from_cents(300)),
+            Sample::from_cents(292)
+        );
+
+        // out of order, wider than the period, or empty
+        assert_eq!(Tuning::from_cents(&[0, 200, 100], 1200), None);
+        assert_eq!(Tuning::from_cents(&[0, 1200], 1200), None);
+        assert_eq!(Tuning::from_cents(&[], 1200), None);
+    }
+
+    #[test]
+    fn test_tuning_settings() {
+        let mut flash = FakeFlash::new(2);
+        let mut store: SettingsStore<_, 2> =
+            embassy_futures::block_on(SettingsStore::mount(&mut flash)).unwrap();
+        assert_eq!(
+            embassy_futures::block_on(Tuning::load(&mut store)),
+            Ok(None)
+        );
+
+        // 5 degrees spans two records
+        let slendro = Tuning::from_cents(&[0, 240, 480, 720, 960], 1200).unwrap();
+        embassy_futures::block_on(slendro.save(&mut store)).unwrap();
+        assert_eq!(
+            embassy_futures::block_on(Tuning::load(&mut store)),
+            Ok(Some(slendro))
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Zero crossing pitch detector
+//!
+//! [`PitchDetector`] times the rising zero crossings of an audio input and
+//! averages the last few periods, which is cheap and works well on the
+//! simple waveforms of most oscillators. Each estimate comes as both a
+//! frequency and a 1v per octave pitch:
+//!
+//! ```
+//! # use wscomp::inputs::AudioState;
+//! # use wscomp::pitchdetect::PitchDetector;
+//! # use wscomp::units::Hertz;
+//! # use wscomp::Sample;
+//! # const SAMPLE_RATE: Hertz = Hertz::new(48_000);
+//! # fn card(audio_state: AudioState) {
+//! let mut detector = PitchDetector::new(SAMPLE_RATE);
+//! loop {
+//!     detector.update(audio_state.audio1.value());
+//!     if let Some(estimate) = detector.estimate() {
+//!         let cents_off = estimate.pitch - Sample::from_semitones(estimate.pitch.to_semitones());
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! A crossing only counts once the input has been below
+//! -[`PitchDetector::HYSTERESIS`] since the last one, so noise around 0v
+//! doesn't add extra crossings. Waveforms with strong harmonics that cross
+//! zero more than twice a cycle still read high, this isn't a tracker for
+//! complex sounds.
+
+use crate::units::Hertz;
+use crate::Sample;
+
+/// Periods averaged per estimate
+const PERIODS: usize = 8;
+
+/// A detected frequency, and the same as a 1v per octave pitch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PitchEstimate {
+    pub frequency: Hertz,
+    pub pitch: Sample,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PitchDetector {
+    rate: Hertz,
+    /// Has the input gone below -HYSTERESIS since the last crossing
+    armed: bool,
+    /// Was there a recent crossing to time the next period from
+    timing: bool,
+    previous: i32,
+    /// Samples since the last crossing, Q8
+    elapsed: u32,
+    /// Recent periods in samples, Q8
+    periods: [u32; PERIODS],
+    next: usize,
+    /// Periods recorded since the input was last silent, up to PERIODS
+    count: usize,
+}
+
+impl PitchDetector {
+    /// Counts below 0v the input has to reach before the next crossing
+    pub const HYSTERESIS: i32 = 32;
+    /// Lowest frequency detected, slower crossings (or none) clear the
+    /// estimate
+    pub const MIN_FREQUENCY: Hertz = Hertz::new(20);
+
+    /// Detector for an input sampled `rate` times a second
+    pub fn new(rate: Hertz) -> Self {
+        PitchDetector {
+            rate,
+            armed: false,
+            timing: false,
+            previous: 0,
+            elapsed: 0,
+            periods: [0; PERIODS],
+            next: 0,
+            count: 0,
+        }
+    }
+
+    /// Feed one input sample
+    pub fn update(&mut self, input: Sample) {
+        let value = input.to_clamped();
+        self.elapsed = self.elapsed.saturating_add(1 << 8);
+        if value < -Self::HYSTERESIS {
+            self.armed = true;
+        }
+        if self.armed && self.previous < 0 && value >= 0 {
+            // the crossing is somewhere between the two samples, how far
+            // back from this one
+            let back = ((value << 8) / (value - self.previous)) as u32;
+            let period = self.elapsed - back;
+            // the first crossing after silence only starts the timing
+            if self.timing {
+                self.periods[self.next] = period;
+                self.next = (self.next + 1) % PERIODS;
+                self.count = (self.count + 1).min(PERIODS);
+            }
+            self.elapsed = back;
+            self.armed = false;
+            self.timing = true;
+        }
+        if self.elapsed >= self.timeout() {
+            self.timing = false;
+            self.count = 0;
+        }
+        self.previous = value;
+    }
+
+    /// Average frequency over the last few periods, `None` until there are
+    /// enough of them or after the input goes quiet
+    pub fn estimate(&self) -> Option<PitchEstimate> {
+        if self.count < PERIODS {
+            return None;
+        }
+        let total: u64 = self.periods.iter().map(|&period| u64::from(period)).sum();
+        let millihertz = u64::from(self.rate.millihertz()) * (PERIODS as u64) * 256 / total;
+        let frequency = Hertz::from_millihertz(millihertz as u32);
+        Some(PitchEstimate {
+            frequency,
+            pitch: Sample::from_frequency(frequency),
+        })
+    }
+
+    /// Forget all periods, for example after switching inputs
+    pub fn reset(&mut self) {
+        *self = Self::new(self.rate);
+    }
+
+    /// Time in samples, Q8, after which the input counts as silent
+    fn timeout(&self) -> u32 {
+        Self::MIN_FREQUENCY.ticks_per_cycle(self.rate) << 8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PitchDetector;
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    const RATE: Hertz = Hertz::new(48_000);
+
+    fn sine(index: usize, frequency: f64, level: f64) -> Sample {
+        let phase = index as f64 * frequency / 48_000.0 * core::f64::consts::TAU;
+        Sample::from((phase.sin() * level) as i32)
+    }
+
+    #[test]
+    fn test_pitch_detector() {
+        let mut detector = PitchDetector::new(RATE);
+        assert_eq!(detector.estimate(), None);
+        for index in 0..4800 {
+            detector.update(sine(index, 440.0, 1500.0));
+        }
+        let estimate = detector.estimate().unwrap();
+        assert!(estimate.frequency.millihertz().abs_diff(440_000) < 100);
+        assert_eq!(estimate.pitch, Sample::from_semitones(9));
+
+        // non integer periods average out
+        for index in 0..4800 {
+            detector.update(sine(index, 1234.5, 1500.0));
+        }
+        let estimate = detector.estimate().unwrap();
+        assert!(estimate.frequency.millihertz().abs_diff(1_234_500) < 500);
+
+        // silence clears the estimate
+        for _ in 0..4800 {
+            detector.update(Sample::from(0_i32));
+        }
+        assert_eq!(detector.estimate(), None);
+    }
+
+    #[test]
+    fn test_pitch_detector_hysteresis() {
+        // a bit of noise around each crossing doesn't count as more
+        let mut detector = PitchDetector::new(RATE);
+        let noise = [0, 20, -20, 10, -10, 25, -25, 0];
+        for index in 0..9600 {
+            let noisy = sine(index, 100.0, 1000.0) + Sample::from(noise[index % noise.len()]);
+            detector.update(noisy);
+        }
+        let estimate = detector.estimate().unwrap();
+        assert!(estimate.frequency.millihertz().abs_diff(100_000) < 200);
+
+        detector.reset();
+        assert_eq!(detector.estimate(), None);
+    }
+}
@@ -0,0 +1,55 @@
+//! Safe output state when running from USB power only
+//!
+//! Without the ±12V rails (a card on a desk, powered over USB for
+//! configuration or asset uploads) the output stages aren't powered properly,
+//! so cards should stop driving CV and pulse outputs. The Computer has no
+//! rail sense input, so cards set this at startup from config, usually a
+//! `usb-power` cargo feature, before spawning tasks.
+
+use portable_atomic::{AtomicBool, Ordering};
+
+use crate::Sample;
+
+static USB_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Mark the card as running from USB power only, disabling CV/pulse drive
+pub fn set_usb_only(usb_only: bool) {
+    USB_ONLY.store(usb_only, Ordering::Relaxed);
+}
+
+pub fn is_usb_only() -> bool {
+    USB_ONLY.load(Ordering::Relaxed)
+}
+
+/// Should CV and pulse outputs be driven?
+pub fn outputs_enabled() -> bool {
+    !is_usb_only()
+}
+
+/// `value` when outputs are enabled, otherwise 0v
+pub fn safe_cv(value: Sample) -> Sample {
+    if outputs_enabled() {
+        value
+    } else {
+        Sample::from(Sample::CENTER)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{outputs_enabled, safe_cv, set_usb_only};
+    use crate::Sample;
+
+    #[test]
+    fn test_usb_only_safe_state() {
+        assert!(outputs_enabled());
+        assert_eq!(safe_cv(Sample::from(1000_i32)), Sample::from(1000_i32));
+
+        set_usb_only(true);
+        assert!(!outputs_enabled());
+        assert_eq!(safe_cv(Sample::from(1000_i32)), Sample::from(0_i32));
+
+        set_usb_only(false);
+        assert!(outputs_enabled());
+    }
+}
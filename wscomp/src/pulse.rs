@@ -0,0 +1,664 @@
+//! Pulse input edge detection and pulse outputs
+//!
+//! [`PulseInput`] wraps a pulse in GPIO, [`PulseDetector`] is the pin
+//! independent logic (edges, debouncing, gate width and period).
+//! [`PulseOut`] drives a pulse out GPIO with gates, triggers and clocks,
+//! and [`TriggerToGate`] stretches short triggers into gates.
+//! Timestamps are passed in as microseconds, e.g.
+//! `Instant::now().as_micros()`, so this doesn't depend on a particular
+//! timer.
+//!
+//! [`TriggerGen`] runs a [`PulseOut`] in its own task, so any other task can
+//! fire a trigger through a shared [`TriggerRequest`] without waiting for
+//! it to end:
+//!
+//! ```
+//! # use embedded_hal::digital::OutputPin;
+//! # use embedded_hal_async::delay::DelayNs;
+//! # use wscomp::pulse::{TriggerGen, TriggerRequest};
+//! # use wscomp::units::Millis;
+//! static TRIGGER: TriggerRequest = TriggerRequest::new();
+//!
+//! // an embassy task, on the card `TriggerGen<Output<'static>, Delay>`
+//! async fn trigger_loop<P: OutputPin, D: DelayNs>(mut trigger: TriggerGen<P, D>) {
+//!     trigger.run(&TRIGGER).await
+//! }
+//!
+//! // anywhere else
+//! TRIGGER.fire(Millis::new(10));
+//! ```
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::delay::DelayNs;
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::units::{Hertz, Millis};
+use crate::Sample;
+
+/// Direction of a pulse edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+/// Edge detection and timing for a gate/trigger signal
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PulseDetector {
+    high: bool,
+    debounce_micros: u64,
+    last_edge: Option<u64>,
+    last_rise: Option<u64>,
+    width: Option<u32>,
+    period: Option<u32>,
+}
+
+impl PulseDetector {
+    /// Default time after an edge during which further changes are ignored
+    pub const DEBOUNCE_MICROS: u32 = 200;
+
+    pub fn new() -> Self {
+        Self::with_debounce(Self::DEBOUNCE_MICROS)
+    }
+
+    pub fn with_debounce(debounce_micros: u32) -> Self {
+        PulseDetector {
+            high: false,
+            debounce_micros: debounce_micros.into(),
+            last_edge: None,
+            last_rise: None,
+            width: None,
+            period: None,
+        }
+    }
+
+    /// Update with the current level, returns an edge if the level changed
+    ///
+    /// Edges are reported immediately, then changes are ignored for the
+    /// debounce time so contact bounce or ringing isn't counted twice.
+    pub fn update(&mut self, high: bool, now_micros: u64) -> Option<Edge> {
+        if high == self.high {
+            return None;
+        }
+        if let Some(last_edge) = self.last_edge {
+            if now_micros.saturating_sub(last_edge) < self.debounce_micros {
+                return None;
+            }
+        }
+        self.high = high;
+        self.last_edge = Some(now_micros);
+        if high {
+            if let Some(last_rise) = self.last_rise {
+                self.period = Some(elapsed(last_rise, now_micros));
+            }
+            self.last_rise = Some(now_micros);
+            Some(Edge::Rising)
+        } else {
+            if let Some(last_rise) = self.last_rise {
+                self.width = Some(elapsed(last_rise, now_micros));
+            }
+            Some(Edge::Falling)
+        }
+    }
+
+    /// Debounced level
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+
+    /// Length of the last complete pulse, rising to falling edge
+    pub fn width_micros(&self) -> Option<u32> {
+        self.width
+    }
+
+    /// Time between the last two rising edges
+    pub fn period_micros(&self) -> Option<u32> {
+        self.period
+    }
+}
+
+impl Default for PulseDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Turns triggers into gates of a set length, and measures incoming gates
+///
+/// The output goes high on each rising edge of the input and stays high
+/// for the length, a new edge restarts it. The input's own width is still
+/// measured, for going the other way: reading how long a gate was held.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TriggerToGate {
+    detector: PulseDetector,
+    length_micros: u64,
+    gate_end: Option<u64>,
+}
+
+impl TriggerToGate {
+    pub fn new(length: Millis) -> Self {
+        TriggerToGate {
+            detector: PulseDetector::new(),
+            length_micros: u64::from(length.millis()) * 1_000,
+            gate_end: None,
+        }
+    }
+
+    /// Output gate length, taking effect from the next trigger
+    pub fn set_length(&mut self, length: Millis) {
+        self.length_micros = u64::from(length.millis()) * 1_000;
+    }
+
+    /// Set the length from a knob or CV, [`Sample::MIN`] is `min` and
+    /// [`Sample::MAX`] is `max`
+    pub fn set_length_from(&mut self, value: Sample, min: Millis, max: Millis) {
+        let position = (value.to_clamped() - Sample::MIN) as u32;
+        let span = max.millis().saturating_sub(min.millis());
+        let millis = u64::from(span) * u64::from(position) / (Sample::MAX - Sample::MIN) as u64;
+        self.set_length(Millis::new(min.millis() + millis as u32));
+    }
+
+    /// Feed the input level, returns the output gate
+    pub fn update(&mut self, high: bool, now_micros: u64) -> bool {
+        if self.detector.update(high, now_micros) == Some(Edge::Rising) {
+            self.gate_end = Some(now_micros + self.length_micros);
+        }
+        self.is_high(now_micros)
+    }
+
+    /// Output gate level at `now_micros`
+    pub fn is_high(&self, now_micros: u64) -> bool {
+        self.gate_end.is_some_and(|end| now_micros < end)
+    }
+
+    /// Width of the last complete input pulse
+    pub fn input_width_micros(&self) -> Option<u32> {
+        self.detector.width_micros()
+    }
+
+    /// Time between the last two input pulses
+    pub fn input_period_micros(&self) -> Option<u32> {
+        self.detector.period_micros()
+    }
+}
+
+/// Microseconds between two timestamps, saturating at u32::MAX (~71 minutes)
+fn elapsed(from: u64, to: u64) -> u32 {
+    to.saturating_sub(from).min(u32::MAX.into()) as u32
+}
+
+/// A pulse input jack
+///
+/// The Computer's pulse inputs are inverted: the GPIO reads low while the
+/// pulse is high, so cards normally create these with `inverted: true`.
+pub struct PulseInput<P> {
+    pin: P,
+    inverted: bool,
+    detector: PulseDetector,
+}
+
+impl<P: InputPin> PulseInput<P> {
+    pub fn new(pin: P, inverted: bool) -> Self {
+        PulseInput {
+            pin,
+            inverted,
+            detector: PulseDetector::new(),
+        }
+    }
+
+    /// Read the pin, returns an edge if the pulse changed
+    ///
+    /// Pin read errors are treated as no change.
+    pub fn poll(&mut self, now_micros: u64) -> Option<Edge> {
+        let high = self.pin.is_high().ok()? != self.inverted;
+        self.detector.update(high, now_micros)
+    }
+
+    pub fn detector(&self) -> &PulseDetector {
+        &self.detector
+    }
+
+    pub fn is_high(&self) -> bool {
+        self.detector.is_high()
+    }
+
+    pub fn width_micros(&self) -> Option<u32> {
+        self.detector.width_micros()
+    }
+
+    pub fn period_micros(&self) -> Option<u32> {
+        self.detector.period_micros()
+    }
+}
+
+/// A pulse output jack
+///
+/// The Computer's pulse outputs are inverted like the inputs: driving the
+/// GPIO low sends the pulse high. Cards create these with `inverted: true`
+/// and then only deal with the pulse level.
+///
+/// Triggers and clocks are timed from `now_micros`, so cards call
+/// [`PulseOut::update`] from a loop, or sleep until
+/// [`PulseOut::next_change_micros`] with `Timer::at()`.
+pub struct PulseOut<P> {
+    pin: P,
+    inverted: bool,
+    enabled: bool,
+    high: bool,
+    /// When the current trigger ends
+    trigger_end: Option<u64>,
+    clock: Option<PulseClock>,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct PulseClock {
+    period_micros: u64,
+    width_micros: u64,
+    next_rise: u64,
+}
+
+impl<P: OutputPin> PulseOut<P> {
+    /// New output, starting low
+    pub fn new(pin: P, inverted: bool) -> Self {
+        let mut pulse = PulseOut {
+            pin,
+            inverted,
+            enabled: true,
+            high: false,
+            trigger_end: None,
+            clock: None,
+        };
+        pulse.write();
+        pulse
+    }
+
+    /// Disabled outputs stay low, e.g. for [`power::outputs_enabled`](crate::power::outputs_enabled)
+    ///
+    /// Gates, triggers and clocks keep running, so the output picks up
+    /// where it should be when enabled again.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.write();
+    }
+
+    /// Set the level directly, cancelling any trigger or clock
+    pub fn gate(&mut self, high: bool) {
+        self.trigger_end = None;
+        self.clock = None;
+        self.set_level(high);
+    }
+
+    /// Go high for `length`, a new trigger restarts the length
+    pub fn trigger(&mut self, length: Millis, now_micros: u64) {
+        self.clock = None;
+        self.trigger_end = Some(now_micros + u64::from(length.millis()) * 1_000);
+        self.set_level(true);
+    }
+
+    /// Output a clock at `rate`, each pulse `width` long, starting now
+    ///
+    /// The width is limited to half the period, so pulses never merge.
+    pub fn start_clock(&mut self, rate: Hertz, width: Millis, now_micros: u64) {
+        let period_micros = 1_000_000_000 / u64::from(rate.millihertz().max(1));
+        let width_micros = (u64::from(width.millis()) * 1_000).min(period_micros / 2);
+        self.trigger_end = None;
+        self.clock = Some(PulseClock {
+            period_micros,
+            width_micros,
+            next_rise: now_micros,
+        });
+        self.update(now_micros);
+    }
+
+    /// Stop the clock, leaving the output low
+    pub fn stop_clock(&mut self) {
+        self.gate(false);
+    }
+
+    /// End triggers and step clocks that are due
+    pub fn update(&mut self, now_micros: u64) {
+        if let Some(end) = self.trigger_end {
+            if now_micros >= end {
+                self.trigger_end = None;
+                self.set_level(false);
+            }
+        }
+        if let Some(mut clock) = self.clock {
+            if now_micros >= clock.next_rise {
+                // skip any missed pulses rather than bunching them up
+                let missed = (now_micros - clock.next_rise) / clock.period_micros;
+                let rise = clock.next_rise + missed * clock.period_micros;
+                clock.next_rise = rise + clock.period_micros;
+                self.clock = Some(clock);
+                let end = rise + clock.width_micros;
+                if now_micros < end {
+                    self.trigger_end = Some(end);
+                    self.set_level(true);
+                } else {
+                    self.set_level(false);
+                }
+            }
+        }
+    }
+
+    /// Next time [`PulseOut::update`] will change the output
+    pub fn next_change_micros(&self) -> Option<u64> {
+        match (self.trigger_end, self.clock) {
+            (Some(end), _) => Some(end),
+            (None, Some(clock)) => Some(clock.next_rise),
+            (None, None) => None,
+        }
+    }
+
+    /// Pulse level, ignoring inversion and whether the output is enabled
+    pub fn is_high(&self) -> bool {
+        self.high
+    }
+
+    fn set_level(&mut self, high: bool) {
+        if high != self.high {
+            self.high = high;
+            self.write();
+        }
+    }
+
+    fn write(&mut self) {
+        let pin_high = (self.high && self.enabled) != self.inverted;
+        // GPIO writes can't fail on the RP2040
+        let _ = self.pin.set_state(pin_high.into());
+    }
+}
+
+/// A pending trigger, shared between the tasks firing it and a
+/// [`TriggerGen`]
+///
+/// Can be a `static`, firing only stores the length.
+#[derive(Debug)]
+pub struct TriggerRequest {
+    /// Length of the pending trigger, 0 for none
+    millis: AtomicU32,
+}
+
+impl TriggerRequest {
+    pub const MIN_LENGTH: Millis = Millis::new(1);
+    pub const MAX_LENGTH: Millis = Millis::new(1_000);
+
+    pub const fn new() -> Self {
+        TriggerRequest {
+            millis: AtomicU32::new(0),
+        }
+    }
+
+    /// Ask for a trigger `length` long, from [`TriggerRequest::MIN_LENGTH`]
+    /// to [`TriggerRequest::MAX_LENGTH`]
+    ///
+    /// Returns straight away. Firing again before the generator picks it
+    /// up replaces the length, firing during a trigger restarts it.
+    pub fn fire(&self, length: Millis) {
+        let millis = length.clamp(Self::MIN_LENGTH, Self::MAX_LENGTH).millis();
+        self.millis.store(millis, Ordering::Release);
+    }
+
+    fn take(&self) -> Option<Millis> {
+        match self.millis.swap(0, Ordering::Acquire) {
+            0 => None,
+            millis => Some(Millis::new(millis)),
+        }
+    }
+}
+
+impl Default for TriggerRequest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drives a [`PulseOut`] from [`TriggerRequest`]s, timed with a delay
+///
+/// Waits in steps of at most [`TriggerGen::POLL_MICROS`] that add up to
+/// exactly the trigger length, so a new request or a retrigger is never
+/// seen later than that.
+pub struct TriggerGen<P, D> {
+    pulse: PulseOut<P>,
+    delay: D,
+    /// Time left in the current trigger
+    remaining_micros: u32,
+}
+
+impl<P: OutputPin, D: DelayNs> TriggerGen<P, D> {
+    /// Longest wait before a new request, or a retrigger, is seen
+    pub const POLL_MICROS: u32 = 100;
+
+    pub fn new(pulse: PulseOut<P>, delay: D) -> Self {
+        TriggerGen {
+            pulse,
+            delay,
+            remaining_micros: 0,
+        }
+    }
+
+    /// The output, e.g. to disable it
+    pub fn pulse_mut(&mut self) -> &mut PulseOut<P> {
+        &mut self.pulse
+    }
+
+    /// Serve `requests` forever
+    pub async fn run(&mut self, requests: &TriggerRequest) -> ! {
+        loop {
+            self.poll(requests).await;
+        }
+    }
+
+    /// Start any pending trigger, then wait up to
+    /// [`TriggerGen::POLL_MICROS`], ending the trigger if it's done
+    pub async fn poll(&mut self, requests: &TriggerRequest) {
+        if let Some(length) = requests.take() {
+            self.remaining_micros = length.millis() * 1_000;
+            self.pulse.gate(true);
+        }
+        let wait = match self.remaining_micros {
+            0 => Self::POLL_MICROS,
+            remaining => remaining.min(Self::POLL_MICROS),
+        };
+        self.delay.delay_us(wait).await;
+        if self.remaining_micros > 0 {
+            self.remaining_micros -= wait;
+            if self.remaining_micros == 0 {
+                self.pulse.gate(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+
+    use embedded_hal_async::delay::DelayNs;
+
+    use super::{
+        Edge, PulseDetector, PulseInput, PulseOut, TriggerGen, TriggerRequest, TriggerToGate,
+    };
+    use crate::units::{Hertz, Millis};
+    use crate::Sample;
+
+    #[test]
+    fn test_trigger_to_gate() {
+        let mut convert = TriggerToGate::new(Millis::new(50));
+        // a 1ms trigger comes out as a 50ms gate
+        assert!(convert.update(true, 1_000));
+        assert!(convert.update(false, 2_000));
+        assert!(convert.update(false, 50_999));
+        assert!(!convert.update(false, 51_000));
+        assert_eq!(convert.input_width_micros(), Some(1_000));
+
+        // and a long gate is cut down to the length, with its width measured
+        convert.set_length_from(Sample::from(Sample::MIN), Millis::new(5), Millis::new(500));
+        assert!(convert.update(true, 100_000));
+        assert!(!convert.update(true, 105_000));
+        assert!(!convert.update(false, 300_000));
+        assert_eq!(convert.input_width_micros(), Some(200_000));
+        assert_eq!(convert.input_period_micros(), Some(99_000));
+    }
+
+    struct FakePin {
+        high: bool,
+    }
+
+    impl ErrorType for FakePin {
+        type Error = Infallible;
+    }
+
+    impl InputPin for FakePin {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.high)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.high)
+        }
+    }
+
+    #[test]
+    fn test_pulse_edges_and_timing() {
+        let mut detector = PulseDetector::new();
+        assert_eq!(detector.update(false, 0), None);
+        assert_eq!(detector.update(true, 1_000), Some(Edge::Rising));
+        assert_eq!(detector.update(true, 2_000), None);
+        assert_eq!(detector.update(false, 11_000), Some(Edge::Falling));
+        assert_eq!(detector.width_micros(), Some(10_000));
+        assert_eq!(detector.period_micros(), None);
+
+        assert_eq!(detector.update(true, 501_000), Some(Edge::Rising));
+        assert_eq!(detector.period_micros(), Some(500_000));
+    }
+
+    #[test]
+    fn test_pulse_debounce() {
+        let mut detector = PulseDetector::with_debounce(500);
+        assert_eq!(detector.update(true, 1_000), Some(Edge::Rising));
+        // bounces inside the debounce time are ignored
+        assert_eq!(detector.update(false, 1_100), None);
+        assert_eq!(detector.update(true, 1_200), None);
+        assert!(detector.is_high());
+        assert_eq!(detector.update(false, 1_600), Some(Edge::Falling));
+    }
+
+    #[test]
+    fn test_pulse_input_inverted() {
+        let mut input = PulseInput::new(FakePin { high: true }, true);
+        // idle high pin is a low pulse
+        assert_eq!(input.poll(0), None);
+        assert!(!input.is_high());
+
+        input.pin.high = false;
+        assert_eq!(input.poll(1_000), Some(Edge::Rising));
+        input.pin.high = true;
+        assert_eq!(input.poll(6_000), Some(Edge::Falling));
+        assert_eq!(input.width_micros(), Some(5_000));
+    }
+
+    impl OutputPin for FakePin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.high = false;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.high = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pulse_out_trigger_and_gate() {
+        let mut pulse = PulseOut::new(FakePin { high: false }, true);
+        // inverted, so low pulses are a high pin
+        assert!(pulse.pin.high);
+
+        pulse.trigger(Millis::new(10), 1_000);
+        assert!(!pulse.pin.high);
+        assert_eq!(pulse.next_change_micros(), Some(11_000));
+        pulse.update(10_999);
+        assert!(pulse.is_high());
+        pulse.update(11_000);
+        assert!(!pulse.is_high());
+        assert!(pulse.pin.high);
+
+        pulse.gate(true);
+        pulse.set_enabled(false);
+        assert!(pulse.is_high());
+        assert!(pulse.pin.high);
+        pulse.set_enabled(true);
+        assert!(!pulse.pin.high);
+    }
+
+    #[test]
+    fn test_pulse_out_clock() {
+        let mut pulse = PulseOut::new(FakePin { high: false }, false);
+        // 10Hz, so every 100ms
+        pulse.start_clock(Hertz::new(10), Millis::new(5), 0);
+        assert!(pulse.is_high());
+        pulse.update(5_000);
+        assert!(!pulse.is_high());
+        assert_eq!(pulse.next_change_micros(), Some(100_000));
+        pulse.update(100_000);
+        assert!(pulse.is_high());
+        // late update, skips the missed pulse and stays in phase
+        pulse.update(350_000);
+        assert!(!pulse.is_high());
+        assert_eq!(pulse.next_change_micros(), Some(400_000));
+        pulse.stop_clock();
+        assert_eq!(pulse.next_change_micros(), None);
+    }
+
+    /// Delay that only counts the time it would have waited
+    struct FakeDelay {
+        micros: u32,
+    }
+
+    impl DelayNs for FakeDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.micros += ns / 1_000;
+        }
+    }
+
+    #[test]
+    fn test_trigger_gen() {
+        let requests = TriggerRequest::new();
+        let pulse = PulseOut::new(FakePin { high: false }, false);
+        let mut trigger = TriggerGen::new(pulse, FakeDelay { micros: 0 });
+        let poll = |trigger: &mut TriggerGen<_, _>| {
+            embassy_futures::block_on(trigger.poll(&requests));
+        };
+
+        // idle, just waits for a request
+        poll(&mut trigger);
+        assert_eq!(trigger.delay.micros, 100);
+        assert!(!trigger.pulse.is_high());
+
+        requests.fire(Millis::new(10));
+        poll(&mut trigger);
+        assert!(trigger.pulse.is_high());
+        while trigger.pulse.is_high() {
+            poll(&mut trigger);
+        }
+        assert_eq!(trigger.delay.micros, 10_100);
+
+        // lengths are clamped, and firing mid trigger restarts it
+        requests.fire(Millis::new(0));
+        poll(&mut trigger);
+        requests.fire(Millis::new(5_000));
+        poll(&mut trigger);
+        while trigger.pulse.is_high() {
+            poll(&mut trigger);
+        }
+        assert_eq!(trigger.delay.micros, 10_100 + 100 + 1_000_000);
+    }
+}
@@ -0,0 +1,188 @@
+//! Scale quantizer for volt per octave CV
+//!
+//! Snaps a [`Sample`] to the nearest note of a [`Scale`], see [`crate::pitch`]
+//! for the volt per octave scaling and [`crate::harmony`] for the scales.
+
+pub use crate::harmony::Scale;
+use crate::Sample;
+
+/// Quantizes a [`Sample`] to the notes of a [`Scale`]
+///
+/// Once a note is chosen, the input has to move `hysteresis` counts closer
+/// to another note before the output changes, so inputs near a boundary
+/// between notes don't flutter.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Quantizer {
+    scale: Scale,
+    root: i32,
+    hysteresis: i32,
+    current: Option<i32>,
+}
+
+impl Quantizer {
+    /// Default hysteresis, about 1/7th of a semitone
+    pub const HYSTERESIS: i32 = 4;
+
+    pub fn new(scale: Scale) -> Self {
+        Quantizer {
+            scale,
+            root: 0,
+            hysteresis: Self::HYSTERESIS,
+            current: None,
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    /// Set the root note as semitones above C (0v)
+    pub fn set_root(&mut self, root: i32) {
+        self.root = root.rem_euclid(12);
+    }
+
+    /// Set hysteresis in counts, 0 disables it
+    pub fn set_hysteresis(&mut self, hysteresis: i32) {
+        self.hysteresis = hysteresis.max(0);
+    }
+
+    /// Current note as semitones from 0v, if any input has been quantized
+    pub fn current_semitones(&self) -> Option<i32> {
+        self.current
+    }
+
+    /// Quantize `input` to the nearest note in the scale
+    pub fn quantize(&mut self, input: Sample) -> Sample {
+        let input = input.to_clamped();
+        let nearest = self.nearest(input);
+        let note = match self.current {
+            Some(current) if current != nearest && self.in_scale(current) => {
+                if distance(nearest, input) + self.hysteresis < distance(current, input) {
+                    nearest
+                } else {
+                    current
+                }
+            }
+            _ => nearest,
+        };
+        self.current = Some(note);
+        Sample::from_semitones(note)
+    }
+
+    fn in_scale(&self, semitone: i32) -> bool {
+        self.scale.contains(semitone - self.root)
+    }
+
+    /// Nearest in scale note to `input` counts, as semitones from 0v
+    fn nearest(&self, input: i32) -> i32 {
+        // semitone at or below the input, then search an octave either side
+        let below = (input * Sample::CV_MILLIVOLTS * 12).div_euclid(Sample::OFFSET * 1000);
+        let mut best = below;
+        let mut best_distance = i32::MAX;
+        for semitone in (below - 12)..=(below + 13) {
+            if !self.in_scale(semitone) {
+                continue;
+            }
+            if distance(semitone, input) < best_distance {
+                best = semitone;
+                best_distance = distance(semitone, input);
+            }
+        }
+        best
+    }
+}
+
+/// Distance in counts between a note and an input
+fn distance(semitone: i32, input: i32) -> i32 {
+    (Sample::from_semitones(semitone).to_clamped() - input).abs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Quantizer, Scale};
+    use crate::Sample;
+
+    #[test]
+    fn test_scale_masks() {
+        let major = [0, 2, 4, 5, 7, 9, 11];
+        let minor = [0, 2, 3, 5, 7, 8, 10];
+        let pentatonic = [0, 2, 4, 7, 9];
+        for semitone in -24_i32..24 {
+            let degree = semitone.rem_euclid(12);
+            assert!(Scale::CHROMATIC.contains(semitone));
+            assert_eq!(Scale::MAJOR.contains(semitone), major.contains(&degree));
+            assert_eq!(Scale::MINOR.contains(semitone), minor.contains(&degree));
+            assert_eq!(
+                Scale::PENTATONIC.contains(semitone),
+                pentatonic.contains(&degree)
+            );
+        }
+        assert_eq!(Scale::from_mask(0).mask(), 1);
+        assert_eq!(Scale::from_mask(0xffff), Scale::CHROMATIC);
+    }
+
+    #[test]
+    fn test_quantize_chromatic() {
+        let mut quantizer = Quantizer::new(Scale::CHROMATIC);
+        quantizer.set_hysteresis(0);
+        for semitone in -60..60 {
+            let note = Sample::from_semitones(semitone);
+            assert_eq!(quantizer.quantize(note), note);
+            assert_eq!(quantizer.quantize(note + Sample::from(10_i32)), note);
+            assert_eq!(quantizer.quantize(note - Sample::from(10_i32)), note);
+        }
+    }
+
+    #[test]
+    fn test_quantize_major() {
+        let mut quantizer = Quantizer::new(Scale::MAJOR);
+        quantizer.set_hysteresis(0);
+        // C# snaps down to C or up to D, whichever is closer
+        let c_sharp = Sample::from_semitones(1);
+        assert_eq!(
+            quantizer.quantize(c_sharp - Sample::from(3_i32)),
+            Sample::from_semitones(0)
+        );
+        assert_eq!(
+            quantizer.quantize(c_sharp + Sample::from(3_i32)),
+            Sample::from_semitones(2)
+        );
+        // negative voltages wrap into the scale correctly (B below C)
+        assert_eq!(
+            quantizer.quantize(Sample::from_semitones(-1)),
+            Sample::from_semitones(-1)
+        );
+
+        // with a root of D, C# is in the scale
+        quantizer.set_root(2);
+        assert_eq!(quantizer.quantize(c_sharp), c_sharp);
+    }
+
+    #[test]
+    fn test_quantize_hysteresis() {
+        let mut quantizer = Quantizer::new(Scale::CHROMATIC);
+        // halfway between C and C#
+        let boundary = Sample::from(14_i32);
+        assert_eq!(
+            quantizer.quantize(boundary - Sample::from(1_i32)),
+            Sample::from_semitones(0)
+        );
+        // just past the boundary isn't enough to change notes
+        assert_eq!(
+            quantizer.quantize(boundary + Sample::from(1_i32)),
+            Sample::from_semitones(0)
+        );
+        assert_eq!(quantizer.current_semitones(), Some(0));
+        // well past the boundary is
+        assert_eq!(
+            quantizer.quantize(boundary + Sample::from(5_i32)),
+            Sample::from_semitones(1)
+        );
+        // and coming back needs the same margin
+        assert_eq!(
+            quantizer.quantize(boundary - Sample::from(1_i32)),
+            Sample::from_semitones(1)
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Seedable random numbers, and seeds from hardware noise
+//!
+//! [`Rng`] is xoshiro128++: small, fast on a 32 bit core, and good enough for
+//! music. A fixed seed gives the same sequence every boot, which is what
+//! tests and reproducible patterns want. Generative cards seed it from
+//! hardware noise at boot instead:
+//!
+//! ```
+//! # use wscomp::inputs::{AdcInput, InputAdc};
+//! # use wscomp::random::{seed_from_adc, seed_from_bits, Rng};
+//! # async fn card<A: InputAdc>(
+//! #     mut adc: A,
+//! #     rosc_random_bit: impl FnMut() -> bool,
+//! # ) -> Result<(), A::Error> {
+//! // ring oscillator random bit, noisy but biased, so take plenty. On the
+//! // card, `|| pac::ROSC.randombit().read().randombit()`
+//! let seed = seed_from_bits(rosc_random_bit);
+//! // or the LSBs of an unplugged input
+//! let seed = seed_from_adc(&mut adc, AdcInput::Audio2).await?;
+//! let mut rng = Rng::new(seed);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The helpers cover what generative cards mostly want, without the off by
+//! one and modulo bias mistakes that are easy to make by hand:
+//!
+//! ```
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::random::Rng;
+//! # use wscomp::Sample;
+//! # const NOTES: [u8; 3] = [60, 63, 67];
+//! # let note_weights = [4, 1, 2];
+//! # let mux_state = MuxState::default();
+//! # let mut rng = Rng::new(1);
+//! if rng.chance(mux_state.main_knob) {
+//!     let note = NOTES[rng.weighted(&note_weights).unwrap_or(0)];
+//!     let velocity = rng.sample_in(Sample::from(1000_i32), Sample::from(Sample::MAX));
+//! }
+//! ```
+
+use crate::inputs::{AdcInput, InputAdc};
+use crate::Sample;
+
+/// Bits to shift a random u32 down to a [`Sample`] range (4096 steps)
+const SAMPLE_SHIFT: u32 = 32 - 12;
+/// Raw readings hashed into a hardware seed, several per seed bit
+const SEED_READINGS: u32 = 256;
+
+/// FNV-1a step, folds one reading into a hash of all readings so far
+fn fnv1a(hash: u64, reading: u64) -> u64 {
+    (hash ^ reading).wrapping_mul(0x0000_0100_0000_01b3)
+}
+
+/// FNV-1a starting hash
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// splitmix64 step, spreads any seed (even 0) into well mixed state
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// xoshiro128++ pseudo random number generator
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rng {
+    state: [u32; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut mix = seed;
+        let low = splitmix64(&mut mix);
+        let high = splitmix64(&mut mix);
+        Rng {
+            state: [
+                low as u32,
+                (low >> 32) as u32,
+                high as u32,
+                (high >> 32) as u32,
+            ],
+        }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let [s0, s1, s2, s3] = self.state;
+        let result = s0.wrapping_add(s3).rotate_left(7).wrapping_add(s0);
+        let t = s1 << 9;
+        let s2 = s2 ^ s0;
+        let s3 = s3 ^ s1;
+        let s1 = s1 ^ s2;
+        let s0 = s0 ^ s3;
+        self.state = [s0, s1, s2 ^ t, s3.rotate_left(11)];
+        result
+    }
+
+    /// Random value from 0 to `bound - 1`, `bound` must not be 0
+    pub fn below(&mut self, bound: u32) -> u32 {
+        ((u64::from(self.next_u32()) * u64::from(bound)) >> 32) as u32
+    }
+
+    /// Full scale random value, [`Sample::MIN`] to [`Sample::MAX`]
+    pub fn next_sample(&mut self) -> Sample {
+        Sample::from((self.next_u32() >> SAMPLE_SHIFT) as i32 + Sample::MIN)
+    }
+
+    /// True with a probability set by a knob or CV, [`Sample::MIN`] never
+    /// and [`Sample::MAX`] always
+    pub fn chance(&mut self, probability: Sample) -> bool {
+        let threshold = (probability.to_clamped() - Sample::MIN) as u32;
+        self.below((Sample::MAX - Sample::MIN) as u32) < threshold
+    }
+
+    /// Random value from `min` to `max` inclusive, in either order
+    pub fn sample_in(&mut self, min: Sample, max: Sample) -> Sample {
+        let (low, high) = (min.to_clamped(), max.to_clamped());
+        let (low, high) = (low.min(high), low.max(high));
+        Sample::from(low + self.below((high - low + 1) as u32) as i32)
+    }
+
+    /// Index picked with odds proportional to its weight, `None` if all the
+    /// weights are 0
+    pub fn weighted(&mut self, weights: &[u16]) -> Option<usize> {
+        let total: u32 = weights.iter().map(|&weight| u32::from(weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = self.below(total);
+        for (index, &weight) in weights.iter().enumerate() {
+            let weight = u32::from(weight);
+            if pick < weight {
+                return Some(index);
+            }
+            pick -= weight;
+        }
+        None
+    }
+}
+
+/// Fold noisy bits into a seed, for example the RP2040 ROSC random bit
+pub fn seed_from_bits(mut next_bit: impl FnMut() -> bool) -> u64 {
+    let mut seed = FNV_OFFSET;
+    for _ in 0..SEED_READINGS {
+        seed = fnv1a(seed, u64::from(next_bit()));
+    }
+    splitmix64(&mut seed)
+}
+
+/// Fold ADC readings into a seed
+///
+/// Only the bottom bits of a reading are noise, so the seed is only as
+/// random as they are. An unplugged jack is a good choice.
+pub async fn seed_from_adc<A: InputAdc>(adc: &mut A, input: AdcInput) -> Result<u64, A::Error> {
+    let mut seed = FNV_OFFSET;
+    for _ in 0..SEED_READINGS {
+        seed = fnv1a(seed, u64::from(adc.read(input).await?));
+    }
+    Ok(splitmix64(&mut seed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{seed_from_adc, seed_from_bits, Rng};
+    use crate::inputs::{AdcInput, InputAdc};
+    use crate::Sample;
+
+    struct NoisyAdc(u16);
+
+    impl InputAdc for NoisyAdc {
+        type Error = ();
+
+        async fn read(&mut self, _input: AdcInput) -> Result<u16, ()> {
+            // steady mid scale level, with a wobble in the low bits
+            self.0 = self.0.wrapping_mul(75).wrapping_add(74);
+            Ok(2048 + self.0 % 8)
+        }
+    }
+
+    #[test]
+    fn test_rng() {
+        let mut rng = Rng::new(0);
+        // reference xoshiro128++ output from a known state
+        let mut reference = Rng {
+            state: [1, 2, 3, 4],
+        };
+        assert_eq!(reference.next_u32(), 641);
+        assert_eq!(reference.next_u32(), 1_573_767);
+
+        assert!((0..1000).all(|_| rng.below(6) < 6));
+        let samples = (0..4096).map(|_| rng.next_sample().to_clamped());
+        let (low, high) = samples.fold((0, 0), |(low, high), value| {
+            (value.min(low), value.max(high))
+        });
+        assert!(low < Sample::MIN + 50 && high > Sample::MAX - 50);
+
+        // same seed, same sequence
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        assert!((0..16).all(|_| a.next_u32() == b.next_u32()));
+        assert_ne!(Rng::new(42).next_u32(), Rng::new(43).next_u32());
+    }
+
+    #[test]
+    fn test_rng_choices() {
+        let mut rng = Rng::new(7);
+        assert!((0..100).all(|_| !rng.chance(Sample::from(Sample::MIN))));
+        assert!((0..100).all(|_| rng.chance(Sample::from(Sample::MAX))));
+        let hits = (0..10_000)
+            .filter(|_| rng.chance(Sample::from(0_i32)))
+            .count();
+        assert!(hits.abs_diff(5_000) < 200, "{hits}");
+
+        let mut counts = [0_u32; 3];
+        for _ in 0..10_000 {
+            let value = rng.sample_in(Sample::from(12_i32), Sample::from(10_i32));
+            counts[(value.to_clamped() - 10) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&count| count.abs_diff(3_333) < 200));
+
+        let mut counts = [0_u32; 4];
+        for _ in 0..10_000 {
+            counts[rng.weighted(&[1, 0, 3, 6]).unwrap()] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        assert!(counts[0].abs_diff(1_000) < 150 && counts[3].abs_diff(6_000) < 250);
+        assert_eq!(rng.weighted(&[0, 0]), None);
+        assert_eq!(rng.weighted(&[]), None);
+    }
+
+    #[test]
+    fn test_hardware_seeds() {
+        let mut toggle = false;
+        let steady = seed_from_bits(|| {
+            toggle = !toggle;
+            toggle
+        });
+        assert_ne!(steady, seed_from_bits(|| true));
+
+        let first = embassy_futures::block_on(seed_from_adc(&mut NoisyAdc(1), AdcInput::Audio2));
+        let second = embassy_futures::block_on(seed_from_adc(&mut NoisyAdc(2), AdcInput::Audio2));
+        assert_ne!(first.unwrap(), second.unwrap());
+    }
+}
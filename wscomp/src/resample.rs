@@ -0,0 +1,377 @@
+//! Fixed point sample rate conversion for 16 bit PCM streams
+//!
+//! [`Resampler`] first doubles the source rate with a [`HalfBand`]
+//! interpolator, then linearly interpolates between those points at the
+//! output rate. Oversampling first keeps the cheap linear interpolation
+//! from adding much imaging, which is plenty for ambience recordings.
+//!
+//! Sources faster than the output are first halved with [`HalfBandDecimator`]
+//! until they're no faster than it, so nothing above the output's Nyquist
+//! frequency folds back. For ratios under 2 that also drops the top of the
+//! output's band, a 64kHz source loses everything above 16kHz.
+
+use crate::fir::HALF_BAND;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResampleError {
+    /// An input or output rate of 0Hz
+    ZeroRate,
+}
+
+/// 2x interpolator using a 7 tap half-band FIR
+///
+/// Coefficients are `[-1, 0, 9, 16, 9, 0, -1] / 16` (with 2x gain for
+/// upsampling), so the even phase is the original signal and the odd
+/// phase is `(-1, 9, 9, -1) / 16` of the four nearest inputs.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HalfBand {
+    history: [i32; 4],
+}
+
+impl HalfBand {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push one input, returns two outputs at twice the rate
+    ///
+    /// Output is delayed by two input samples.
+    pub fn upsample(&mut self, input: i16) -> [i16; 2] {
+        self.history = [
+            self.history[1],
+            self.history[2],
+            self.history[3],
+            i32::from(input),
+        ];
+        let [h0, h1, h2, h3] = self.history;
+        let mid = (9 * (h1 + h2) - h0 - h3 + 8) >> 4;
+        [
+            h1 as i16,
+            mid.clamp(i16::MIN.into(), i16::MAX.into()) as i16,
+        ]
+    }
+}
+
+/// 2x decimator, low passing with the [`HALF_BAND`] kernel before dropping
+/// every other sample
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HalfBandDecimator {
+    /// Inputs, as a ring with the oldest at `position`
+    history: [i32; HALF_BAND.len()],
+    position: usize,
+}
+
+impl HalfBandDecimator {
+    pub fn new() -> Self {
+        HalfBandDecimator {
+            history: [0; HALF_BAND.len()],
+            position: 0,
+        }
+    }
+
+    /// Push two inputs, returns one output at half the rate
+    ///
+    /// Output is delayed by nine input samples.
+    pub fn decimate(&mut self, inputs: [i16; 2]) -> i16 {
+        for input in inputs {
+            self.history[self.position] = i32::from(input);
+            self.position = (self.position + 1) % HALF_BAND.len();
+        }
+        // full scale 16 bit inputs overflow an i32 sum of Q15 products
+        let (recent, oldest) = self.history.split_at(self.position);
+        let sum: i64 = recent
+            .iter()
+            .rev()
+            .chain(oldest.iter().rev())
+            .zip(HALF_BAND.iter())
+            .map(|(&input, &coefficient)| i64::from(input * i32::from(coefficient)))
+            .sum();
+        ((sum + (1 << 14)) >> 15).clamp(i16::MIN.into(), i16::MAX.into()) as i16
+    }
+}
+
+impl Default for HalfBandDecimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Converts a stream of 16 bit samples from one sample rate to another
+///
+/// When the rates match, samples pass through untouched.
+pub struct Resampler<I> {
+    source: I,
+    /// Halving stages ahead of the interpolator, the first `decimations`
+    /// are used
+    decimators: [HalfBandDecimator; MAX_DECIMATIONS],
+    decimations: usize,
+    half_band: HalfBand,
+    pending: Option<i16>,
+    // positions in the 2x oversampled stream, 16.16 fixed point
+    step: u32,
+    position: u32,
+    previous: i32,
+    next: i32,
+    passthrough: bool,
+}
+
+/// Most halvings before interpolating, enough for 768kHz to 48kHz
+pub const MAX_DECIMATIONS: usize = 4;
+
+impl<I: Iterator<Item = i16>> Resampler<I> {
+    const FRACTION_BITS: u32 = 16;
+
+    /// Convert `source` from `input_rate` to `output_rate`, in hertz
+    pub fn new(source: I, input_rate: u32, output_rate: u32) -> Result<Self, ResampleError> {
+        if input_rate == 0 || output_rate == 0 {
+            return Err(ResampleError::ZeroRate);
+        }
+        let mut decimations = 0;
+        while input_rate > output_rate << decimations && decimations < MAX_DECIMATIONS {
+            decimations += 1;
+        }
+        let step = ((u64::from(input_rate) * 2) << Self::FRACTION_BITS)
+            / (u64::from(output_rate) << decimations);
+        Ok(Resampler {
+            source,
+            decimators: core::array::from_fn(|_| HalfBandDecimator::new()),
+            decimations,
+            half_band: HalfBand::new(),
+            pending: None,
+            step: step as u32,
+            position: 0,
+            previous: 0,
+            next: 0,
+            passthrough: input_rate == output_rate,
+        })
+    }
+
+    /// Next sample of the source, after `stages` of decimation
+    fn next_decimated(source: &mut I, stages: &mut [HalfBandDecimator]) -> Option<i16> {
+        match stages.split_last_mut() {
+            None => source.next(),
+            Some((last, earlier)) => {
+                let first = Self::next_decimated(source, earlier)?;
+                let second = Self::next_decimated(source, earlier)?;
+                Some(last.decimate([first, second]))
+            }
+        }
+    }
+
+    /// Next sample of the 2x oversampled source
+    fn next_oversampled(&mut self) -> Option<i16> {
+        if let Some(sample) = self.pending.take() {
+            return Some(sample);
+        }
+        let stages = &mut self.decimators[..self.decimations];
+        let input = Self::next_decimated(&mut self.source, stages)?;
+        let [even, odd] = self.half_band.upsample(input);
+        self.pending = Some(odd);
+        Some(even)
+    }
+}
+
+impl<I: Iterator<Item = i16>> Iterator for Resampler<I> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.passthrough {
+            return self.source.next();
+        }
+        let one = 1 << Self::FRACTION_BITS;
+        while self.position >= one {
+            self.position -= one;
+            self.previous = self.next;
+            self.next = self.next_oversampled()?.into();
+        }
+        // full scale steps are 17 bits, so the product needs 64
+        let fraction = i64::from(self.position);
+        let step = i64::from(self.next - self.previous) * fraction;
+        let output = self.previous + (step >> Self::FRACTION_BITS) as i32;
+        self.position += self.step;
+        Some(output as i16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HalfBand, HalfBandDecimator, ResampleError, Resampler};
+
+    #[test]
+    fn test_half_band_upsample() {
+        let mut half_band = HalfBand::new();
+        let mut output = vec![];
+        for _ in 0..8 {
+            output.extend(half_band.upsample(1000));
+        }
+        // after the filter fills, a constant input is a constant output
+        assert!(output[6..].iter().all(|&s| s == 1000), "{output:?}");
+
+        // a ramp is interpolated at the midpoints
+        let mut half_band = HalfBand::new();
+        let mut output = vec![];
+        for i in 0..8 {
+            output.extend(half_band.upsample(i * 100));
+        }
+        assert_eq!(&output[6..12], &[100, 150, 200, 250, 300, 350]);
+    }
+
+    #[test]
+    fn test_resampler_passthrough() {
+        let input: Vec<i16> = (0..100).map(|i| i * 7 - 300).collect();
+        let output: Vec<i16> = Resampler::new(input.iter().copied(), 48_000, 48_000)
+            .unwrap()
+            .collect();
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn test_resampler_lengths() {
+        for (input_rate, expected) in [
+            (22_050, 2177),
+            (24_000, 2000),
+            (32_000, 1500),
+            (44_100, 1088),
+        ] {
+            let input = core::iter::repeat_n(500_i16, 1000);
+            let output: Vec<i16> = Resampler::new(input, input_rate, 48_000).unwrap().collect();
+            assert!(
+                (output.len() as i32 - expected).abs() <= 4,
+                "{input_rate}: {} samples",
+                output.len()
+            );
+            // settles on the constant input level
+            assert!(output[10..].iter().all(|&s| s == 500));
+        }
+    }
+
+    #[test]
+    fn test_resampler_doubles_rate() {
+        // 48kHz to 96kHz is the half-band on its own: every other output is
+        // an input, three input samples late
+        let input: Vec<i16> = (0..100).map(|i| (i * 37 % 200 - 100) * 50).collect();
+        let output: Vec<i16> = Resampler::new(input.iter().copied(), 48_000, 96_000)
+            .unwrap()
+            .collect();
+        assert!((output.len() as i32 - 200).abs() <= 2, "{}", output.len());
+        for (index, &sample) in input.iter().enumerate().take(90) {
+            assert_eq!(output[2 * index + 6], sample, "{index}");
+        }
+    }
+
+    #[test]
+    fn test_resampler_full_scale() {
+        // alternating extremes, and a pattern where the half-band overshoots
+        // between them, so neighbouring oversampled points are more than 32768
+        // apart
+        let patterns = [
+            [i16::MIN, i16::MAX, i16::MIN, i16::MAX, i16::MIN, i16::MAX],
+            [i16::MIN, i16::MIN, i16::MAX, i16::MIN, i16::MIN, i16::MAX],
+        ];
+        for pattern in patterns {
+            let input = pattern.into_iter().cycle().take(1200);
+            let mut resampler = Resampler::new(input.clone(), 44_100, 48_000).unwrap();
+            let step = u64::from(resampler.step);
+
+            // each output is interpolated between the oversampled points
+            // either side of it, starting from silence
+            let mut half_band = HalfBand::new();
+            let oversampled: Vec<i64> = [0, 0]
+                .into_iter()
+                .chain(input.flat_map(|s| half_band.upsample(s)).map(i64::from))
+                .collect();
+            for tick in 0.. {
+                let Some(output) = resampler.next() else {
+                    break;
+                };
+                let position = tick * step;
+                let index = (position >> 16) as usize;
+                let (previous, next) = (oversampled[index], oversampled[index + 1]);
+                let expected = previous + (((next - previous) * (position & 0xffff) as i64) >> 16);
+                assert_eq!(i64::from(output), expected, "{tick}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_resampler_preserves_sine() {
+        // 1kHz from each common WAV rate should still be 1kHz at 48kHz, 48
+        // samples per cycle
+        for input_rate in [22_050, 32_000, 44_100] {
+            let input = (0..input_rate / 10).map(|i| {
+                let phase = i as f32 * 1000.0 / input_rate as f32 * core::f32::consts::TAU;
+                (phase.sin() * 10_000.0) as i16
+            });
+            let output: Vec<i16> = Resampler::new(input, input_rate, 48_000).unwrap().collect();
+            let settled = &output[96..4704];
+            let crossings = settled.windows(2).filter(|w| w[0] < 0 && w[1] >= 0).count();
+            assert_eq!(crossings, 96, "{input_rate}");
+            let peak = settled.iter().map(|s| s.abs()).max().unwrap();
+            assert!(
+                (9_800..=10_100).contains(&peak),
+                "{input_rate} peak: {peak}"
+            );
+        }
+    }
+
+    /// Peak of the output after it settles, for a cosine at `frequency`
+    fn settled_peak(frequency: f64, input_rate: u32) -> i16 {
+        let input = (0..input_rate / 10).map(|i| {
+            let phase = f64::from(i) * frequency / f64::from(input_rate);
+            ((phase * core::f64::consts::TAU).cos() * 10_000.0) as i16
+        });
+        let output: Vec<i16> = Resampler::new(input, input_rate, 48_000).unwrap().collect();
+        output[200..4000].iter().map(|s| s.abs()).max().unwrap()
+    }
+
+    #[test]
+    fn test_half_band_decimator() {
+        let mut decimator = HalfBandDecimator::new();
+        let output: Vec<i16> = (0..20).map(|_| decimator.decimate([1000, 1000])).collect();
+        // unity gain at DC once the filter fills
+        assert!(
+            output[10..].iter().all(|&s| s.abs_diff(1000) <= 1),
+            "{output:?}"
+        );
+
+        // full scale doesn't overflow
+        let mut decimator = HalfBandDecimator::new();
+        for _ in 0..20 {
+            decimator.decimate([i16::MIN, i16::MAX]);
+        }
+        decimator.decimate([i16::MAX; 2]);
+    }
+
+    #[test]
+    fn test_resampler_downsampling() {
+        // tones in a half-band's stop band are removed rather than folding
+        // back, to 8kHz and 11kHz without the filters
+        assert!(settled_peak(40_000.0, 96_000) < 10, "96kHz");
+        assert!(settled_peak(85_000.0, 192_000) < 10, "192kHz");
+        // while lower tones pass
+        assert!(settled_peak(1_000.0, 96_000).abs_diff(10_000) < 100);
+        assert!(settled_peak(1_000.0, 192_000).abs_diff(10_000) < 100);
+
+        // 96kHz halves to the output rate, so it takes every other sample
+        let input = core::iter::repeat_n(500_i16, 1000);
+        let output: Vec<i16> = Resampler::new(input, 96_000, 48_000).unwrap().collect();
+        assert!((output.len() as i32 - 500).abs() <= 8, "{}", output.len());
+        assert!(output[20..].iter().all(|&s| s.abs_diff(500) <= 1));
+    }
+
+    #[test]
+    fn test_resampler_zero_rate() {
+        let input = || core::iter::repeat_n(0_i16, 10);
+        assert_eq!(
+            Resampler::new(input(), 44_100, 0).err(),
+            Some(ResampleError::ZeroRate)
+        );
+        assert_eq!(
+            Resampler::new(input(), 0, 48_000).err(),
+            Some(ResampleError::ZeroRate)
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Small fixed point reverb
+//!
+//! A mono Freeverb: four damped comb filters in parallel, then two allpass
+//! filters in series, with the delay lengths scaled from Freeverb's 44.1kHz
+//! tuning to 48kHz. At about 13KB it's a lot for one task's stack, so take
+//! it from the card's [`Arena`](crate::arena::Arena) with the other large
+//! buffers:
+//!
+//! ```
+//! # use wscomp::arena::ArenaStorage;
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::reverb::Reverb;
+//! # use wscomp::Sample;
+//! # static ARENA: ArenaStorage<{ 16 * 1024 }> = ArenaStorage::new();
+//! # let mut arena = ARENA.take().unwrap();
+//! # let (mux_state, dry) = (MuxState::default(), Sample::from(0_i32));
+//! let reverb = arena
+//!     .alloc_value(Reverb::new())
+//!     .expect("not enough RAM for the reverb");
+//! reverb.set_size(mux_state.main_knob);
+//! reverb.set_damping(mux_state.x_knob);
+//! let wet = reverb.process(dry);
+//! let mixed = Sample::lerp(dry, wet, mux_state.y_knob);
+//! ```
+//!
+//! Only the wet signal comes out, mixing it with the dry signal is up to the
+//! card.
+
+use crate::Sample;
+
+/// Internal levels are this many bits above a [`Sample`], for quieter tails
+const HEADROOM_BITS: u32 = 2;
+
+/// Feedback at the smallest size, 0.7 in Q15
+const FEEDBACK_MIN: i32 = 22_938;
+/// Feedback added at the largest size, 0.28 in Q15
+const FEEDBACK_RANGE: i32 = 9_175;
+/// Damping at full, 0.4 in Q15
+const DAMPING_RANGE: i32 = 13_107;
+
+/// Knob position from 0 to 32767, Q15
+fn position(value: Sample) -> i32 {
+    (value.to_clamped() - Sample::MIN) << (15 - 12)
+}
+
+/// Round a Q15 product back down
+fn q15(value: i32) -> i32 {
+    (value + (1 << 14)) >> 15
+}
+
+fn saturate(value: i32) -> i16 {
+    value.clamp(i16::MIN.into(), i16::MAX.into()) as i16
+}
+
+/// Feedback comb with a one pole low pass in the loop
+struct Comb<const N: usize> {
+    buffer: [i16; N],
+    index: usize,
+    filter: i32,
+}
+
+impl<const N: usize> Comb<N> {
+    const fn new() -> Self {
+        Comb {
+            buffer: [0; N],
+            index: 0,
+            filter: 0,
+        }
+    }
+
+    fn process(&mut self, input: i32, feedback: i32, damping: i32) -> i32 {
+        let output = i32::from(self.buffer[self.index]);
+        self.filter = q15(output * (32_768 - damping) + self.filter * damping);
+        self.buffer[self.index] = saturate(input + q15(self.filter * feedback));
+        self.index = (self.index + 1) % N;
+        output
+    }
+
+    fn clear(&mut self) {
+        self.buffer = [0; N];
+        self.filter = 0;
+    }
+}
+
+/// Schroeder allpass with a gain of 0.5, smears echoes into a wash
+struct Allpass<const N: usize> {
+    buffer: [i16; N],
+    index: usize,
+}
+
+impl<const N: usize> Allpass<N> {
+    const fn new() -> Self {
+        Allpass {
+            buffer: [0; N],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: i32) -> i32 {
+        let delayed = i32::from(self.buffer[self.index]);
+        self.buffer[self.index] = saturate(input + (delayed >> 1));
+        self.index = (self.index + 1) % N;
+        delayed - input
+    }
+
+    fn clear(&mut self) {
+        self.buffer = [0; N];
+    }
+}
+
+/// Mono reverb with size and damping controls
+pub struct Reverb {
+    combs: (Comb<1215>, Comb<1293>, Comb<1390>, Comb<1476>),
+    allpasses: (Allpass<605>, Allpass<480>),
+    /// Comb feedback, Q15
+    feedback: i32,
+    /// Comb low pass amount, Q15
+    damping: i32,
+}
+
+impl Reverb {
+    /// New silent reverb, at half size and half damping
+    pub const fn new() -> Self {
+        Reverb {
+            combs: (Comb::new(), Comb::new(), Comb::new(), Comb::new()),
+            allpasses: (Allpass::new(), Allpass::new()),
+            feedback: FEEDBACK_MIN + FEEDBACK_RANGE / 2,
+            damping: DAMPING_RANGE / 2,
+        }
+    }
+
+    /// Room size, [`Sample::MIN`] is a short room and [`Sample::MAX`] a long
+    /// hall
+    pub fn set_size(&mut self, size: Sample) {
+        self.feedback = FEEDBACK_MIN + ((position(size) * FEEDBACK_RANGE) >> 15);
+    }
+
+    /// High frequency damping, [`Sample::MIN`] is bright and [`Sample::MAX`]
+    /// dark
+    pub fn set_damping(&mut self, damping: Sample) {
+        self.damping = (position(damping) * DAMPING_RANGE) >> 15;
+    }
+
+    /// Run one sample, returns the wet signal
+    pub fn process(&mut self, input: Sample) -> Sample {
+        // Freeverb feeds its combs a small fraction of the input, they ring
+        // up a lot at large sizes
+        let input = (input.to_clamped() << HEADROOM_BITS) >> 3;
+        let (feedback, damping) = (self.feedback, self.damping);
+        let mut wet = self.combs.0.process(input, feedback, damping)
+            + self.combs.1.process(input, feedback, damping)
+            + self.combs.2.process(input, feedback, damping)
+            + self.combs.3.process(input, feedback, damping);
+        wet = self.allpasses.0.process(wet);
+        wet = self.allpasses.1.process(wet);
+        Sample::from(wet >> HEADROOM_BITS)
+    }
+
+    /// Silence the tail
+    pub fn clear(&mut self) {
+        self.combs.0.clear();
+        self.combs.1.clear();
+        self.combs.2.clear();
+        self.combs.3.clear();
+        self.allpasses.0.clear();
+        self.allpasses.1.clear();
+    }
+}
+
+impl Default for Reverb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Reverb;
+    use crate::Sample;
+
+    /// Sum of absolute output over a window of samples after an impulse
+    fn tail_energy(reverb: &mut Reverb, window: core::ops::Range<usize>) -> i64 {
+        reverb.clear();
+        let mut energy = 0;
+        for index in 0..window.end {
+            let input = Sample::from(if index < 16 { 1500_i32 } else { 0 });
+            let output = reverb.process(input).to_clamped();
+            if window.contains(&index) {
+                energy += i64::from(output.abs());
+            }
+        }
+        energy
+    }
+
+    #[test]
+    fn test_reverb_tail() {
+        let mut reverb = Box::new(Reverb::new());
+        // nothing until the shortest comb comes around
+        let early = tail_energy(&mut reverb, 0..1200);
+        assert!(early < 200, "early {early}");
+        assert!(tail_energy(&mut reverb, 1200..12_000) > 10_000);
+
+        // bigger rooms ring for longer
+        reverb.set_size(Sample::from(Sample::MIN));
+        let small = tail_energy(&mut reverb, 24_000..48_000);
+        reverb.set_size(Sample::from(Sample::MAX));
+        let large = tail_energy(&mut reverb, 24_000..48_000);
+        assert!(large > small * 10, "small {small} large {large}");
+
+        // and a small room dies away within a second
+        reverb.set_size(Sample::from(Sample::MIN));
+        reverb.clear();
+        reverb.process(Sample::from(Sample::MAX));
+        let last = (0..48_000)
+            .map(|_| reverb.process(Sample::from(0_i32)).to_clamped())
+            .last();
+        assert!(last.unwrap().abs() <= 1);
+    }
+
+    #[test]
+    fn test_reverb_damping() {
+        // a burst at Nyquist loses more to damping than it does bright
+        let burst = |reverb: &mut Reverb| {
+            reverb.clear();
+            (0..24_000)
+                .map(|index| {
+                    let input = match index {
+                        0..480 if index % 2 == 0 => 1000_i32,
+                        0..480 => -1000,
+                        _ => 0,
+                    };
+                    i64::from(reverb.process(Sample::from(input)).to_clamped().abs())
+                })
+                .skip(4800)
+                .sum::<i64>()
+        };
+        let mut reverb = Box::new(Reverb::new());
+        reverb.set_damping(Sample::from(Sample::MIN));
+        let bright = burst(&mut reverb);
+        reverb.set_damping(Sample::from(Sample::MAX));
+        let dark = burst(&mut reverb);
+        assert!(bright > dark * 2, "bright {bright} dark {dark}");
+    }
+}
@@ -0,0 +1,155 @@
+//! Single producer, single consumer ring buffer of PCM samples
+//!
+//! Lets one task decode audio ahead into a buffer while another (possibly on
+//! the other core) mixes from it, without locks. A [`SampleRing`] is usually
+//! a static, or taken from an [`Arena`](crate::arena::Arena) with
+//! `alloc_value`, and split once at startup:
+//!
+//! ```
+//! # use wscomp::ring::SampleRing;
+//! // in a static on the card, so each half can go to a different task
+//! let mut ring = SampleRing::<4096>::new();
+//! let (mut producer, mut consumer) = ring.split();
+//! producer.push(1000);
+//! assert_eq!(consumer.pop(), Some(1000));
+//! ```
+
+use core::cell::UnsafeCell;
+
+use portable_atomic::{AtomicUsize, Ordering};
+
+pub struct SampleRing<const N: usize> {
+    buffer: UnsafeCell<[i16; N]>,
+    /// Total samples read, wrapping
+    read: AtomicUsize,
+    /// Total samples written, wrapping
+    write: AtomicUsize,
+}
+
+// SAFETY: the producer only writes slots the consumer has finished with, and
+// the consumer only reads slots the producer has published, see split()
+unsafe impl<const N: usize> Sync for SampleRing<N> {}
+
+impl<const N: usize> SampleRing<N> {
+    /// New empty ring, `N` must be a power of two
+    pub const fn new() -> Self {
+        assert!(
+            N.is_power_of_two(),
+            "SampleRing size must be a power of two"
+        );
+        SampleRing {
+            buffer: UnsafeCell::new([0; N]),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    /// Split into the two ends, each can be given to a different task
+    pub fn split(&mut self) -> (RingProducer<'_, N>, RingConsumer<'_, N>) {
+        let ring = &*self;
+        (RingProducer { ring }, RingConsumer { ring })
+    }
+
+    fn len(&self) -> usize {
+        self.write
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.read.load(Ordering::Acquire))
+    }
+}
+
+impl<const N: usize> Default for SampleRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writing end of a [`SampleRing`]
+pub struct RingProducer<'a, const N: usize> {
+    ring: &'a SampleRing<N>,
+}
+
+impl<const N: usize> RingProducer<'_, N> {
+    /// Space for more samples
+    pub fn free(&self) -> usize {
+        N - self.ring.len()
+    }
+
+    /// Add a sample, false if the ring is full
+    pub fn push(&mut self, sample: i16) -> bool {
+        self.fill_from(&mut core::iter::once(sample), 1) == 1
+    }
+
+    /// Add up to `max` samples from `samples`, returns how many were added
+    pub fn fill_from(&mut self, samples: &mut impl Iterator<Item = i16>, max: usize) -> usize {
+        let write = self.ring.write.load(Ordering::Relaxed);
+        let count = max.min(self.free());
+        let mut added = 0;
+        for sample in samples.take(count) {
+            // SAFETY: this slot is free, the consumer won't read it until
+            // `write` is published below
+            unsafe {
+                (*self.ring.buffer.get())[write.wrapping_add(added) % N] = sample;
+            }
+            added += 1;
+        }
+        self.ring
+            .write
+            .store(write.wrapping_add(added), Ordering::Release);
+        added
+    }
+}
+
+/// Reading end of a [`SampleRing`]
+pub struct RingConsumer<'a, const N: usize> {
+    ring: &'a SampleRing<N>,
+}
+
+impl<const N: usize> RingConsumer<'_, N> {
+    /// Samples ready to read
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn pop(&mut self) -> Option<i16> {
+        if self.is_empty() {
+            return None;
+        }
+        let read = self.ring.read.load(Ordering::Relaxed);
+        // SAFETY: the producer published this slot, and won't reuse it until
+        // `read` is updated below
+        let sample = unsafe { (*self.ring.buffer.get())[read % N] };
+        self.ring
+            .read
+            .store(read.wrapping_add(1), Ordering::Release);
+        Some(sample)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SampleRing;
+
+    #[test]
+    fn test_sample_ring() {
+        let mut ring = SampleRing::<4>::new();
+        let (mut producer, mut consumer) = ring.split();
+        assert_eq!(consumer.pop(), None);
+
+        let mut samples = 1..;
+        assert_eq!(producer.fill_from(&mut samples, 3), 3);
+        assert_eq!(producer.free(), 1);
+        assert_eq!(consumer.pop(), Some(1));
+        // wraps around, and stops when full
+        assert_eq!(producer.fill_from(&mut samples, 10), 2);
+        assert!(!producer.push(-1));
+        assert_eq!(consumer.len(), 4);
+        let read: Vec<i16> = core::iter::from_fn(|| consumer.pop()).collect();
+        assert_eq!(read, [2, 3, 4, 5]);
+        assert!(producer.push(-1));
+        assert_eq!(consumer.pop(), Some(-1));
+    }
+}
@@ -0,0 +1,525 @@
+//! Musical time grid and step patterns for sequencer style cards
+//!
+//! [`TimeGrid`] tracks bar/beat/step position as clock steps arrive (from a
+//! pulse input, an internal clock, etc.), and [`Pattern`] stores which steps
+//! fire plus a value for each step. [`Song`] chains patterns together.
+
+use crate::Sample;
+
+/// Position within the grid, all zero based
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Position {
+    pub bar: u32,
+    pub beat: u8,
+    pub step: u8,
+}
+
+/// Tracks bar, beat and step position from clock steps
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimeGrid {
+    steps_per_beat: u8,
+    beats_per_bar: u8,
+    steps: u32,
+    started: bool,
+}
+
+impl TimeGrid {
+    /// New grid, for example `TimeGrid::new(4, 4)` for sixteenths in 4/4
+    pub fn new(steps_per_beat: u8, beats_per_bar: u8) -> Self {
+        TimeGrid {
+            steps_per_beat: steps_per_beat.max(1),
+            beats_per_bar: beats_per_bar.max(1),
+            steps: 0,
+            started: false,
+        }
+    }
+
+    /// Advance one step, call once per clock step
+    ///
+    /// The first tick after a reset lands on the first step of the first bar.
+    pub fn tick(&mut self) -> Position {
+        if self.started {
+            self.steps = self.steps.wrapping_add(1);
+        }
+        self.started = true;
+        self.position()
+    }
+
+    /// Go back to the start, the next tick is the first step again
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.started = false;
+    }
+
+    /// Steps since the first tick after a reset
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    pub fn steps_per_bar(&self) -> u32 {
+        u32::from(self.steps_per_beat) * u32::from(self.beats_per_bar)
+    }
+
+    pub fn position(&self) -> Position {
+        let steps_per_beat = u32::from(self.steps_per_beat);
+        Position {
+            bar: self.steps / self.steps_per_bar(),
+            beat: ((self.steps / steps_per_beat) % u32::from(self.beats_per_bar)) as u8,
+            step: (self.steps % steps_per_beat) as u8,
+        }
+    }
+
+    /// Is the current step the first step of a beat?
+    pub fn is_beat(&self) -> bool {
+        self.position().step == 0
+    }
+
+    /// Is the current step the first step of a bar?
+    pub fn is_bar(&self) -> bool {
+        self.steps.is_multiple_of(self.steps_per_bar())
+    }
+}
+
+/// Errors from building or (de)serializing a [`Pattern`] or [`Song`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PatternError {
+    BufferTooSmall,
+    InvalidLength,
+    /// No room for another [`Song`] entry
+    Full,
+}
+
+/// Up to 32 steps, each with a gate bit and a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pattern {
+    length: u8,
+    gates: u32,
+    values: [i16; Pattern::MAX_STEPS],
+}
+
+impl Pattern {
+    pub const MAX_STEPS: usize = 32;
+    /// Bytes needed by [`Pattern::to_bytes`]
+    pub const SERIALIZED_SIZE: usize = 1 + 4 + 2 * Self::MAX_STEPS;
+
+    /// New empty pattern with `length` steps (clamped to 1..=32)
+    pub fn new(length: u8) -> Self {
+        Pattern {
+            length: length.clamp(1, Self::MAX_STEPS as u8),
+            gates: 0,
+            values: [0; Self::MAX_STEPS],
+        }
+    }
+
+    /// New pattern with gates from a bitmask, bit 0 is the first step
+    pub fn from_gates(length: u8, gates: u32) -> Self {
+        let mut pattern = Self::new(length);
+        pattern.gates = gates & pattern.mask();
+        pattern
+    }
+
+    pub fn len(&self) -> usize {
+        self.length.into()
+    }
+
+    /// Always false, a pattern has at least one step
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Is every step within the length silent?
+    pub fn has_no_gates(&self) -> bool {
+        self.gates() == 0
+    }
+
+    /// Change the length, steps beyond it keep their values but don't play
+    pub fn set_len(&mut self, length: u8) {
+        self.length = length.clamp(1, Self::MAX_STEPS as u8);
+    }
+
+    pub fn gates(&self) -> u32 {
+        self.gates & self.mask()
+    }
+
+    /// Gate for a step, wraps around the pattern length
+    pub fn gate(&self, step: usize) -> bool {
+        self.gates & (1 << (step % self.len())) != 0
+    }
+
+    pub fn set_gate(&mut self, step: usize, gate: bool) {
+        let bit = 1 << (step % self.len());
+        if gate {
+            self.gates |= bit;
+        } else {
+            self.gates &= !bit;
+        }
+    }
+
+    /// Value for a step, wraps around the pattern length
+    pub fn value(&self, step: usize) -> Sample {
+        Sample::from(self.values[step % self.len()])
+    }
+
+    pub fn set_value(&mut self, step: usize, value: Sample) {
+        let step = step % self.len();
+        self.values[step] = value.to_clamped() as i16;
+    }
+
+    /// Write the pattern into `buffer`, returns bytes written
+    ///
+    /// Layout: length (1 byte), gates (u32 LE), then a LE i16 value per step.
+    pub fn to_bytes(&self, buffer: &mut [u8]) -> Result<usize, PatternError> {
+        let size = 5 + 2 * self.len();
+        if buffer.len() < size {
+            return Err(PatternError::BufferTooSmall);
+        }
+        buffer[0] = self.length;
+        buffer[1..5].copy_from_slice(&self.gates().to_le_bytes());
+        for (step, chunk) in buffer[5..size].chunks_exact_mut(2).enumerate() {
+            chunk.copy_from_slice(&self.values[step].to_le_bytes());
+        }
+        Ok(size)
+    }
+
+    /// Read a pattern written by [`Pattern::to_bytes`]
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, PatternError> {
+        let length = *buffer.first().ok_or(PatternError::BufferTooSmall)?;
+        if length == 0 || usize::from(length) > Self::MAX_STEPS {
+            return Err(PatternError::InvalidLength);
+        }
+        let size = 5 + 2 * usize::from(length);
+        if buffer.len() < size {
+            return Err(PatternError::BufferTooSmall);
+        }
+        let mut gates = [0_u8; 4];
+        gates.copy_from_slice(&buffer[1..5]);
+        let mut pattern = Self::from_gates(length, u32::from_le_bytes(gates));
+        for (step, chunk) in buffer[5..size].chunks_exact(2).enumerate() {
+            pattern.values[step] = i16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(pattern)
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX >> (Self::MAX_STEPS - self.len())
+    }
+}
+
+/// One step of a [`Song`]: which pattern to play and how many times
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChainEntry {
+    /// Index into the card's patterns
+    pub pattern: u8,
+    /// Times to play the pattern, 0 is treated as 1
+    pub repeats: u8,
+}
+
+impl ChainEntry {
+    pub fn new(pattern: u8, repeats: u8) -> Self {
+        ChainEntry { pattern, repeats }
+    }
+}
+
+/// An ordered chain of patterns with repeat counts
+///
+/// Call [`Song::pattern_finished`] when the current pattern reaches its end
+/// to move through the chain, or jump around with [`Song::skip`] (from a
+/// pulse input) or [`Song::select`] (from CV).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Song {
+    entries: [ChainEntry; Song::MAX_ENTRIES],
+    len: u8,
+    position: u8,
+    played: u8,
+}
+
+impl Song {
+    pub const MAX_ENTRIES: usize = 16;
+    /// Bytes needed by [`Song::to_bytes`]
+    pub const SERIALIZED_SIZE: usize = 1 + 2 * Self::MAX_ENTRIES;
+
+    pub fn new() -> Self {
+        Song {
+            entries: [ChainEntry::new(0, 1); Self::MAX_ENTRIES],
+            len: 0,
+            position: 0,
+            played: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.into()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn entries(&self) -> &[ChainEntry] {
+        &self.entries[..self.len()]
+    }
+
+    pub fn push(&mut self, entry: ChainEntry) -> Result<(), PatternError> {
+        if self.len() >= Self::MAX_ENTRIES {
+            return Err(PatternError::Full);
+        }
+        self.entries[self.len()] = entry;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Index of the current entry in the chain
+    pub fn position(&self) -> usize {
+        self.position.into()
+    }
+
+    /// Pattern index to play now, `None` for an empty song
+    pub fn current(&self) -> Option<u8> {
+        self.entries()
+            .get(self.position())
+            .map(|entry| entry.pattern)
+    }
+
+    /// Back to the start of the chain
+    pub fn reset(&mut self) {
+        self.jump(0);
+    }
+
+    /// The current pattern played through once, returns the pattern to play next
+    ///
+    /// Moves to the next entry after its repeats, wrapping at the end.
+    pub fn pattern_finished(&mut self) -> Option<u8> {
+        let entry = *self.entries().get(self.position())?;
+        self.played = self.played.saturating_add(1);
+        if self.played >= entry.repeats.max(1) {
+            self.skip();
+        }
+        self.current()
+    }
+
+    /// Skip to the next entry, wrapping at the end
+    pub fn skip(&mut self) -> Option<u8> {
+        if !self.is_empty() {
+            self.jump((self.position() + 1) % self.len());
+        }
+        self.current()
+    }
+
+    /// Select an entry by CV, 0v and below is the first, max is the last
+    ///
+    /// Only changes position (and restarts repeats) when the entry changes.
+    pub fn select(&mut self, cv: Sample) -> Option<u8> {
+        if !self.is_empty() {
+            let entry = cv.to_clamped().max(0) as usize * self.len() / (Sample::MAX as usize + 1);
+            if entry != self.position() {
+                self.jump(entry);
+            }
+        }
+        self.current()
+    }
+
+    /// Write the chain into `buffer`, returns bytes written
+    ///
+    /// Layout: entry count (1 byte), then pattern and repeats bytes per entry.
+    pub fn to_bytes(&self, buffer: &mut [u8]) -> Result<usize, PatternError> {
+        let size = 1 + 2 * self.len();
+        if buffer.len() < size {
+            return Err(PatternError::BufferTooSmall);
+        }
+        buffer[0] = self.len;
+        for (entry, chunk) in self
+            .entries()
+            .iter()
+            .zip(buffer[1..size].chunks_exact_mut(2))
+        {
+            chunk.copy_from_slice(&[entry.pattern, entry.repeats]);
+        }
+        Ok(size)
+    }
+
+    /// Read a chain written by [`Song::to_bytes`], positioned at the start
+    pub fn from_bytes(buffer: &[u8]) -> Result<Self, PatternError> {
+        let len = *buffer.first().ok_or(PatternError::BufferTooSmall)?;
+        if usize::from(len) > Self::MAX_ENTRIES {
+            return Err(PatternError::InvalidLength);
+        }
+        let size = 1 + 2 * usize::from(len);
+        if buffer.len() < size {
+            return Err(PatternError::BufferTooSmall);
+        }
+        let mut song = Self::new();
+        for chunk in buffer[1..size].chunks_exact(2) {
+            song.push(ChainEntry::new(chunk[0], chunk[1]))?;
+        }
+        Ok(song)
+    }
+
+    fn jump(&mut self, position: usize) {
+        self.position = position as u8;
+        self.played = 0;
+    }
+}
+
+impl Default for Song {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChainEntry, Pattern, PatternError, Position, Song, TimeGrid};
+    use crate::Sample;
+
+    #[test]
+    fn test_time_grid() {
+        let mut grid = TimeGrid::new(4, 3);
+        assert_eq!(
+            grid.tick(),
+            Position {
+                bar: 0,
+                beat: 0,
+                step: 0
+            }
+        );
+        assert!(grid.is_bar());
+        for _ in 0..5 {
+            grid.tick();
+        }
+        assert_eq!(
+            grid.position(),
+            Position {
+                bar: 0,
+                beat: 1,
+                step: 1
+            }
+        );
+        assert!(!grid.is_beat());
+        for _ in 0..7 {
+            grid.tick();
+        }
+        assert_eq!(
+            grid.position(),
+            Position {
+                bar: 1,
+                beat: 0,
+                step: 0
+            }
+        );
+        assert!(grid.is_bar() && grid.is_beat());
+
+        grid.reset();
+        assert_eq!(grid.tick().bar, 0);
+        assert_eq!(grid.steps(), 0);
+    }
+
+    #[test]
+    fn test_pattern_steps() {
+        let mut pattern = Pattern::from_gates(8, 0b1000_1001);
+        assert!(pattern.gate(0) && !pattern.gate(1) && pattern.gate(3) && pattern.gate(7));
+        // wraps around the length
+        assert!(pattern.gate(8));
+        pattern.set_gate(1, true);
+        pattern.set_gate(0, false);
+        assert_eq!(pattern.gates(), 0b1000_1010);
+
+        pattern.set_value(2, Sample::from(-1234_i32));
+        assert_eq!(pattern.value(10), Sample::from(-1234_i32));
+
+        // gates past the length are dropped
+        assert_eq!(Pattern::from_gates(4, 0xff).gates(), 0xf);
+        assert_eq!(Pattern::from_gates(32, u32::MAX).gates(), u32::MAX);
+
+        // a pattern of rests still has steps
+        let mut pattern = Pattern::new(0);
+        assert!(pattern.has_no_gates() && !pattern.is_empty());
+        assert_eq!(pattern.len(), 1);
+        pattern.set_gate(0, true);
+        assert!(!pattern.has_no_gates());
+        // a gate past the length doesn't play
+        pattern.set_gate(0, false);
+        pattern.set_len(8);
+        pattern.set_gate(5, true);
+        pattern.set_len(4);
+        assert!(pattern.has_no_gates());
+    }
+
+    #[test]
+    fn test_pattern_bytes() {
+        let mut pattern = Pattern::from_gates(16, 0b1010_0000_1111_0001);
+        for step in 0..16 {
+            pattern.set_value(step, Sample::from(step as i32 * 100 - 800));
+        }
+        let mut buffer = [0_u8; Pattern::SERIALIZED_SIZE];
+        let size = pattern.to_bytes(&mut buffer).unwrap();
+        assert_eq!(size, 37);
+        assert_eq!(Pattern::from_bytes(&buffer[..size]), Ok(pattern.clone()));
+
+        assert_eq!(
+            pattern.to_bytes(&mut [0_u8; 10]),
+            Err(PatternError::BufferTooSmall)
+        );
+        assert_eq!(
+            Pattern::from_bytes(&buffer[..20]),
+            Err(PatternError::BufferTooSmall)
+        );
+        assert_eq!(
+            Pattern::from_bytes(&[33, 0, 0, 0, 0]),
+            Err(PatternError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn test_song_chain() {
+        let mut song = Song::new();
+        assert_eq!(song.pattern_finished(), None);
+        song.push(ChainEntry::new(3, 2)).unwrap();
+        song.push(ChainEntry::new(1, 0)).unwrap();
+        song.push(ChainEntry::new(7, 1)).unwrap();
+
+        let mut played = vec![song.current().unwrap()];
+        for _ in 0..7 {
+            played.push(song.pattern_finished().unwrap());
+        }
+        assert_eq!(played, [3, 3, 1, 7, 3, 3, 1, 7]);
+
+        // a pulse skips ahead, restarting the repeat count
+        song.reset();
+        song.pattern_finished();
+        assert_eq!(song.skip(), Some(1));
+        assert_eq!(song.skip(), Some(7));
+        assert_eq!(song.skip(), Some(3));
+        assert_eq!(song.pattern_finished(), Some(3));
+    }
+
+    #[test]
+    fn test_song_select_and_bytes() {
+        let mut song = Song::new();
+        for pattern in 0..4 {
+            song.push(ChainEntry::new(pattern * 2, 4)).unwrap();
+        }
+        assert_eq!(song.select(Sample::from(-1000_i32)), Some(0));
+        assert_eq!(song.select(Sample::from(600_i32)), Some(2));
+        assert_eq!(song.select(Sample::from(Sample::MAX)), Some(6));
+        assert_eq!(song.position(), 3);
+
+        let mut buffer = [0_u8; Song::SERIALIZED_SIZE];
+        let size = song.to_bytes(&mut buffer).unwrap();
+        assert_eq!(size, 9);
+        let loaded = Song::from_bytes(&buffer[..size]).unwrap();
+        assert_eq!(loaded.entries(), song.entries());
+        assert_eq!(loaded.position(), 0);
+        assert_eq!(Song::from_bytes(&[17]), Err(PatternError::InvalidLength));
+
+        for _ in 0..12 {
+            song.push(ChainEntry::new(0, 1)).unwrap();
+        }
+        assert_eq!(song.push(ChainEntry::new(0, 1)), Err(PatternError::Full));
+    }
+}
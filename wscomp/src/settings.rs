@@ -0,0 +1,552 @@
+//! Card settings stored in flash, and scheduling writes so they don't
+//! glitch audio
+//!
+//! [`SettingsStore`] keeps small key/value settings in the last few flash
+//! sectors. Each `set` appends a record to the current sector, and when it
+//! fills, the latest value of each key is copied to the next sector, so
+//! erases rotate through all of them. Records and sector headers carry a
+//! CRC, so a write interrupted by power loss is ignored on the next boot.
+//!
+//! Erasing or programming flash stalls XIP (execute in place), so nothing
+//! running from flash can run until the write finishes. Cards queue writes
+//! in a [`WriteQueue`], and a low priority task takes them one at a time with
+//! [`WriteQueue::ready`] once the audio output buffer holds enough samples to
+//! cover the stall. While a write runs, other non-audio tasks check
+//! [`is_paused`] and skip their work, so the mixer gets to refill the buffer
+//! first afterwards.
+
+use portable_atomic::{AtomicBool, Ordering};
+
+use crate::assets::crc32;
+use crate::units::{Hertz, Millis};
+use crate::MaybeFormat;
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Ask non-audio tasks to skip work, call just before a flash write
+pub fn pause_background() {
+    PAUSED.store(true, Ordering::Relaxed);
+}
+
+/// Let non-audio tasks run again, call once the flash write is done
+pub fn resume_background() {
+    PAUSED.store(false, Ordering::Relaxed);
+}
+
+/// Should non-audio tasks skip work this loop?
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Fixed size queue of pending settings writes
+///
+/// Writes come out in the order they were queued, one per
+/// [`WriteQueue::ready`] call, so each stall is at most one write long.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WriteQueue<T, const N: usize> {
+    items: [Option<T>; N],
+    head: usize,
+    len: usize,
+    stall_samples: usize,
+}
+
+impl<T: Copy, const N: usize> WriteQueue<T, N> {
+    /// New queue for writes stalling up to `stall`, with audio output at `rate`
+    pub fn new(stall: Millis, rate: Hertz) -> Self {
+        WriteQueue {
+            items: [None; N],
+            head: 0,
+            len: 0,
+            stall_samples: stall.ticks(rate) as usize,
+        }
+    }
+
+    /// Queue a write, gives it back if the queue is full
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.len == N {
+            return Err(item);
+        }
+        self.items[(self.head + self.len) % N] = Some(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Buffered audio samples needed to cover one write
+    pub fn samples_needed(&self) -> usize {
+        self.stall_samples
+    }
+
+    /// Next write, if `buffered` audio samples are enough to cover it
+    pub fn ready(&mut self, buffered: usize) -> Option<T> {
+        if self.len == 0 || buffered < self.stall_samples {
+            return None;
+        }
+        let item = self.items[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        item
+    }
+}
+
+/// Flash erase size on the RP2040
+pub const SECTOR_SIZE: u32 = 4096;
+/// Longest value [`SettingsStore::set`] accepts
+pub const MAX_VALUE_LEN: usize = 8;
+/// Most distinct keys a store can hold
+pub const MAX_KEYS: usize = 64;
+
+const RECORD_SIZE: u32 = 16;
+const RECORDS_PER_SECTOR: u32 = SECTOR_SIZE / RECORD_SIZE;
+const HEADER_MARKER: u32 = 0x5753_5354;
+const EMPTY_KEY: u16 = 0xffff;
+
+/// Flash access for [`SettingsStore`]
+///
+/// Offsets are relative to the start of the settings sectors, cards
+/// implement this for their flash driver with the offset added.
+// cards run on single threaded executors, so the future not being Send is fine
+#[allow(async_fn_in_trait)]
+pub trait SettingsFlash {
+    type Error: MaybeFormat;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+    /// Erase the sector starting at `offset`, setting it to 0xff
+    async fn erase(&mut self, offset: u32) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SettingsError<E> {
+    Flash(E),
+    /// Value longer than [`MAX_VALUE_LEN`]
+    TooLong,
+    /// Key 0xffff marks empty records, so can't be used
+    InvalidKey,
+    /// More than [`MAX_KEYS`] distinct keys
+    TooManyKeys,
+}
+
+impl<E> From<E> for SettingsError<E> {
+    fn from(error: E) -> Self {
+        SettingsError::Flash(error)
+    }
+}
+
+/// Key/value settings in `SECTORS` flash sectors, at least 2
+pub struct SettingsStore<F, const SECTORS: usize> {
+    flash: F,
+    sector: u32,
+    sequence: u32,
+    /// Next free record in the current sector
+    next: u32,
+}
+
+impl<F: SettingsFlash, const SECTORS: usize> SettingsStore<F, SECTORS> {
+    /// Find the current sector, or start fresh if none is valid
+    pub async fn mount(mut flash: F) -> Result<Self, SettingsError<F::Error>> {
+        let mut current: Option<(u32, u32)> = None;
+        for sector in 0..SECTORS as u32 {
+            let mut header = [0; RECORD_SIZE as usize];
+            flash.read(sector * SECTOR_SIZE, &mut header).await?;
+            if let Some(sequence) = parse_header(&header) {
+                if current.is_none_or(|(_, newest)| sequence > newest) {
+                    current = Some((sector, sequence));
+                }
+            }
+        }
+        let mut store = SettingsStore {
+            flash,
+            sector: 0,
+            sequence: 0,
+            next: 1,
+        };
+        match current {
+            Some((sector, sequence)) => {
+                store.sector = sector;
+                store.sequence = sequence;
+                while store.next < RECORDS_PER_SECTOR {
+                    if store.read_record(store.next).await?.0 == EMPTY_KEY {
+                        break;
+                    }
+                    store.next += 1;
+                }
+            }
+            None => {
+                store.flash.erase(0).await?;
+                store.write_header(0, 1).await?;
+                store.sequence = 1;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Read the latest value for `key` into `value`, returns its length
+    pub async fn get(
+        &mut self,
+        key: u16,
+        value: &mut [u8],
+    ) -> Result<Option<usize>, SettingsError<F::Error>> {
+        for index in (1..self.next).rev() {
+            let (record_key, record) = self.read_record(index).await?;
+            if record_key == key {
+                if let Some(stored) = record {
+                    let stored = stored.as_slice();
+                    let len = stored.len().min(value.len());
+                    value[..len].copy_from_slice(&stored[..len]);
+                    return Ok(Some(stored.len()));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Store `value` for `key`, skipped if it's unchanged
+    ///
+    /// Stalls flash, see [`WriteQueue`] for when to call this.
+    pub async fn set(&mut self, key: u16, value: &[u8]) -> Result<(), SettingsError<F::Error>> {
+        if key == EMPTY_KEY {
+            return Err(SettingsError::InvalidKey);
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(SettingsError::TooLong);
+        }
+        let mut current = [0; MAX_VALUE_LEN];
+        if self.get(key, &mut current).await? == Some(value.len())
+            && current[..value.len()] == *value
+        {
+            return Ok(());
+        }
+        if self.next == RECORDS_PER_SECTOR {
+            self.compact().await?;
+        }
+        if self.next == RECORDS_PER_SECTOR {
+            return Err(SettingsError::TooManyKeys);
+        }
+        let offset = self.record_offset(self.next);
+        self.flash.write(offset, &encode_record(key, value)).await?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Copy the latest value of each key into the next sector
+    async fn compact(&mut self) -> Result<(), SettingsError<F::Error>> {
+        let target = (self.sector + 1) % SECTORS as u32;
+        self.flash.erase(target * SECTOR_SIZE).await?;
+        let mut seen = [EMPTY_KEY; MAX_KEYS];
+        let mut seen_len = 0;
+        let mut next = 1;
+        // newest first, so the first record found for a key is its value
+        for index in (1..self.next).rev() {
+            let (key, record) = self.read_record(index).await?;
+            let Some(value) = record else { continue };
+            if seen[..seen_len].contains(&key) {
+                continue;
+            }
+            if seen_len == MAX_KEYS {
+                return Err(SettingsError::TooManyKeys);
+            }
+            seen[seen_len] = key;
+            seen_len += 1;
+            let offset = target * SECTOR_SIZE + next * RECORD_SIZE;
+            self.flash
+                .write(offset, &encode_record(key, value.as_slice()))
+                .await?;
+            next += 1;
+        }
+        // header last, so an interrupted copy leaves the old sector current
+        self.write_header(target, self.sequence + 1).await?;
+        self.sector = target;
+        self.sequence += 1;
+        self.next = next;
+        Ok(())
+    }
+
+    async fn write_header(&mut self, sector: u32, sequence: u32) -> Result<(), F::Error> {
+        let mut header = [0xff; RECORD_SIZE as usize];
+        header[0..4].copy_from_slice(&HEADER_MARKER.to_le_bytes());
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        let crc = crc32(&header[..12]);
+        header[12..16].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write(sector * SECTOR_SIZE, &header).await
+    }
+
+    fn record_offset(&self, index: u32) -> u32 {
+        self.sector * SECTOR_SIZE + index * RECORD_SIZE
+    }
+
+    /// Key and value of a record, the value is `None` if the record is damaged
+    async fn read_record(&mut self, index: u32) -> Result<(u16, Option<StoredValue>), F::Error> {
+        let mut record = [0; RECORD_SIZE as usize];
+        self.flash
+            .read(self.record_offset(index), &mut record)
+            .await?;
+        Ok(decode_record(&record))
+    }
+}
+
+struct StoredValue {
+    bytes: [u8; MAX_VALUE_LEN],
+    len: usize,
+}
+
+impl StoredValue {
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Sequence number of a valid sector header
+fn parse_header(header: &[u8; RECORD_SIZE as usize]) -> Option<u32> {
+    let word = |offset: usize| {
+        u32::from_le_bytes([
+            header[offset],
+            header[offset + 1],
+            header[offset + 2],
+            header[offset + 3],
+        ])
+    };
+    (word(0) == HEADER_MARKER && word(12) == crc32(&header[..12])).then(|| word(4))
+}
+
+/// Record layout: key (u16), length, unused, value padded with 0xff, CRC
+fn encode_record(key: u16, value: &[u8]) -> [u8; RECORD_SIZE as usize] {
+    let mut record = [0xff; RECORD_SIZE as usize];
+    record[0..2].copy_from_slice(&key.to_le_bytes());
+    record[2] = value.len() as u8;
+    record[4..4 + value.len()].copy_from_slice(value);
+    let crc = crc32(&record[..12]);
+    record[12..16].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+fn decode_record(record: &[u8; RECORD_SIZE as usize]) -> (u16, Option<StoredValue>) {
+    let key = u16::from_le_bytes([record[0], record[1]]);
+    let crc = u32::from_le_bytes([record[12], record[13], record[14], record[15]]);
+    let len = usize::from(record[2]);
+    if crc != crc32(&record[..12]) || len > MAX_VALUE_LEN {
+        return (key, None);
+    }
+    let mut bytes = [0; MAX_VALUE_LEN];
+    bytes.copy_from_slice(&record[4..12]);
+    (key, Some(StoredValue { bytes, len }))
+}
+
+/// Declare a [`SettingsFlash`] over embassy-rp's blocking flash driver
+///
+/// `rp_settings_flash!(pub struct Name, FLASH_SIZE)` declares `Name<'d>`,
+/// built with `Name::new(p.FLASH, SECTORS)`, which keeps settings in the
+/// last `SECTORS` sectors of a `FLASH_SIZE` byte flash. Leave those sectors
+/// out of `FLASH` in the card's `memory.x`. Expands in the card, which needs
+/// an `embassy-rp` dependency of its own.
+///
+/// ```text
+/// wscomp::rp_settings_flash!(struct SettingsSectors, FLASH_SIZE);
+///
+/// let flash = SettingsSectors::new(p.FLASH, SETTINGS_SECTORS);
+/// let mut settings = SettingsStore::<_, SETTINGS_SECTORS>::mount(flash).await?;
+/// ```
+///
+/// Writes and erases stall both cores, see the module docs for scheduling
+/// them around audio.
+#[cfg(feature = "embassy")]
+#[macro_export]
+macro_rules! rp_settings_flash {
+    ($vis:vis struct $name:ident, $flash_size:expr) => {
+        /// embassy-rp flash, offset to the settings sectors at its end
+        $vis struct $name<'d> {
+            flash: ::embassy_rp::flash::Flash<
+                'd,
+                ::embassy_rp::peripherals::FLASH,
+                ::embassy_rp::flash::Blocking,
+                { $flash_size },
+            >,
+            start: u32,
+        }
+
+        impl<'d> $name<'d> {
+            /// The last `sectors` sectors of the flash
+            $vis fn new(flash: ::embassy_rp::peripherals::FLASH, sectors: usize) -> Self {
+                let size = sectors as u32 * $crate::settings::SECTOR_SIZE;
+                $name {
+                    flash: ::embassy_rp::flash::Flash::new_blocking(flash),
+                    start: ($flash_size) as u32 - size,
+                }
+            }
+        }
+
+        impl $crate::settings::SettingsFlash for $name<'_> {
+            type Error = ::embassy_rp::flash::Error;
+
+            async fn read(
+                &mut self,
+                offset: u32,
+                bytes: &mut [u8],
+            ) -> Result<(), ::embassy_rp::flash::Error> {
+                self.flash.blocking_read(self.start + offset, bytes)
+            }
+
+            async fn write(
+                &mut self,
+                offset: u32,
+                bytes: &[u8],
+            ) -> Result<(), ::embassy_rp::flash::Error> {
+                self.flash.blocking_write(self.start + offset, bytes)
+            }
+
+            async fn erase(&mut self, offset: u32) -> Result<(), ::embassy_rp::flash::Error> {
+                let from = self.start + offset;
+                self.flash
+                    .blocking_erase(from, from + $crate::settings::SECTOR_SIZE)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use super::{
+        is_paused, pause_background, resume_background, SettingsError, SettingsFlash,
+        SettingsStore, WriteQueue, SECTOR_SIZE,
+    };
+    use crate::units::{Hertz, Millis};
+
+    /// NOR flash: erase sets bytes to 0xff, writes can only clear bits
+    pub(crate) struct FakeFlash {
+        memory: Vec<u8>,
+        erases: Vec<u32>,
+    }
+
+    impl FakeFlash {
+        pub(crate) fn new(sectors: usize) -> Self {
+            FakeFlash {
+                memory: vec![0xff; sectors * SECTOR_SIZE as usize],
+                erases: Vec::new(),
+            }
+        }
+    }
+
+    impl SettingsFlash for &mut FakeFlash {
+        type Error = Infallible;
+
+        async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Infallible> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Infallible> {
+            let offset = offset as usize;
+            for (memory, byte) in self.memory[offset..].iter_mut().zip(bytes) {
+                *memory &= byte;
+            }
+            Ok(())
+        }
+
+        async fn erase(&mut self, offset: u32) -> Result<(), Infallible> {
+            let offset = offset as usize;
+            self.memory[offset..offset + SECTOR_SIZE as usize].fill(0xff);
+            self.erases.push(offset as u32 / SECTOR_SIZE);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_queue() {
+        // 10ms at 48kHz
+        let mut queue: WriteQueue<u8, 2> = WriteQueue::new(Millis::new(10), Hertz::new(48_000));
+        assert_eq!(queue.samples_needed(), 480);
+        assert_eq!(queue.ready(1024), None);
+
+        assert_eq!(queue.push(1), Ok(()));
+        assert_eq!(queue.push(2), Ok(()));
+        assert_eq!(queue.push(3), Err(3));
+        assert_eq!(queue.len(), 2);
+
+        // not enough audio buffered yet
+        assert_eq!(queue.ready(100), None);
+        assert_eq!(queue.ready(480), Some(1));
+        assert_eq!(queue.push(3), Ok(()));
+        assert_eq!(queue.ready(1024), Some(2));
+        assert_eq!(queue.ready(1024), Some(3));
+        assert!(queue.is_empty());
+
+        pause_background();
+        assert!(is_paused());
+        resume_background();
+        assert!(!is_paused());
+    }
+
+    #[test]
+    fn test_settings_store() {
+        let mut flash = FakeFlash::new(2);
+        block_on(async {
+            let mut store: SettingsStore<_, 2> = SettingsStore::mount(&mut flash).await.unwrap();
+            let mut value = [0; 8];
+            assert_eq!(store.get(1, &mut value).await, Ok(None));
+
+            store.set(1, &[10, 20]).await.unwrap();
+            store.set(2, &42_u32.to_le_bytes()).await.unwrap();
+            store.set(1, &[30]).await.unwrap();
+            assert_eq!(store.get(1, &mut value).await, Ok(Some(1)));
+            assert_eq!(value[0], 30);
+            assert_eq!(store.set(3, &[0; 9]).await, Err(SettingsError::TooLong));
+            assert_eq!(
+                store.set(0xffff, &[0]).await,
+                Err(SettingsError::InvalidKey)
+            );
+        });
+        assert_eq!(flash.erases, [0]);
+
+        // values survive a reboot
+        block_on(async {
+            let mut store: SettingsStore<_, 2> = SettingsStore::mount(&mut flash).await.unwrap();
+            let mut value = [0; 4];
+            assert_eq!(store.get(2, &mut value).await, Ok(Some(4)));
+            assert_eq!(u32::from_le_bytes(value), 42);
+        });
+    }
+
+    #[test]
+    fn test_settings_store_wear_leveling() {
+        let mut flash = FakeFlash::new(2);
+        block_on(async {
+            let mut store: SettingsStore<_, 2> = SettingsStore::mount(&mut flash).await.unwrap();
+            // a sector holds 255 records, so this fills it a few times over
+            for count in 0..1_000_u16 {
+                store.set(count % 3, &count.to_le_bytes()).await.unwrap();
+            }
+        });
+        // erases alternate between the two sectors
+        assert_eq!(flash.erases, [0, 1, 0, 1]);
+
+        // a record torn by power loss is ignored, back to the previous value
+        let last = flash
+            .memory
+            .chunks(16)
+            .rposition(|record| record[0] != 0xff)
+            .unwrap();
+        flash.memory[last * 16 + 12] = 0;
+        block_on(async {
+            let mut store: SettingsStore<_, 2> = SettingsStore::mount(&mut flash).await.unwrap();
+            let mut value = [0; 2];
+            assert_eq!(store.get(0, &mut value).await, Ok(Some(2)));
+            assert_eq!(u16::from_le_bytes(value), 996);
+            assert_eq!(store.get(2, &mut value).await, Ok(Some(2)));
+            assert_eq!(u16::from_le_bytes(value), 998);
+        });
+    }
+}
@@ -0,0 +1,126 @@
+//! Waveshaping: soft clipping and wavefolding
+//!
+//! Summing layers can go past full scale, and a [`Sample`] keeps the over
+//! until it's clamped on output. Clamping flattens the tops of the waveform
+//! abruptly, which sounds harsh. [`soft_clip`] bends them over instead:
+//!
+//! ```
+//! # use wscomp::dac::DacSamplePair;
+//! # use wscomp::shaper::soft_clip;
+//! # use wscomp::Sample;
+//! # let (medium, heavy) = (Sample::from(900_i32), Sample::from(1500_i32));
+//! # let (intensity, out2) = (Sample::from(1200_i32), 2048);
+//! let mixed = medium.scale_inverted(intensity) + heavy.scale(intensity);
+//! let dac_sample = DacSamplePair::new(soft_clip(mixed).to_output(), out2);
+//! ```
+//!
+//! [`wavefold`] goes the other way, amplifying and then reflecting the
+//! waveform back off full scale, for west coast style harmonics.
+
+use crate::Sample;
+
+/// Level where [`soft_clip`] starts bending, half of full scale
+pub const SOFT_CLIP_KNEE: i32 = Sample::OFFSET / 2;
+/// Level where [`soft_clip`] reaches full scale, 1.5x full scale
+const SOFT_CLIP_LIMIT: i32 = 2 * Sample::MAX - SOFT_CLIP_KNEE;
+
+/// Compress levels above [`SOFT_CLIP_KNEE`] smoothly into full scale
+///
+/// Unchanged below the knee. Above it a quadratic curve takes inputs up to
+/// 1.5x full scale into the top half of the range, with no corner at the
+/// knee or at full scale, and anything louder stays at full scale.
+pub fn soft_clip(input: Sample) -> Sample {
+    let value = input.to_unclamped();
+    let magnitude = value.abs().min(SOFT_CLIP_LIMIT);
+    if magnitude <= SOFT_CLIP_KNEE {
+        return Sample::from(value);
+    }
+    // y = x - (x - knee)^2 / (2 (limit - knee)), slope 1 at the knee and 0
+    // at the limit, where y = (knee + limit) / 2 = MAX
+    let over = magnitude - SOFT_CLIP_KNEE;
+    let shaped = magnitude - over * over / (2 * (SOFT_CLIP_LIMIT - SOFT_CLIP_KNEE));
+    Sample::from(if value < 0 { -shaped } else { shaped })
+}
+
+/// Most [`wavefold`] gain, at full depth
+pub const WAVEFOLD_MAX_GAIN: i32 = 8;
+
+/// Amplify by `depth`, then fold anything past full scale back in
+///
+/// `depth` from 0 (or below) to [`Sample::MAX`] sets the gain from 1, no
+/// folding, to [`WAVEFOLD_MAX_GAIN`], where a full scale input folds
+/// several times. Folding reflects at full scale, like light between two
+/// mirrors, so the output is always in range and changes direction instead
+/// of flattening.
+pub fn wavefold(input: Sample, depth: Sample) -> Sample {
+    let depth = depth.to_clamped().max(0);
+    // gain in 1/256ths
+    let gain = 256 + depth * (WAVEFOLD_MAX_GAIN - 1) * 256 / Sample::MAX;
+    let amplified = input.to_clamped() * gain / 256;
+
+    // one fold period is up to the top, back down to the bottom, and back
+    let threshold = Sample::MAX;
+    let position = (amplified + threshold).rem_euclid(4 * threshold);
+    Sample::from(if position < 2 * threshold {
+        position - threshold
+    } else {
+        3 * threshold - position
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{soft_clip, wavefold, SOFT_CLIP_KNEE, WAVEFOLD_MAX_GAIN};
+    use crate::Sample;
+
+    #[test]
+    fn test_soft_clip() {
+        for value in [0, 100, -700, SOFT_CLIP_KNEE, -SOFT_CLIP_KNEE] {
+            assert_eq!(soft_clip(Sample::from(value)), Sample::from(value));
+        }
+        // monotonic, and only reaches full scale at 1.5x
+        let mut last = soft_clip(Sample::from(-4000_i32)).to_clamped();
+        assert_eq!(last, -Sample::MAX);
+        for value in -3999..4000 {
+            let shaped = soft_clip(Sample::from(value)).to_clamped();
+            assert!(shaped >= last && shaped - last <= 1);
+            last = shaped;
+        }
+        assert!(soft_clip(Sample::from(Sample::MAX)).to_clamped() < 1900);
+        assert_eq!(soft_clip(Sample::from(3070_i32)).to_clamped(), Sample::MAX);
+
+        // a sum past full scale is compressed, not clamped
+        let sum = Sample::from(1500_i32) + Sample::from(1000_i32);
+        let shaped = soft_clip(sum).to_clamped();
+        assert!(shaped > 1900 && shaped < Sample::MAX);
+    }
+
+    #[test]
+    fn test_wavefold() {
+        let none = Sample::from(0_i32);
+        for value in [0, 1000, -1000, Sample::MAX, -Sample::MAX] {
+            assert_eq!(wavefold(Sample::from(value), none), Sample::from(value));
+        }
+        // about 2x gain takes 1500 to 2994, folded back to 2 * 2047 - 2994
+        let depth = Sample::from(Sample::MAX / 7);
+        assert_eq!(wavefold(Sample::from(1500_i32), depth).to_clamped(), 1100);
+        assert_eq!(wavefold(Sample::from(-1500_i32), depth).to_clamped(), -1100);
+
+        // at full depth a ramp stays in range and changes in small steps,
+        // no jumps
+        let full = Sample::from(Sample::MAX);
+        let mut last = wavefold(Sample::from(Sample::MIN), full).to_clamped();
+        let mut turns = 0;
+        let mut rising = true;
+        for value in Sample::MIN..=Sample::MAX {
+            let folded = wavefold(Sample::from(value), full).to_clamped();
+            assert!((folded - last).abs() <= WAVEFOLD_MAX_GAIN);
+            if (folded > last) != rising && folded != last {
+                rising = !rising;
+                turns += 1;
+            }
+            last = folded;
+        }
+        assert!(turns >= 6);
+    }
+}
@@ -0,0 +1,336 @@
+//! Alternative smoothing filters for inputs
+//!
+//! [`Sample`] itself smooths with a single first-order IIR, which is fine for
+//! knobs. These filters implement the same [`SampleUpdate`] trait for inputs
+//! that need something else: [`Median`] drops single sample ADC spikes,
+//! [`MovingAverage`] settles on steps in a fixed number of updates, and
+//! [`TwoStageIir`] rolls off noise more steeply. [`Smoother`] picks one at
+//! runtime, so it can be chosen per input, see
+//! [`InputReader::set_smoothing`](crate::inputs::InputReader::set_smoothing).
+//!
+//! Like [`Sample`], each filter inverts raw `u16` ADC updates if the
+//! `initial` value it was built from has an inverted source.
+
+use crate::{Sample, SampleUpdate};
+
+/// Median of the last `N` updates
+///
+/// Odd `N` works best, 3 or 5 is plenty to remove spikes.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Median<const N: usize> {
+    history: [i32; N],
+    index: usize,
+    inverted_source: bool,
+}
+
+impl<const N: usize> Median<N> {
+    pub fn new(initial: Sample) -> Self {
+        Median {
+            history: [initial.to_clamped(); N],
+            index: 0,
+            inverted_source: initial.inverted_source,
+        }
+    }
+
+    pub fn value(&self) -> Sample {
+        let mut sorted = self.history;
+        sorted.sort_unstable();
+        Sample::from(sorted[N / 2])
+    }
+}
+
+impl<const N: usize> SampleUpdate<i32> for Median<N> {
+    /// Update with new value from i32, within -2048..2048
+    fn update(&mut self, value: i32) {
+        self.history[self.index] = value;
+        self.index = (self.index + 1) % N;
+    }
+}
+
+/// Mean of the last `N` updates
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MovingAverage<const N: usize> {
+    history: [i32; N],
+    index: usize,
+    sum: i32,
+    inverted_source: bool,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    pub fn new(initial: Sample) -> Self {
+        let value = initial.to_clamped();
+        MovingAverage {
+            history: [value; N],
+            index: 0,
+            sum: value * N as i32,
+            inverted_source: initial.inverted_source,
+        }
+    }
+
+    pub fn value(&self) -> Sample {
+        Sample::from(self.sum / N as i32)
+    }
+}
+
+impl<const N: usize> SampleUpdate<i32> for MovingAverage<N> {
+    /// Update with new value from i32, within -2048..2048
+    fn update(&mut self, value: i32) {
+        self.sum += value - self.history[self.index];
+        self.history[self.index] = value;
+        self.index = (self.index + 1) % N;
+    }
+}
+
+/// Two of [`Sample`]'s first-order IIR filters in series
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TwoStageIir {
+    first: Sample,
+    second: Sample,
+}
+
+impl TwoStageIir {
+    pub fn new(initial: Sample) -> Self {
+        TwoStageIir {
+            first: initial,
+            second: initial,
+        }
+    }
+
+    pub fn value(&self) -> Sample {
+        self.second
+    }
+}
+
+impl SampleUpdate<i32> for TwoStageIir {
+    /// Update with new value from i32, within -2048..2048
+    fn update(&mut self, value: i32) {
+        self.first.update(value);
+        self.second.update(self.first);
+    }
+}
+
+/// Which [`Smoother`] to use, a setting that can be stored or sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Smoothing {
+    #[default]
+    Iir,
+    Median,
+    MovingAverage,
+    TwoStageIir,
+}
+
+/// Runtime selectable smoothing strategy
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Smoother {
+    /// Same single IIR as [`Sample`], good for knobs
+    Iir(Sample),
+    Median(Median<5>),
+    MovingAverage(MovingAverage<8>),
+    TwoStageIir(TwoStageIir),
+}
+
+impl Smoother {
+    /// New `smoothing` filter, starting from `initial`
+    pub fn new(smoothing: Smoothing, initial: Sample) -> Self {
+        match smoothing {
+            Smoothing::Iir => Smoother::Iir(initial),
+            Smoothing::Median => Smoother::Median(Median::new(initial)),
+            Smoothing::MovingAverage => Smoother::MovingAverage(MovingAverage::new(initial)),
+            Smoothing::TwoStageIir => Smoother::TwoStageIir(TwoStageIir::new(initial)),
+        }
+    }
+
+    pub fn smoothing(&self) -> Smoothing {
+        match self {
+            Smoother::Iir(_) => Smoothing::Iir,
+            Smoother::Median(_) => Smoothing::Median,
+            Smoother::MovingAverage(_) => Smoothing::MovingAverage,
+            Smoother::TwoStageIir(_) => Smoothing::TwoStageIir,
+        }
+    }
+
+    pub fn value(&self) -> Sample {
+        match self {
+            Smoother::Iir(sample) => *sample,
+            Smoother::Median(median) => median.value(),
+            Smoother::MovingAverage(average) => average.value(),
+            Smoother::TwoStageIir(iir) => iir.value(),
+        }
+    }
+}
+
+impl SampleUpdate<i32> for Smoother {
+    fn update(&mut self, value: i32) {
+        match self {
+            Smoother::Iir(sample) => sample.update(value),
+            Smoother::Median(median) => median.update(value),
+            Smoother::MovingAverage(average) => average.update(value),
+            Smoother::TwoStageIir(iir) => iir.update(value),
+        }
+    }
+}
+
+// u16 (from the ADC/mux) and Sample updates, using the i32 implementations
+// for the core logic
+
+/// Offset a raw ADC reading so center is at zero, inverting if needed
+fn from_adc(value: u16, inverted_source: bool) -> i32 {
+    let value = i32::from(value) - Sample::OFFSET;
+    match inverted_source {
+        false => value,
+        true => -value,
+    }
+}
+
+impl<const N: usize> SampleUpdate<u16> for Median<N> {
+    fn update(&mut self, value: u16) {
+        self.update(from_adc(value, self.inverted_source));
+    }
+}
+
+impl<const N: usize> SampleUpdate<Sample> for Median<N> {
+    fn update(&mut self, value: Sample) {
+        self.update(value.to_clamped());
+    }
+}
+
+impl<const N: usize> SampleUpdate<u16> for MovingAverage<N> {
+    fn update(&mut self, value: u16) {
+        self.update(from_adc(value, self.inverted_source));
+    }
+}
+
+impl<const N: usize> SampleUpdate<Sample> for MovingAverage<N> {
+    fn update(&mut self, value: Sample) {
+        self.update(value.to_clamped());
+    }
+}
+
+impl SampleUpdate<u16> for TwoStageIir {
+    fn update(&mut self, value: u16) {
+        // the first stage handles inverted sources, like a lone Sample
+        self.first.update(value);
+        self.second.update(self.first);
+    }
+}
+
+impl SampleUpdate<Sample> for TwoStageIir {
+    fn update(&mut self, value: Sample) {
+        self.update(value.to_clamped());
+    }
+}
+
+impl SampleUpdate<u16> for Smoother {
+    fn update(&mut self, value: u16) {
+        match self {
+            Smoother::Iir(sample) => sample.update(value),
+            Smoother::Median(median) => median.update(value),
+            Smoother::MovingAverage(average) => average.update(value),
+            Smoother::TwoStageIir(iir) => iir.update(value),
+        }
+    }
+}
+
+impl SampleUpdate<Sample> for Smoother {
+    fn update(&mut self, value: Sample) {
+        self.update(value.to_clamped());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Median, MovingAverage, Smoother, Smoothing, TwoStageIir};
+    use crate::{Sample, SampleUpdate};
+
+    #[test]
+    fn test_median_rejects_spikes() {
+        let mut median = Median::<5>::new(Sample::from(100_i32));
+        median.update(2000_i32);
+        assert_eq!(median.value(), Sample::from(100_i32));
+        median.update(-2000_i32);
+        assert_eq!(median.value(), Sample::from(100_i32));
+        // a real step gets through after (N + 1) / 2 updates
+        for _ in 0..3 {
+            median.update(500_i32);
+        }
+        assert_eq!(median.value(), Sample::from(500_i32));
+    }
+
+    #[test]
+    fn test_moving_average_step() {
+        let mut average = MovingAverage::<4>::new(Sample::from(0_i32));
+        average.update(Sample::from(400_i32));
+        assert_eq!(average.value(), Sample::from(100_i32));
+        for _ in 0..3 {
+            average.update(Sample::from(400_i32));
+        }
+        // fully settled after N updates, no overshoot or tail
+        assert_eq!(average.value(), Sample::from(400_i32));
+        average.update(2048_u16 + 400);
+        assert_eq!(average.value(), Sample::from(400_i32));
+    }
+
+    #[test]
+    fn test_two_stage_iir() {
+        let mut iir = TwoStageIir::new(Sample::from(0_i32));
+        let mut single = Sample::from(0_i32);
+        iir.update(1000_i32);
+        single.update(1000_i32);
+        // a spike is attenuated more than by the single stage
+        assert!(iir.value() < single);
+        for _ in 0..200 {
+            iir.update(1000_i32);
+        }
+        assert!((iir.value().to_clamped() - 1000).abs() <= 8);
+    }
+
+    #[test]
+    fn test_smoother_selection() {
+        let mut smoothers = [
+            Smoother::Iir(Sample::from(0_i32)),
+            Smoother::Median(Median::new(Sample::from(0_i32))),
+            Smoother::MovingAverage(MovingAverage::new(Sample::from(0_i32))),
+            Smoother::TwoStageIir(TwoStageIir::new(Sample::from(0_i32))),
+        ];
+        for smoother in smoothers.iter_mut() {
+            for _ in 0..200 {
+                smoother.update(Sample::from(-700_i32));
+            }
+            assert!((smoother.value().to_clamped() + 700).abs() <= 8);
+        }
+    }
+
+    #[test]
+    fn test_smoother_inverted_source() {
+        // CV inputs read inverted, 1000 counts below center is +1000
+        let inverted = Sample::new(Sample::CENTER, true);
+        for smoothing in [
+            Smoothing::Iir,
+            Smoothing::Median,
+            Smoothing::MovingAverage,
+            Smoothing::TwoStageIir,
+        ] {
+            let mut smoother = Smoother::new(smoothing, inverted);
+            assert_eq!(smoother.smoothing(), smoothing);
+            for _ in 0..200 {
+                smoother.update(2048_u16 - 1000);
+            }
+            let value = smoother.value().to_clamped();
+            assert!((value - 1000).abs() <= 8, "{smoothing:?} {value}");
+        }
+
+        let mut median = Median::<3>::new(inverted);
+        for _ in 0..2 {
+            median.update(2048_u16 + 500);
+        }
+        assert_eq!(median.value(), Sample::from(-500_i32));
+        let mut average = MovingAverage::<2>::new(Sample::new(Sample::CENTER, false));
+        average.update(2048_u16 + 500);
+        assert_eq!(average.value(), Sample::from(250_i32));
+    }
+}
@@ -0,0 +1,201 @@
+//! Pluggable audio sources, rendered a [`SampleBlock`] at a time
+//!
+//! [`AudioSource`] is the one interface a mixer needs, whatever is behind
+//! it: a decoded ADPCM file, raw PCM, an oscillator or nothing at all. It's
+//! object safe, so a card can keep a sound set as an array of trait objects
+//! and swap or add layers without touching the mixer:
+//!
+//! ```
+//! # use wscomp::block::SampleBlock;
+//! # use wscomp::osc::{OscShape, Oscillator};
+//! # use wscomp::noise::PinkNoise;
+//! # use wscomp::source::{AudioSource, Silence};
+//! # use wscomp::units::Hertz;
+//! # struct Rain(PinkNoise);
+//! # impl AudioSource<64> for Rain {
+//! #     fn next_block(&mut self, block: &mut SampleBlock<64>) {
+//! #         block.fill_from(&mut self.0);
+//! #     }
+//! # }
+//! # let mut rain = Rain(PinkNoise::new(1));
+//! # let mut drone = Oscillator::new(OscShape::Triangle, Hertz::new(55), Hertz::new(48_000));
+//! let mut layers: [&mut dyn AudioSource<64>; 3] = [&mut rain, &mut drone, &mut Silence];
+//! let mut block = SampleBlock::<64>::silent();
+//! let mut mixed = SampleBlock::<64>::silent();
+//! for layer in layers.iter_mut() {
+//!     layer.next_block(&mut block);
+//!     mixed.mix(&block);
+//! }
+//! ```
+//!
+//! Finite sources fill with 0v once they run out, rather than stopping, so
+//! a mixer never has to special case a layer that ended.
+
+use crate::adpcm::AdpcmStream;
+use crate::block::SampleBlock;
+use crate::osc::Oscillator;
+use crate::resample::Resampler;
+use crate::wav::{Wav, WavCodec};
+use crate::wavetable::WavetableOsc;
+use crate::Sample;
+
+/// Anything that can render blocks of `N` samples
+pub trait AudioSource<const N: usize> {
+    /// Overwrite `block` with the next `N` samples
+    fn next_block(&mut self, block: &mut SampleBlock<N>);
+}
+
+/// Fill `block` from 16 bit samples, 0v after they run out
+fn fill_pcm16<const N: usize>(block: &mut SampleBlock<N>, samples: impl Iterator<Item = i16>) {
+    let mut samples = samples.map(Sample::from_pcm16);
+    block.fill_from(&mut samples);
+}
+
+/// Always 0v, for an empty slot in a sound set
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Silence;
+
+impl<const N: usize> AudioSource<N> for Silence {
+    fn next_block(&mut self, block: &mut SampleBlock<N>) {
+        block.fill(Sample::from(0_i32));
+    }
+}
+
+/// Mono 16 bit little endian PCM, straight from a byte slice
+///
+/// Works directly on `include_bytes!` data, which has no alignment to read
+/// it as `&[i16]`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PcmSource<'a> {
+    data: &'a [u8],
+    /// Next sample to read
+    position: usize,
+    looping: bool,
+}
+
+impl<'a> PcmSource<'a> {
+    /// Source over `data`, an odd trailing byte is ignored
+    pub fn new(data: &'a [u8]) -> Self {
+        PcmSource {
+            data,
+            position: 0,
+            looping: false,
+        }
+    }
+
+    /// Source over the data of a mono 16 bit PCM [`Wav`], `None` for any
+    /// other format
+    pub fn from_wav(wav: &Wav<'a>) -> Option<Self> {
+        let format = wav.format;
+        if format.codec != WavCodec::Pcm || format.channels != 1 || format.bits_per_sample != 16 {
+            return None;
+        }
+        Some(Self::new(wav.data))
+    }
+
+    /// Go back to the start after the last sample, rather than ending
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Total samples
+    pub fn len(&self) -> usize {
+        self.data.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Next sample, `None` at the end unless looping
+    pub fn next_sample(&mut self) -> Option<i16> {
+        if self.position >= self.len() {
+            if !self.looping || self.is_empty() {
+                return None;
+            }
+            self.position = 0;
+        }
+        let offset = self.position * 2;
+        self.position += 1;
+        Some(i16::from_le_bytes([
+            self.data[offset],
+            self.data[offset + 1],
+        ]))
+    }
+}
+
+impl<const N: usize> AudioSource<N> for PcmSource<'_> {
+    fn next_block(&mut self, block: &mut SampleBlock<N>) {
+        fill_pcm16(block, core::iter::from_fn(|| self.next_sample()));
+    }
+}
+
+impl<const N: usize> AudioSource<N> for AdpcmStream<'_> {
+    fn next_block(&mut self, block: &mut SampleBlock<N>) {
+        fill_pcm16(block, self);
+    }
+}
+
+/// Any 16 bit stream after rate conversion, usually an [`AdpcmStream`]
+impl<I: Iterator<Item = i16>, const N: usize> AudioSource<N> for Resampler<I> {
+    fn next_block(&mut self, block: &mut SampleBlock<N>) {
+        fill_pcm16(block, self);
+    }
+}
+
+impl<const N: usize> AudioSource<N> for Oscillator {
+    fn next_block(&mut self, block: &mut SampleBlock<N>) {
+        block.map(|_| self.tick());
+    }
+}
+
+impl<const N: usize> AudioSource<N> for WavetableOsc<'_> {
+    fn next_block(&mut self, block: &mut SampleBlock<N>) {
+        block.map(|_| self.tick());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AudioSource, PcmSource, Silence};
+    use crate::block::SampleBlock;
+    use crate::osc::{OscShape, Oscillator};
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    #[test]
+    fn test_audio_sources() {
+        let bytes: Vec<u8> = [1600_i16, -1600, 3200]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        let mut pcm = PcmSource::new(&bytes);
+        assert_eq!(pcm.len(), 3);
+        let mut block = SampleBlock::<4>::filled(Sample::from(7_i32));
+        pcm.next_block(&mut block);
+        assert_eq!(
+            block.into_array(),
+            [100_i32, -100, 200, 0].map(Sample::from)
+        );
+        pcm.set_looping(true);
+        pcm.next_block(&mut block);
+        assert_eq!(
+            block.into_array(),
+            [100_i32, -100, 200, 100].map(Sample::from)
+        );
+
+        let mut osc = Oscillator::new(OscShape::Square, Hertz::new(480), Hertz::new(48_000));
+        let mut layers: [&mut dyn AudioSource<4>; 3] = [&mut pcm, &mut osc, &mut Silence];
+        let mut mixed = SampleBlock::<4>::silent();
+        for layer in layers.iter_mut() {
+            layer.next_block(&mut block);
+            mixed.mix(&block);
+        }
+        // the square is at full level a few samples into its cycle
+        assert_eq!(mixed[2], Sample::from(100_i32) + Sample::from(Sample::MAX));
+        Silence.next_block(&mut block);
+        assert_eq!(block, SampleBlock::silent());
+    }
+}
@@ -0,0 +1,227 @@
+//! The three position Z switch, and press/hold/double tap events from it
+
+use crate::accessibility::Accessibility;
+use crate::units::Millis;
+
+/// The state of the three position Z switch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ZSwitch {
+    On,
+    #[default]
+    Off,
+    Momentary,
+}
+
+impl ZSwitch {
+    /// State from the switch's 12 bit ADC reading (via the mux)
+    pub fn from_adc(level: u16) -> Self {
+        match level {
+            level if level < 1000 => ZSwitch::Momentary,
+            level if level > 3000 => ZSwitch::On,
+            _ => ZSwitch::Off,
+        }
+    }
+}
+
+/// Something the user did with the Z switch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SwitchEvent {
+    /// Moved between On and Off, with the new state
+    Changed(ZSwitch),
+    /// Momentary press released before a long hold
+    Press,
+    /// Second short press soon after a first, instead of [`SwitchEvent::Press`]
+    DoubleTap,
+    /// Momentary held down, sent once while still held
+    LongHold,
+}
+
+/// Tracks the Z switch over time and reports [`SwitchEvent`]s
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ZSwitchReader {
+    state: ZSwitch,
+    pressed_at: Option<u64>,
+    long_hold_sent: bool,
+    last_press: Option<u64>,
+    accessibility: Accessibility,
+}
+
+impl ZSwitchReader {
+    /// How long the momentary position is held for a [`SwitchEvent::LongHold`]
+    pub const LONG_HOLD: Millis = Millis::new(800);
+    /// Max time from releasing one press to starting a second, for a
+    /// [`SwitchEvent::DoubleTap`]
+    pub const DOUBLE_TAP: Millis = Millis::new(300);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// New reader, with longer hold and double tap windows for [`Accessibility::slow_ui`]
+    pub fn with_accessibility(accessibility: Accessibility) -> Self {
+        ZSwitchReader {
+            accessibility,
+            ..Self::default()
+        }
+    }
+
+    /// Apply an accessibility profile, takes effect on the next update
+    pub fn set_accessibility(&mut self, accessibility: Accessibility) {
+        self.accessibility = accessibility;
+    }
+
+    pub fn state(&self) -> ZSwitch {
+        self.state
+    }
+
+    /// Update from an ADC reading, see [`ZSwitchReader::update`]
+    pub fn update_adc(&mut self, level: u16, now_micros: u64) -> Option<SwitchEvent> {
+        self.update(ZSwitch::from_adc(level), now_micros)
+    }
+
+    /// Update with the current switch state, call regularly
+    pub fn update(&mut self, state: ZSwitch, now_micros: u64) -> Option<SwitchEvent> {
+        let previous = core::mem::replace(&mut self.state, state);
+        if state == previous {
+            return self.check_long_hold(now_micros);
+        }
+        if state == ZSwitch::Momentary {
+            self.pressed_at = Some(now_micros);
+            self.long_hold_sent = false;
+            return None;
+        }
+        if previous != ZSwitch::Momentary {
+            return Some(SwitchEvent::Changed(state));
+        }
+
+        // released
+        let pressed_at = self.pressed_at.take()?;
+        if self.long_hold_sent {
+            self.last_press = None;
+            return None;
+        }
+        let double_tap = self.last_press.is_some_and(|last| {
+            pressed_at.saturating_sub(last) <= micros(self.accessibility.timing(Self::DOUBLE_TAP))
+        });
+        if double_tap {
+            self.last_press = None;
+            Some(SwitchEvent::DoubleTap)
+        } else {
+            self.last_press = Some(now_micros);
+            Some(SwitchEvent::Press)
+        }
+    }
+
+    fn check_long_hold(&mut self, now_micros: u64) -> Option<SwitchEvent> {
+        let pressed_at = self.pressed_at?;
+        if !self.long_hold_sent
+            && now_micros.saturating_sub(pressed_at)
+                >= micros(self.accessibility.timing(Self::LONG_HOLD))
+        {
+            self.long_hold_sent = true;
+            return Some(SwitchEvent::LongHold);
+        }
+        None
+    }
+}
+
+fn micros(duration: Millis) -> u64 {
+    u64::from(duration.millis()) * 1000
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SwitchEvent, ZSwitch, ZSwitchReader};
+    use crate::accessibility::Accessibility;
+
+    const MS: u64 = 1000;
+
+    #[test]
+    fn test_zswitch_from_adc() {
+        assert_eq!(ZSwitch::from_adc(0), ZSwitch::Momentary);
+        assert_eq!(ZSwitch::from_adc(2048), ZSwitch::Off);
+        assert_eq!(ZSwitch::from_adc(4095), ZSwitch::On);
+    }
+
+    #[test]
+    fn test_switch_changes_and_presses() {
+        let mut reader = ZSwitchReader::new();
+        assert_eq!(reader.update(ZSwitch::Off, 0), None);
+        assert_eq!(
+            reader.update(ZSwitch::On, 10 * MS),
+            Some(SwitchEvent::Changed(ZSwitch::On))
+        );
+        assert_eq!(
+            reader.update(ZSwitch::Off, 20 * MS),
+            Some(SwitchEvent::Changed(ZSwitch::Off))
+        );
+
+        assert_eq!(reader.update(ZSwitch::Momentary, 1000 * MS), None);
+        assert_eq!(reader.state(), ZSwitch::Momentary);
+        assert_eq!(
+            reader.update(ZSwitch::Off, 1100 * MS),
+            Some(SwitchEvent::Press)
+        );
+        // a second press soon after is a double tap
+        assert_eq!(reader.update(ZSwitch::Momentary, 1300 * MS), None);
+        assert_eq!(
+            reader.update(ZSwitch::Off, 1350 * MS),
+            Some(SwitchEvent::DoubleTap)
+        );
+        // and a third starts over
+        assert_eq!(reader.update(ZSwitch::Momentary, 1500 * MS), None);
+        assert_eq!(
+            reader.update(ZSwitch::Off, 1550 * MS),
+            Some(SwitchEvent::Press)
+        );
+        // presses further apart are separate presses
+        reader.update(ZSwitch::Momentary, 3000 * MS);
+        assert_eq!(
+            reader.update(ZSwitch::Off, 3050 * MS),
+            Some(SwitchEvent::Press)
+        );
+    }
+
+    #[test]
+    fn test_switch_long_hold() {
+        let mut reader = ZSwitchReader::new();
+        reader.update(ZSwitch::Momentary, 0);
+        assert_eq!(reader.update(ZSwitch::Momentary, 500 * MS), None);
+        assert_eq!(
+            reader.update(ZSwitch::Momentary, 800 * MS),
+            Some(SwitchEvent::LongHold)
+        );
+        // only once
+        assert_eq!(reader.update(ZSwitch::Momentary, 900 * MS), None);
+
+        // twice as long with slow UI timings
+        let mut slow = ZSwitchReader::with_accessibility(Accessibility {
+            high_contrast: false,
+            slow_ui: true,
+        });
+        slow.update(ZSwitch::Momentary, 0);
+        assert_eq!(slow.update(ZSwitch::Momentary, 1000 * MS), None);
+        assert_eq!(
+            slow.update(ZSwitch::Momentary, 1600 * MS),
+            Some(SwitchEvent::LongHold)
+        );
+        let mut later = ZSwitchReader::new();
+        later.set_accessibility(Accessibility {
+            high_contrast: false,
+            slow_ui: true,
+        });
+        later.update(ZSwitch::Momentary, 0);
+        assert_eq!(later.update(ZSwitch::Momentary, 1000 * MS), None);
+
+        // and releasing isn't a press
+        assert_eq!(reader.update(ZSwitch::Off, 1000 * MS), None);
+        reader.update(ZSwitch::Momentary, 1100 * MS);
+        assert_eq!(
+            reader.update(ZSwitch::Off, 1150 * MS),
+            Some(SwitchEvent::Press)
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! Shared lookup tables, generated at compile time
+//!
+//! Sine, exponential (with its inverse), equal power and Hann window curves,
+//! each 256 steps plus a guard entry, computed by `const fn`s so there's no build script and no pasted
+//! numbers to drift out of sync. Each has an interpolated lookup, which is
+//! what the rest of the crate uses:
+//!
+//! ```
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::tables;
+//! # let (phase, fraction, grain_position) = (1 << 30, 1 << 15, 1 << 15);
+//! # let mux_state = MuxState::default();
+//! let level = tables::sine(phase);
+//! let ratio = tables::exp2(fraction);
+//! let fraction = tables::log2(ratio);
+//! let (fade_out, fade_in) = tables::equal_power(mux_state.main_knob);
+//! let gain = tables::hann(grain_position);
+//! ```
+
+use crate::interp::Interpolator;
+use crate::Sample;
+
+/// Steps in each table, the tables have one more entry for the end point
+const STEPS: usize = 256;
+/// 1.0 in the Q16 positions and values below
+const ONE: u32 = 1 << 16;
+
+/// Taylor series for sine, accurate to well under a table step from 0 to
+/// pi/2
+const fn taylor_sin(x: f64) -> f64 {
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 1;
+    while n < 12 {
+        term = -term * x * x / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+/// Taylor series for e^x, for small `x`
+const fn taylor_exp(x: f64) -> f64 {
+    if x < 0.0 {
+        return 1.0 / taylor_exp(-x);
+    }
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    let mut n = 1;
+    while n < 40 {
+        term = term * x / n as f64;
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+/// How quickly [`exp_curve`] settles, like an RC circuit charging for 4
+/// time constants
+const EXP_CURVE_RATE: f64 = 4.0;
+
+/// Curves the tables are generated from, for x from 0 to 1
+#[derive(Clone, Copy)]
+enum Curve {
+    QuarterSine,
+    Exp2,
+    Exponential,
+    HalfHann,
+}
+
+impl Curve {
+    const fn at(self, x: f64) -> f64 {
+        match self {
+            Curve::QuarterSine => taylor_sin(x * core::f64::consts::FRAC_PI_2),
+            Curve::Exp2 => taylor_exp(x * core::f64::consts::LN_2),
+            Curve::Exponential => {
+                (1.0 - taylor_exp(-EXP_CURVE_RATE * x)) / (1.0 - taylor_exp(-EXP_CURVE_RATE))
+            }
+            Curve::HalfHann => {
+                let sin = taylor_sin(x * core::f64::consts::FRAC_PI_2);
+                sin * sin
+            }
+        }
+    }
+
+    /// Table of the curve at i / 256 for i in 0..=256, scaled by `scale` and
+    /// rounded
+    const fn table(self, scale: f64) -> [u32; STEPS + 1] {
+        let mut table = [0; STEPS + 1];
+        let mut index = 0;
+        while index <= STEPS {
+            table[index] = (self.at(index as f64 / STEPS as f64) * scale + 0.5) as u32;
+            index += 1;
+        }
+        table
+    }
+}
+
+/// `sin(x * pi/2)` for x from 0 to 1, scaled to 65535
+static QUARTER_SINE: [u32; STEPS + 1] = Curve::QuarterSine.table(65_535.0);
+/// `2^x` for x from 0 to 1, Q16
+static EXP2: [u32; STEPS + 1] = Curve::Exp2.table(65_536.0);
+/// `(1 - e^(-4x)) / (1 - e^-4)` for x from 0 to 1, scaled to 65535
+static EXP_CURVE: [u32; STEPS + 1] = Curve::Exponential.table(65_535.0);
+/// `sin^2(x * pi/2)`, the rising half of a Hann window, for x from 0 to 1,
+/// scaled to 65535
+static HALF_HANN: [u32; STEPS + 1] = Curve::HalfHann.table(65_535.0);
+
+/// Linear interpolation at a Q16 position from 0 to 1 inclusive
+fn lookup(table: &[u32; STEPS + 1], position: u32) -> u32 {
+    let position = position.min(ONE);
+    let index = (position >> 8) as usize;
+    if index == STEPS {
+        return table[STEPS];
+    }
+    let fraction = position & 0xff;
+    let low = table[index];
+    let high = table[index + 1];
+    // tables are all rising, so high >= low
+    low + ((high - low) * fraction + 0x80) / 0x100
+}
+
+/// [`lookup`], with the blend on an [`Interpolator`]
+fn lookup_with(interp: &mut impl Interpolator, table: &[u32; STEPS + 1], position: u32) -> u32 {
+    let position = position.min(ONE);
+    let index = (position >> 8) as usize;
+    if index == STEPS {
+        return table[STEPS];
+    }
+    let (low, high) = (table[index] as i32, table[index + 1] as i32);
+    interp.blend_rounded(low, high, position as u8) as u32
+}
+
+/// Sine of a 32 bit phase, -[`Sample::MAX`] to [`Sample::MAX`], starting
+/// at 0 heading up
+pub fn sine(phase: u32) -> Sample {
+    let quadrant = phase >> 30;
+    let within = (phase >> 14) & 0xffff;
+    // second and fourth quarters run back down the table
+    let position = if quadrant & 1 == 0 {
+        within
+    } else {
+        ONE - within
+    };
+    let value = lookup(&QUARTER_SINE, position) as i32;
+    let value = (value * Sample::MAX + 32_767) / 65_535;
+    Sample::from(if quadrant >= 2 { -value } else { value })
+}
+
+/// `2^x` for a Q16 fraction `x` from 0 to 1, Q16 (65536 to 131072)
+///
+/// Exponential pitch within an octave, shift the result for whole octaves.
+pub fn exp2(fraction: u32) -> u32 {
+    lookup(&EXP2, fraction)
+}
+
+/// `log2(x)` for a Q16 `x` from 1 to 2, Q16 (0 to 65536), the inverse of
+/// [`exp2`]
+pub fn log2(value: u32) -> u32 {
+    let value = value.clamp(ONE, 2 * ONE);
+    // last entry at or below value
+    let index = EXP2.partition_point(|&entry| entry <= value) - 1;
+    if index == STEPS {
+        return ONE;
+    }
+    let low = EXP2[index];
+    let high = EXP2[index + 1];
+    ((index as u32) << 8) + ((value - low) * 0x100 + (high - low) / 2) / (high - low)
+}
+
+/// Rising exponential curve from 0 to 65535, for a Q16 position from 0 to
+/// 1
+///
+/// Fast at the start and settling into the end, like an RC circuit
+/// charging, so envelope segments sound natural.
+pub fn exp_curve(position: u32) -> u32 {
+    lookup(&EXP_CURVE, position)
+}
+
+/// Hann window, 0 to 65535, for a Q16 position from 0 to 1 across the
+/// window
+///
+/// A raised cosine, 0 at both ends and 65535 in the middle with no corners,
+/// so grains fade in and out without clicks. Overlapping windows spaced
+/// half a window apart sum to a constant.
+pub fn hann(position: u32) -> u32 {
+    let position = position.min(ONE);
+    // symmetric, the falling half runs back down the table
+    let half = if position <= ONE / 2 {
+        position
+    } else {
+        ONE - position
+    };
+    lookup(&HALF_HANN, half * 2)
+}
+
+/// Triangular window, 0 to 65535, for a Q16 position from 0 to 1 across
+/// the window
+///
+/// Straight lines need no table. Cheaper than [`hann`] but with corners at
+/// the ends and the peak, which can be heard on short grains.
+pub fn triangle(position: u32) -> u32 {
+    let position = position.min(ONE);
+    let half = if position <= ONE / 2 {
+        position
+    } else {
+        ONE - position
+    };
+    (half * 2).min(65_535)
+}
+
+/// Equal power crossfade gains, `(fade_out, fade_in)`
+///
+/// [`Sample::MIN`] is all fade out and [`Sample::MAX`] all fade in. The
+/// gains follow a quarter cosine and sine, so the summed power stays
+/// constant and uncorrelated sources don't dip in the middle the way a
+/// linear crossfade does. Apply them with [`Sample::scale`].
+pub fn equal_power(position: Sample) -> (Sample, Sample) {
+    let position = crossfade_position(position);
+    let gain = |position| power_gain(lookup(&QUARTER_SINE, position));
+    (gain(ONE - position), gain(position))
+}
+
+/// [`equal_power`], blending between table entries on an [`Interpolator`]
+///
+/// The same gains, see [`interp`](crate::interp).
+pub fn equal_power_with(interp: &mut impl Interpolator, position: Sample) -> (Sample, Sample) {
+    let position = crossfade_position(position);
+    let mut gain = |position| power_gain(lookup_with(interp, &QUARTER_SINE, position));
+    (gain(ONE - position), gain(position))
+}
+
+/// Crossfade position, Q16 from 0 at [`Sample::MIN`] to 1 at [`Sample::MAX`]
+fn crossfade_position(position: Sample) -> u32 {
+    let position = (position.to_clamped() - Sample::MIN) as u32;
+    position * ONE / (Sample::MAX - Sample::MIN) as u32
+}
+
+/// 0 to 65535 table value as a gain from 0 to [`Sample::MAX`]
+fn power_gain(value: u32) -> Sample {
+    Sample::from((value as i32 * Sample::MAX + 32_767) / 65_535)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        equal_power, equal_power_with, exp2, exp_curve, hann, log2, sine, triangle, EXP2,
+        EXP_CURVE, HALF_HANN, ONE, QUARTER_SINE,
+    };
+    use crate::interp::SoftInterp;
+    use crate::Sample;
+
+    #[test]
+    fn test_tables() {
+        for (index, &value) in QUARTER_SINE.iter().enumerate() {
+            let x = index as f64 / 256.0;
+            let expected = (x * core::f64::consts::FRAC_PI_2).sin() * 65_535.0;
+            assert_eq!(value, expected.round() as u32);
+            let expected = 2_f64.powf(x) * 65_536.0;
+            assert_eq!(EXP2[index], expected.round() as u32);
+            let expected = (1.0 - (-4.0 * x).exp()) / (1.0 - (-4_f64).exp()) * 65_535.0;
+            assert_eq!(EXP_CURVE[index], expected.round() as u32);
+            let expected = (x * core::f64::consts::FRAC_PI_2).sin().powi(2) * 65_535.0;
+            assert_eq!(HALF_HANN[index], expected.round() as u32);
+        }
+    }
+
+    #[test]
+    fn test_lookups() {
+        assert_eq!(sine(0), Sample::from(0_i32));
+        assert_eq!(sine(1 << 30).to_clamped(), Sample::MAX);
+        assert_eq!(sine(3 << 30).to_clamped(), -Sample::MAX);
+        for step in 0..1024_u32 {
+            let phase = step << 22;
+            let expected = (phase as f64 / 2f64.powi(32) * core::f64::consts::TAU).sin();
+            let error = sine(phase).to_clamped() - (expected * 2047.0).round() as i32;
+            assert!(error.abs() <= 1, "{step} {error}");
+        }
+
+        assert_eq!(exp2(0), ONE);
+        assert_eq!(exp2(ONE), 2 * ONE);
+        // a fifth, 7 semitones
+        assert!(exp2(7 * ONE / 12).abs_diff(98_193) <= 1);
+        assert_eq!(log2(ONE), 0);
+        assert_eq!(log2(2 * ONE), ONE);
+        for fraction in (0..ONE).step_by(97) {
+            assert!(log2(exp2(fraction)).abs_diff(fraction) <= 2, "{fraction}");
+        }
+        assert_eq!(exp_curve(0), 0);
+        assert_eq!(exp_curve(ONE * 2), 65_535);
+        assert!(exp_curve(ONE / 4) > 65_535 * 6 / 10);
+
+        assert_eq!(hann(0), 0);
+        assert_eq!(hann(ONE / 2), 65_535);
+        assert_eq!(hann(ONE), 0);
+        assert_eq!(hann(ONE / 4), hann(3 * ONE / 4));
+        // half overlapped windows sum flat
+        for position in (0..ONE / 2).step_by(101) {
+            let sum = hann(position) + hann(position + ONE / 2);
+            assert!(sum.abs_diff(65_535) <= 2, "{position}");
+        }
+        assert_eq!(triangle(0), 0);
+        assert_eq!(triangle(ONE / 4), ONE / 2);
+        assert_eq!(triangle(ONE / 2), 65_535);
+        assert_eq!(triangle(ONE), 0);
+
+        let (out, into) = equal_power(Sample::from(Sample::MIN));
+        assert_eq!((out.to_clamped(), into.to_clamped()), (Sample::MAX, 0));
+        let (out, into) = equal_power(Sample::from(Sample::MAX));
+        assert_eq!((out.to_clamped(), into.to_clamped()), (0, Sample::MAX));
+        // -3dB each in the middle, not -6dB
+        let (out, into) = equal_power(Sample::from(0_i32));
+        assert!((1445..=1450).contains(&out.to_clamped()));
+        assert!((1445..=1450).contains(&into.to_clamped()));
+    }
+
+    #[test]
+    fn test_equal_power_with_interp() {
+        for position in Sample::MIN..=Sample::MAX {
+            let position = Sample::from(position);
+            let (out, into) = equal_power_with(&mut SoftInterp, position);
+            let expected = equal_power(position);
+            assert_eq!(
+                (out.to_clamped(), into.to_clamped()),
+                (expected.0.to_clamped(), expected.1.to_clamped())
+            );
+        }
+    }
+}
@@ -0,0 +1,650 @@
+//! Tempo from taps and external clocks
+//!
+//! [`TapTempo`] averages the time between the last few taps of the Z
+//! switch or a pulse input, so one early or late press nudges the tempo
+//! rather than jumping it:
+//!
+//! ```
+//! # use embedded_hal::digital::InputPin;
+//! # use wscomp::lfo::Lfo;
+//! # use wscomp::pulse::{Edge, PulseInput};
+//! # use wscomp::tempo::TapTempo;
+//! # use wscomp::units::Hertz;
+//! # struct Instant(u64);
+//! # impl Instant {
+//! #     fn now() -> Self {
+//! #         Instant(0)
+//! #     }
+//! #     fn as_micros(&self) -> u64 {
+//! #         self.0
+//! #     }
+//! # }
+//! # fn card<I: InputPin>(mut pulse_in: PulseInput<I>, mut lfo: Lfo) {
+//! let mut tap = TapTempo::new();
+//! loop {
+//!     let now_micros = Instant::now().as_micros();
+//!     if pulse_in.poll(now_micros) == Some(Edge::Rising) {
+//!         if let Some(period) = tap.tap(now_micros) {
+//!             lfo.set_frequency(Hertz::from_period(period));
+//!         }
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! A pause longer than [`TapTempo::TIMEOUT_MICROS`] starts a new set of
+//! taps, keeping the old tempo until there are two. A tap far off the
+//! average is taken as a new tempo rather than averaged in.
+//!
+//! [`ClockFollower`] locks a free running phase to a clock input, pulling
+//! towards each pulse rather than jumping to it, so jitter is smoothed out
+//! and the phase keeps going through missing pulses:
+//!
+//! ```
+//! # use embedded_hal::digital::InputPin;
+//! # use embedded_hal::pwm::SetDutyCycle;
+//! # use wscomp::cv::CvOut;
+//! # use wscomp::euclid::Euclid;
+//! # use wscomp::pulse::{Edge, PulseInput};
+//! # use wscomp::tempo::ClockFollower;
+//! # struct Instant(u64);
+//! # impl Instant {
+//! #     fn now() -> Self {
+//! #         Instant(0)
+//! #     }
+//! #     fn as_micros(&self) -> u64 {
+//! #         self.0
+//! #     }
+//! # }
+//! # fn card<I: InputPin, P: SetDutyCycle>(
+//! #     mut clock_in: PulseInput<I>,
+//! #     mut cv_out: CvOut<P>,
+//! #     mut sequence: Euclid,
+//! # ) -> Result<(), P::Error> {
+//! let mut follower = ClockFollower::new();
+//! loop {
+//!     let now_micros = Instant::now().as_micros();
+//!     if clock_in.poll(now_micros) == Some(Edge::Rising) {
+//!         follower.pulse(now_micros);
+//!     }
+//!     if follower.update(now_micros) {
+//!         sequence.tick();
+//!     }
+//!     cv_out.set(follower.phase())?;
+//! }
+//! # }
+//! ```
+//!
+//! [`SwingClock`] splits a beat into subdivisions and delays every second
+//! one for swing. Like [`PulseOut`](crate::pulse::PulseOut) it's timed from
+//! timestamps, so a task can sleep until the next tick:
+//!
+//! ```
+//! # use embedded_hal::digital::OutputPin;
+//! # use wscomp::pulse::PulseOut;
+//! # use wscomp::tempo::SwingClock;
+//! # use wscomp::units::Millis;
+//! # struct Instant(u64);
+//! # impl Instant {
+//! #     fn now() -> Self {
+//! #         Instant(0)
+//! #     }
+//! #     fn from_micros(micros: u64) -> Self {
+//! #         Instant(micros)
+//! #     }
+//! #     fn as_micros(&self) -> u64 {
+//! #         self.0
+//! #     }
+//! # }
+//! # struct Timer;
+//! # impl Timer {
+//! #     async fn at(_instant: Instant) {}
+//! # }
+//! # async fn card<O: OutputPin>(mut pulse_out: PulseOut<O>) {
+//! let mut swing = SwingClock::new(Millis::new(500), 4);
+//! swing.set_swing(62);
+//! swing.start(Instant::now().as_micros());
+//! loop {
+//!     Timer::at(Instant::from_micros(swing.next_tick_micros())).await;
+//!     if let Some(step) = swing.update(Instant::now().as_micros()) {
+//!         pulse_out.trigger(Millis::new(10), Instant::now().as_micros());
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::units::{Hertz, Millis};
+use crate::Sample;
+
+/// Intervals averaged
+const INTERVALS: usize = 4;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TapTempo {
+    last_tap: Option<u64>,
+    intervals: [u32; INTERVALS],
+    next: usize,
+    /// Intervals recorded since the tempo last changed, up to INTERVALS
+    count: usize,
+    period_micros: Option<u32>,
+}
+
+impl TapTempo {
+    /// Taps further apart than this (30 BPM) start over
+    pub const TIMEOUT_MICROS: u32 = 2_000_000;
+    /// Taps closer than this (600 BPM) are ignored as bounces
+    pub const MIN_INTERVAL_MICROS: u32 = 100_000;
+
+    pub fn new() -> Self {
+        TapTempo {
+            last_tap: None,
+            intervals: [0; INTERVALS],
+            next: 0,
+            count: 0,
+            period_micros: None,
+        }
+    }
+
+    /// Record a tap, returns the new period once there are two taps in a
+    /// row
+    pub fn tap(&mut self, now_micros: u64) -> Option<Millis> {
+        let Some(last_tap) = self.last_tap else {
+            self.last_tap = Some(now_micros);
+            return None;
+        };
+        let interval = now_micros.saturating_sub(last_tap);
+        if interval < u64::from(Self::MIN_INTERVAL_MICROS) {
+            return None;
+        }
+        self.last_tap = Some(now_micros);
+        if interval > u64::from(Self::TIMEOUT_MICROS) {
+            self.count = 0;
+            return None;
+        }
+        let interval = interval as u32;
+
+        // more than half off is a new tempo, not a sloppy tap
+        if let Some(period) = self.period_micros.filter(|_| self.count > 0) {
+            if interval.abs_diff(period) > period / 2 {
+                self.count = 0;
+            }
+        }
+        self.intervals[self.next] = interval;
+        self.next = (self.next + 1) % INTERVALS;
+        self.count = (self.count + 1).min(INTERVALS);
+
+        let recent = (0..self.count)
+            .map(|back| self.intervals[(self.next + INTERVALS - 1 - back) % INTERVALS]);
+        let total: u32 = recent.sum();
+        let period = (total + self.count as u32 / 2) / self.count as u32;
+        self.period_micros = Some(period);
+        self.period()
+    }
+
+    /// Averaged time between taps
+    pub fn period_micros(&self) -> Option<u32> {
+        self.period_micros
+    }
+
+    /// Averaged time between taps, rounded to whole milliseconds
+    pub fn period(&self) -> Option<Millis> {
+        self.period_micros
+            .map(|micros| Millis::new((micros + 500) / 1_000))
+    }
+
+    /// Tempo in beats per minute, rounded, with a tap on every beat
+    pub fn bpm(&self) -> Option<u32> {
+        self.period_micros
+            .map(|micros| (60_000_000 + micros / 2) / micros)
+    }
+
+    /// Tap rate, e.g. for an LFO or clock
+    pub fn frequency(&self) -> Option<Hertz> {
+        self.period_micros.map(|micros| {
+            Hertz::from_millihertz(
+                ((1_000_000_000_u64 + u64::from(micros) / 2) / u64::from(micros)) as u32,
+            )
+        })
+    }
+
+    /// Forget the taps and the tempo
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for TapTempo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Internal clock phase locked to an external pulse input
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockFollower {
+    /// 32 bit phase, wraps once per clock period
+    phase: u32,
+    period_micros: Option<u32>,
+    last_update: Option<u64>,
+    last_pulse: Option<u64>,
+    locked: bool,
+    /// Wrapped while handling a pulse, reported by the next update
+    wrapped: bool,
+}
+
+impl ClockFollower {
+    /// Pulses closer than this are ignored as bounces
+    pub const MIN_PERIOD_MICROS: u32 = 1_000;
+    /// Pulses this far apart or more are too slow to follow
+    pub const MAX_PERIOD_MICROS: u32 = 10_000_000;
+    /// Missing pulses in a row before the follower counts as unlocked
+    pub const MAX_MISSED: u32 = 4;
+
+    pub fn new() -> Self {
+        ClockFollower {
+            phase: 0,
+            period_micros: None,
+            last_update: None,
+            last_pulse: None,
+            locked: false,
+            wrapped: false,
+        }
+    }
+
+    /// Record a rising edge of the clock input
+    ///
+    /// An interval close to a whole number of periods counts as missed
+    /// pulses and still tracks. One more than a quarter off the period is a
+    /// new tempo, and the phase restarts at the pulse.
+    pub fn pulse(&mut self, now_micros: u64) {
+        self.wrapped = self.update(now_micros);
+        let Some(last_pulse) = self.last_pulse else {
+            self.last_pulse = Some(now_micros);
+            self.phase = 0;
+            return;
+        };
+        let interval = now_micros.saturating_sub(last_pulse);
+        if interval < u64::from(Self::MIN_PERIOD_MICROS) {
+            return;
+        }
+        self.last_pulse = Some(now_micros);
+        if interval >= u64::from(Self::MAX_PERIOD_MICROS) {
+            self.period_micros = None;
+            self.locked = false;
+            self.phase = 0;
+            return;
+        }
+        let interval = interval as u32;
+
+        let tracked = self.period_micros.and_then(|period| {
+            let multiple = ((interval + period / 2) / period).max(1);
+            let measured = interval / multiple;
+            (measured.abs_diff(period) <= period / 4).then_some((period, measured))
+        });
+        match tracked {
+            Some((period, measured)) => {
+                // follow tempo drift slowly, and pull the phase half way
+                // back to the pulse, so jitter is averaged out
+                let drift = (i64::from(measured) - i64::from(period)) / 4;
+                self.period_micros = Some((i64::from(period) + drift) as u32);
+                let error = self.phase as i32;
+                self.phase = self.phase.wrapping_sub((error / 2) as u32);
+            }
+            None => {
+                self.period_micros = Some(interval);
+                self.phase = 0;
+            }
+        }
+        self.locked = true;
+    }
+
+    /// Advance the phase to `now_micros`, returns true if it wrapped
+    ///
+    /// Call often, each wrap is a beat of the internal clock.
+    pub fn update(&mut self, now_micros: u64) -> bool {
+        let elapsed = match self.last_update {
+            Some(last_update) => now_micros.saturating_sub(last_update),
+            None => 0,
+        };
+        self.last_update = Some(now_micros);
+        let wrapped = core::mem::take(&mut self.wrapped);
+        let Some(period) = self.period_micros else {
+            return wrapped;
+        };
+        if let Some(last_pulse) = self.last_pulse {
+            let since = now_micros.saturating_sub(last_pulse);
+            if since > u64::from(period) * u64::from(Self::MAX_MISSED + 1) {
+                self.locked = false;
+            }
+        }
+        let advance = (elapsed << 32) / u64::from(period);
+        let phase = u64::from(self.phase) + advance;
+        self.phase = phase as u32;
+        wrapped || phase >> 32 > 0
+    }
+
+    /// Phase as a rising ramp from 0 to [`Sample::MAX`] each period
+    pub fn phase(&self) -> Sample {
+        Sample::from(((u64::from(self.phase) * Sample::MAX as u64) >> 32) as i32)
+    }
+
+    /// Phase as a 32 bit accumulator, for oscillators and LFOs
+    pub fn raw_phase(&self) -> u32 {
+        self.phase
+    }
+
+    /// Tracked clock period
+    pub fn period_micros(&self) -> Option<u32> {
+        self.period_micros
+    }
+
+    /// Tracked clock rate
+    pub fn frequency(&self) -> Option<Hertz> {
+        self.period_micros.map(|micros| {
+            Hertz::from_millihertz(
+                ((1_000_000_000_u64 + u64::from(micros) / 2) / u64::from(micros)) as u32,
+            )
+        })
+    }
+
+    /// True while pulses are arriving, the phase free runs at the last
+    /// period otherwise
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Forget the clock, for example when the input is unplugged
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Default for ClockFollower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Subdivided clock with swing
+///
+/// Swing delays the second tick of each pair of subdivisions: at 50% the
+/// pair is split evenly, at 66% it's a triplet shuffle, up to
+/// [`SwingClock::MAX_SWING`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SwingClock {
+    period_micros: u32,
+    subdivisions: u8,
+    swing: u8,
+    /// Start of the current beat
+    beat_start: u64,
+    /// Next tick within the beat
+    next: u8,
+}
+
+impl SwingClock {
+    /// Straight, pairs split evenly
+    pub const MIN_SWING: u8 = 50;
+    /// Heaviest swing, percent of each pair
+    pub const MAX_SWING: u8 = 75;
+    /// Most ticks per beat
+    pub const MAX_SUBDIVISIONS: u8 = 24;
+
+    /// Clock with `subdivisions` ticks per beat of `period`, straight,
+    /// starting at time 0
+    pub fn new(period: Millis, subdivisions: u8) -> Self {
+        let mut clock = SwingClock {
+            period_micros: 1,
+            subdivisions: 1,
+            swing: Self::MIN_SWING,
+            beat_start: 0,
+            next: 0,
+        };
+        clock.set_period_micros(period.millis() * 1_000);
+        clock.set_subdivisions(subdivisions);
+        clock
+    }
+
+    /// Beat length, for example from [`TapTempo::period_micros`] or
+    /// [`ClockFollower::period_micros`]
+    ///
+    /// Takes effect from the next beat.
+    pub fn set_period_micros(&mut self, period_micros: u32) {
+        self.period_micros = period_micros.max(1);
+    }
+
+    pub fn period_micros(&self) -> u32 {
+        self.period_micros
+    }
+
+    /// Ticks per beat, 1 to [`SwingClock::MAX_SUBDIVISIONS`]
+    pub fn set_subdivisions(&mut self, subdivisions: u8) {
+        self.subdivisions = subdivisions.clamp(1, Self::MAX_SUBDIVISIONS);
+        self.next = self.next.min(self.subdivisions - 1);
+    }
+
+    pub fn subdivisions(&self) -> u8 {
+        self.subdivisions
+    }
+
+    /// Percent of each pair of ticks before the second one, from
+    /// [`SwingClock::MIN_SWING`] to [`SwingClock::MAX_SWING`]
+    pub fn set_swing(&mut self, percent: u8) {
+        self.swing = percent.clamp(Self::MIN_SWING, Self::MAX_SWING);
+    }
+
+    /// Set swing from a knob or CV, [`Sample::MIN`] is straight
+    pub fn set_swing_from(&mut self, value: Sample) {
+        let position = value.to_clamped() - Sample::MIN;
+        let range = i32::from(Self::MAX_SWING - Self::MIN_SWING);
+        let swing = position * range / (Sample::MAX - Sample::MIN);
+        self.set_swing(Self::MIN_SWING + swing as u8);
+    }
+
+    pub fn swing(&self) -> u8 {
+        self.swing
+    }
+
+    /// Start a beat at `now_micros`, the first tick is due straight away
+    pub fn start(&mut self, now_micros: u64) {
+        self.beat_start = now_micros;
+        self.next = 0;
+    }
+
+    /// Start a new beat on an external clock pulse
+    ///
+    /// Any ticks left in the beat are dropped. If the downbeat already went
+    /// out just before the pulse, it isn't repeated.
+    pub fn sync(&mut self, now_micros: u64) {
+        let early = self.next == 1
+            && now_micros.saturating_sub(self.beat_start)
+                < u64::from(self.subdivision_micros() / 2);
+        self.beat_start = now_micros;
+        self.next = if early { 1 } else { 0 };
+    }
+
+    /// Time the next tick is due
+    pub fn next_tick_micros(&self) -> u64 {
+        let subdivision = u64::from(self.subdivision_micros());
+        let pair_start = self.beat_start + u64::from(self.next / 2) * 2 * subdivision;
+        if self.next.is_multiple_of(2) {
+            pair_start
+        } else {
+            pair_start + 2 * subdivision * u64::from(self.swing) / 100
+        }
+    }
+
+    /// Returns the tick's index in the beat if one is due, 0 is the
+    /// downbeat
+    ///
+    /// At most one tick per call. If the clock falls more than a beat
+    /// behind, it skips ahead rather than bunching ticks up.
+    pub fn update(&mut self, now_micros: u64) -> Option<u8> {
+        let period = u64::from(self.period_micros);
+        if now_micros >= self.beat_start + 2 * period {
+            let missed = (now_micros - self.beat_start) / period;
+            self.beat_start += missed * period;
+            self.next = 0;
+        }
+        if now_micros < self.next_tick_micros() {
+            return None;
+        }
+        let tick = self.next;
+        self.next += 1;
+        if self.next == self.subdivisions {
+            self.next = 0;
+            self.beat_start += period;
+        }
+        Some(tick)
+    }
+
+    fn subdivision_micros(&self) -> u32 {
+        self.period_micros / u32::from(self.subdivisions)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClockFollower, SwingClock, TapTempo};
+    use crate::units::{Hertz, Millis};
+    use crate::Sample;
+
+    #[test]
+    fn test_tap_tempo() {
+        let mut tap = TapTempo::new();
+        assert_eq!(tap.tap(1_000_000), None);
+        assert_eq!(tap.tap(1_500_000), Some(Millis::new(500)));
+        assert_eq!(tap.bpm(), Some(120));
+        assert_eq!(tap.frequency(), Some(Hertz::new(2)));
+
+        // sloppy taps average out
+        for (index, jitter) in [10_000_i64, -30_000, 20_000, 0, 10_000].iter().enumerate() {
+            let beat = 2_000_000 + 500_000 * index as i64;
+            tap.tap((beat + jitter) as u64);
+        }
+        assert_eq!(tap.period(), Some(Millis::new(500)));
+        // bounces are ignored
+        assert_eq!(tap.tap(4_010_000 + 50_000), None);
+        assert_eq!(tap.bpm(), Some(120));
+    }
+
+    #[test]
+    fn test_tap_tempo_changes() {
+        let mut tap = TapTempo::new();
+        for beat in 0..4 {
+            tap.tap(beat * 500_000);
+        }
+        // a much slower tap starts a new average straight away
+        assert_eq!(tap.tap(1_500_000 + 1_000_000), Some(Millis::new(1_000)));
+        assert_eq!(tap.tap(3_500_000), Some(Millis::new(1_000)));
+
+        // a long pause keeps the tempo, then starts over
+        assert_eq!(tap.tap(10_000_000), None);
+        assert_eq!(tap.bpm(), Some(60));
+        assert_eq!(tap.tap(10_250_000), Some(Millis::new(250)));
+        assert_eq!(tap.bpm(), Some(240));
+
+        tap.reset();
+        assert_eq!(tap.period(), None);
+    }
+
+    #[test]
+    fn test_clock_follower() {
+        let mut follower = ClockFollower::new();
+        assert!(!follower.update(0));
+        // jittery 100ms clock, with the 6th pulse missing
+        let jitter = [
+            0_i64, 2_000, -3_000, 1_000, -2_000, 0, 3_000, -1_000, 0, 2_000,
+        ];
+        let mut wraps = 0;
+        for (index, jitter) in jitter.iter().enumerate() {
+            let beat = 1_000_000 + 100_000 * index as u64;
+            for now in (beat - 100_000..beat).step_by(1_000) {
+                wraps += u32::from(follower.update(now));
+            }
+            if index != 5 {
+                follower.pulse((beat as i64 + jitter) as u64);
+            }
+        }
+        assert!(follower.is_locked());
+        assert!(follower.period_micros().unwrap().abs_diff(100_000) < 2_000);
+        let millihertz = follower.frequency().unwrap().millihertz();
+        assert!(millihertz.abs_diff(10_000) < 200);
+        // a wrap every period once the period is known, the last one at
+        // the pulse is reported by the next update
+        wraps += u32::from(follower.update(1_950_000));
+        assert_eq!(wraps, 8);
+
+        // half way to the next pulse is half way up the ramp
+        follower.pulse(2_000_000);
+        follower.update(2_050_000);
+        assert!(follower.phase().to_clamped().abs_diff(Sample::MAX / 2) < 50);
+
+        // pulses stopping leave it free running, unlocked
+        follower.update(3_000_000);
+        assert!(!follower.is_locked());
+        assert!(follower.period_micros().is_some());
+        follower.reset();
+        assert_eq!(follower.period_micros(), None);
+    }
+
+    /// Tick times and indexes over `until` micros, updating every 100us
+    fn ticks(clock: &mut SwingClock, from: u64, until: u64) -> Vec<(u64, u8)> {
+        (from..until)
+            .step_by(100)
+            .filter_map(|now| clock.update(now).map(|tick| (now, tick)))
+            .collect()
+    }
+
+    #[test]
+    fn test_swing_clock() {
+        // 120 BPM sixteenths, straight
+        let mut clock = SwingClock::new(Millis::new(500), 4);
+        assert_eq!(clock.next_tick_micros(), 0);
+        let straight = ticks(&mut clock, 0, 1_000_000);
+        assert_eq!(
+            straight.iter().map(|tick| tick.0).collect::<Vec<_>>(),
+            (0..8).map(|step| step * 125_000).collect::<Vec<_>>()
+        );
+        assert_eq!(straight[5].1, 1);
+
+        // 2:1 shuffle, every second sixteenth moves late
+        clock.set_swing(66);
+        clock.start(1_000_000);
+        let swung = ticks(&mut clock, 1_000_000, 1_500_000);
+        assert_eq!(
+            swung,
+            [
+                (1_000_000, 0),
+                (1_165_000, 1),
+                (1_250_000, 2),
+                (1_415_000, 3)
+            ]
+        );
+        clock.set_swing(90);
+        assert_eq!(clock.swing(), SwingClock::MAX_SWING);
+        clock.set_swing_from(Sample::from(Sample::MIN));
+        assert_eq!(clock.swing(), SwingClock::MIN_SWING);
+    }
+
+    #[test]
+    fn test_swing_clock_sync() {
+        let mut clock = SwingClock::new(Millis::new(500), 2);
+        clock.start(0);
+        ticks(&mut clock, 0, 499_000);
+        // the external beat is a little late, the downbeat waits for it
+        assert_eq!(clock.update(500_000), Some(0));
+        clock.sync(503_000);
+        assert_eq!(clock.update(503_000), None);
+        assert_eq!(clock.next_tick_micros(), 753_000);
+        // and when it's early, the rest of the beat is dropped
+        clock.sync(990_000);
+        assert_eq!(clock.update(990_000), Some(0));
+
+        // a long stall skips ahead instead of catching up
+        assert_eq!(clock.update(5_000_000), Some(0));
+        assert_eq!(clock.next_tick_micros(), 4_990_000 + 250_000);
+    }
+}
@@ -0,0 +1,97 @@
+//! CSV traces of signals, for plotting on the host
+//!
+//! Host side tests and tools can step DSP code in a loop and record any
+//! signals of interest, one row per tick, then load the CSV into a
+//! spreadsheet or plotting tool:
+//!
+//! ```no_run
+//! # use wscomp::lfo::{Lfo, LfoShape};
+//! # use wscomp::trace::CsvTrace;
+//! # use wscomp::units::Hertz;
+//! # use wscomp::Sample;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let mut lfo = Lfo::new(LfoShape::Sine, Hertz::new(1), Hertz::new(1_000));
+//! # let intensity = Sample::from(1000_i32);
+//! let mut csv = String::new();
+//! let mut trace = CsvTrace::new(&mut csv, ["lfo", "intensity"]);
+//! for _ in 0..10_000 {
+//!     lfo.tick();
+//!     trace.record([lfo.current(), intensity])?;
+//! }
+//! std::fs::write("lfo.csv", csv)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Values are written as clamped 12 bit counts, so small steps and
+//! discontinuities show up exactly as the card would output them.
+
+use core::fmt::{self, Write};
+
+use crate::Sample;
+
+/// Writes one CSV row per [`CsvTrace::record`], with a leading tick column
+pub struct CsvTrace<W, const N: usize> {
+    out: W,
+    names: [&'static str; N],
+    tick: u64,
+}
+
+impl<W: Write, const N: usize> CsvTrace<W, N> {
+    /// New trace with a column per signal, the header is written on the first record
+    pub fn new(out: W, names: [&'static str; N]) -> Self {
+        CsvTrace {
+            out,
+            names,
+            tick: 0,
+        }
+    }
+
+    /// Write a row for the current tick, then advance it
+    pub fn record(&mut self, values: [Sample; N]) -> fmt::Result {
+        if self.tick == 0 {
+            self.out.write_str("tick")?;
+            for name in self.names {
+                write!(self.out, ",{}", name)?;
+            }
+            self.out.write_char('\n')?;
+        }
+        write!(self.out, "{}", self.tick)?;
+        for value in values {
+            write!(self.out, ",{}", value.to_clamped())?;
+        }
+        self.out.write_char('\n')?;
+        self.tick += 1;
+        Ok(())
+    }
+
+    /// Rows recorded so far
+    pub fn ticks(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CsvTrace;
+    use crate::Sample;
+
+    #[test]
+    fn test_csv_trace() {
+        let mut trace = CsvTrace::new(String::new(), ["ramp", "inverted"]);
+        for value in [0_i32, 1000, 3000] {
+            trace
+                .record([Sample::from(value), Sample::from(-value)])
+                .unwrap();
+        }
+        assert_eq!(trace.ticks(), 3);
+        assert_eq!(
+            trace.into_inner(),
+            "tick,ramp,inverted\n0,0,0\n1,1000,-1000\n2,2047,-2048\n"
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Small unit types for rates and durations
+//!
+//! These keep sample rates, control rates and musical durations out of
+//! magic numbers, and do the conversions to phase increments and tick counts
+//! with integer math.
+//!
+//! Nothing here panics on a zero: a conversion that would divide by zero
+//! saturates to `u32::MAX` instead, an infinitely long period or an
+//! infinitely fast rate.
+
+/// A frequency, stored in millihertz so slow LFO rates don't lose precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Hertz {
+    millihertz: u32,
+}
+
+impl Hertz {
+    pub const fn new(hz: u32) -> Self {
+        Hertz {
+            millihertz: hz * 1000,
+        }
+    }
+
+    pub const fn from_millihertz(millihertz: u32) -> Self {
+        Hertz { millihertz }
+    }
+
+    /// Frequency with one cycle per `period`, `u32::MAX` millihertz for a
+    /// zero period
+    pub const fn from_period(period: Millis) -> Self {
+        Hertz {
+            millihertz: saturating_div(1_000_000, period.millis),
+        }
+    }
+
+    /// Whole hertz, rounded down
+    pub const fn hz(&self) -> u32 {
+        self.millihertz / 1000
+    }
+
+    pub const fn millihertz(&self) -> u32 {
+        self.millihertz
+    }
+
+    /// Length of one cycle, rounded down to whole milliseconds, `u32::MAX`
+    /// for 0Hz
+    pub const fn period(&self) -> Millis {
+        Millis {
+            millis: saturating_div(1_000_000, self.millihertz),
+        }
+    }
+
+    /// Increment per tick of a 32 bit phase accumulator updated at `rate`
+    ///
+    /// The accumulator wraps once per cycle of this frequency. A zero `rate`
+    /// gives `u32::MAX`.
+    pub const fn phase_increment(&self, rate: Hertz) -> u32 {
+        if rate.millihertz == 0 {
+            return u32::MAX;
+        }
+        (((self.millihertz as u64) << 32) / rate.millihertz as u64) as u32
+    }
+
+    /// Ticks of `rate` in one cycle of this frequency
+    ///
+    /// For example, how many control loop ticks between LFO steps. 0Hz
+    /// never completes a cycle, so gives `u32::MAX`.
+    pub const fn ticks_per_cycle(&self, rate: Hertz) -> u32 {
+        saturating_div(rate.millihertz, self.millihertz)
+    }
+}
+
+/// `numerator / denominator`, `u32::MAX` when `denominator` is 0
+const fn saturating_div(numerator: u32, denominator: u32) -> u32 {
+    match numerator.checked_div(denominator) {
+        Some(quotient) => quotient,
+        None => u32::MAX,
+    }
+}
+
+/// A duration in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Millis {
+    millis: u32,
+}
+
+impl Millis {
+    pub const fn new(millis: u32) -> Self {
+        Millis { millis }
+    }
+
+    pub const fn millis(&self) -> u32 {
+        self.millis
+    }
+
+    /// Ticks of `rate` in this duration, rounded down
+    pub const fn ticks(&self, rate: Hertz) -> u32 {
+        ((self.millis as u64 * rate.millihertz as u64) / 1_000_000) as u32
+    }
+}
+
+/// A musical duration in beats (quarter notes)
+///
+/// Stored as pulses at [`Beats::PPQN`] so common subdivisions like sixteenths
+/// and triplets are exact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Beats {
+    pulses: u32,
+}
+
+impl Beats {
+    /// Pulses per quarter note
+    pub const PPQN: u32 = 96;
+
+    pub const fn new(beats: u32) -> Self {
+        Beats {
+            pulses: beats * Self::PPQN,
+        }
+    }
+
+    /// `numerator / denominator` beats, rounded down to whole pulses
+    pub const fn from_fraction(numerator: u32, denominator: u32) -> Self {
+        Beats {
+            pulses: numerator * Self::PPQN / denominator,
+        }
+    }
+
+    pub const fn from_pulses(pulses: u32) -> Self {
+        Beats { pulses }
+    }
+
+    pub const fn pulses(&self) -> u32 {
+        self.pulses
+    }
+
+    /// Length at `bpm` beats per minute
+    pub const fn to_millis(&self, bpm: u32) -> Millis {
+        Millis {
+            millis: ((self.pulses as u64 * 60_000) / (bpm as u64 * Self::PPQN as u64)) as u32,
+        }
+    }
+
+    /// Ticks of `rate` in this duration at `bpm` beats per minute
+    pub const fn ticks(&self, bpm: u32, rate: Hertz) -> u32 {
+        ((self.pulses as u64 * 60 * rate.millihertz as u64)
+            / (bpm as u64 * Self::PPQN as u64 * 1000)) as u32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Beats, Hertz, Millis};
+
+    #[test]
+    fn test_hertz() {
+        assert_eq!(Hertz::new(480).hz(), 480);
+        assert_eq!(Hertz::from_millihertz(7_500).hz(), 7);
+        assert_eq!(Hertz::new(4).period(), Millis::new(250));
+        assert_eq!(Hertz::from_period(Millis::new(250)), Hertz::new(4));
+
+        // a 7.5Hz step, every 64 ticks of a 480Hz control loop
+        assert_eq!(
+            Hertz::from_millihertz(7_500).ticks_per_cycle(Hertz::new(480)),
+            64
+        );
+    }
+
+    #[test]
+    fn test_hertz_zero() {
+        assert_eq!(Hertz::new(0).period(), Millis::new(u32::MAX));
+        assert_eq!(Hertz::from_period(Millis::new(0)).millihertz(), u32::MAX);
+        assert_eq!(Hertz::new(0).ticks_per_cycle(Hertz::new(480)), u32::MAX);
+        assert_eq!(Hertz::new(1).phase_increment(Hertz::new(0)), u32::MAX);
+    }
+
+    #[test]
+    fn test_phase_increment() {
+        let rate = Hertz::new(48_000);
+        assert_eq!(Hertz::new(24_000).phase_increment(rate), 1 << 31);
+        assert_eq!(Hertz::new(12_000).phase_increment(rate), 1 << 30);
+        // 1Hz wraps after 48000 ticks
+        let increment = Hertz::new(1).phase_increment(rate);
+        assert_eq!(increment, 89_478);
+        assert!((u64::from(increment) * 48_000).abs_diff(1 << 32) < 48_000);
+    }
+
+    #[test]
+    fn test_millis_ticks() {
+        assert_eq!(Millis::new(1000).ticks(Hertz::new(480)), 480);
+        assert_eq!(Millis::new(10).ticks(Hertz::new(48_000)), 480);
+        assert_eq!(Millis::new(1).ticks(Hertz::new(60)), 0);
+    }
+
+    #[test]
+    fn test_beats() {
+        assert_eq!(Beats::new(1).to_millis(120), Millis::new(500));
+        assert_eq!(Beats::from_fraction(1, 4).to_millis(120), Millis::new(125));
+        // triplet eighths are exact pulses
+        assert_eq!(Beats::from_fraction(1, 3).pulses(), 32);
+        assert_eq!(Beats::new(4).ticks(120, Hertz::new(1000)), 2000);
+        assert_eq!(
+            Beats::from_fraction(1, 4).ticks(120, Hertz::new(48_000)),
+            6000
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! Voltage controlled amplifier
+//!
+//! Loudness is heard roughly logarithmically, so a knob or CV scaling the
+//! level linearly does all its work in the top few degrees of travel. A
+//! [`Vca`] set to [`VcaResponse::Exponential`] spreads the level evenly in
+//! decibels instead:
+//!
+//! ```
+//! # use wscomp::inputs::{AudioState, MuxState};
+//! # use wscomp::vca::{Vca, VcaResponse};
+//! # fn card(mux_state: MuxState, audio_state: AudioState) {
+//! let mut vca = Vca::new(VcaResponse::Exponential);
+//! loop {
+//!     vca.set_control(mux_state.main_knob);
+//!     let out = vca.process(audio_state.audio1.value());
+//! }
+//! # }
+//! ```
+//!
+//! Gain is worked out once per control change, [`Vca::process`] is a
+//! multiply and a shift.
+
+use crate::tables;
+use crate::Sample;
+
+/// 1.0 in the Q16 gains below
+const ONE: u32 = 1 << 16;
+
+/// How control voltage maps to gain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VcaResponse {
+    /// Gain proportional to control, for CV and modulation
+    Linear,
+    /// Gain even in decibels over [`Vca::EXP_OCTAVES`], for level controls
+    Exponential,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Vca {
+    response: VcaResponse,
+    control: Sample,
+    /// Q16, 0 to 1
+    gain: u32,
+}
+
+impl Vca {
+    /// Range of the exponential response, in 6dB octaves, so about 60dB
+    /// from full control down to the last step before silence
+    pub const EXP_OCTAVES: u32 = 10;
+
+    /// Closed VCA, with `response`
+    pub fn new(response: VcaResponse) -> Self {
+        Vca {
+            response,
+            control: Sample::from(0_i32),
+            gain: 0,
+        }
+    }
+
+    pub fn set_response(&mut self, response: VcaResponse) {
+        self.response = response;
+        self.set_control(self.control);
+    }
+
+    pub fn response(&self) -> VcaResponse {
+        self.response
+    }
+
+    /// Set the control, 0 (or below) is closed and [`Sample::MAX`] unity
+    /// gain
+    pub fn set_control(&mut self, control: Sample) {
+        self.control = control;
+        let position = control.to_clamped().max(0) as u32 * ONE / Sample::MAX as u32;
+        self.gain = match self.response {
+            VcaResponse::Linear => position,
+            VcaResponse::Exponential if position == 0 => 0,
+            VcaResponse::Exponential => {
+                // 2^-octaves, as 2^(1 - fraction) shifted down a whole
+                // octave more
+                let octaves = (ONE - position) * Self::EXP_OCTAVES;
+                tables::exp2(ONE - (octaves & (ONE - 1))) >> ((octaves >> 16) + 1)
+            }
+        };
+    }
+
+    /// Current gain, 0 to [`Sample::MAX`]
+    pub fn gain(&self) -> Sample {
+        Sample::from(((self.gain * Sample::MAX as u32 + ONE / 2) >> 16) as i32)
+    }
+
+    /// Apply the gain to one sample
+    pub fn process(&self, input: Sample) -> Sample {
+        let scaled = i64::from(input.to_unclamped()) * i64::from(self.gain);
+        Sample::from(((scaled + i64::from(ONE / 2)) >> 16) as i32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Vca, VcaResponse};
+    use crate::Sample;
+
+    #[test]
+    fn test_vca() {
+        let input = Sample::from(2000_i32);
+        let mut vca = Vca::new(VcaResponse::Linear);
+        assert_eq!(vca.process(input), Sample::from(0_i32));
+        vca.set_control(Sample::from(Sample::MAX));
+        assert_eq!(vca.process(input), input);
+        assert_eq!(vca.gain(), Sample::from(Sample::MAX));
+        vca.set_control(Sample::from(Sample::MAX / 2));
+        // 1023 of 2047
+        assert_eq!(vca.process(input), Sample::from(999_i32));
+        vca.set_control(Sample::from(-500_i32));
+        assert_eq!(vca.process(input), Sample::from(0_i32));
+
+        vca.set_response(VcaResponse::Exponential);
+        assert_eq!(vca.process(input), Sample::from(0_i32));
+        vca.set_control(Sample::from(Sample::MAX));
+        assert_eq!(vca.process(input), input);
+        // halfway is 5 octaves down, -30dB
+        vca.set_control(Sample::from(Sample::MAX / 2));
+        assert!(vca.process(input).to_clamped().abs_diff(2000 / 32) <= 1);
+    }
+
+    #[test]
+    fn test_vca_exponential_curve() {
+        let mut vca = Vca::new(VcaResponse::Exponential);
+        let mut previous = 0;
+        for control in 1..=Sample::MAX {
+            vca.set_control(Sample::from(control));
+            let gain = vca.gain;
+            assert!(gain >= previous, "{control}");
+            previous = gain;
+        }
+        // every 10% of travel is the same 6dB step
+        vca.set_control(Sample::from(Sample::MAX * 9 / 10));
+        let nine_tenths = f64::from(vca.gain);
+        vca.set_control(Sample::from(Sample::MAX * 8 / 10));
+        let ratio = nine_tenths / f64::from(vca.gain);
+        assert!((ratio - 2.0).abs() < 0.01, "{ratio}");
+    }
+}
@@ -0,0 +1,239 @@
+//! WAV file parser
+//!
+//! Finds the `fmt ` and `data` chunks of a RIFF WAVE file wherever they
+//! are, skipping any other chunks (`LIST`, `fact`, ...) in between, and
+//! returns an error rather than panicking on anything it can't read:
+//!
+//! ```
+//! # use wscomp::wav::{Wav, WavCodec, WavError};
+//! # fn play(file: &[u8]) -> Result<(), WavError> {
+//! // `file` from `include_bytes!`, or an asset bank
+//! let wav = Wav::parse(file)?;
+//! if wav.format.codec == WavCodec::ImaAdpcm {
+//!     for block in wav.data.chunks_exact(wav.format.block_align.into()) {
+//!         // decode
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Nothing is copied, [`Wav::data`] borrows from the file.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WavError {
+    /// Doesn't start with a RIFF WAVE header
+    NotWave,
+    /// A chunk header or the `fmt ` chunk runs past the end of the file
+    Truncated,
+    /// No `fmt ` chunk before the `data` chunk
+    MissingFormat,
+    /// No `data` chunk
+    MissingData,
+    /// `fmt ` chunk is too short, or has 0 channels, rate or block align
+    InvalidFormat,
+}
+
+/// Sample encoding, from the `fmt ` chunk's format tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WavCodec {
+    /// Uncompressed integer PCM
+    Pcm,
+    /// IMA (DVI) ADPCM, 4 bits per sample
+    ImaAdpcm,
+    /// Anything else, with its format tag
+    Other(u16),
+}
+
+impl WavCodec {
+    fn from_tag(tag: u16) -> Self {
+        match tag {
+            0x0001 => WavCodec::Pcm,
+            0x0011 => WavCodec::ImaAdpcm,
+            tag => WavCodec::Other(tag),
+        }
+    }
+}
+
+/// Contents of the `fmt ` chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WavFormat {
+    pub codec: WavCodec,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Bytes per frame for PCM, bytes per block for ADPCM
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+}
+
+impl WavFormat {
+    /// Samples per channel in each ADPCM block, 0 if the block is too small
+    /// to hold its headers
+    ///
+    /// Each block starts with a 4 byte header per channel holding the first
+    /// sample, then packs the rest at 4 bits each.
+    pub fn adpcm_samples_per_block(&self) -> usize {
+        let channels = usize::from(self.channels);
+        let header = 4 * channels;
+        match usize::from(self.block_align).checked_sub(header) {
+            Some(packed) => packed * 2 / channels + 1,
+            None => 0,
+        }
+    }
+}
+
+/// A parsed WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Wav<'a> {
+    pub format: WavFormat,
+    /// Contents of the `data` chunk
+    ///
+    /// Cut short if the file is, some encoders write the chunk size before
+    /// they know the final length.
+    pub data: &'a [u8],
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl<'a> Wav<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, WavError> {
+        if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+            return Err(WavError::NotWave);
+        }
+
+        let mut format = None;
+        let mut offset = 12;
+        while offset < bytes.len() {
+            let id = bytes.get(offset..offset + 4).ok_or(WavError::Truncated)?;
+            let size = u32_at(bytes, offset + 4).ok_or(WavError::Truncated)? as usize;
+            let start = offset + 8;
+            match id {
+                b"fmt " => {
+                    let chunk = bytes
+                        .get(start..start.saturating_add(size))
+                        .ok_or(WavError::Truncated)?;
+                    format = Some(Self::parse_format(chunk)?);
+                }
+                b"data" => {
+                    let format = format.ok_or(WavError::MissingFormat)?;
+                    let end = start.saturating_add(size).min(bytes.len());
+                    return Ok(Wav {
+                        format,
+                        data: &bytes[start..end],
+                    });
+                }
+                _ => {}
+            }
+            // chunks are padded to an even length
+            offset = start.saturating_add(size).saturating_add(size & 1);
+        }
+        Err(WavError::MissingData)
+    }
+
+    fn parse_format(chunk: &[u8]) -> Result<WavFormat, WavError> {
+        let field = |offset| u16_at(chunk, offset).ok_or(WavError::InvalidFormat);
+        let format = WavFormat {
+            codec: WavCodec::from_tag(field(0)?),
+            channels: field(2)?,
+            sample_rate: u32_at(chunk, 4).ok_or(WavError::InvalidFormat)?,
+            block_align: field(12)?,
+            bits_per_sample: field(14)?,
+        };
+        if format.channels == 0 || format.sample_rate == 0 || format.block_align == 0 {
+            return Err(WavError::InvalidFormat);
+        }
+        Ok(format)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Wav, WavCodec, WavError};
+
+    /// A WAV with the given chunks after the header
+    fn wav(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, data) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(&body);
+        file
+    }
+
+    /// IMA ADPCM, mono, 22050Hz, 1024 byte blocks
+    const FMT: [u8; 20] = [
+        0x11, 0, 1, 0, 0x22, 0x56, 0, 0, 0, 0, 0, 0, 0, 4, 4, 0, 2, 0, 0xf9, 7,
+    ];
+
+    #[test]
+    fn test_wav_parse() {
+        let file = wav(&[
+            (b"LIST", b"odd"),
+            (b"fmt ", &FMT),
+            (b"fact", &[0; 4]),
+            (b"data", &[1, 2, 3, 4]),
+        ]);
+        let parsed = Wav::parse(&file).unwrap();
+        assert_eq!(parsed.format.codec, WavCodec::ImaAdpcm);
+        assert_eq!(parsed.format.channels, 1);
+        assert_eq!(parsed.format.sample_rate, 22_050);
+        assert_eq!(parsed.format.block_align, 1024);
+        assert_eq!(parsed.format.bits_per_sample, 4);
+        assert_eq!(parsed.format.adpcm_samples_per_block(), 2041);
+        assert_eq!(parsed.data, &[1, 2, 3, 4]);
+
+        // data chunk sizes past the end of the file are cut short
+        let mut file = wav(&[(b"fmt ", &FMT), (b"data", &[1, 2, 3, 4])]);
+        let size_offset = file.len() - 8;
+        file[size_offset..size_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Wav::parse(&file).unwrap().data, &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_wav_errors() {
+        assert_eq!(Wav::parse(b"RIFF"), Err(WavError::NotWave));
+        assert_eq!(
+            Wav::parse(&wav(&[(b"data", &[0; 4])])),
+            Err(WavError::MissingFormat)
+        );
+        assert_eq!(
+            Wav::parse(&wav(&[(b"fmt ", &FMT)])),
+            Err(WavError::MissingData)
+        );
+        assert_eq!(
+            Wav::parse(&wav(&[(b"fmt ", &FMT[..10]), (b"data", &[])])),
+            Err(WavError::InvalidFormat)
+        );
+        let mut no_channels = FMT;
+        no_channels[2] = 0;
+        assert_eq!(
+            Wav::parse(&wav(&[(b"fmt ", &no_channels), (b"data", &[])])),
+            Err(WavError::InvalidFormat)
+        );
+
+        let mut truncated = wav(&[(b"fmt ", &FMT), (b"data", &[])]);
+        truncated.truncate(30);
+        assert_eq!(Wav::parse(&truncated), Err(WavError::Truncated));
+        truncated.truncate(14);
+        assert_eq!(Wav::parse(&truncated), Err(WavError::Truncated));
+    }
+}
@@ -0,0 +1,279 @@
+//! Wavetable oscillator playing single cycle tables from flash
+//!
+//! A [`Wavetable`] is a set of single cycle tables of [`Wavetable::LEN`]
+//! samples each, stored back to back as little endian `i16` in the
+//! [`Sample`] range (-2048 to 2047). [`include_wavetable!`] embeds one in
+//! flash at build time, and checks its size then:
+//!
+//! ```
+//! # use wscomp::inputs::MuxState;
+//! # use wscomp::units::Hertz;
+//! # use wscomp::wavetable::{Wavetable, WavetableOsc};
+//! # const SAMPLE_RATE: Hertz = Hertz::new(48_000);
+//! # const FORMANTS: &[u8] = &[0; 1024];
+//! # fn card(mux_state: MuxState) {
+//! // or `include_wavetable!("formants.bin")`, which checks the size at build time
+//! const TABLES: Wavetable<'static> = Wavetable::from_bytes(FORMANTS).unwrap();
+//!
+//! let mut osc = WavetableOsc::new(TABLES, Hertz::new(110), SAMPLE_RATE);
+//! loop {
+//!     osc.set_morph(mux_state.main_knob);
+//!     let sample = osc.tick();
+//! }
+//! # }
+//! ```
+//!
+//! [`WavetableOsc`] interpolates linearly between samples within a table,
+//! and crossfades between the two tables either side of the morph position.
+
+use crate::interp::Interpolator;
+use crate::units::Hertz;
+use crate::Sample;
+
+/// Embed a wavetable file with `include_bytes!`, failing the build if its
+/// size isn't a whole number of tables
+#[macro_export]
+macro_rules! include_wavetable {
+    ($path:expr) => {
+        const {
+            match $crate::wavetable::Wavetable::from_bytes(include_bytes!($path)) {
+                Some(table) => table,
+                None => panic!("wavetable size must be a non-zero multiple of 512 bytes"),
+            }
+        }
+    };
+}
+
+/// Single cycle tables, borrowed from flash or an asset bank
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Wavetable<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Wavetable<'a> {
+    /// Samples in each table
+    pub const LEN: usize = 256;
+    const BYTES: usize = Self::LEN * 2;
+
+    /// Tables from raw bytes, `None` unless they're a non-zero whole number
+    /// of tables
+    pub const fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(Self::BYTES) {
+            return None;
+        }
+        Some(Wavetable { bytes })
+    }
+
+    /// Number of tables
+    pub fn tables(&self) -> usize {
+        self.bytes.len() / Self::BYTES
+    }
+
+    /// One sample, `index` wraps within the table, clamped to the
+    /// [`Sample`] range
+    pub fn sample(&self, table: usize, index: usize) -> i32 {
+        let offset = table * Self::BYTES + (index % Self::LEN) * 2;
+        let value = i16::from_le_bytes([self.bytes[offset], self.bytes[offset + 1]]);
+        i32::from(value).clamp(Sample::MIN, Sample::MAX)
+    }
+
+    /// Linearly interpolated sample at a 32 bit phase
+    fn interpolated(&self, table: usize, phase: u32) -> i32 {
+        let index = (phase >> 24) as usize;
+        let fraction = ((phase >> 8) & 0xffff) as i32;
+        let low = self.sample(table, index);
+        let high = self.sample(table, index + 1);
+        low + (((high - low) * fraction) >> 16)
+    }
+
+    /// [`Wavetable::interpolated`], with the blend on an [`Interpolator`]
+    fn interpolated_with(&self, interp: &mut impl Interpolator, table: usize, phase: u32) -> i32 {
+        let index = (phase >> 24) as usize;
+        let low = self.sample(table, index);
+        let high = self.sample(table, index + 1);
+        interp.lerp_q16(low, high, (phase >> 8) as u16)
+    }
+}
+
+/// Phase accumulating oscillator over a [`Wavetable`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WavetableOsc<'a> {
+    table: Wavetable<'a>,
+    rate: Hertz,
+    phase: u32,
+    increment: u32,
+    /// Position across the tables, Q16 table index
+    morph: u32,
+}
+
+impl<'a> WavetableOsc<'a> {
+    /// Oscillator at `frequency`, ticked `rate` times a second, playing the
+    /// first table
+    pub fn new(table: Wavetable<'a>, frequency: Hertz, rate: Hertz) -> Self {
+        WavetableOsc {
+            table,
+            rate,
+            phase: 0,
+            increment: frequency.phase_increment(rate),
+            morph: 0,
+        }
+    }
+
+    /// Swap tables, keeping the phase and morph position
+    pub fn set_table(&mut self, table: Wavetable<'a>) {
+        self.table = table;
+    }
+
+    pub fn set_frequency(&mut self, frequency: Hertz) {
+        self.increment = frequency.phase_increment(self.rate);
+    }
+
+    /// Morph across the tables, [`Sample::MIN`] is the first table and
+    /// [`Sample::MAX`] the last
+    pub fn set_morph(&mut self, position: Sample) {
+        let position = (position.to_clamped() - Sample::MIN) as u64;
+        let last = (self.table.tables() - 1) as u64;
+        self.morph = (((position * last) << 16) / (Sample::MAX - Sample::MIN) as u64) as u32;
+    }
+
+    /// Restart the cycle, for hard sync
+    pub fn reset(&mut self) {
+        self.phase = 0;
+    }
+
+    /// Level at the current phase and morph position
+    pub fn current(&self) -> Sample {
+        let last = self.table.tables() - 1;
+        let table = ((self.morph >> 16) as usize).min(last);
+        let fraction = (self.morph & 0xffff) as i32;
+        let low = self.table.interpolated(table, self.phase);
+        if fraction == 0 || table == last {
+            return Sample::from(low);
+        }
+        let high = self.table.interpolated(table + 1, self.phase);
+        Sample::from(low + (((high - low) * fraction) >> 16))
+    }
+
+    /// [`WavetableOsc::current`], with the blends on an [`Interpolator`]
+    ///
+    /// The same level, see [`interp`](crate::interp).
+    pub fn current_with(&self, interp: &mut impl Interpolator) -> Sample {
+        let last = self.table.tables() - 1;
+        let table = ((self.morph >> 16) as usize).min(last);
+        let fraction = self.morph as u16;
+        let low = self.table.interpolated_with(interp, table, self.phase);
+        if fraction == 0 || table == last {
+            return Sample::from(low);
+        }
+        let high = self.table.interpolated_with(interp, table + 1, self.phase);
+        Sample::from(interp.lerp_q16(low, high, fraction))
+    }
+
+    /// Return the level at the current phase, then advance one tick
+    pub fn tick(&mut self) -> Sample {
+        let sample = self.current();
+        self.phase = self.phase.wrapping_add(self.increment);
+        sample
+    }
+
+    /// [`WavetableOsc::tick`], with the blends on an [`Interpolator`]
+    pub fn tick_with(&mut self, interp: &mut impl Interpolator) -> Sample {
+        let sample = self.current_with(interp);
+        self.phase = self.phase.wrapping_add(self.increment);
+        sample
+    }
+}
+
+/// Endless, for use as a graph source through
+/// [`IterSource`](crate::graph::IterSource)
+impl Iterator for WavetableOsc<'_> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        Some(self.tick())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Wavetable, WavetableOsc};
+    use crate::interp::SoftInterp;
+    use crate::random::Rng;
+    use crate::units::Hertz;
+    use crate::Sample;
+
+    /// A rising ramp, then a falling one
+    fn ramps() -> Vec<u8> {
+        let rising = (0..256).map(|index| index * 16 - 2048);
+        let falling = (0..256).map(|index| 2047 - index * 16);
+        rising
+            .chain(falling)
+            .flat_map(|value: i32| (value as i16).to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_wavetable_bytes() {
+        let bytes = ramps();
+        assert!(Wavetable::from_bytes(&[]).is_none());
+        assert!(Wavetable::from_bytes(&bytes[..510]).is_none());
+        let table = Wavetable::from_bytes(&bytes).unwrap();
+        assert_eq!(table.tables(), 2);
+        assert_eq!(table.sample(0, 0), Sample::MIN);
+        assert_eq!(table.sample(0, 257), -2032);
+        assert_eq!(table.sample(1, 0), Sample::MAX);
+
+        // out of range values clamp
+        let loud = [0x00, 0x40].repeat(256);
+        assert_eq!(
+            Wavetable::from_bytes(&loud).unwrap().sample(0, 3),
+            Sample::MAX
+        );
+    }
+
+    #[test]
+    fn test_wavetable_osc() {
+        let bytes = ramps();
+        let table = Wavetable::from_bytes(&bytes).unwrap();
+        // half a table step per tick
+        let rate = Hertz::new(48_000);
+        let mut osc = WavetableOsc::new(table, Hertz::from_millihertz(93_750), rate);
+        let first: [i32; 4] = core::array::from_fn(|_| osc.tick().to_clamped());
+        assert_eq!(first, [-2048, -2040, -2032, -2024]);
+
+        // wraps from the end of the table back to the start
+        osc.reset();
+        // 255 table steps per tick
+        osc.set_frequency(Hertz::from_millihertz(47_812_500));
+        osc.tick();
+        assert_eq!(osc.tick().to_clamped(), 2032);
+        assert_eq!(osc.current().to_clamped(), 2016);
+
+        // halfway between the ramps is flat
+        osc.reset();
+        osc.set_morph(Sample::from(0_i32));
+        assert!(osc.current().to_clamped().abs() <= 1);
+        osc.set_morph(Sample::from(Sample::MAX));
+        assert_eq!(osc.current().to_clamped(), Sample::MAX);
+    }
+
+    #[test]
+    fn test_wavetable_osc_with_interp() {
+        // noisy tables, so the steps between samples are large
+        let mut rng = Rng::new(1755);
+        let bytes: Vec<u8> = (0..3 * 256)
+            .flat_map(|_| (rng.next_sample().to_clamped() as i16).to_le_bytes())
+            .collect();
+        let table = Wavetable::from_bytes(&bytes).unwrap();
+        let mut osc = WavetableOsc::new(table, Hertz::from_millihertz(440_123), Hertz::new(48_000));
+        for morph in [Sample::MIN, -1000, 0, 3, 1500, Sample::MAX] {
+            osc.set_morph(Sample::from(morph));
+            let mut reference = osc.clone();
+            for _ in 0..2000 {
+                assert_eq!(osc.tick_with(&mut SoftInterp), reference.tick(), "{morph}");
+            }
+        }
+    }
+}
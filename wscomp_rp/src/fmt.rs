@@ -0,0 +1,36 @@
+//! Logging that compiles away without the `defmt` feature, like wscomp's
+
+#![allow(unused_macros)]
+
+macro_rules! info {
+    ($($arg:expr),* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::info!($($arg),*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $arg ),*);
+        }
+    };
+}
+
+macro_rules! warn {
+    ($($arg:expr),* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::warn!($($arg),*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $arg ),*);
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:expr),* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::error!($($arg),*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $arg ),*);
+        }
+    };
+}
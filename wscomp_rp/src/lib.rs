@@ -0,0 +1,270 @@
+//! Workshop System Computer board support on embassy-rp
+//!
+//! [`Computer::init`] takes the RP2040 peripherals and sets up every jack,
+//! knob and LED on the pins from [`wscomp::board`], wrapped in the wscomp
+//! drivers. Cards destructure the [`Computer`] and hand each part to the task
+//! that drives it:
+//!
+//! ```text
+//! let p = embassy_rp::init(Default::default());
+//! power::set_usb_only(cfg!(feature = "usb-power"));
+//! let Computer { inputs, cv_out, leds, dac, .. } = Computer::init(p);
+//! ```
+//!
+//! This crate binds `ADC_IRQ_FIFO` for the input reader, and `USBCTRL_IRQ`
+//! with the `usb-config` feature, so cards using it must not bind those
+//! interrupts again. The host-testable drivers stay in
+//! wscomp, this crate is only the embassy-rp wiring.
+//!
+//! Logging and `defmt::Format` come from the default `defmt` feature, which
+//! is forwarded to wscomp and embassy-rp.
+
+#![no_std]
+
+// first, so the logging macros are visible in every module
+#[macro_use]
+mod fmt;
+
+#[cfg(feature = "usb-config")]
+pub mod usb_config;
+
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::{
+    CORE1, DMA_CH1, DMA_CH10, DMA_CH11, DMA_CH2, DMA_CH3, DMA_CH4, DMA_CH5, DMA_CH6, DMA_CH7,
+    DMA_CH8, DMA_CH9, FLASH, I2C0, I2C1, PIN_0, PIN_1, PIN_20, PIN_5, PIN_6, PIN_7, PIO0, PIO1,
+    PWM_SLICE0, PWM_SLICE1, PWM_SLICE2, PWM_SLICE4, SPI0, SPI1, UART0, UART1, USB, WATCHDOG,
+};
+use embassy_rp::pwm::{self, Pwm, PwmOutput};
+use embassy_rp::{adc, bind_interrupts, clocks, i2c, spi};
+use embassy_time::Delay;
+use wscomp::cv::{self, CvCalibration, CvOut};
+use wscomp::dac::Dac;
+use wscomp::eeprom::Eeprom;
+use wscomp::inputs::InputReader;
+use wscomp::leds;
+use wscomp::power;
+use wscomp::pulse::{PulseInput, PulseOut};
+
+wscomp::rp_input_reader!(adc: pub struct ComputerAdc);
+
+bind_interrupts!(struct Irqs {
+    ADC_IRQ_FIFO => adc::InterruptHandler;
+});
+
+/// Knobs, switch, CV and audio inputs
+pub type Inputs = InputReader<ComputerAdc<'static>, Output<'static>, Delay>;
+/// MCP4822 audio DAC, on SPI0 with DMA channel 0
+pub type AudioDac = Dac<spi::Spi<'static, SPI0, spi::Async>, Output<'static>>;
+/// Calibration EEPROM, on I2C0
+pub type CalibrationEeprom = Eeprom<i2c::I2c<'static, I2C0, i2c::Blocking>, Delay>;
+
+/// Every jack, knob and LED on the Computer
+pub struct Computer {
+    pub inputs: Inputs,
+    pub pulse_in: [PulseInput<Input<'static>>; 2],
+    /// Disabled when [`power::outputs_enabled`] was false at init
+    pub pulse_out: [PulseOut<Output<'static>>; 2],
+    /// LED PWM channels, set up for [`leds::PWM_TOP`]. Wrap them in one
+    /// [`Leds`](wscomp::leds::Leds), or split them between tasks.
+    pub leds: [PwmOutput<'static>; 6],
+    /// With the factory calibration from the EEPROM, when it has one
+    pub cv_out: [CvOut<PwmOutput<'static>>; 2],
+    pub dac: AudioDac,
+    pub eeprom: CalibrationEeprom,
+    pub spare: Spare,
+}
+
+/// Peripherals the Computer doesn't wire to anything, for the card to use
+///
+/// The GPIOs aren't wired to jacks or the front panel by [`Computer::init`],
+/// check the schematic for what else is on each before driving them.
+pub struct Spare {
+    pub core1: CORE1,
+    pub pio0: PIO0,
+    pub pio1: PIO1,
+    /// DMA_CH0 feeds the audio DAC
+    pub dma_ch1: DMA_CH1,
+    pub dma_ch2: DMA_CH2,
+    pub dma_ch3: DMA_CH3,
+    pub dma_ch4: DMA_CH4,
+    pub dma_ch5: DMA_CH5,
+    pub dma_ch6: DMA_CH6,
+    pub dma_ch7: DMA_CH7,
+    pub dma_ch8: DMA_CH8,
+    pub dma_ch9: DMA_CH9,
+    pub dma_ch10: DMA_CH10,
+    pub dma_ch11: DMA_CH11,
+    /// PWM slices without pins in use, for timers or PWM on the spare GPIOs.
+    /// Slices 3, 5, 6 and 7 drive the CV outputs and LEDs.
+    pub pwm_slice0: PWM_SLICE0,
+    pub pwm_slice1: PWM_SLICE1,
+    pub pwm_slice2: PWM_SLICE2,
+    pub pwm_slice4: PWM_SLICE4,
+    pub i2c1: I2C1,
+    pub spi1: SPI1,
+    pub uart0: UART0,
+    pub uart1: UART1,
+    pub pin_0: PIN_0,
+    pub pin_1: PIN_1,
+    pub pin_5: PIN_5,
+    pub pin_6: PIN_6,
+    pub pin_7: PIN_7,
+    pub pin_20: PIN_20,
+    /// For [`wscomp::rp_settings_flash!`]
+    pub flash: FLASH,
+    pub usb: USB,
+    pub watchdog: WATCHDOG,
+}
+
+impl Computer {
+    /// Set up the whole board from `embassy_rp::init()`'s peripherals
+    ///
+    /// Set the power mode with [`power::set_usb_only`] first, pulse outputs
+    /// start disabled in USB power mode.
+    pub fn init(p: embassy_rp::Peripherals) -> Self {
+        // the mux starts on the Z switch, and the probe low
+        let inputs = InputReader::new(
+            ComputerAdc {
+                adc: adc::Adc::new(p.ADC, Irqs, adc::Config::default()),
+                audio1: adc::Channel::new_pin(p.PIN_27, Pull::None),
+                audio2: adc::Channel::new_pin(p.PIN_26, Pull::None),
+                mux_io_1: adc::Channel::new_pin(p.PIN_28, Pull::None),
+                mux_io_2: adc::Channel::new_pin(p.PIN_29, Pull::None),
+            },
+            Output::new(p.PIN_4, Level::Low),
+            Output::new(p.PIN_24, Level::Low),
+            Output::new(p.PIN_25, Level::Low),
+            Delay,
+        );
+
+        let pulse_in = [
+            PulseInput::new(Input::new(p.PIN_2, Pull::Up), true),
+            PulseInput::new(Input::new(p.PIN_3, Pull::Up), true),
+        ];
+        let mut pulse_out = [
+            PulseOut::new(Output::new(p.PIN_8, Level::High), true),
+            PulseOut::new(Output::new(p.PIN_9, Level::High), true),
+        ];
+        for pulse in pulse_out.iter_mut() {
+            pulse.set_enabled(power::outputs_enabled());
+        }
+
+        let mut led_config = pwm::Config::default();
+        led_config.top = leds::PWM_TOP;
+        let [led1, led2] = pwm_pair(Pwm::new_output_ab(
+            p.PWM_SLICE5,
+            p.PIN_10,
+            p.PIN_11,
+            led_config.clone(),
+        ));
+        let [led3, led4] = pwm_pair(Pwm::new_output_ab(
+            p.PWM_SLICE6,
+            p.PIN_12,
+            p.PIN_13,
+            led_config.clone(),
+        ));
+        let [led5, led6] = pwm_pair(Pwm::new_output_ab(
+            p.PWM_SLICE7,
+            p.PIN_14,
+            p.PIN_15,
+            led_config,
+        ));
+
+        let mut eeprom = Eeprom::new(
+            i2c::I2c::new_blocking(p.I2C0, p.PIN_17, p.PIN_16, i2c::Config::default()),
+            Delay,
+        );
+        // factory CV output calibration, outputs are uncalibrated without it
+        let calibration = match eeprom.read_calibration() {
+            Ok(Ok(calibration)) => {
+                info!("CV calibration: {}", calibration);
+                calibration.cv_out
+            }
+            Ok(Err(e)) => {
+                warn!("no CV calibration: {}", e);
+                [CvCalibration::NONE; 2]
+            }
+            Err(_) => {
+                error!("error reading EEPROM");
+                [CvCalibration::NONE; 2]
+            }
+        };
+
+        let mut cv_config = pwm::Config::default();
+        cv_config.top = cv::pwm_top(clocks::clk_sys_freq());
+        cv_config.divider = cv::PWM_DIVIDER.into();
+        // CV out 2 has the lower GPIO, so it's channel A
+        let [cv2, cv1] = pwm_pair(Pwm::new_output_ab(
+            p.PWM_SLICE3,
+            p.PIN_22,
+            p.PIN_23,
+            cv_config,
+        ));
+
+        let dac = Dac::new(
+            spi::Spi::new_txonly(
+                p.SPI0,
+                p.PIN_18,
+                p.PIN_19,
+                p.DMA_CH0,
+                spi::Config::default(),
+            ),
+            Output::new(p.PIN_21, Level::High),
+        );
+
+        Computer {
+            inputs,
+            pulse_in,
+            pulse_out,
+            leds: [led1, led2, led3, led4, led5, led6],
+            cv_out: [
+                CvOut::with_calibration(cv1, calibration[0]),
+                CvOut::with_calibration(cv2, calibration[1]),
+            ],
+            dac,
+            eeprom,
+            spare: Spare {
+                core1: p.CORE1,
+                pio0: p.PIO0,
+                pio1: p.PIO1,
+                dma_ch1: p.DMA_CH1,
+                dma_ch2: p.DMA_CH2,
+                dma_ch3: p.DMA_CH3,
+                dma_ch4: p.DMA_CH4,
+                dma_ch5: p.DMA_CH5,
+                dma_ch6: p.DMA_CH6,
+                dma_ch7: p.DMA_CH7,
+                dma_ch8: p.DMA_CH8,
+                dma_ch9: p.DMA_CH9,
+                dma_ch10: p.DMA_CH10,
+                dma_ch11: p.DMA_CH11,
+                pwm_slice0: p.PWM_SLICE0,
+                pwm_slice1: p.PWM_SLICE1,
+                pwm_slice2: p.PWM_SLICE2,
+                pwm_slice4: p.PWM_SLICE4,
+                i2c1: p.I2C1,
+                spi1: p.SPI1,
+                uart0: p.UART0,
+                uart1: p.UART1,
+                pin_0: p.PIN_0,
+                pin_1: p.PIN_1,
+                pin_5: p.PIN_5,
+                pin_6: p.PIN_6,
+                pin_7: p.PIN_7,
+                pin_20: p.PIN_20,
+                flash: p.FLASH,
+                usb: p.USB,
+                watchdog: p.WATCHDOG,
+            },
+        }
+    }
+}
+
+/// Both channels of a slice set up with `new_output_ab`, A then B
+fn pwm_pair(pwm: Pwm<'static>) -> [PwmOutput<'static>; 2] {
+    let (Some(a), Some(b)) = pwm.split() else {
+        // new_output_ab always has both channels
+        unreachable!()
+    };
+    [a, b]
+}
@@ -0,0 +1,92 @@
+//! Host configuration over USB, the transport for [`wscomp::config`]
+//!
+//! A USB serial port (CDC-ACM) on the Computer's USB-C jack. Each packet
+//! from the host is one request, answered with one response packet. Cards
+//! run [`serve`] in a task, with the USB peripheral from
+//! [`Spare`](crate::Spare) and a handler for the decoded requests:
+//!
+//! ```text
+//! #[embassy_executor::task]
+//! async fn config_loop(usb: peripherals::USB) {
+//!     usb_config::serve(usb, |request| {
+//!         MATRIX.lock(|matrix| matrix.borrow_mut().configure(request))
+//!     })
+//!     .await
+//! }
+//! ```
+
+use embassy_futures::join::join;
+use embassy_rp::bind_interrupts;
+use embassy_rp::peripherals::USB;
+use embassy_rp::usb::{self, Driver};
+use embassy_usb::class::cdc_acm::{CdcAcmClass, State};
+use embassy_usb::driver::EndpointError;
+use embassy_usb::{Builder, Config};
+use wscomp::config::{Request, Response};
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => usb::InterruptHandler<USB>;
+});
+
+/// pid.codes test IDs, for development use
+const VENDOR_ID: u16 = 0x1209;
+const PRODUCT_ID: u16 = 0x0001;
+
+/// Largest full speed bulk packet
+const PACKET_SIZE: u16 = 64;
+
+/// Answer configuration requests from the host with `handle`, forever
+///
+/// Requests that don't decode are answered with their error, without
+/// calling `handle`.
+pub async fn serve(usb: USB, mut handle: impl FnMut(Request) -> Response) -> ! {
+    let mut config = Config::new(VENDOR_ID, PRODUCT_ID);
+    config.manufacturer = Some("Music Thing Modular");
+    config.product = Some("Workshop System Computer");
+    config.max_power = 100;
+    config.max_packet_size_0 = PACKET_SIZE as u8;
+
+    let mut config_descriptor = [0; 256];
+    let mut bos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+    let mut state = State::new();
+    let mut builder = Builder::new(
+        Driver::new(usb, Irqs),
+        config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut [],
+        &mut control_buf,
+    );
+    let mut class = CdcAcmClass::new(&mut builder, &mut state, PACKET_SIZE);
+    let mut device = builder.build();
+
+    let requests = async {
+        loop {
+            class.wait_connection().await;
+            info!("USB config connected");
+            if let Err(EndpointError::BufferOverflow) = answer(&mut class, &mut handle).await {
+                error!("USB config packet too large");
+            }
+            info!("USB config disconnected");
+        }
+    };
+    let (never, _) = join(device.run(), requests).await;
+    never
+}
+
+/// Answer requests until the host disconnects
+async fn answer<'d>(
+    class: &mut CdcAcmClass<'d, Driver<'d, USB>>,
+    handle: &mut impl FnMut(Request) -> Response,
+) -> Result<(), EndpointError> {
+    let mut packet = [0; PACKET_SIZE as usize];
+    loop {
+        let len = class.read_packet(&mut packet).await?;
+        let response = match Request::decode(&packet[..len]) {
+            Ok(request) => handle(request),
+            Err(error) => Response::Error(error),
+        };
+        class.write_packet(&response.encode()).await?;
+    }
+}